@@ -0,0 +1,38 @@
+//! evaluation of the generated worlds of `datalog::workloads`, with and
+//! without join planning
+//!
+//! ```text
+//! cargo bench --bench workloads
+//! ```
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use datalog::workloads::{self, derived};
+use datalog::{Rule, RunLimits, SymbolTable, World};
+
+fn run(world: &World, rule: &Rule, plan_joins: bool) -> usize {
+    let mut world = world.clone();
+    world.add_rule(rule.clone());
+    world.run_with_limits(RunLimits { plan_joins, ..RunLimits::unlimited() }).unwrap();
+    derived(&world, rule)
+}
+
+fn bench(c: &mut Criterion, name: &str, sizes: &[usize], generate: impl Fn(usize) -> (World, SymbolTable, Rule)) {
+    let mut group = c.benchmark_group(name);
+    for size in sizes {
+        let (world, _, rule) = generate(*size);
+        for plan_joins in [false, true] {
+            let id = BenchmarkId::new(if plan_joins { "planned" } else { "in order" }, size);
+            group.bench_with_input(id, &(&world, &rule), |b, (world, rule)| b.iter(|| run(world, rule, plan_joins)));
+        }
+    }
+    group.finish();
+}
+
+fn workloads(c: &mut Criterion) {
+    bench(c, "transitive_chain", &[16, 64], workloads::transitive_chain);
+    bench(c, "join", &[10, 100], |n| workloads::join(n, n));
+    bench(c, "suffix_filter", &[10, 100], |n| workloads::suffix_filter(n, 10));
+    bench(c, "expressions", &[100, 1000], workloads::expressions);
+}
+
+criterion_group!(benches, workloads);
+criterion_main!(benches);
@@ -0,0 +1,261 @@
+//! interactive prompt to explore a world
+//!
+//! ```text
+//! cargo run --example repl
+//! > parent(#A, #B)
+//! > parent(#B, #C)
+//! > grandparent($a, $c) <- parent($a, $b), parent($b, $c)
+//! > run
+//! 1 new facts, 3 facts
+//! > query grandparent($x, $y)
+//! $x = #A, $y = #C
+//! ```
+//!
+//! lines containing `<-` are rules, other lines are facts, unless they
+//! start with one of the commands listed by `help`
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Write};
+use std::time::Duration;
+
+use datalog::parser::{parse_fact, parse_predicate, parse_rule};
+use datalog::{error, RunLimits, SymbolTable, World};
+
+const HELP: &str = "\
+<fact>                  adds a fact, like parent(#A, #B)
+<rule>                  adds a rule, like ancestor($a, $b) <- parent($a, $b)
+run                     applies the rules with the current limits
+query <predicate>       prints the bindings of the predicate's variables
+limits [<name>=<value>] prints or sets max_facts, max_iterations,
+                        max_time (in ms) or max_candidates, `none` removes one
+dump                    prints the facts and rules
+help                    prints this message
+quit                    exits";
+
+struct Repl {
+    world: World,
+    symbols: SymbolTable,
+    limits: RunLimits,
+}
+
+impl Repl {
+    fn new() -> Self {
+        Repl {
+            world: World::new(),
+            symbols: SymbolTable::new(),
+            limits: RunLimits { max_time: Some(Duration::from_secs(1)), ..RunLimits::default() },
+        }
+    }
+
+    /// runs one line, returns its output, or `None` to exit
+    fn eval(&mut self, line: &str) -> Option<String> {
+        let line = line.trim();
+        let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+        let output = match command {
+            "" => String::new(),
+            "quit" | "exit" => return None,
+            "help" => HELP.to_string(),
+            "run" => self.run(),
+            "query" => self.query(args.trim()),
+            "limits" => self.set_limits(args),
+            "dump" => self.symbols.print_world(&self.world),
+            _ if line.contains("<-") => match parse_rule(line, &mut self.symbols) {
+                Ok(rule) => {
+                    self.world.add_rule(rule);
+                    String::new()
+                },
+                Err(e) => parse_error(line, &e),
+            },
+            _ => match parse_fact(line, &mut self.symbols) {
+                Ok(fact) => {
+                    self.world.add_fact(fact);
+                    String::new()
+                },
+                Err(e) => parse_error(line, &e),
+            },
+        };
+        Some(output)
+    }
+
+    fn run(&mut self) -> String {
+        let before = self.world.facts.len();
+        match self.world.run_with_limits(self.limits.clone()) {
+            Ok(()) => format!("{} new facts, {} facts", self.world.facts.len() - before, self.world.facts.len()),
+            Err(error::Error::RunLimit(e)) => format!("error: {}", self.symbols.print_run_limit(&e, &self.world.rules)),
+            Err(e) => format!("error: {}", self.symbols.print_error(&e)),
+        }
+    }
+
+    fn query(&mut self, input: &str) -> String {
+        let predicate = match parse_predicate(input, &mut self.symbols) {
+            Ok(predicate) => predicate,
+            Err(e) => return parse_error(input, &e),
+        };
+
+        let mut lines = self.world.query_bindings(&predicate, &[]).into_iter().map(|bindings| {
+            let mut bindings = bindings.into_iter().collect::<Vec<_>>();
+            bindings.sort();
+            bindings.iter()
+                .map(|(variable, value)| {
+                    format!("{} = {}", self.symbols.print_variable(*variable), self.symbols.print_id(value))
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        }).collect::<Vec<_>>();
+        if lines.is_empty() {
+            return "no match".to_string();
+        }
+        lines.sort();
+        lines.join("\n")
+    }
+
+    fn set_limits(&mut self, args: &str) -> String {
+        for arg in args.split_whitespace() {
+            let (name, value) = match arg.split_once('=') {
+                Some(pair) => pair,
+                None => return format!("error: expected <name>=<value>, got `{}`", arg),
+            };
+            let value = match value {
+                "none" => None,
+                value => match value.trim_end_matches("ms").parse::<u64>() {
+                    Ok(value) => Some(value),
+                    Err(_) => return format!("error: invalid value for {}: `{}`", name, value),
+                },
+            };
+            let as_u32 = |value: Option<u64>| value.map(|v| u32::try_from(v).unwrap_or(u32::MAX));
+            match name {
+                "max_facts" => self.limits.max_facts = as_u32(value),
+                "max_iterations" => self.limits.max_iterations = as_u32(value),
+                "max_time" => self.limits.max_time = value.map(Duration::from_millis),
+                "max_candidates" => self.limits.max_candidates = value.unwrap_or(u64::MAX),
+                _ => return format!("error: unknown limit `{}`", name),
+            }
+        }
+
+        let print = |value: Option<String>| value.unwrap_or_else(|| "none".to_string());
+        format!(
+            "max_facts={} max_iterations={} max_time={} max_candidates={}",
+            print(self.limits.max_facts.map(|v| v.to_string())),
+            print(self.limits.max_iterations.map(|v| v.to_string())),
+            print(self.limits.max_time.map(|t| format!("{}ms", t.as_millis()))),
+            print(Some(self.limits.max_candidates).filter(|v| *v != u64::MAX).map(|v| v.to_string())),
+        )
+    }
+}
+
+/// the error, and the input with a caret under the position where parsing
+/// stopped
+fn parse_error(input: &str, e: &error::Parse) -> String {
+    let column = input.get(..e.offset).map_or(0, |prefix| prefix.chars().count());
+    format!("error: {}\n  {}\n  {}^", e, input, " ".repeat(column))
+}
+
+/// reads commands from `input` until it ends or `quit` is entered. With
+/// `echo`, the commands are written to `output` after the prompt, so that
+/// it reads like a terminal session
+fn session(input: impl BufRead, mut output: impl Write, echo: bool) -> io::Result<()> {
+    let mut repl = Repl::new();
+    write!(output, "> ")?;
+    output.flush()?;
+    for line in input.lines() {
+        let line = line?;
+        if echo {
+            writeln!(output, "{}", line)?;
+        }
+        match repl.eval(&line) {
+            None => return Ok(()),
+            Some(result) if result.is_empty() => {},
+            Some(result) => writeln!(output, "{}", result)?,
+        }
+        write!(output, "> ")?;
+        output.flush()?;
+    }
+    writeln!(output)
+}
+
+fn main() -> io::Result<()> {
+    session(io::stdin().lock(), io::stdout().lock(), false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the durations in the errors vary between runs
+    fn without_durations(output: &str) -> String {
+        output.lines().map(|line| {
+            line.split(", ")
+                .map(|part| {
+                    let duration = part.starts_with(|c: char| c.is_ascii_digit()) && part.ends_with('s') && !part.contains(' ');
+                    if duration { "<elapsed>" } else { part }
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        }).collect::<Vec<_>>().join("\n")
+    }
+
+    #[test]
+    fn transcript() {
+        let input = "\
+parent(#A, #B)
+parent(#B, #C)
+parent(#C, #D)
+grandparent($a, $c) <- parent($a, $b), parent($b, $c)
+parent(#D #E)
+run
+query grandparent($x, $y)
+query grandparent(#A, $y)
+query parent(#E, $y)
+query parent($x
+limits max_facts=5 max_time=none
+grandparent($a, $c) <- grandparent($a, $b), parent($b, $c)
+run
+dump
+quit
+run
+";
+        let mut output = Vec::new();
+        session(input.as_bytes(), &mut output, true).unwrap();
+        assert_eq!(without_durations(&String::from_utf8(output).unwrap()), "\
+> parent(#A, #B)
+> parent(#B, #C)
+> parent(#C, #D)
+> grandparent($a, $c) <- parent($a, $b), parent($b, $c)
+> parent(#D #E)
+error: expected ) at byte 10
+  parent(#D #E)
+            ^
+> run
+2 new facts, 5 facts
+> query grandparent($x, $y)
+$x = #A, $y = #C
+$x = #B, $y = #D
+> query grandparent(#A, $y)
+$y = #C
+> query parent(#E, $y)
+no match
+> query parent($x
+error: expected ) at byte 9
+  parent($x
+           ^
+> limits max_facts=5 max_time=none
+max_facts=5 max_iterations=100 max_time=none max_candidates=1000000
+> grandparent($a, $c) <- grandparent($a, $b), parent($b, $c)
+> run
+error: too many facts after 0 iterations, 5 facts, <elapsed>, while applying rule 1: grandparent($a, $c) <- grandparent($a, $b), parent($b, $c)
+> dump
+World {
+  facts: [
+    \"parent(#A, #B)\",
+    \"parent(#B, #C)\",
+    \"parent(#C, #D)\",
+    \"grandparent(#A, #C)\",
+    \"grandparent(#B, #D)\",
+]
+  rules: [
+    \"grandparent($a, $c) <- parent($a, $b), parent($b, $c)\",
+    \"grandparent($a, $c) <- grandparent($a, $b), parent($b, $c)\",
+]
+}
+> quit");
+    }
+}
@@ -0,0 +1,628 @@
+//! compact binary format for a world and its symbol table, meant to cache
+//! derived worlds
+//!
+//! the input starts with `MAGIC` and a version byte. Integers are LEB128
+//! varints, zigzag encoded if signed, and strings, byte strings and
+//! sequences are prefixed by their length. Facts are sorted and maps are
+//! written in key order, so a world is always serialized to the same bytes.
+//!
+//! Decoding does not trust length prefixes: sequences grow as their
+//! elements are read, so a truncated input with a huge length fails with
+//! `Decode::Truncated` instead of allocating that length
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use super::{
+    AggregateOp, Binary, BytesConstraint, Constraint, ConstraintKind, DateConstraint, Derivation, Expression,
+    Fact, FactSet, IntConstraint, Op, Origin, Predicate, Rule, SetHandle, StrConstraint, SymbolConstraint, SymbolTable,
+    Unary, World, ID,
+};
+use crate::error::Decode;
+
+const MAGIC: [u8; 4] = *b"DLWB";
+
+/// version of the format written by `World::serialize_into`
+pub const BINARY_VERSION: u8 = 1;
+
+/// nesting limit of sets, so decoding cannot overflow the stack
+const MAX_DEPTH: usize = 64;
+
+/// capacity reserved before reading a sequence, whatever its length prefix
+const MAX_PREALLOCATION: usize = 256;
+
+impl World {
+    /// writes the symbol table and the world, including the provenance of
+    /// derived facts. `out` should be buffered
+    pub fn serialize_into<W: Write>(&self, symbols: &SymbolTable, out: &mut W) -> io::Result<()> {
+        let mut e = Encoder { out };
+        e.out.write_all(&MAGIC)?;
+        e.out.write_all(&[BINARY_VERSION])?;
+
+        e.seq(&symbols.symbols, |e, s| e.string(s))?;
+
+        e.bool(self.facts.is_sorted())?;
+        let mut facts = self.facts.iter().collect::<Vec<_>>();
+        facts.sort();
+        e.seq(&facts, |e, f| e.fact(f))?;
+        e.seq(&self.rules, Encoder::rule)?;
+
+        e.seq(&sorted(&self.arities), |e, (name, arity)| {
+            e.uint(**name)?;
+            e.len(**arity)
+        })?;
+        e.seq(&sorted(&self.derivations), |e, (fact, derivation)| {
+            e.fact(fact)?;
+            e.len(derivation.rule)?;
+            e.seq(&sorted(&derivation.bindings), |e, (variable, value)| {
+                e.uint(u64::from(**variable))?;
+                e.id(value, 0)
+            })?;
+            e.seq(&derivation.premises, Encoder::fact)
+        })?;
+        e.seq(&sorted(&self.fact_origins), |e, (fact, origins)| {
+            e.fact(fact)?;
+            e.len(origins.len())?;
+            origins.iter().try_for_each(|o| e.origin(o))
+        })?;
+        e.seq(&sorted(&self.rule_origins), |e, (rule, origin)| {
+            e.len(**rule)?;
+            e.origin(origin)
+        })?;
+        e.bool(self.partial)
+    }
+
+    /// reads a world written by `World::serialize_into`. `input` should
+    /// be buffered
+    pub fn deserialize_from<R: Read>(input: &mut R) -> Result<(World, SymbolTable), Decode> {
+        let mut d = Decoder { input };
+        let mut header = [0u8; 5];
+        d.input.read_exact(&mut header).map_err(|_| Decode::InvalidHeader)?;
+        if header[..4] != MAGIC {
+            return Err(Decode::InvalidHeader);
+        }
+        if header[4] > BINARY_VERSION {
+            return Err(Decode::UnsupportedVersion { found: header[4], supported: BINARY_VERSION });
+        }
+
+        let symbols = SymbolTable::from_symbols(d.seq(Decoder::string)?).map_err(Decode::Symbol)?;
+
+        let mut facts = if d.bool()? { FactSet::sorted() } else { FactSet::new() };
+        for _ in 0..d.len()? {
+            facts.insert(d.fact()?);
+        }
+        let rules = d.seq(Decoder::rule)?;
+
+        let arities = d.seq(|d| Ok((d.uint()?, d.len()?)))?.into_iter().collect();
+        let derivations = d.seq(|d| {
+            let fact = d.fact()?;
+            let rule = d.len()?;
+            let bindings = d.seq(|d| Ok((d.u32()?, d.id(0)?)))?.into_iter().collect();
+            let premises = d.seq(Decoder::fact)?;
+            Ok((fact, Derivation { rule, bindings, premises }))
+        })?.into_iter().collect();
+        let fact_origins = d.seq(|d| {
+            let fact = d.fact()?;
+            let origins = d.seq(Decoder::origin)?.into_iter().collect::<BTreeSet<_>>();
+            Ok((fact, origins))
+        })?.into_iter().collect();
+        let rule_origins = d.seq(|d| Ok((d.len()?, d.origin()?)))?.into_iter().collect();
+        let partial = d.bool()?;
+
+        let world = World { facts, rules: rules.into(), arities, derivations, fact_origins, rule_origins, partial, ..World::default() };
+        Ok((world, symbols))
+    }
+}
+
+fn sorted<K: Ord, V>(map: &HashMap<K, V>) -> Vec<(&K, &V)> {
+    let mut entries = map.iter().collect::<Vec<_>>();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+fn sorted_set<T: Ord>(set: &HashSet<T>) -> Vec<&T> {
+    let mut elements = set.iter().collect::<Vec<_>>();
+    elements.sort();
+    elements
+}
+
+struct Encoder<'a, W> {
+    out: &'a mut W,
+}
+
+impl<W: Write> Encoder<'_, W> {
+    fn byte(&mut self, b: u8) -> io::Result<()> {
+        self.out.write_all(&[b])
+    }
+
+    fn bool(&mut self, b: bool) -> io::Result<()> {
+        self.byte(b as u8)
+    }
+
+    fn uint(&mut self, mut i: u64) -> io::Result<()> {
+        let mut buf = [0u8; 10];
+        let mut len = 0;
+        loop {
+            let b = (i & 0x7f) as u8;
+            i >>= 7;
+            if i == 0 {
+                buf[len] = b;
+                len += 1;
+                break;
+            }
+            buf[len] = b | 0x80;
+            len += 1;
+        }
+        self.out.write_all(&buf[..len])
+    }
+
+    fn int(&mut self, i: i64) -> io::Result<()> {
+        self.uint(((i << 1) ^ (i >> 63)) as u64)
+    }
+
+    fn len(&mut self, len: usize) -> io::Result<()> {
+        self.uint(len as u64)
+    }
+
+    fn bytes(&mut self, b: &[u8]) -> io::Result<()> {
+        self.len(b.len())?;
+        self.out.write_all(b)
+    }
+
+    fn string(&mut self, s: &String) -> io::Result<()> {
+        self.bytes(s.as_bytes())
+    }
+
+    fn seq<T, F: FnMut(&mut Self, &T) -> io::Result<()>>(&mut self, elements: &[T], mut f: F) -> io::Result<()> {
+        self.len(elements.len())?;
+        elements.iter().try_for_each(|element| f(self, element))
+    }
+
+    fn id(&mut self, id: &ID, depth: usize) -> io::Result<()> {
+        match id {
+            ID::Symbol(s) => {
+                self.byte(0)?;
+                self.uint(*s)
+            },
+            ID::Variable(v) => {
+                self.byte(1)?;
+                self.uint(u64::from(*v))
+            },
+            ID::Integer(i) => {
+                self.byte(2)?;
+                self.int(*i)
+            },
+            ID::Str(s) => {
+                self.byte(3)?;
+                self.string(s)
+            },
+            ID::Date(d) => {
+                self.byte(4)?;
+                self.uint(*d)
+            },
+            ID::Bytes(b) => {
+                self.byte(5)?;
+                self.bytes(b)
+            },
+            ID::Bool(b) => {
+                self.byte(6)?;
+                self.bool(*b)
+            },
+            ID::Set(set) => {
+                if depth >= MAX_DEPTH {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "sets are nested too deeply"));
+                }
+                self.byte(7)?;
+                self.len(set.len())?;
+                set.iter().try_for_each(|id| self.id(id, depth + 1))
+            },
+            ID::Wildcard => self.byte(8),
+        }
+    }
+
+    fn predicate(&mut self, p: &Predicate) -> io::Result<()> {
+        self.uint(p.name)?;
+        self.len(p.ids.len())?;
+        p.ids.iter().try_for_each(|id| self.id(id, 0))
+    }
+
+    fn fact(&mut self, f: &Fact) -> io::Result<()> {
+        self.predicate(&f.predicate)
+    }
+
+    fn rule(&mut self, r: &Rule) -> io::Result<()> {
+        self.predicate(&r.head)?;
+        self.seq(&r.body, Encoder::predicate)?;
+        self.seq(&r.constraints, Encoder::constraint)?;
+        self.seq(&r.expressions, Encoder::expression)?;
+        self.seq(&r.negated, Encoder::predicate)?;
+        match &r.aggregate {
+            None => self.byte(0),
+            Some((op, variable)) => {
+                self.byte(match op {
+                    AggregateOp::Count => 1,
+                    AggregateOp::Sum => 2,
+                    AggregateOp::Min => 3,
+                    AggregateOp::Max => 4,
+                })?;
+                self.uint(u64::from(*variable))
+            },
+        }
+    }
+
+    fn constraint(&mut self, c: &Constraint) -> io::Result<()> {
+        self.uint(u64::from(c.id))?;
+        match &c.kind {
+            ConstraintKind::Int(IntConstraint::LessThan(i)) => { self.byte(0)?; self.int(*i) },
+            ConstraintKind::Int(IntConstraint::GreaterThan(i)) => { self.byte(1)?; self.int(*i) },
+            ConstraintKind::Int(IntConstraint::LessOrEqual(i)) => { self.byte(2)?; self.int(*i) },
+            ConstraintKind::Int(IntConstraint::GreaterOrEqual(i)) => { self.byte(3)?; self.int(*i) },
+            ConstraintKind::Int(IntConstraint::Equal(i)) => { self.byte(4)?; self.int(*i) },
+            ConstraintKind::Int(IntConstraint::In(s)) => { self.byte(5)?; self.seq(&s.iter().collect::<Vec<_>>(), |e, i| e.int(**i)) },
+            ConstraintKind::Int(IntConstraint::NotIn(s)) => { self.byte(6)?; self.seq(&s.iter().collect::<Vec<_>>(), |e, i| e.int(**i)) },
+            ConstraintKind::Str(StrConstraint::Prefix(s)) => { self.byte(7)?; self.string(s) },
+            ConstraintKind::Str(StrConstraint::Suffix(s)) => { self.byte(8)?; self.string(s) },
+            ConstraintKind::Str(StrConstraint::Equal(s)) => { self.byte(9)?; self.string(s) },
+            ConstraintKind::Str(StrConstraint::In(s)) => { self.byte(10)?; self.seq(&s.iter().collect::<Vec<_>>(), |e, s| e.string(s)) },
+            ConstraintKind::Str(StrConstraint::NotIn(s)) => { self.byte(11)?; self.seq(&s.iter().collect::<Vec<_>>(), |e, s| e.string(s)) },
+            ConstraintKind::Str(StrConstraint::Regex(s)) => { self.byte(12)?; self.string(s) },
+            ConstraintKind::Date(DateConstraint::Before(d)) => { self.byte(13)?; self.uint(*d) },
+            ConstraintKind::Date(DateConstraint::After(d)) => { self.byte(14)?; self.uint(*d) },
+            ConstraintKind::Symbol(SymbolConstraint::In(s)) => { self.byte(15)?; self.seq(&s.iter().collect::<Vec<_>>(), |e, s| e.uint(**s)) },
+            ConstraintKind::Symbol(SymbolConstraint::NotIn(s)) => { self.byte(16)?; self.seq(&s.iter().collect::<Vec<_>>(), |e, s| e.uint(**s)) },
+            ConstraintKind::Bytes(BytesConstraint::Equal(b)) => { self.byte(17)?; self.bytes(b) },
+            ConstraintKind::Bytes(BytesConstraint::In(s)) => { self.byte(18)?; self.seq(&s.iter().collect::<Vec<_>>(), |e, b| e.bytes(b)) },
+            ConstraintKind::Bytes(BytesConstraint::NotIn(s)) => { self.byte(19)?; self.seq(&s.iter().collect::<Vec<_>>(), |e, b| e.bytes(b)) },
+            ConstraintKind::Bytes(BytesConstraint::InSet(h)) => { self.byte(20)?; self.set_handle(h) },
+            ConstraintKind::Bytes(BytesConstraint::NotInSet(h)) => { self.byte(21)?; self.set_handle(h) },
+        }
+    }
+
+    fn set_handle(&mut self, h: &SetHandle) -> io::Result<()> {
+        self.uint(h.name())?;
+        self.seq(&sorted_set(h.set()), |e, b| e.bytes(b))
+    }
+
+    fn expression(&mut self, e: &Expression) -> io::Result<()> {
+        self.seq(&e.ops, |e, op| match op {
+            Op::Value(id) => {
+                e.byte(0)?;
+                e.id(id, 0)
+            },
+            Op::Unary(Unary::Negate) => e.byte(1),
+            Op::Binary(b) => {
+                e.byte(2)?;
+                e.byte(BINARY_OPS.iter().position(|op| op == b).expect("all binary operators are listed") as u8)
+            },
+            Op::Bind(v) => {
+                e.byte(3)?;
+                e.uint(u64::from(*v))
+            },
+        })
+    }
+
+    fn origin(&mut self, o: &Origin) -> io::Result<()> {
+        match o {
+            Origin::Authority => self.byte(0),
+            Origin::Ambient => self.byte(1),
+            Origin::Block(i) => {
+                self.byte(2)?;
+                self.uint(u64::from(*i))
+            },
+        }
+    }
+}
+
+/// indexed by their tag
+const BINARY_OPS: [Binary; 12] = [
+    Binary::LessThan, Binary::GreaterThan, Binary::LessOrEqual, Binary::GreaterOrEqual, Binary::Equal,
+    Binary::In, Binary::NotIn, Binary::Prefix, Binary::Suffix, Binary::Regex, Binary::Add, Binary::And,
+];
+
+struct Decoder<'a, R> {
+    input: &'a mut R,
+}
+
+impl<R: Read> Decoder<'_, R> {
+    fn byte(&mut self) -> Result<u8, Decode> {
+        let mut b = [0u8; 1];
+        self.input.read_exact(&mut b)?;
+        Ok(b[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, Decode> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(Decode::InvalidTag { field: "bool", tag }),
+        }
+    }
+
+    fn uint(&mut self) -> Result<u64, Decode> {
+        let mut i = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            let bits = u64::from(b & 0x7f);
+            // the 10th byte can only hold the highest bit
+            if shift == 63 && bits > 1 {
+                return Err(Decode::InvalidInteger);
+            }
+            i |= bits << shift;
+            if b & 0x80 == 0 {
+                return Ok(i);
+            }
+        }
+        Err(Decode::InvalidInteger)
+    }
+
+    fn int(&mut self) -> Result<i64, Decode> {
+        let i = self.uint()?;
+        Ok((i >> 1) as i64 ^ -((i & 1) as i64))
+    }
+
+    fn u32(&mut self) -> Result<u32, Decode> {
+        u32::try_from(self.uint()?).map_err(|_| Decode::InvalidInteger)
+    }
+
+    fn len(&mut self) -> Result<usize, Decode> {
+        usize::try_from(self.uint()?).map_err(|_| Decode::InvalidInteger)
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, Decode> {
+        let len = self.uint()?;
+        let mut bytes = Vec::new();
+        // grows with the data actually read
+        (&mut *self.input).take(len).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < len {
+            return Err(Decode::Truncated);
+        }
+        Ok(bytes)
+    }
+
+    fn string(&mut self) -> Result<String, Decode> {
+        String::from_utf8(self.bytes()?).map_err(|_| Decode::InvalidString)
+    }
+
+    fn seq<T, F: FnMut(&mut Self) -> Result<T, Decode>>(&mut self, mut f: F) -> Result<Vec<T>, Decode> {
+        let len = self.len()?;
+        let mut elements = Vec::with_capacity(len.min(MAX_PREALLOCATION));
+        for _ in 0..len {
+            elements.push(f(self)?);
+        }
+        Ok(elements)
+    }
+
+    fn id(&mut self, depth: usize) -> Result<ID, Decode> {
+        Ok(match self.byte()? {
+            0 => ID::Symbol(self.uint()?),
+            1 => ID::Variable(self.u32()?),
+            2 => ID::Integer(self.int()?),
+            3 => ID::Str(self.string()?),
+            4 => ID::Date(self.uint()?),
+            5 => ID::Bytes(self.bytes()?),
+            6 => ID::Bool(self.bool()?),
+            7 => {
+                if depth >= MAX_DEPTH {
+                    return Err(Decode::TooDeep);
+                }
+                ID::Set(self.seq(|d| d.id(depth + 1))?.into_iter().collect())
+            },
+            8 => ID::Wildcard,
+            tag => return Err(Decode::InvalidTag { field: "ID", tag }),
+        })
+    }
+
+    fn predicate(&mut self) -> Result<Predicate, Decode> {
+        let name = self.uint()?;
+        let ids = self.seq(|d| d.id(0))?;
+        Ok(Predicate { name, ids: ids.into() })
+    }
+
+    fn fact(&mut self) -> Result<Fact, Decode> {
+        Ok(Fact { predicate: self.predicate()? })
+    }
+
+    fn rule(&mut self) -> Result<Rule, Decode> {
+        let head = self.predicate()?;
+        let body = self.seq(Decoder::predicate)?;
+        let constraints = self.seq(Decoder::constraint)?;
+        let expressions = self.seq(Decoder::expression)?;
+        let negated = self.seq(Decoder::predicate)?;
+        let aggregate = match self.byte()? {
+            0 => None,
+            1 => Some((AggregateOp::Count, self.u32()?)),
+            2 => Some((AggregateOp::Sum, self.u32()?)),
+            3 => Some((AggregateOp::Min, self.u32()?)),
+            4 => Some((AggregateOp::Max, self.u32()?)),
+            tag => return Err(Decode::InvalidTag { field: "aggregate", tag }),
+        };
+        Ok(Rule { head, body, constraints, expressions, negated, aggregate })
+    }
+
+    fn constraint(&mut self) -> Result<Constraint, Decode> {
+        let id = self.u32()?;
+        let kind = match self.byte()? {
+            0 => ConstraintKind::Int(IntConstraint::LessThan(self.int()?)),
+            1 => ConstraintKind::Int(IntConstraint::GreaterThan(self.int()?)),
+            2 => ConstraintKind::Int(IntConstraint::LessOrEqual(self.int()?)),
+            3 => ConstraintKind::Int(IntConstraint::GreaterOrEqual(self.int()?)),
+            4 => ConstraintKind::Int(IntConstraint::Equal(self.int()?)),
+            5 => ConstraintKind::Int(IntConstraint::In(self.seq(Decoder::int)?.into_iter().collect())),
+            6 => ConstraintKind::Int(IntConstraint::NotIn(self.seq(Decoder::int)?.into_iter().collect())),
+            7 => ConstraintKind::Str(StrConstraint::Prefix(self.string()?)),
+            8 => ConstraintKind::Str(StrConstraint::Suffix(self.string()?)),
+            9 => ConstraintKind::Str(StrConstraint::Equal(self.string()?)),
+            10 => ConstraintKind::Str(StrConstraint::In(self.seq(Decoder::string)?.into_iter().collect())),
+            11 => ConstraintKind::Str(StrConstraint::NotIn(self.seq(Decoder::string)?.into_iter().collect())),
+            12 => ConstraintKind::Str(StrConstraint::Regex(self.string()?)),
+            13 => ConstraintKind::Date(DateConstraint::Before(self.uint()?)),
+            14 => ConstraintKind::Date(DateConstraint::After(self.uint()?)),
+            15 => ConstraintKind::Symbol(SymbolConstraint::In(self.seq(Decoder::uint)?.into_iter().collect())),
+            16 => ConstraintKind::Symbol(SymbolConstraint::NotIn(self.seq(Decoder::uint)?.into_iter().collect())),
+            17 => ConstraintKind::Bytes(BytesConstraint::Equal(self.bytes()?)),
+            18 => ConstraintKind::Bytes(BytesConstraint::In(self.seq(Decoder::bytes)?.into_iter().collect())),
+            19 => ConstraintKind::Bytes(BytesConstraint::NotIn(self.seq(Decoder::bytes)?.into_iter().collect())),
+            20 => ConstraintKind::Bytes(BytesConstraint::InSet(SetHandle::new(self.uint()?, self.seq(Decoder::bytes)?))),
+            21 => ConstraintKind::Bytes(BytesConstraint::NotInSet(SetHandle::new(self.uint()?, self.seq(Decoder::bytes)?))),
+            tag => return Err(Decode::InvalidTag { field: "constraint", tag }),
+        };
+        Ok(Constraint { id, kind })
+    }
+
+    fn expression(&mut self) -> Result<Expression, Decode> {
+        let ops = self.seq(|d| Ok(match d.byte()? {
+            0 => Op::Value(d.id(0)?),
+            1 => Op::Unary(Unary::Negate),
+            2 => {
+                let tag = d.byte()?;
+                let op = BINARY_OPS.get(usize::from(tag)).ok_or(Decode::InvalidTag { field: "binary operator", tag })?;
+                Op::Binary(op.clone())
+            },
+            3 => Op::Bind(d.u32()?),
+            tag => return Err(Decode::InvalidTag { field: "op", tag }),
+        }))?;
+        Ok(Expression { ops })
+    }
+
+    fn origin(&mut self) -> Result<Origin, Decode> {
+        match self.byte()? {
+            0 => Ok(Origin::Authority),
+            1 => Ok(Origin::Ambient),
+            2 => Ok(Origin::Block(self.u32()?)),
+            tag => Err(Decode::InvalidTag { field: "origin", tag }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_fact, parse_rule};
+    use crate::RunLimits;
+    use std::time::Duration;
+
+    fn world(facts: usize) -> (World, SymbolTable) {
+        let mut syms = SymbolTable::with_defaults();
+        let mut w = World::new();
+        let file = syms.insert("file");
+        let user = syms.insert("user");
+        let read = syms.insert("read");
+        w.declare_predicate(file, 3);
+        for i in 0..facts {
+            let origin = if i % 3 == 0 { Origin::Authority } else { Origin::Block(i as u32 % 4) };
+            w.add_fact_with_origin(Fact::new(file, &[
+                ID::Integer(i as i64 - 500),
+                ID::Str(format!("file{}", i)),
+                ID::Date(1_608_542_592 + i as u64),
+            ]), origin);
+            if i % 100 == 0 {
+                w.add_fact(Fact::new(user, &[
+                    ID::Symbol(read),
+                    ID::Bytes(vec![i as u8, 0xff]),
+                    ID::Bool(i % 200 == 0),
+                    ID::Set([ID::Integer(i as i64), ID::Str("a".to_string())].iter().cloned().collect()),
+                ]));
+            }
+        }
+
+        let rules = [
+            "recent($f) <- file($i, $f, $d) @ $d >= 2020-12-21T10:00:00+00:00, $i in [1, 2, 3]",
+            "named($f) <- file($i, $f, _) @ $f in [\"file1\", \"file2\"], $f matches /^file1.*$/ | $i + 1 > 10",
+            "count(count($f)) <- file($i, $f, $d)",
+            "flag($b) <- user(#read, $x, $b, $s), !file(1, _, _) @ $x in [hex:00ff], $s not in [#write]",
+            "bound($j) <- file($i, $f, $d) @ $i < -490 | $j = -$i + 1, $f matches *\"2\" && $f matches \"f\"*",
+        ];
+        for (i, r) in rules.iter().enumerate() {
+            let origin = if i == 0 { Origin::Ambient } else { Origin::Block(1) };
+            w.add_rule_with_origin(parse_rule(r, &mut syms).expect(r), origin);
+        }
+        w.run_with_provenance(RunLimits {
+            max_facts: Some(100_000),
+            max_time: Some(Duration::from_secs(60)),
+            ..RunLimits::default()
+        }).unwrap();
+        (w, syms)
+    }
+
+    fn serialize(w: &World, syms: &SymbolTable) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        w.serialize_into(syms, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let (w, syms) = world(10_000);
+        assert!(w.facts.len() > 10_000);
+        assert!(!w.derivations.is_empty());
+        assert!(!w.fact_origins.is_empty());
+
+        let bytes = serialize(&w, &syms);
+        assert_eq!(&bytes[..5], b"DLWB\x01");
+        let (decoded, decoded_syms) = World::deserialize_from(&mut &bytes[..]).unwrap();
+        assert_eq!(decoded, w);
+        assert_eq!(decoded_syms, syms);
+        assert_eq!(decoded_syms.print_world(&decoded), syms.print_world(&w));
+        assert!(decoded.is_complete());
+
+        // independent of the hash map order
+        assert!(serialize(&decoded, &decoded_syms) == bytes);
+
+        let mut sorted = World::new();
+        sorted.facts = FactSet::sorted();
+        sorted.add_fact(parse_fact("a(1)", &mut SymbolTable::new()).unwrap());
+        let (decoded, _) = World::deserialize_from(&mut &serialize(&sorted, &SymbolTable::new())[..]).unwrap();
+        assert!(decoded.facts.is_sorted());
+        assert_eq!(decoded, sorted);
+    }
+
+    #[test]
+    fn corrupted() {
+        let (w, syms) = world(20);
+        let bytes = serialize(&w, &syms);
+
+        for len in 0..bytes.len() {
+            let e = World::deserialize_from(&mut &bytes[..len]).unwrap_err();
+            assert!(matches!(e, Decode::Truncated | Decode::InvalidHeader), "{}: {:?}", len, e);
+        }
+        for offset in (0..bytes.len()).step_by(7) {
+            for value in [0x00, 0x7f, 0x80, 0xff].iter() {
+                let mut corrupted = bytes.clone();
+                corrupted[offset] = *value;
+                // may still be valid, but must not panic
+                let _ = World::deserialize_from(&mut &corrupted[..]);
+            }
+        }
+
+        assert_eq!(World::deserialize_from(&mut &b"JSON{}"[..]), Err(Decode::InvalidHeader));
+        assert_eq!(
+            World::deserialize_from(&mut &b"DLWB\x02"[..]),
+            Err(Decode::UnsupportedVersion { found: 2, supported: 1 })
+        );
+
+        // length prefixes are not trusted
+        let mut huge = b"DLWB\x01".to_vec();
+        huge.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0x0f]);
+        assert_eq!(World::deserialize_from(&mut &huge[..]), Err(Decode::Truncated));
+        let mut huge = b"DLWB\x01\x01".to_vec();
+        huge.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f, b'a']);
+        assert_eq!(World::deserialize_from(&mut &huge[..]), Err(Decode::Truncated));
+        let mut overflow = b"DLWB\x01".to_vec();
+        overflow.extend_from_slice(&[0xff; 10]);
+        assert_eq!(World::deserialize_from(&mut &overflow[..]), Err(Decode::InvalidInteger));
+
+        // empty symbol table, one fact a(0, [[[...]]])
+        let mut deep = b"DLWB\x01\x00\x00\x01\x00\x01".to_vec();
+        deep.extend(core::iter::repeat_n([7u8, 1], MAX_DEPTH + 1).flatten());
+        assert_eq!(World::deserialize_from(&mut &deep[..]), Err(Decode::TooDeep));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn size() {
+        let (w, syms) = world(10_000);
+        let binary = serialize(&w, &syms).len();
+        let json = w.to_json(&syms).len();
+        assert!(binary * 5 < json, "{} bytes, {} in JSON", binary, json);
+    }
+}
@@ -0,0 +1,367 @@
+use alloc::vec::Vec;
+use super::{AggregateOp, Caveat, Constraint, Expression, Predicate, Rule, Symbol, SymbolTable, ID};
+
+/// incremental construction of a rule
+///
+/// ```ignore
+/// let r = RuleBuilder::new()
+///     .head(grandparent, &[var(&mut syms, "a"), var(&mut syms, "c")])
+///     .body(pred(parent, &[var(&mut syms, "a"), var(&mut syms, "b")]))
+///     .body(pred(parent, &[var(&mut syms, "b"), var(&mut syms, "c")]))
+///     .build();
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RuleBuilder {
+    head: Option<Predicate>,
+    body: Vec<Predicate>,
+    constraints: Vec<Constraint>,
+    expressions: Vec<Expression>,
+    negated: Vec<Predicate>,
+    aggregate: Option<(AggregateOp, u32)>,
+}
+
+impl RuleBuilder {
+    pub fn new() -> Self {
+        RuleBuilder::default()
+    }
+
+    pub fn head<I: AsRef<ID>>(mut self, name: Symbol, ids: &[I]) -> Self {
+        self.head = Some(super::pred(name, ids));
+        self
+    }
+
+    pub fn body<P: AsRef<Predicate>>(mut self, predicate: P) -> Self {
+        self.body.push(predicate.as_ref().clone());
+        self
+    }
+
+    pub fn constraint<C: AsRef<Constraint>>(mut self, constraint: C) -> Self {
+        self.constraints.push(constraint.as_ref().clone());
+        self
+    }
+
+    pub fn expression<E: AsRef<Expression>>(mut self, expression: E) -> Self {
+        self.expressions.push(expression.as_ref().clone());
+        self
+    }
+
+    pub fn negated<P: AsRef<Predicate>>(mut self, predicate: P) -> Self {
+        self.negated.push(predicate.as_ref().clone());
+        self
+    }
+
+    pub fn aggregate(mut self, op: AggregateOp, variable: u32) -> Self {
+        self.aggregate = Some((op, variable));
+        self
+    }
+
+    /// creates the rule without validating it
+    ///
+    /// panics if the head was not set
+    pub fn build(self) -> Rule {
+        self.build_unchecked().expect("the rule's head must be set")
+    }
+
+    /// creates the rule and validates it with `Rule::validate`
+    pub fn build_checked(self) -> Result<Rule, crate::error::Rule> {
+        let rule = self.build_unchecked()?;
+        rule.validate()?;
+        Ok(rule)
+    }
+
+    fn build_unchecked(self) -> Result<Rule, crate::error::Rule> {
+        let head = self.head.ok_or(crate::error::Rule::MissingHead)?;
+
+        Ok(Rule {
+            head,
+            body: self.body,
+            constraints: self.constraints,
+            expressions: self.expressions,
+            negated: self.negated,
+            aggregate: self.aggregate,
+        })
+    }
+}
+
+/// incremental construction of a caveat, from rules or their text
+///
+/// ```ignore
+/// let c = CaveatBuilder::new()
+///     .query(rule(caveat1, &[&file1], &[pred(resource, &[&ambient, &file1])]))
+///     .parse("caveat2($f) <- resource(#ambient, $f), owner(#authority, $f)", &mut syms)?
+///     .build_checked()?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CaveatBuilder {
+    queries: Vec<Rule>,
+}
+
+impl CaveatBuilder {
+    pub fn new() -> Self {
+        CaveatBuilder::default()
+    }
+
+    pub fn query<R: AsRef<Rule>>(mut self, rule: R) -> Self {
+        self.queries.push(rule.as_ref().clone());
+        self
+    }
+
+    /// adds a query in the format of `parser::parse_rule`
+    pub fn parse(mut self, query: &str, symbols: &mut SymbolTable) -> Result<Self, crate::error::Parse> {
+        self.queries.push(crate::parser::parse_rule(query, symbols)?);
+        Ok(self)
+    }
+
+    /// creates the caveat without validating it
+    pub fn build(self) -> Caveat {
+        Caveat { queries: self.queries }
+    }
+
+    /// creates the caveat and validates each query with `Rule::validate`
+    pub fn build_checked(self) -> Result<Caveat, crate::error::InvalidCaveat> {
+        if self.queries.is_empty() {
+            return Err(crate::error::InvalidCaveat::NoQueries);
+        }
+        for (index, query) in self.queries.iter().enumerate() {
+            query.validate().map_err(|error| crate::error::InvalidCaveat::Query { index, error })?;
+        }
+        Ok(self.build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn numbers() {
+        let mut syms = SymbolTable::new();
+        let t1 = syms.insert("t1");
+        let t2 = syms.insert("t2");
+        let join = syms.insert("join");
+
+        let expected = expressed_rule(
+            join,
+            &[var(&mut syms, "left"), var(&mut syms, "right")],
+            &[
+                pred(t1, &[var(&mut syms, "id"), var(&mut syms, "left")]),
+                pred(t2, &[var(&mut syms, "t2_id"), var(&mut syms, "right"), var(&mut syms, "id")]),
+            ],
+            &[ Expression { ops: vec![
+                Op::Value(var(&mut syms, "id")),
+                Op::Value(ID::Integer(1)),
+                Op::Binary(Binary::LessThan),
+            ] } ],
+        );
+
+        let built = RuleBuilder::new()
+            .head(join, &[var(&mut syms, "left"), var(&mut syms, "right")])
+            .body(pred(t1, &[var(&mut syms, "id"), var(&mut syms, "left")]))
+            .body(pred(t2, &[var(&mut syms, "t2_id"), var(&mut syms, "right"), var(&mut syms, "id")]))
+            .expression(Expression { ops: vec![
+                Op::Value(var(&mut syms, "id")),
+                Op::Value(ID::Integer(1)),
+                Op::Binary(Binary::LessThan),
+            ] })
+            .build_checked()
+            .unwrap();
+
+        assert_eq!(built, expected);
+    }
+
+    #[test]
+    fn int_expr() {
+        let mut syms = SymbolTable::new();
+        let x = syms.insert("x");
+        let less_than = syms.insert("less_than");
+
+        let expected = expressed_rule(
+            less_than,
+            &[var(&mut syms, "nb"), var(&mut syms, "val")],
+            &[pred(x, &[var(&mut syms, "nb"), var(&mut syms, "val")])],
+            &[
+                Expression { ops: vec![
+                    Op::Value(ID::Integer(5)),
+                    Op::Value(ID::Integer(-4)),
+                    Op::Binary(Binary::Add),
+                    Op::Unary(Unary::Negate),
+                    Op::Value(var(&mut syms, "nb")),
+                    Op::Binary(Binary::LessThan),
+                ] },
+            ],
+        );
+
+        let built = RuleBuilder::new()
+            .head(less_than, &[var(&mut syms, "nb"), var(&mut syms, "val")])
+            .body(pred(x, &[var(&mut syms, "nb"), var(&mut syms, "val")]))
+            .expression(Expression { ops: vec![
+                Op::Value(ID::Integer(5)),
+                Op::Value(ID::Integer(-4)),
+                Op::Binary(Binary::Add),
+                Op::Unary(Unary::Negate),
+                Op::Value(var(&mut syms, "nb")),
+                Op::Binary(Binary::LessThan),
+            ] })
+            .build_checked()
+            .unwrap();
+
+        assert_eq!(built, expected);
+    }
+
+    #[test]
+    fn constraints_and_expressions() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let x = syms.insert("x");
+        let res = syms.insert("res");
+
+        for i in 0..10 {
+            w.add_fact(fact(x, &[&int(i), &string(&format!("value{}", i))]));
+        }
+
+        let r = RuleBuilder::new()
+            .head(res, &[var(&mut syms, "nb"), var(&mut syms, "val")])
+            .body(pred(x, &[var(&mut syms, "nb"), var(&mut syms, "val")]))
+            .constraint(Constraint {
+                id: syms.insert("nb") as u32,
+                kind: ConstraintKind::Int(IntConstraint::LessThan(5)),
+            })
+            .expression(Expression { ops: vec![
+                Op::Value(var(&mut syms, "val")),
+                Op::Value(string("value")),
+                Op::Binary(Binary::Prefix),
+            ] })
+            .expression(Expression { ops: vec![
+                Op::Value(var(&mut syms, "nb")),
+                Op::Value(int(2)),
+                Op::Binary(Binary::GreaterThan),
+            ] })
+            .build_checked()
+            .unwrap();
+
+        assert_eq!(syms.print_rule(&r), r#"res($nb, $val) <- x($nb, $val) @ $nb < 5 | $val matches "value"*, $nb > 2"#);
+        let res2 = w.query_rule(r).iter().cloned().collect::<HashSet<_>>();
+        let compared = (vec![
+            fact(res, &[&int(3), &string("value3")]),
+            fact(res, &[&int(4), &string("value4")]),
+        ])
+        .drain(..)
+        .collect::<HashSet<_>>();
+        assert_eq!(res2, compared);
+    }
+
+    #[test]
+    fn validation() {
+        let mut syms = SymbolTable::new();
+        let x = syms.insert("x");
+        let res = syms.insert("res");
+
+        assert_eq!(
+            RuleBuilder::new().body(pred(x, &[var(&mut syms, "a")])).build_checked(),
+            Err(error::Rule::MissingHead)
+        );
+
+        assert_eq!(
+            RuleBuilder::new().head(res, &[var(&mut syms, "a")]).build_checked(),
+            Err(error::Rule::EmptyBody)
+        );
+
+        assert_eq!(
+            RuleBuilder::new()
+                .head(res, &[var(&mut syms, "b")])
+                .body(pred(x, &[var(&mut syms, "a")]))
+                .build_checked(),
+            Err(error::Rule::UnboundHeadVariable(syms.insert("b") as u32))
+        );
+
+        assert_eq!(
+            RuleBuilder::new()
+                .head(res, &[var(&mut syms, "a")])
+                .body(pred(x, &[var(&mut syms, "a")]))
+                .constraint(Constraint {
+                    id: syms.insert("c") as u32,
+                    kind: ConstraintKind::Int(IntConstraint::LessThan(5)),
+                })
+                .build_checked(),
+            Err(error::Rule::UnboundConstraintVariable(syms.insert("c") as u32))
+        );
+
+        assert_eq!(
+            RuleBuilder::new()
+                .head(res, &[var(&mut syms, "a")])
+                .body(pred(x, &[var(&mut syms, "a")]))
+                .expression(Expression { ops: vec![
+                    Op::Value(var(&mut syms, "d")),
+                    Op::Value(int(1)),
+                    Op::Binary(Binary::LessThan),
+                ] })
+                .build_checked(),
+            Err(error::Rule::UnboundExpressionVariable(syms.insert("d") as u32))
+        );
+
+        // variables bound by an expression can be used in the head
+        assert!(RuleBuilder::new()
+            .head(res, &[var(&mut syms, "e")])
+            .body(pred(x, &[var(&mut syms, "a")]))
+            .expression(Expression { ops: vec![
+                Op::Value(var(&mut syms, "a")),
+                Op::Value(int(1)),
+                Op::Binary(Binary::Add),
+                Op::Bind(syms.insert("e") as u32),
+            ] })
+            .build_checked()
+            .is_ok());
+    }
+
+    #[test]
+    fn caveats() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+        let resource = syms.insert("resource");
+        let right = syms.insert("right");
+        let check = syms.insert("check");
+        let file1 = sym(&mut syms, "file1");
+        let file2 = sym(&mut syms, "file2");
+        let read = sym(&mut syms, "read");
+
+        w.add_fact(fact(resource, &[&file2]));
+        w.add_fact(fact(right, &[&file2, &read]));
+
+        let c1 = caveat(&[rule(check, &[&file1], &[pred(resource, &[&file1])])]);
+        let c2 = CaveatBuilder::new()
+            .parse("check($f) <- resource($f), right($f, #read)", &mut syms)
+            .unwrap()
+            .build_checked()
+            .unwrap();
+        assert_eq!(c2.queries.len(), 1);
+        assert_eq!(w.check_caveat(&c1, None), Err(error::Caveat::NoMatch));
+        assert_eq!(w.check_caveat(&c2, None), Ok(()));
+
+        let either = c1.clone().or(c2.clone());
+        assert_eq!(either.queries, vec![c1.queries[0].clone(), c2.queries[0].clone()]);
+        assert_eq!(w.check_caveat(&either, None), Ok(()));
+
+        let both = c1.clone().and(c2.clone());
+        let failed = w.check_caveats(&both, None, &HashMap::new(), &syms).unwrap_err();
+        assert_eq!(failed.iter().map(|f| f.index).collect::<Vec<_>>(), vec![0]);
+
+        let built = CaveatBuilder::new().query(&c1.queries[0]).query(c2.queries[0].clone()).build_checked();
+        assert_eq!(built, Ok(either));
+
+        assert_eq!(CaveatBuilder::new().build_checked(), Err(error::InvalidCaveat::NoQueries));
+        assert_eq!(
+            CaveatBuilder::new()
+                .query(&c1.queries[0])
+                .parse("check($f) <- resource($g)", &mut syms)
+                .unwrap()
+                .build_checked(),
+            Err(error::InvalidCaveat::Query {
+                index: 1,
+                error: error::Rule::UnboundHeadVariable(syms.get("f").unwrap() as u32),
+            })
+        );
+        assert!(CaveatBuilder::new().parse("check($f) <- resource(", &mut syms).is_err());
+    }
+}
@@ -1,6 +1,1068 @@
+//! errors of the crate
+//!
+//! every error implements `core::error::Error`, and can be converted to the
+//! top-level `Error`. Errors wrapping another one with more context return
+//! it from `source()`, and `SymbolTable::print_error` prints the chain with
+//! the symbol and variable names
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::error::Error as StdError;
+use core::fmt;
+use core::time::Duration;
+
+use super::{Op, RuleComplexity, RunStats, TermType};
+
+/// any error of the crate, prints and chains like the wrapped error
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+    RunLimit(RunLimit),
+    Stratification(Stratification),
+    Rule(Rule),
+    Expression(Expression),
+    RuleFailed(RuleFailed),
+    InvalidDate(InvalidDate),
+    Check(Check),
+    Caveat(Caveat),
+    InvalidCaveat(InvalidCaveat),
+    Policy(Policy),
+    /// boxed, as it is larger than the other errors
+    Authorization(Box<Authorization>),
+    Symbol(Symbol),
+    Merge(Merge),
+    Parse(Parse),
+    Format(Format),
+    Import(Import),
+    Decode(Decode),
+    Unbounded(Unbounded),
+    Conversion(Conversion),
+    InvalidFact(InvalidFact),
+    WorldLimit(WorldLimit),
+    Schema(Schema),
+}
+
+macro_rules! from_errors {
+    ($($variant:ident),*; boxed: $($boxed:ident),*) => {
+        $(
+            impl From<$variant> for Error {
+                fn from(e: $variant) -> Self {
+                    Error::$variant(e)
+                }
+            }
+        )*
+        $(
+            impl From<$boxed> for Error {
+                fn from(e: $boxed) -> Self {
+                    Error::$boxed(Box::new(e))
+                }
+            }
+        )*
+
+        impl Error {
+            /// the wrapped error
+            pub fn inner(&self) -> &(dyn StdError + Send + Sync + 'static) {
+                match self {
+                    $(Error::$variant(e) => e,)*
+                    $(Error::$boxed(e) => e.as_ref(),)*
+                }
+            }
+        }
+    };
+}
+
+from_errors!(
+    RunLimit, Stratification, Rule, Expression, RuleFailed, InvalidDate, Check, Caveat, InvalidCaveat, Policy,
+    Symbol, Merge, Parse, Format, Import, Decode, Unbounded, Conversion, InvalidFact, WorldLimit, Schema;
+    boxed: Authorization
+);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.inner(), f)
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.inner().source()
+    }
+}
+
+/// error returned by `World::run_with_stats`, with the counters up to the
+/// point where the run stopped
 #[derive(Debug)]
+pub struct RunStopped {
+    pub error: Error,
+    /// boxed to keep the error small
+    pub stats: Box<RunStats>,
+}
+
+impl fmt::Display for RunStopped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "run stopped after {} iterations", self.stats.iterations.len())
+    }
+}
+
+impl StdError for RunStopped {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
 pub enum RunLimit {
-    TooManyFacts,
-    TooManyIterations,
-    Timeout,
+    TooManyFacts(LimitContext),
+    TooManyIterations(LimitContext),
+    Timeout(LimitContext),
+    /// too many facts were examined or expressions evaluated while
+    /// matching rule bodies
+    TooManyCandidates(LimitContext),
+    /// the facts' estimated size is over `RunLimits::max_memory`
+    TooMuchMemory(LimitContext),
+    /// the run was stopped by the callback of `World::run_with_callback`
+    Cancelled,
+}
+
+/// state of the run when a limit was reached
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LimitContext {
+    /// iterations completed, across all strata
+    pub iterations: u32,
+    /// facts in the world, or generated by the query
+    pub facts: usize,
+    pub elapsed: Duration,
+    /// index in `World::rules` of the rule being applied, if any. For
+    /// `TooManyFacts`, the rule that generated the most new facts in the
+    /// last iteration. It can be printed with `SymbolTable::print_run_limit`
+    pub rule: Option<usize>,
+}
+
+impl RunLimit {
+    pub fn context(&self) -> Option<&LimitContext> {
+        match self {
+            RunLimit::TooManyFacts(c)
+            | RunLimit::TooManyIterations(c)
+            | RunLimit::Timeout(c)
+            | RunLimit::TooManyCandidates(c)
+            | RunLimit::TooMuchMemory(c) => Some(c),
+            RunLimit::Cancelled => None,
+        }
+    }
+
+    /// replaces the context, if this error has one
+    pub(crate) fn with_context(mut self, context: LimitContext) -> Self {
+        match &mut self {
+            RunLimit::TooManyFacts(c)
+            | RunLimit::TooManyIterations(c)
+            | RunLimit::Timeout(c)
+            | RunLimit::TooManyCandidates(c)
+            | RunLimit::TooMuchMemory(c) => *c = context,
+            RunLimit::Cancelled => {},
+        }
+        self
+    }
+}
+
+impl fmt::Display for RunLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            RunLimit::TooManyFacts(_) => "too many facts",
+            RunLimit::TooManyIterations(_) => "too many iterations",
+            RunLimit::Timeout(_) => "timeout",
+            RunLimit::TooManyCandidates(_) => "too many candidate facts",
+            RunLimit::TooMuchMemory(_) => "too much memory",
+            RunLimit::Cancelled => return write!(f, "run cancelled"),
+        };
+        write!(f, "{}", reason)?;
+        if let Some(c) = self.context() {
+            write!(f, " after {} iterations, {} facts, {:?}", c.iterations, c.facts, c.elapsed)?;
+            if let Some(rule) = c.rule {
+                write!(f, ", while applying rule {}", rule)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StdError for RunLimit {}
+
+/// returned by `RunLimitsBuilder::build` when no fact, memory, iteration
+/// or time limit is set
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Unbounded;
+
+impl fmt::Display for Unbounded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no fact, memory, iteration or time limit is set")
+    }
+}
+
+impl StdError for Unbounded {}
+
+/// a predicate depends negatively on itself, directly or transitively
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stratification {
+    pub predicate: crate::Symbol,
+}
+
+impl fmt::Display for Stratification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "predicate {} depends negatively on itself", self.predicate)
+    }
+}
+
+impl StdError for Stratification {}
+
+/// a rule that cannot be evaluated, see `Rule::validate`
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Rule {
+    MissingHead,
+    EmptyBody,
+    UnboundHeadVariable(u32),
+    UnboundConstraintVariable(u32),
+    UnboundExpressionVariable(u32),
+    WildcardInHead,
+}
+
+impl Rule {
+    /// the message, with `variable` printing the variable ids
+    pub(crate) fn write(&self, f: &mut dyn fmt::Write, variable: &dyn Fn(u32) -> String) -> fmt::Result {
+        match self {
+            Rule::MissingHead => write!(f, "the rule has no head"),
+            Rule::EmptyBody => write!(f, "the rule has no body predicate"),
+            Rule::UnboundHeadVariable(v) => {
+                write!(f, "variable {} of the head is not bound by the body", variable(*v))
+            },
+            Rule::UnboundConstraintVariable(v) => {
+                write!(f, "variable {} of a constraint is not bound by the body", variable(*v))
+            },
+            Rule::UnboundExpressionVariable(v) => {
+                write!(f, "variable {} of an expression is not bound by the body", variable(*v))
+            },
+            Rule::WildcardInHead => write!(f, "wildcards cannot be used in the head"),
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, &|v| v.to_string())
+    }
+}
+
+impl StdError for Rule {}
+
+/// an expression that cannot be evaluated, see `Expression::try_evaluate`
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Expression {
+    UnknownVariable(u32),
+    /// an operator or a binding found an empty stack
+    MissingOperand,
+    /// number of values left on the stack, instead of one
+    InvalidStack(usize),
+    /// an operator was applied to values of the wrong types, `operands`
+    /// has their type names (see `ID::type_name`)
+    InvalidType { op: Op, operands: Vec<&'static str> },
+    Overflow,
+    InvalidRegex(String),
+}
+
+impl Expression {
+    /// the message, with `variable` printing the variable ids
+    pub(crate) fn write(&self, f: &mut dyn fmt::Write, variable: &dyn Fn(u32) -> String) -> fmt::Result {
+        match self {
+            Expression::UnknownVariable(v) => write!(f, "unknown variable {}", variable(*v)),
+            Expression::MissingOperand => write!(f, "expected a value on the stack"),
+            Expression::InvalidStack(n) => write!(f, "the expression left {} values on the stack", n),
+            Expression::InvalidType { op, operands } => {
+                write!(f, "operator {} cannot be applied to {}", op.name(), operands.join(" and "))
+            },
+            Expression::Overflow => write!(f, "integer overflow"),
+            Expression::InvalidRegex(r) => write!(f, "invalid regex {:?}", r),
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, &|v| v.to_string())
+    }
+}
+
+impl StdError for Expression {}
+
+/// a match of a rule's body that could not produce a fact, see
+/// `RuleErrors`, or a problem reported as a `Diagnostic`
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Evaluation {
+    Expression(Expression),
+    /// a variable of the head that the match did not bind
+    UnboundHeadVariable(u32),
+    /// the values of a group cannot be aggregated
+    Aggregate(crate::AggregateOp),
+    /// a constraint's regex does not compile, it never matches
+    InvalidRegex(String),
+    /// a computed value of the head is over `RunLimits::max_term_size`
+    TermTooLarge { size: usize, max: usize },
+    /// a constraint rejected a value of another type, see
+    /// `Constraint::type_mismatch`
+    ConstraintType { variable: u32, expected: TermType, found: TermType },
+}
+
+impl From<Expression> for Evaluation {
+    fn from(e: Expression) -> Self {
+        Evaluation::Expression(e)
+    }
+}
+
+impl Evaluation {
+    /// the message, with `variable` printing the variable ids
+    pub(crate) fn write(&self, f: &mut dyn fmt::Write, variable: &dyn Fn(u32) -> String) -> fmt::Result {
+        match self {
+            Evaluation::Expression(_) => write!(f, "an expression failed"),
+            Evaluation::UnboundHeadVariable(v) => write!(f, "variable {} of the head is not bound", variable(*v)),
+            Evaluation::Aggregate(op) => write!(f, "cannot compute {}", op.print("..".to_string())),
+            Evaluation::InvalidRegex(r) => write!(f, "invalid regex {:?} in a constraint", r),
+            Evaluation::TermTooLarge { size, max } => {
+                write!(f, "computed term of {} bytes, the maximum is {}", size, max)
+            },
+            // the names of the types are the names of the `ID` variants
+            Evaluation::ConstraintType { variable: v, expected, found } => write!(
+                f,
+                "the constraint on {} applies to {} values, it never matches {} values: did you mean ID::{:?}?",
+                variable(*v), expected.name(), found.name(), expected
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, &|v| v.to_string())
+    }
+}
+
+impl StdError for Evaluation {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Evaluation::Expression(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// returned by the run with `RuleErrors::Fatal`, `rule` is the index of
+/// the failing rule in the world
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleFailed {
+    pub rule: usize,
+    pub error: Evaluation,
+}
+
+impl fmt::Display for RuleFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "rule {} failed", self.rule)
+    }
+}
+
+impl StdError for RuleFailed {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// returned by `try_date` for a time before the unix epoch
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InvalidDate;
+
+impl fmt::Display for InvalidDate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "dates before the unix epoch are not supported")
+    }
+}
+
+impl StdError for InvalidDate {}
+
+/// a fact that cannot be stored, see `Fact::validate`
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum InvalidFact {
+    Variable(u32),
+    Wildcard,
+}
+
+impl InvalidFact {
+    /// the message, with `variable` printing the variable ids
+    pub(crate) fn write(&self, f: &mut dyn fmt::Write, variable: &dyn Fn(u32) -> String) -> fmt::Result {
+        match self {
+            InvalidFact::Variable(v) => write!(f, "the fact contains variable {}", variable(*v)),
+            InvalidFact::Wildcard => write!(f, "the fact contains a wildcard"),
+        }
+    }
+}
+
+impl fmt::Display for InvalidFact {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, &|v| v.to_string())
+    }
+}
+
+impl StdError for InvalidFact {}
+
+/// a fact or rule that does not follow the schema of a predicate, see
+/// `World::declare_schema`. Positions start at 0
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Schema {
+    Arity { predicate: crate::Symbol, expected: usize, found: usize },
+    TermType { predicate: crate::Symbol, position: usize, expected: TermType, found: TermType },
+    /// the variable is used at a position of type `expected`, after one of
+    /// type `found`
+    Variable { variable: u32, predicate: crate::Symbol, position: usize, expected: TermType, found: TermType },
+}
+
+impl Schema {
+    /// the message, with `symbol` printing the predicate names and
+    /// `variable` the variable ids
+    pub(crate) fn write(
+        &self,
+        f: &mut dyn fmt::Write,
+        symbol: &dyn Fn(crate::Symbol) -> String,
+        variable: &dyn Fn(u32) -> String,
+    ) -> fmt::Result {
+        match self {
+            Schema::Arity { predicate, expected, found } => {
+                write!(f, "predicate {} has {} terms, its schema has {}", symbol(*predicate), found, expected)
+            },
+            Schema::TermType { predicate, position, expected, found } => write!(
+                f,
+                "term {} of predicate {} is a {}, expected {}",
+                position, symbol(*predicate), found.name(), expected.name()
+            ),
+            Schema::Variable { variable: v, predicate, position, expected, found } => write!(
+                f,
+                "variable {} is a {}, term {} of predicate {} expects {}",
+                variable(*v), found.name(), position, symbol(*predicate), expected.name()
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, &|s| s.to_string(), &|v| v.to_string())
+    }
+}
+
+impl StdError for Schema {}
+
+/// returned by `World::try_add_fact` and `World::try_add_rule`, when the
+/// `WorldConfig` of the world rejects a fact or rule
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum WorldLimit {
+    /// the world already holds the maximum number of facts
+    TooManyFacts(usize),
+    /// the world already holds the maximum number of rules
+    TooManyRules(usize),
+    /// a term has strings or byte arrays of `size` bytes, above `max`
+    TermTooLarge { size: usize, max: usize },
+    InvalidFact(InvalidFact),
+    InvalidRule(Rule),
+    Schema(Schema),
+}
+
+impl fmt::Display for WorldLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WorldLimit::TooManyFacts(max) => write!(f, "the world already has {} facts", max),
+            WorldLimit::TooManyRules(max) => write!(f, "the world already has {} rules", max),
+            WorldLimit::TermTooLarge { size, max } => write!(f, "a term has {} bytes, the maximum is {}", size, max),
+            WorldLimit::InvalidFact(_) => write!(f, "invalid fact"),
+            WorldLimit::InvalidRule(_) => write!(f, "invalid rule"),
+            WorldLimit::Schema(_) => write!(f, "the schema of a predicate is not followed"),
+        }
+    }
+}
+
+impl StdError for WorldLimit {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            WorldLimit::InvalidFact(e) => Some(e),
+            WorldLimit::InvalidRule(e) => Some(e),
+            WorldLimit::Schema(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// errors returned by the checked adders of `World`
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Check {
+    ArityMismatch { predicate: crate::Symbol, expected: usize, found: usize },
+    RuleTooComplex(RuleComplexity),
+    TooManyBodyPredicates { found: usize, max: usize },
+    RunLimit(RunLimit),
+}
+
+impl From<RunLimit> for Check {
+    fn from(e: RunLimit) -> Self {
+        Check::RunLimit(e)
+    }
+}
+
+impl Check {
+    /// the message, with `symbol` printing the predicate names
+    pub(crate) fn write(&self, f: &mut dyn fmt::Write, symbol: &dyn Fn(crate::Symbol) -> String) -> fmt::Result {
+        match self {
+            Check::ArityMismatch { predicate, expected, found } => {
+                write!(f, "predicate {} has {} terms, {} expected", symbol(*predicate), found, expected)
+            },
+            Check::RuleTooComplex(c) => write!(
+                f,
+                "the rule is too complex: {} predicates, {} constraints, {} expression ops, {} bytes",
+                c.predicates, c.constraints, c.expression_ops, c.payload_size
+            ),
+            Check::TooManyBodyPredicates { found, max } => {
+                write!(f, "the rule has {} body predicates, the maximum is {}", found, max)
+            },
+            Check::RunLimit(_) => write!(f, "the check reached a limit"),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, &|s| s.to_string())
+    }
+}
+
+impl StdError for Check {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Check::RunLimit(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// a caveat that was not verified
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Caveat {
+    /// none of the queries produced a fact
+    NoMatch,
+    /// returned by `crate::Caveat::check`, with the printed queries that
+    /// produced no facts
+    NoMatchingQuery(Vec<String>),
+    RunLimit(RunLimit),
+    /// the world is missing facts after a run stopped on a limit
+    Incomplete,
+    /// the rules could not be run, with `crate::CaveatScope::AfterRun`
+    Stratification(Stratification),
+    /// a rule failed during the run of `crate::CaveatScope::AfterRun`,
+    /// with `crate::RuleErrors::Fatal`
+    RuleFailed(RuleFailed),
+    /// another error of the run of `crate::CaveatScope::AfterRun`
+    Run(Box<Error>),
+}
+
+impl From<RunLimit> for Caveat {
+    fn from(e: RunLimit) -> Self {
+        Caveat::RunLimit(e)
+    }
+}
+
+impl fmt::Display for Caveat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Caveat::NoMatch => write!(f, "none of the caveat's queries matched"),
+            Caveat::NoMatchingQuery(queries) => write!(f, "none of the queries matched: {}", queries.join(" || ")),
+            Caveat::RunLimit(_) => write!(f, "the caveat's queries reached a limit"),
+            Caveat::Incomplete => write!(f, "the world is incomplete, its last run stopped on a limit"),
+            Caveat::Stratification(_) | Caveat::RuleFailed(_) | Caveat::Run(_) => {
+                write!(f, "the world could not be run before the check")
+            },
+        }
+    }
+}
+
+impl StdError for Caveat {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Caveat::RunLimit(e) => Some(e),
+            Caveat::Stratification(e) => Some(e),
+            Caveat::RuleFailed(e) => Some(e),
+            Caveat::Run(e) => Some(e.inner()),
+            _ => None,
+        }
+    }
+}
+
+/// error returned by `World::evaluate_policies`
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Policy {
+    /// none of the policies matched
+    NoMatch,
+    RunLimit(RunLimit),
+    /// the world is missing facts after a run stopped on a limit
+    Incomplete,
+}
+
+impl From<RunLimit> for Policy {
+    fn from(e: RunLimit) -> Self {
+        Policy::RunLimit(e)
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Policy::NoMatch => write!(f, "no policy matched"),
+            Policy::RunLimit(_) => write!(f, "the policies' queries reached a limit"),
+            Policy::Incomplete => write!(f, "the world is incomplete, its last run stopped on a limit"),
+        }
+    }
+}
+
+impl StdError for Policy {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Policy::RunLimit(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// error returned by `World::authorize`
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Authorization {
+    /// some caveats failed, `policy` is the result of the policies
+    FailedCaveats { failed: Vec<FailedCaveat>, policy: Result<crate::PolicyDecision, Policy> },
+    /// the deny policy at this position matched first
+    Denied(usize),
+    Policy(Policy),
+}
+
+impl fmt::Display for Authorization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Authorization::FailedCaveats { failed, .. } => write!(f, "{} caveats failed", failed.len()),
+            Authorization::Denied(index) => write!(f, "denied by policy {}", index),
+            Authorization::Policy(_) => write!(f, "no policy allowed the request"),
+        }
+    }
+}
+
+impl StdError for Authorization {
+    /// the first failed caveat
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Authorization::FailedCaveats { failed, .. } => failed.first().map(|e| e as &(dyn StdError + 'static)),
+            Authorization::Denied(_) => None,
+            Authorization::Policy(e) => Some(e),
+        }
+    }
+}
+
+/// error returned by `CaveatBuilder::build_checked`
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum InvalidCaveat {
+    /// a caveat without queries can never succeed
+    NoQueries,
+    Query { index: usize, error: Rule },
+}
+
+impl fmt::Display for InvalidCaveat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvalidCaveat::NoQueries => write!(f, "the caveat has no queries"),
+            InvalidCaveat::Query { index, .. } => write!(f, "invalid query {}", index),
+        }
+    }
+}
+
+impl StdError for InvalidCaveat {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            InvalidCaveat::NoQueries => None,
+            InvalidCaveat::Query { error, .. } => Some(error),
+        }
+    }
+}
+
+/// failure report for one of the caveats passed to `World::check_caveats`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailedCaveat {
+    /// position of the caveat in the list
+    pub index: usize,
+    /// the caveat's queries, as printed by `SymbolTable::print_caveat`
+    pub caveat: String,
+    pub error: Caveat,
+}
+
+impl fmt::Display for FailedCaveat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "caveat {} failed: {}", self.index, self.caveat)
+    }
+}
+
+impl StdError for FailedCaveat {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// errors related to the symbol table
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum Symbol {
+    /// the index, used as a symbol or a variable name, is not in the table
+    Unknown(crate::Symbol),
+    /// the table already has `max` symbols, see `SymbolTable::set_max_symbols`
+    TooManySymbols { max: usize },
+    /// the symbol appears twice in `SymbolTable::from_symbols`
+    Duplicate(String),
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Symbol::Unknown(s) => write!(f, "unknown symbol {}", s),
+            Symbol::TooManySymbols { max } => write!(f, "the symbol table is limited to {} symbols", max),
+            Symbol::Duplicate(s) => write!(f, "duplicate symbol {:?}", s),
+        }
+    }
+}
+
+impl StdError for Symbol {}
+
+/// symbol tables or worlds that cannot be combined, see
+/// `SymbolTable::try_extend` and `World::try_merge`
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Merge {
+    /// the source table has the symbol at two indexes, they would be
+    /// remapped to the same one
+    Collision { symbol: String, first: crate::Symbol, second: crate::Symbol },
+    /// the index, used as a symbol or a variable name, is not in the
+    /// source table of the remapping
+    MissingSymbol(crate::Symbol),
+    /// the predicate is declared or used with `found` terms, while the
+    /// destination world declares `expected`
+    ArityConflict { predicate: crate::Symbol, expected: usize, found: usize },
+    /// the destination table cannot hold the new symbols
+    Symbol(Symbol),
+}
+
+impl From<Symbol> for Merge {
+    fn from(e: Symbol) -> Self {
+        Merge::Symbol(e)
+    }
+}
+
+impl Merge {
+    /// the message, with `symbol` printing the predicate names
+    pub(crate) fn write(&self, f: &mut dyn fmt::Write, symbol: &dyn Fn(crate::Symbol) -> String) -> fmt::Result {
+        match self {
+            Merge::Collision { symbol, first, second } => {
+                write!(f, "symbol {:?} is at indexes {} and {} of the source table", symbol, first, second)
+            },
+            Merge::MissingSymbol(s) => write!(f, "index {} is not in the source table", s),
+            Merge::ArityConflict { predicate, expected, found } => write!(
+                f,
+                "predicate {} has {} terms in the merged world, {} expected",
+                symbol(*predicate), found, expected
+            ),
+            Merge::Symbol(_) => write!(f, "cannot add the symbols"),
+        }
+    }
+}
+
+impl fmt::Display for Merge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, &|s| s.to_string())
+    }
+}
+
+impl StdError for Merge {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Merge::Symbol(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// error returned by the `parser` functions
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parse {
+    /// position in bytes in the input
+    pub offset: usize,
+    pub kind: ParseKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ParseKind {
+    Expected(&'static str),
+    UnterminatedString,
+    InvalidInteger,
+    /// dates are written in RFC 3339 format, after the Unix epoch
+    InvalidDate,
+    InvalidBytes,
+    /// the operator cannot be used with the value, or the values of a set
+    /// have different types
+    InvalidConstraint,
+    VariableInFact,
+    TrailingInput,
+    Symbol(Symbol),
+    /// `FromStr` was used outside of `parser::with_symbols`
+    NoSymbolTable,
+    /// `in set #name` was parsed without a world to take the set from, see
+    /// `parser::parse_rule_with_sets`
+    NamedSet,
+    /// the world has no revocation set with this name
+    UnknownSet,
+}
+
+impl fmt::Display for Parse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            ParseKind::Expected(s) => write!(f, "expected {}", s)?,
+            ParseKind::UnterminatedString => write!(f, "unterminated string")?,
+            ParseKind::InvalidInteger => write!(f, "invalid integer")?,
+            ParseKind::InvalidDate => write!(f, "invalid date")?,
+            ParseKind::InvalidBytes => write!(f, "invalid hex bytes")?,
+            ParseKind::InvalidConstraint => write!(f, "invalid constraint")?,
+            ParseKind::VariableInFact => write!(f, "variables are not allowed in facts")?,
+            ParseKind::TrailingInput => write!(f, "unexpected input")?,
+            ParseKind::Symbol(e) => write!(f, "{}", e)?,
+            ParseKind::NoSymbolTable => write!(f, "no symbol table, see parser::with_symbols")?,
+            ParseKind::NamedSet => write!(f, "named sets need a world, see parser::parse_rule_with_sets")?,
+            ParseKind::UnknownSet => write!(f, "unknown revocation set")?,
+        }
+        write!(f, " at byte {}", self.offset)
+    }
+}
+
+impl StdError for Parse {}
+
+/// error returned when decoding the `format` messages
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Format {
+    /// the bytes are not a valid protobuf message
+    Decode(String),
+    /// a `oneof` field or a required message is not set
+    MissingField(&'static str),
+    UnknownEnumValue { field: &'static str, value: i32 },
+    Symbol(Symbol),
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Decode(e) => write!(f, "invalid message: {}", e),
+            Format::MissingField(field) => write!(f, "missing field {}", field),
+            Format::UnknownEnumValue { field, value } => write!(f, "unknown value {} for {}", value, field),
+            Format::Symbol(e) => e.fmt(f),
+        }
+    }
+}
+
+impl StdError for Format {}
+
+/// error returned by `World::from_json`
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Import {
+    Json(String),
+    /// the document was written by a newer version of this crate
+    UnsupportedVersion { found: u32, supported: u32 },
+}
+
+impl fmt::Display for Import {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Import::Json(e) => write!(f, "invalid JSON document: {}", e),
+            Import::UnsupportedVersion { found, supported } => {
+                write!(f, "unsupported document version {}, the latest supported is {}", found, supported)
+            },
+        }
+    }
+}
+
+impl StdError for Import {}
+
+/// error returned by `World::deserialize_from`
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Decode {
+    /// the input does not start with the magic bytes of the format
+    InvalidHeader,
+    UnsupportedVersion { found: u8, supported: u8 },
+    /// the input ended in the middle of a value
+    Truncated,
+    /// an unknown tag for the type of a value
+    InvalidTag { field: &'static str, tag: u8 },
+    /// an integer is too large for its field
+    InvalidInteger,
+    InvalidString,
+    /// sets are nested too deeply
+    TooDeep,
+    Symbol(Symbol),
+    Io(String),
+}
+
+impl fmt::Display for Decode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Decode::InvalidHeader => write!(f, "not a serialized world"),
+            Decode::UnsupportedVersion { found, supported } => {
+                write!(f, "unsupported format version {}, the latest supported is {}", found, supported)
+            },
+            Decode::Truncated => write!(f, "truncated input"),
+            Decode::InvalidTag { field, tag } => write!(f, "invalid tag {} for {}", tag, field),
+            Decode::InvalidInteger => write!(f, "integer out of range"),
+            Decode::InvalidString => write!(f, "invalid UTF-8 string"),
+            Decode::TooDeep => write!(f, "sets are nested too deeply"),
+            Decode::Symbol(e) => e.fmt(f),
+            Decode::Io(e) => write!(f, "read error: {}", e),
+        }
+    }
+}
+
+impl StdError for Decode {}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for Decode {
+    fn from(e: std::io::Error) -> Self {
+        if e.kind() == std::io::ErrorKind::UnexpectedEof {
+            Decode::Truncated
+        } else {
+            Decode::Io(e.to_string())
+        }
+    }
+}
+
+/// a term that cannot be read as a Rust value, see `ResultSet::typed_column`
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Conversion {
+    /// the term has another type
+    InvalidType { expected: &'static str, found: &'static str },
+    /// the rows have no value for this variable, it is not in the query
+    MissingVariable(u32),
+}
+
+impl Conversion {
+    /// the message, with `variable` printing the variable ids
+    pub(crate) fn write(&self, f: &mut dyn fmt::Write, variable: &dyn Fn(u32) -> String) -> fmt::Result {
+        match self {
+            Conversion::InvalidType { expected, found } => write!(f, "expected {}, found {}", expected, found),
+            Conversion::MissingVariable(v) => write!(f, "variable {} is not in the results", variable(*v)),
+        }
+    }
+}
+
+impl fmt::Display for Conversion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, &|v| v.to_string())
+    }
+}
+
+impl StdError for Conversion {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_rule;
+    use crate::{RunLimits, SymbolTable, World};
+
+    fn is_error<E: StdError + Send + Sync + 'static>() {}
+
+    #[test]
+    fn traits() {
+        is_error::<Error>();
+        is_error::<RunStopped>();
+        is_error::<RunLimit>();
+        is_error::<Unbounded>();
+        is_error::<Stratification>();
+        is_error::<Rule>();
+        is_error::<Check>();
+        is_error::<Caveat>();
+        is_error::<Policy>();
+        is_error::<Authorization>();
+        is_error::<InvalidCaveat>();
+        is_error::<FailedCaveat>();
+        is_error::<Symbol>();
+        is_error::<Parse>();
+        is_error::<Format>();
+        is_error::<Import>();
+        is_error::<Decode>();
+        is_error::<Conversion>();
+        is_error::<InvalidFact>();
+        is_error::<WorldLimit>();
+        is_error::<Schema>();
+    }
+
+    #[test]
+    fn display() {
+        let limit = RunLimit::TooManyFacts(LimitContext { iterations: 2, facts: 10, ..Default::default() });
+        assert_eq!(limit.to_string(), "too many facts after 2 iterations, 10 facts, 0ns");
+
+        let e = Error::from(Caveat::RunLimit(limit));
+        assert_eq!(e.to_string(), "the caveat's queries reached a limit");
+        assert_eq!(e.source().unwrap().to_string(), limit.to_string());
+
+        assert_eq!(Error::from(Unbounded).to_string(), "no fact, memory, iteration or time limit is set");
+        assert_eq!(
+            Check::ArityMismatch { predicate: 3, expected: 2, found: 1 }.to_string(),
+            "predicate 3 has 1 terms, 2 expected"
+        );
+        assert_eq!(
+            Rule::UnboundHeadVariable(4).to_string(),
+            "variable 4 of the head is not bound by the body"
+        );
+        assert_eq!(Policy::NoMatch.to_string(), "no policy matched");
+        assert_eq!(Authorization::Denied(2).to_string(), "denied by policy 2");
+
+        let invalid = InvalidCaveat::Query { index: 1, error: Rule::EmptyBody };
+        assert_eq!(invalid.to_string(), "invalid query 1");
+        assert_eq!(invalid.source().unwrap().to_string(), "the rule has no body predicate");
+    }
+
+    #[test]
+    fn print_with_symbols() {
+        let mut syms = SymbolTable::new();
+        let rule = parse_rule("head($a, $b) <- body($a)", &mut syms).unwrap();
+        let e = rule.validate().unwrap_err();
+        assert_eq!(e.to_string(), format!("variable {} of the head is not bound by the body", syms.get("b").unwrap()));
+        assert_eq!(syms.print_error(&e), "variable $b of the head is not bound by the body");
+
+        let mut w = World::new();
+        w.add_rule(parse_rule("p($x) <- q($x), !p($x)", &mut syms).unwrap());
+        let e = w.run_with_limits(RunLimits::default()).unwrap_err();
+        assert_eq!(syms.print_error(&e), "predicate p depends negatively on itself");
+
+        let failing = crate::caveat(&[parse_rule("check($x) <- q($x)", &mut syms).unwrap()]);
+        let e = Error::from(w.authorize(&[failing], &[], &RunLimits::default(), &syms).unwrap_err());
+        assert_eq!(
+            syms.print_error(&e),
+            "1 caveats failed: caveat 0 failed: check($x) <- q($x): none of the caveat's queries matched"
+        );
+
+        let stopped = RunStopped { error: Error::from(RunLimit::Cancelled), stats: Default::default() };
+        assert_eq!(syms.print_error(&stopped), "run stopped after 0 iterations: run cancelled");
+    }
 }
@@ -0,0 +1,258 @@
+//! step by step evaluation of a rule, see `World::query_rule_traced`
+//!
+//! the body predicates are joined in order, and each fact with the name
+//! and arity of a predicate is tried against the variables bound by the
+//! previous ones. The trace is a tree: the steps of a predicate hold the
+//! steps of the next one, tried with the variables they bound
+use alloc::vec::Vec;
+
+use super::{error, evaluate_expressions, negation_matches, Budget, Fact, HashMap, Rule, RunLimits, World, ID};
+use crate::time::{self, Instant};
+
+/// the candidates of the first body predicate, see `TraceStep`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvaluationTrace {
+    pub rule: Rule,
+    pub steps: Vec<TraceStep>,
+    /// set if the limits stopped the evaluation, the trace is then
+    /// incomplete
+    pub limit: Option<error::RunLimit>,
+}
+
+/// a fact tried for a body predicate
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceStep {
+    /// index of the predicate in the rule's body
+    pub predicate: usize,
+    pub fact: Fact,
+    pub outcome: TraceOutcome,
+    /// the facts tried for the next predicate, if this one was `Joined`
+    pub next: Vec<TraceStep>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceOutcome {
+    /// the variable was bound to another value by a previous term
+    Conflict(u32),
+    /// the constraint at this index rejected the value of its variable
+    Constraint(usize),
+    /// the variables were bound, the next predicate is tried with them
+    Joined,
+    /// all the variables were bound, and the expression at this index
+    /// rejected them
+    Expression(usize),
+    /// a fact matches the negated predicate at this index
+    Negated(usize),
+    Produced(Fact),
+    /// the bindings are accepted, and counted by the rule's aggregate
+    Aggregated,
+    /// the bindings are accepted but do not produce a fact
+    Failed(error::Evaluation),
+}
+
+impl TraceOutcome {
+    /// the fact was unified with the predicate and its constraints hold
+    pub fn is_accepted(&self) -> bool {
+        !matches!(self, TraceOutcome::Conflict(_) | TraceOutcome::Constraint(_))
+    }
+}
+
+impl EvaluationTrace {
+    /// number of facts tried for the body predicate at this index, and the
+    /// number of them that were accepted
+    pub fn counts(&self, predicate: usize) -> (usize, usize) {
+        let mut counts = (0, 0);
+        visit(&self.steps, &mut |step| if step.predicate == predicate {
+            counts.0 += 1;
+            counts.1 += step.outcome.is_accepted() as usize;
+        });
+        counts
+    }
+
+    /// number of bindings rejected by the expression at this index
+    pub fn rejected_by_expression(&self, expression: usize) -> usize {
+        let mut count = 0;
+        visit(&self.steps, &mut |step| count += (step.outcome == TraceOutcome::Expression(expression)) as usize);
+        count
+    }
+
+    /// the facts in the order they were produced, including duplicates
+    pub fn produced(&self) -> Vec<&Fact> {
+        let mut facts = Vec::new();
+        visit(&self.steps, &mut |step| if let TraceOutcome::Produced(fact) = &step.outcome {
+            facts.push(fact);
+        });
+        facts
+    }
+}
+
+fn visit<'a>(steps: &'a [TraceStep], f: &mut dyn FnMut(&'a TraceStep)) {
+    for step in steps {
+        f(step);
+        visit(&step.next, f);
+    }
+}
+
+pub(crate) fn trace(world: &World, rule: &Rule, limits: &RunLimits) -> EvaluationTrace {
+    let budget = Budget::new(usize::MAX, limits, Instant::now(time::default_clock()));
+    let steps = if rule.body.is_empty() {
+        Vec::new()
+    } else {
+        try_facts(world, rule, 0, &HashMap::new(), &budget)
+    };
+    EvaluationTrace { rule: rule.clone(), steps, limit: budget.check().err() }
+}
+
+/// the steps of the predicate at this index, with the variables bound by
+/// the previous ones
+fn try_facts(world: &World, rule: &Rule, predicate: usize, bound: &HashMap<u32, ID>, budget: &Budget<'_>) -> Vec<TraceStep> {
+    let mut steps = Vec::new();
+    for fact in world.facts.matching(&rule.body[predicate]) {
+        if !budget.examine() {
+            break;
+        }
+        let mut variables = bound.clone();
+        let outcome = match unify(rule, predicate, fact, &mut variables) {
+            Some(outcome) => outcome,
+            None if predicate + 1 < rule.body.len() => TraceOutcome::Joined,
+            None => complete(world, rule, variables.clone(), budget),
+        };
+        let next = match outcome {
+            TraceOutcome::Joined => try_facts(world, rule, predicate + 1, &variables, budget),
+            _ => Vec::new(),
+        };
+        steps.push(TraceStep { predicate, fact: fact.clone(), outcome, next });
+    }
+    steps
+}
+
+/// binds the variables of the predicate to the terms of the fact, and
+/// checks the constraints on the new ones. Returns the reason for a
+/// rejection
+fn unify(rule: &Rule, predicate: usize, fact: &Fact, variables: &mut HashMap<u32, ID>) -> Option<TraceOutcome> {
+    for (id, value) in rule.body[predicate].ids.iter().zip(&fact.predicate.ids) {
+        let v = match id {
+            ID::Variable(v) => *v,
+            _ => continue,
+        };
+        match variables.get(&v) {
+            Some(bound) if bound != value => return Some(TraceOutcome::Conflict(v)),
+            Some(_) => continue,
+            None => {
+                variables.insert(v, value.clone());
+            },
+        }
+        if let Some(i) = rule.constraints.iter().position(|c| !c.check(v, value)) {
+            return Some(TraceOutcome::Constraint(i));
+        }
+    }
+    None
+}
+
+/// checks the expressions and negated predicates once all the variables
+/// are bound, then produces the fact
+fn complete(world: &World, rule: &Rule, mut variables: HashMap<u32, ID>, budget: &Budget<'_>) -> TraceOutcome {
+    for (i, e) in rule.expressions.iter().enumerate() {
+        match evaluate_expressions(core::iter::once(e), variables, Some(budget)) {
+            Some(next) => variables = next,
+            None => return TraceOutcome::Expression(i),
+        }
+    }
+    if let Some(i) = rule.negated.iter().position(|p| negation_matches(p, &variables, &world.facts)) {
+        return TraceOutcome::Negated(i);
+    }
+    if rule.aggregate.is_some() {
+        return TraceOutcome::Aggregated;
+    }
+    match rule.bind_head(&variables, None) {
+        Ok(predicate) => TraceOutcome::Produced(Fact { predicate }),
+        Err(e) => TraceOutcome::Failed(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_fact, parse_rule};
+    use crate::SymbolTable;
+
+    fn numbers(syms: &mut SymbolTable) -> World {
+        let mut w = World::new();
+        for f in [
+            "t1(0, #abc)",
+            "t1(1, #def)",
+            "t1(2, #ghi)",
+            "t2(0, #AAA, 0)",
+            "t2(1, #BBB, 0)",
+            "t2(2, #CCC, 1)",
+        ] {
+            w.add_fact(parse_fact(f, syms).unwrap());
+        }
+        w
+    }
+
+    #[test]
+    fn join() {
+        let mut syms = SymbolTable::new();
+        let w = numbers(&mut syms);
+        let rule = parse_rule("join($left, $right) <- t1($id, $left), t2($t2_id, $right, $id)", &mut syms).unwrap();
+        let (facts, trace) = w.query_rule_traced(&rule);
+
+        assert_eq!(facts.len(), 3);
+        assert_eq!(trace.limit, None);
+        assert_eq!(trace.counts(0), (3, 3));
+        // each t1 fact is tried with the 3 t2 facts, only the ones with the
+        // same id are accepted
+        assert_eq!(trace.counts(1), (9, 3));
+        let mut produced = trace.produced().into_iter().map(|f| syms.print_fact(f)).collect::<Vec<_>>();
+        produced.sort();
+        assert_eq!(produced, ["join(#abc, #AAA)", "join(#abc, #BBB)", "join(#def, #CCC)"]);
+        let mut facts = facts.iter().map(|f| syms.print_fact(f)).collect::<Vec<_>>();
+        facts.sort();
+        assert_eq!(facts, produced);
+
+        // the steps of t2 are under the t1 fact they were joined with
+        let ghi = trace.steps.iter().find(|s| s.fact == parse_fact("t1(2, #ghi)", &mut syms).unwrap()).unwrap();
+        assert_eq!(ghi.outcome, TraceOutcome::Joined);
+        assert_eq!(ghi.next.len(), 3);
+        let id = syms.get("id").unwrap() as u32;
+        assert!(ghi.next.iter().all(|s| s.outcome == TraceOutcome::Conflict(id)));
+    }
+
+    #[test]
+    fn rejections() {
+        let mut syms = SymbolTable::new();
+        let w = numbers(&mut syms);
+        let rule = parse_rule(
+            "join($left, $right) <- t1($id, $left), t2($t2_id, $right, $id) @ $t2_id < 2 | $id < 1",
+            &mut syms,
+        ).unwrap();
+        let (facts, trace) = w.query_rule_traced(&rule);
+
+        assert_eq!(facts.len(), 2);
+        assert_eq!(trace.counts(0), (3, 3));
+        // #CCC is rejected by the constraint, and #def has no other match
+        assert_eq!(trace.counts(1), (9, 2));
+        assert_eq!(trace.rejected_by_expression(0), 0);
+        assert_eq!(trace.produced().len(), 2);
+
+        let rule = parse_rule("join($left, $right) <- t1($id, $left), t2($t2_id, $right, $id) | $t2_id < 1", &mut syms).unwrap();
+        let (_, trace) = w.query_rule_traced(&rule);
+        assert_eq!(trace.rejected_by_expression(0), 2);
+        assert_eq!(trace.produced().len(), 1);
+
+        let rule = parse_rule("small($id) <- t1($id, $left) @ $id < 1", &mut syms).unwrap();
+        let (facts, trace) = w.query_rule_traced(&rule);
+        assert_eq!(facts.len(), 1);
+        // the facts are tried in the order of the fact set
+        let printed = syms.print_evaluation_trace(&trace);
+        let mut lines = printed.lines().collect::<Vec<_>>();
+        lines[1..].sort_unstable();
+        assert_eq!(lines, [
+            "small($id) <- t1($id, $left) @ $id < 1",
+            "  t1(0, #abc): produced small(0)",
+            "  t1(1, #def): rejected by $id < 1",
+            "  t1(2, #ghi): rejected by $id < 1",
+        ]);
+    }
+}
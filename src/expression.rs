@@ -1,45 +1,77 @@
-use std::collections::HashMap;
-use super::ID;
+use alloc::borrow::Cow;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use super::{HashMap, HashSet, Symbol, ID};
 use super::SymbolTable;
-use regex::Regex;
+use crate::error;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Expression {
     pub ops: Vec<Op>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Op {
     Value(ID),
     Unary(Unary),
     Binary(Binary),
+    /// terminal op: binds the value on top of the stack to a variable
+    /// that can then be used in the rule's head
+    Bind(u32),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl Op {
+    /// the operator as written in expressions, see `error::Expression`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Value(_) => "value",
+            Op::Unary(unary) => unary.operator(),
+            Op::Binary(binary) => binary.name(),
+            Op::Bind(_) => "=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Unary {
     Negate,
 }
 
 impl Unary {
-    fn evaluate(&self, value: ID) -> Option<ID> {
+    /// `Negate` applies to integers and booleans. Dates are not negated:
+    /// they are durations since the epoch, and there is no date before it
+    fn evaluate(&self, value: &ID) -> Result<ID, error::Expression> {
         match (self, value) {
-            (Unary::Negate, ID::Integer(i)) => Some(ID::Integer(-1i64 * i)),
-            (Unary::Negate, ID::Bool(b)) => Some(ID::Bool(!b)),
-             _ => {
-                 println!("unexpected value type on the stack");
-                 return None;
-             }
+            (Unary::Negate, ID::Integer(i)) => i.checked_neg().map(ID::Integer).ok_or(error::Expression::Overflow),
+            (Unary::Negate, ID::Bool(b)) => Ok(ID::Bool(!b)),
+            _ => Err(error::Expression::InvalidType {
+                op: Op::Unary(self.clone()),
+                operands: vec![value.type_name()],
+            }),
         }
     }
 
-    pub fn print(&self, value: String, symbols: &SymbolTable) -> String {
+    /// written before the operand
+    fn operator(&self) -> &'static str {
         match self {
-            Unary::Negate => format!("-{}", value),
+            Unary::Negate => "-",
         }
     }
+
+    pub fn print(&self, value: String, _symbols: &SymbolTable) -> String {
+        format!("{}{}", self.operator(), value)
+    }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// printing precedence of `Unary` operators, above the `Binary` ones
+const UNARY_PRECEDENCE: u8 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Binary {
     LessThan,
     GreaterThan,
@@ -56,133 +88,272 @@ pub enum Binary {
 }
 
 impl Binary {
-    fn evaluate(&self, left: ID, right: ID) -> Option<ID> {
+    fn evaluate(&self, left: &ID, right: &ID) -> Result<ID, error::Expression> {
         match (self, left, right) {
-            (Binary::LessThan, ID::Integer(i), ID::Integer(j)) => Some(ID::Bool(i < j)),
-            (Binary::GreaterThan, ID::Integer(i), ID::Integer(j)) => Some(ID::Bool(i > j)),
-            (Binary::LessOrEqual, ID::Integer(i), ID::Integer(j)) => Some(ID::Bool(i <= j)),
-            (Binary::GreaterOrEqual, ID::Integer(i), ID::Integer(j)) => Some(ID::Bool(i >= j)),
-            (Binary::Equal, ID::Integer(i), ID::Integer(j)) => Some(ID::Bool(i == j)),
-            (Binary::In, ID::Integer(i), ID::Set(set)) => Some(ID::Bool(set.contains(&ID::Integer(i)))),
-            (Binary::NotIn, ID::Integer(i), ID::Set(set)) => Some(ID::Bool(!set.contains(&ID::Integer(i)))),
-
-            (Binary::Prefix, ID::Str(s), ID::Str(pref)) => Some(ID::Bool(s.as_str().starts_with(pref.as_str()))),
-            (Binary::Suffix, ID::Str(s), ID::Str(suff)) => Some(ID::Bool(s.as_str().ends_with(suff.as_str()))),
-            (Binary::Regex, ID::Str(s), ID::Str(r)) => {
-                Some(ID::Bool(Regex::new(&r).map(|re| re.is_match(&s)).unwrap_or(false)))
+            (Binary::LessThan, ID::Integer(i), ID::Integer(j)) => Ok(ID::Bool(i < j)),
+            (Binary::GreaterThan, ID::Integer(i), ID::Integer(j)) => Ok(ID::Bool(i > j)),
+            (Binary::LessOrEqual, ID::Integer(i), ID::Integer(j)) => Ok(ID::Bool(i <= j)),
+            (Binary::GreaterOrEqual, ID::Integer(i), ID::Integer(j)) => Ok(ID::Bool(i >= j)),
+            (Binary::Equal, ID::Integer(i), ID::Integer(j)) => Ok(ID::Bool(i == j)),
+            (Binary::In, ID::Integer(_), ID::Set(set)) => Ok(ID::Bool(set.contains(left))),
+            (Binary::NotIn, ID::Integer(_), ID::Set(set)) => Ok(ID::Bool(!set.contains(left))),
+
+            (Binary::Prefix, ID::Str(s), ID::Str(pref)) => Ok(ID::Bool(s.starts_with(pref.as_str()))),
+            (Binary::Suffix, ID::Str(s), ID::Str(suff)) => Ok(ID::Bool(s.ends_with(suff.as_str()))),
+            (Binary::Regex, ID::Str(s), ID::Str(r)) => match crate::regex_match(r, s) {
+                Some(matched) => Ok(ID::Bool(matched)),
+                None => Err(error::Expression::InvalidRegex(r.clone())),
             },
-            (Binary::Equal, ID::Str(i), ID::Str(j)) => Some(ID::Bool(i == j)),
-            (Binary::In, ID::Str(i), ID::Set(set)) => Some(ID::Bool(set.contains(&ID::Str(i)))),
-            (Binary::NotIn, ID::Str(i), ID::Set(set)) => Some(ID::Bool(!set.contains(&ID::Str(i)))),
+            (Binary::Equal, ID::Str(i), ID::Str(j)) => Ok(ID::Bool(i == j)),
+            (Binary::In, ID::Str(_), ID::Set(set)) => Ok(ID::Bool(set.contains(left))),
+            (Binary::NotIn, ID::Str(_), ID::Set(set)) => Ok(ID::Bool(!set.contains(left))),
 
-            (Binary::LessOrEqual, ID::Date(i), ID::Date(j)) => Some(ID::Bool(i <= j)),
-            (Binary::GreaterOrEqual, ID::Date(i), ID::Date(j)) => Some(ID::Bool(i >= j)),
+            (Binary::LessOrEqual, ID::Date(i), ID::Date(j)) => Ok(ID::Bool(i <= j)),
+            (Binary::GreaterOrEqual, ID::Date(i), ID::Date(j)) => Ok(ID::Bool(i >= j)),
 
-            (Binary::In, ID::Symbol(i), ID::Set(set)) => Some(ID::Bool(set.contains(&ID::Symbol(i)))),
-            (Binary::NotIn, ID::Symbol(i), ID::Set(set)) => Some(ID::Bool(!set.contains(&ID::Symbol(i)))),
+            (Binary::In, ID::Symbol(_), ID::Set(set)) => Ok(ID::Bool(set.contains(left))),
+            (Binary::NotIn, ID::Symbol(_), ID::Set(set)) => Ok(ID::Bool(!set.contains(left))),
 
-            (Binary::Equal, ID::Bytes(i), ID::Bytes(j)) => Some(ID::Bool(i == j)),
-            (Binary::In, ID::Bytes(i), ID::Set(set)) => Some(ID::Bool(set.contains(&ID::Bytes(i)))),
-            (Binary::NotIn, ID::Bytes(i), ID::Set(set)) => Some(ID::Bool(!set.contains(&ID::Bytes(i)))),
+            (Binary::Equal, ID::Bytes(i), ID::Bytes(j)) => Ok(ID::Bool(i == j)),
+            (Binary::In, ID::Bytes(_), ID::Set(set)) => Ok(ID::Bool(set.contains(left))),
+            (Binary::NotIn, ID::Bytes(_), ID::Set(set)) => Ok(ID::Bool(!set.contains(left))),
 
-            (Binary::Add, ID::Integer(i), ID::Integer(j)) => Some(ID::Integer(i + j)),
-            (Binary::And, ID::Bool(i), ID::Bool(j)) => Some(ID::Bool(i & j)),
-            _ => {
-                println!("unexpected value type on the stack");
-                return None;
-            }
+            (Binary::Add, ID::Integer(i), ID::Integer(j)) => {
+                i.checked_add(*j).map(ID::Integer).ok_or(error::Expression::Overflow)
+            },
+            (Binary::Add, ID::Str(i), ID::Str(j)) => Ok(ID::Str(format!("{}{}", i, j))),
+            (Binary::And, ID::Bool(i), ID::Bool(j)) => Ok(ID::Bool(*i && *j)),
+            _ => Err(error::Expression::InvalidType {
+                op: Op::Binary(self.clone()),
+                operands: vec![left.type_name(), right.type_name()],
+            }),
         }
     }
 
-    pub fn print(&self, left: String, right: String, symbols: &SymbolTable) -> String {
+    /// used in errors, the printed form of `Prefix`, `Suffix` and `Regex`
+    /// surrounds the right operand
+    fn name(&self) -> &'static str {
         match self {
-            Binary::LessThan => format!("{} < {}", left, right),
-            Binary::GreaterThan => format!("{} > {}", left, right),
-            Binary::LessOrEqual => format!("{} <= {}", left, right),
-            Binary::GreaterOrEqual => format!("{} >= {}", left, right),
-            Binary::Equal => format!("{} == {}", left, right),
-            Binary::In => format!("{} in {}", left, right),
-            Binary::NotIn => format!("{} not in {}", left, right),
-            Binary::Prefix => format!("{} matches {}*", left, right),
-            Binary::Suffix => format!("{} matches *{}", left, right),
-            Binary::Regex => format!("{} matches /{}/", left, right),
-            Binary::Add => format!("{} + {}", left, right),
-            Binary::And => format!("{} && {}", left, right),
+            Binary::Prefix => "prefix",
+            Binary::Suffix => "suffix",
+            Binary::Regex => "regex",
+            _ => self.operator().0.trim(),
         }
     }
+
+    /// binding strength of the operator when printed, higher binds tighter
+    fn precedence(&self) -> u8 {
+        match self {
+            Binary::And => 1,
+            Binary::Add => 3,
+            _ => 2,
+        }
+    }
+
+    /// written between the operands, and after the right one
+    fn operator(&self) -> (&'static str, &'static str) {
+        match self {
+            Binary::LessThan => (" < ", ""),
+            Binary::GreaterThan => (" > ", ""),
+            Binary::LessOrEqual => (" <= ", ""),
+            Binary::GreaterOrEqual => (" >= ", ""),
+            Binary::Equal => (" == ", ""),
+            Binary::In => (" in ", ""),
+            Binary::NotIn => (" not in ", ""),
+            Binary::Prefix => (" matches ", "*"),
+            Binary::Suffix => (" matches *", ""),
+            Binary::Regex => (" matches /", "/"),
+            Binary::Add => (" + ", ""),
+            Binary::And => (" && ", ""),
+        }
+    }
+
+    pub fn print(&self, left: String, right: String, _symbols: &SymbolTable) -> String {
+        let (between, after) = self.operator();
+        format!("{}{}{}{}", left, between, right, after)
+    }
 }
 
 impl Expression {
+    /// returns the variable this expression binds its result to, if it
+    /// ends with `Op::Bind`
+    pub fn binding(&self) -> Option<u32> {
+        match self.ops.last() {
+            Some(Op::Bind(v)) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// variables used as values in the expression
+    pub fn variables(&self) -> HashSet<u32> {
+        self.ops.iter().filter_map(|op| match op {
+            Op::Value(ID::Variable(i)) => Some(*i),
+            _ => None,
+        }).collect()
+    }
+
+    /// replaces the symbols and variables found in `table`, see
+    /// `World::merge_translated`
+    pub fn translate(&self, table: &HashMap<Symbol, Symbol>) -> Expression {
+        Expression {
+            ops: self.ops.iter().map(|op| match op {
+                Op::Value(id) => Op::Value(id.translate(table)),
+                Op::Bind(v) => Op::Bind(crate::translate_variable(table, *v)),
+                op => op.clone(),
+            }).collect(),
+        }
+    }
+
+    /// the value of the expression, or `None` if it cannot be evaluated,
+    /// see `try_evaluate`
     pub fn evaluate(&self, values: &HashMap<u32, ID>) -> Option<ID> {
-        let mut stack: Vec<ID> = Vec::new();
+        self.try_evaluate(values).ok()
+    }
+
+    /// fails on unknown variables, type errors and integer overflows, and
+    /// if the expression does not leave exactly one value on the stack
+    pub fn try_evaluate(&self, values: &HashMap<u32, ID>) -> Result<ID, error::Expression> {
+        // values and variables are only cloned if they are the result
+        let mut stack: Vec<Cow<'_, ID>> = Vec::new();
 
         for op in self.ops.iter() {
-            println!("op: {:?}\t| stack: {:?}", op, stack);
             match op {
-                Op::Value(ID::Variable(i)) => match values.get(&i) {
-                    Some(id) => stack.push(id.clone()),
-                    None => {
-                        println!("unknown variable {}", i);
-                        return None;
-                    }
+                Op::Value(ID::Variable(i)) => match values.get(i) {
+                    Some(id) => stack.push(Cow::Borrowed(id)),
+                    None => return Err(error::Expression::UnknownVariable(*i)),
                 }
-                Op::Value(id) => stack.push(id.clone()),
+                Op::Value(id) => stack.push(Cow::Borrowed(id)),
                 Op::Unary(unary) => match stack.pop() {
-                    None => {
-                        println!("expected a value on the stack");
-                        return None;
-                    }
-                    Some(id) => match unary.evaluate(id) {
-                        Some(res) => stack.push(res),
-                        None => return None,
-                    }
+                    None => return Err(error::Expression::MissingOperand),
+                    Some(id) => stack.push(Cow::Owned(unary.evaluate(&id)?)),
                 },
                 Op::Binary(binary) => match (stack.pop(), stack.pop()) {
-                    (Some(right_id), Some(left_id)) => match binary.evaluate(left_id, right_id) {
-                        Some(res) => stack.push(res),
-                        None => return None,
-                    },
-                    _ => {
-                        println!("expected two values on the stack");
-                        return None;
-                    }
+                    (Some(right_id), Some(left_id)) => stack.push(Cow::Owned(binary.evaluate(&left_id, &right_id)?)),
+                    _ => return Err(error::Expression::MissingOperand),
+                }
+                // the value stays on the stack and is returned to the caller,
+                // which is responsible for binding it
+                Op::Bind(_) => if stack.is_empty() {
+                    return Err(error::Expression::MissingOperand);
                 }
             }
         }
 
-        if stack.len() == 1 {
-            Some(stack.remove(0))
-        } else {
-            None
+        match stack.len() {
+            1 => Ok(stack.remove(0).into_owned()),
+            len => Err(error::Expression::InvalidStack(len)),
         }
     }
 
+    /// operations are only wrapped in parentheses when their precedence
+    /// requires it, like `-(1 + 2) < $a`
+    ///
+    /// the operations are first arranged in a tree, then written in a
+    /// single pass, so that deeply nested expressions print in linear time
     pub fn print(&self, symbols: &SymbolTable) -> Option<String> {
-        let mut stack: Vec<String> = Vec::new();
-
+        let mut nodes: Vec<Node> = Vec::with_capacity(self.ops.len());
+        let mut stack: Vec<usize> = Vec::new();
         for op in self.ops.iter() {
-            println!("op: {:?}\t| stack: {:?}", op, stack);
-            match op {
-                Op::Value(i) => stack.push(symbols.print_id(&i)),
-                Op::Unary(unary) => match stack.pop() {
-                    None => return None,
-                    Some(s) => stack.push(unary.print(s, symbols)),
+            let node = match op {
+                Op::Value(id) => Node::Value(id),
+                Op::Unary(unary) => Node::Unary(unary, stack.pop()?),
+                Op::Binary(binary) => {
+                    let right = stack.pop()?;
+                    Node::Binary(binary, stack.pop()?, right)
+                },
+                Op::Bind(v) => Node::Bind(*v, stack.pop()?),
+            };
+            stack.push(nodes.len());
+            nodes.push(node);
+        }
+        let root = match stack[..] {
+            [root] => root,
+            _ => return None,
+        };
+
+        // an explicit stack instead of recursion, for deep trees
+        let mut output = String::new();
+        let mut parts = vec![Part::Node(root, 0)];
+        while let Some(part) = parts.pop() {
+            let (index, min) = match part {
+                Part::Text(s) => {
+                    output.push_str(s);
+                    continue;
+                },
+                Part::Node(index, min) => (index, min),
+            };
+            if nodes[index].precedence() < min {
+                output.push('(');
+                parts.push(Part::Text(")"));
+            }
+
+            match nodes[index] {
+                Node::Value(id) => output.push_str(&symbols.print_id(id)),
+                Node::Unary(unary, operand) => {
+                    output.push_str(unary.operator());
+                    // `-(4)`, as `-4` is an integer
+                    let number = match nodes[operand] {
+                        Node::Value(id) => symbols.print_id(id).starts_with(|c: char| c == '-' || c.is_ascii_digit()),
+                        Node::Unary(..) => true,
+                        _ => false,
+                    };
+                    if number {
+                        output.push('(');
+                        parts.push(Part::Text(")"));
+                        parts.push(Part::Node(operand, 0));
+                    } else {
+                        parts.push(Part::Node(operand, UNARY_PRECEDENCE));
+                    }
+                },
+                Node::Binary(binary, left, right) => {
+                    let precedence = binary.precedence();
+                    // only `+` and `&&` can be chained without parentheses
+                    let left_min = if precedence == 2 { precedence + 1 } else { precedence };
+                    let (between, after) = binary.operator();
+                    parts.push(Part::Text(after));
+                    parts.push(Part::Node(right, precedence + 1));
+                    parts.push(Part::Text(between));
+                    parts.push(Part::Node(left, left_min));
+                },
+                Node::Bind(v, operand) => {
+                    output.push_str(&symbols.print_variable(v));
+                    output.push_str(" = ");
+                    parts.push(Part::Node(operand, 0));
                 },
-                Op::Binary(binary) => match (stack.pop(), stack.pop()) {
-                    (Some(right), Some(left)) => stack.push(binary.print(left, right, symbols)),
-                    _ => return None,
-                }
             }
         }
+        Some(output)
+    }
+}
+
+/// operation in the tree built by `Expression::print`, with the indexes
+/// of its operands
+enum Node<'a> {
+    Value(&'a ID),
+    Unary(&'a Unary, usize),
+    Binary(&'a Binary, usize, usize),
+    Bind(u32, usize),
+}
 
-        if stack.len() == 1 {
-            Some(stack.remove(0))
-        } else {
-            None
+impl Node<'_> {
+    /// binding strength of the operation when printed
+    fn precedence(&self) -> u8 {
+        match self {
+            Node::Value(_) => u8::MAX,
+            Node::Unary(..) => UNARY_PRECEDENCE,
+            Node::Binary(binary, ..) => binary.precedence(),
+            Node::Bind(..) => 0,
         }
     }
 }
 
+/// what is left to write in `Expression::print`: a node, wrapped in
+/// parentheses if its precedence is below the minimum, or some text
+enum Part {
+    Node(usize, u8),
+    Text(&'static str),
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -191,13 +362,11 @@ mod tests {
 
     #[test]
     fn negate() {
-        let symbols = SymbolTable {
-            symbols: vec![
-                "test1".to_string(),
-                "test2".to_string(),
-                "var1".to_string(),
-            ],
-        };
+        let symbols = SymbolTable::from(vec![
+            "test1".to_string(),
+            "test2".to_string(),
+            "var1".to_string(),
+        ]);
 
         let ops = vec![
             Op::Value(ID::Integer(1)),
@@ -216,19 +385,16 @@ mod tests {
 
         let res = e.evaluate(&values);
         assert_eq!(res, Some(ID::Bool(true)));
-        panic!();
     }
 
 
     #[test]
     fn printer() {
-        let symbols = SymbolTable {
-            symbols: vec![
-                "test1".to_string(),
-                "test2".to_string(),
-                "var1".to_string(),
-            ],
-        };
+        let symbols = SymbolTable::from(vec![
+            "test1".to_string(),
+            "test2".to_string(),
+            "var1".to_string(),
+        ]);
 
         let ops1 = vec![
             Op::Value(ID::Integer(1)),
@@ -260,7 +426,8 @@ mod tests {
         let e2 = Expression { ops: ops2 };
         let e3 = Expression { ops: ops3 };
 
-        assert_eq!(e1.print(&symbols).unwrap(), "-1 < $var1");
+        // `-1` would be the integer -1
+        assert_eq!(e1.print(&symbols).unwrap(), "-(1) < $var1");
 
         assert_eq!(e2.print(&symbols).unwrap(), "1 < 2 + 3");
 
@@ -268,4 +435,123 @@ mod tests {
         //panic!();
     }
 
+    #[test]
+    fn bind() {
+        let symbols = SymbolTable::from(vec![
+            "test1".to_string(),
+            "test2".to_string(),
+            "var1".to_string(),
+        ]);
+
+        let ops = vec![
+            Op::Value(ID::Variable(0)),
+            Op::Value(ID::Integer(2)),
+            Op::Binary(Binary::Add),
+            Op::Bind(2),
+        ];
+
+        let values: HashMap<u32, ID> = [(0, ID::Integer(1))]
+            .iter().cloned().collect();
+
+        let e = Expression { ops };
+        assert_eq!(e.binding(), Some(2));
+        assert_eq!(e.print(&symbols).unwrap(), "$var1 = $test1 + 2");
+        assert_eq!(e.evaluate(&values), Some(ID::Integer(3)));
+
+        let e = Expression { ops: vec![
+            Op::Value(ID::Str("hello ".to_string())),
+            Op::Value(ID::Str("world".to_string())),
+            Op::Binary(Binary::Add),
+        ] };
+        assert_eq!(e.binding(), None);
+        assert_eq!(e.evaluate(&values), Some(ID::Str("hello world".to_string())));
+    }
+
+    #[test]
+    fn errors() {
+        let values: HashMap<u32, ID> = [(0, ID::Integer(i64::MAX))].iter().cloned().collect();
+        let cases = vec![
+            (vec![Op::Value(ID::Variable(1))], error::Expression::UnknownVariable(1)),
+            (vec![Op::Unary(Unary::Negate)], error::Expression::MissingOperand),
+            (vec![Op::Value(ID::Integer(1)), Op::Binary(Binary::Add)], error::Expression::MissingOperand),
+            (vec![Op::Bind(0)], error::Expression::MissingOperand),
+            (vec![], error::Expression::InvalidStack(0)),
+            (vec![Op::Value(ID::Integer(1)), Op::Value(ID::Integer(2))], error::Expression::InvalidStack(2)),
+            (
+                vec![Op::Value(ID::Str("a".to_string())), Op::Value(ID::Integer(1)), Op::Binary(Binary::LessThan)],
+                error::Expression::InvalidType { op: Op::Binary(Binary::LessThan), operands: vec!["string", "integer"] },
+            ),
+            (vec![Op::Value(ID::Variable(0)), Op::Value(ID::Integer(1)), Op::Binary(Binary::Add)], error::Expression::Overflow),
+            (vec![Op::Value(ID::Integer(i64::MIN)), Op::Unary(Unary::Negate)], error::Expression::Overflow),
+        ];
+
+        for (ops, expected) in cases {
+            let e = Expression { ops };
+            assert_eq!(e.try_evaluate(&values), Err(expected), "{:?}", e.ops);
+            assert_eq!(e.evaluate(&values), None);
+        }
+    }
+
+    #[test]
+    fn invalid_types() {
+        let values = HashMap::new();
+        let cases = vec![
+            (vec![Op::Value(ID::Date(10)), Op::Unary(Unary::Negate)], "operator - cannot be applied to date"),
+            (vec![Op::Value(ID::Str("a".to_string())), Op::Unary(Unary::Negate)], "operator - cannot be applied to string"),
+            (vec![Op::Value(ID::Bytes(vec![1])), Op::Unary(Unary::Negate)], "operator - cannot be applied to bytes"),
+            (
+                vec![Op::Value(ID::Date(1)), Op::Value(ID::Date(2)), Op::Binary(Binary::LessThan)],
+                "operator < cannot be applied to date and date",
+            ),
+            (
+                vec![Op::Value(ID::Integer(1)), Op::Value(ID::Str("a".to_string())), Op::Binary(Binary::Add)],
+                "operator + cannot be applied to integer and string",
+            ),
+            (
+                vec![Op::Value(ID::Symbol(0)), Op::Value(ID::Str("a".to_string())), Op::Binary(Binary::Prefix)],
+                "operator prefix cannot be applied to symbol and string",
+            ),
+        ];
+
+        for (ops, message) in cases {
+            let op = ops.last().unwrap().clone();
+            let operands = ops[..ops.len() - 1].iter()
+                .map(|op| match op {
+                    Op::Value(id) => id.type_name(),
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>();
+            let e = Expression { ops }.try_evaluate(&values).unwrap_err();
+            assert_eq!(e, error::Expression::InvalidType { op, operands });
+            assert_eq!(e.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn deeply_nested() {
+        let symbols = SymbolTable::new();
+        let values = HashMap::new();
+
+        // ((1 + 1) + 1) + ...
+        let mut ops = vec![Op::Value(ID::Integer(1))];
+        for _ in 1..10_000 {
+            ops.push(Op::Value(ID::Integer(1)));
+            ops.push(Op::Binary(Binary::Add));
+        }
+        let e = Expression { ops };
+        let start = std::time::Instant::now();
+        let printed = e.print(&symbols).unwrap();
+        assert_eq!(e.evaluate(&values), Some(ID::Integer(10_000)));
+        assert!(start.elapsed() < std::time::Duration::from_secs(2), "took {:?}", start.elapsed());
+        assert_eq!(printed.len(), "1".len() + 9_999 * " + 1".len());
+        assert!(printed.starts_with("1 + 1 + 1 + "));
+
+        // -(-(...-(1)...))
+        let mut ops = vec![Op::Value(ID::Integer(1))];
+        ops.extend((0..10_000).map(|_| Op::Unary(Unary::Negate)));
+        let e = Expression { ops };
+        let printed = e.print(&symbols).unwrap();
+        assert_eq!(printed, format!("{}1{}", "-(".repeat(10_000), ")".repeat(10_000)));
+        assert_eq!(e.evaluate(&values), Some(ID::Integer(1)));
+    }
 }
@@ -0,0 +1,598 @@
+use alloc::boxed::Box;
+use alloc::collections::{btree_set, BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::hash::{BuildHasher, BuildHasherDefault, Hasher};
+use core::slice;
+
+use super::{hash_map, match_preds, Fact, HashMap, Predicate, RandomState, Symbol};
+
+#[cfg(test)]
+thread_local! {
+    /// number of facts examined by `FactSet::matching`, to measure the
+    /// effect of the index in tests
+    pub(crate) static CANDIDATES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    /// number of times a fact's content was hashed by a `FactSet`
+    pub(crate) static FACT_HASHES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// set of facts indexed by predicate name and arity
+///
+/// rule application only looks at the facts with the same name and
+/// arity as each body predicate, instead of the whole set.
+///
+/// A sorted set (see `FactSet::sorted`) iterates over the facts in a
+/// reproducible order, at the cost of slower insertions and lookups.
+///
+/// Cloning is cheap: the buckets are shared between the clones, and only
+/// copied when one of them modifies a bucket.
+///
+/// Each fact is hashed once per operation, the hash being stored with the
+/// fact in unsorted buckets
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(into = "Vec<Fact>", from = "Vec<Fact>"))]
+pub struct FactSet {
+    // buckets are removed when they become empty
+    facts: BTreeMap<(Symbol, usize), Arc<Bucket>>,
+    len: usize,
+    // sum of `Fact::estimated_size`
+    size: usize,
+    sorted: bool,
+    // copied to the clones, so the hashes stored in shared buckets stay valid
+    hasher: RandomState,
+}
+
+/// uses the fact hashes computed by `FactSet::hash` as is
+#[derive(Default)]
+struct Prehashed(u64);
+
+impl Hasher for Prehashed {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 = self.0.rotate_left(8) ^ u64::from(*b);
+        }
+    }
+
+    fn write_u64(&mut self, hash: u64) {
+        self.0 = hash;
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Bucket {
+    Hashed(Hashed),
+    Sorted(BTreeSet<Fact>),
+}
+
+/// facts keyed by their hash. Facts with the same hash as one of the
+/// map's facts, but a different content, go in `collisions`
+#[derive(Debug, Clone, Default)]
+struct Hashed {
+    facts: HashMap<u64, Fact, BuildHasherDefault<Prehashed>>,
+    collisions: Vec<(u64, Fact)>,
+}
+
+impl Hashed {
+    fn insert(&mut self, hash: u64, fact: Fact) -> bool {
+        match self.facts.entry(hash) {
+            hash_map::Entry::Vacant(e) => {
+                e.insert(fact);
+                true
+            },
+            hash_map::Entry::Occupied(e) if *e.get() == fact => false,
+            hash_map::Entry::Occupied(_) => {
+                if self.collisions.iter().any(|(h, f)| *h == hash && *f == fact) {
+                    return false;
+                }
+                self.collisions.push((hash, fact));
+                true
+            },
+        }
+    }
+
+    fn remove(&mut self, hash: u64, fact: &Fact) -> bool {
+        if self.facts.get(&hash) == Some(fact) {
+            self.facts.remove(&hash);
+            // keeps the colliding facts reachable from the map
+            if let Some(i) = self.collisions.iter().position(|(h, _)| *h == hash) {
+                let (_, f) = self.collisions.swap_remove(i);
+                self.facts.insert(hash, f);
+            }
+            return true;
+        }
+        match self.collisions.iter().position(|(h, f)| *h == hash && f == fact) {
+            Some(i) => {
+                self.collisions.swap_remove(i);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn contains(&self, hash: u64, fact: &Fact) -> bool {
+        match self.facts.get(&hash) {
+            Some(f) if f == fact => true,
+            Some(_) => self.collisions.iter().any(|(h, f)| *h == hash && f == fact),
+            None => false,
+        }
+    }
+
+    fn retain<F: FnMut(&Fact) -> bool>(&mut self, mut f: F) {
+        self.facts.retain(|_, fact| f(fact));
+        self.collisions.retain(|(_, fact)| f(fact));
+        for (hash, fact) in core::mem::take(&mut self.collisions) {
+            if let hash_map::Entry::Vacant(e) = self.facts.entry(hash) {
+                e.insert(fact);
+            } else {
+                self.collisions.push((hash, fact));
+            }
+        }
+    }
+}
+
+impl Bucket {
+    fn new(sorted: bool) -> Self {
+        if sorted {
+            Bucket::Sorted(BTreeSet::new())
+        } else {
+            Bucket::Hashed(Hashed::default())
+        }
+    }
+
+    fn insert(&mut self, hash: u64, fact: Fact) -> bool {
+        match self {
+            Bucket::Hashed(s) => s.insert(hash, fact),
+            Bucket::Sorted(s) => s.insert(fact),
+        }
+    }
+
+    fn remove(&mut self, hash: u64, fact: &Fact) -> bool {
+        match self {
+            Bucket::Hashed(s) => s.remove(hash, fact),
+            Bucket::Sorted(s) => s.remove(fact),
+        }
+    }
+
+    fn contains(&self, hash: u64, fact: &Fact) -> bool {
+        match self {
+            Bucket::Hashed(s) => s.contains(hash, fact),
+            Bucket::Sorted(s) => s.contains(fact),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Bucket::Hashed(s) => s.facts.len() + s.collisions.len(),
+            Bucket::Sorted(s) => s.len(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn iter(&self) -> BucketIter<'_> {
+        match self {
+            Bucket::Hashed(s) => BucketIter::Hashed(s.facts.values(), s.collisions.iter()),
+            Bucket::Sorted(s) => BucketIter::Sorted(s.iter()),
+        }
+    }
+
+    fn retain<F: FnMut(&Fact) -> bool>(&mut self, f: F) {
+        match self {
+            Bucket::Hashed(s) => s.retain(f),
+            Bucket::Sorted(s) => s.retain(f),
+        }
+    }
+}
+
+impl IntoIterator for Bucket {
+    type Item = Fact;
+    type IntoIter = Box<dyn Iterator<Item = Fact>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        match self {
+            Bucket::Hashed(s) => Box::new(s.facts.into_values().chain(s.collisions.into_iter().map(|(_, f)| f))),
+            Bucket::Sorted(s) => Box::new(s.into_iter()),
+        }
+    }
+}
+
+enum BucketIter<'a> {
+    Hashed(hash_map::Values<'a, u64, Fact>, slice::Iter<'a, (u64, Fact)>),
+    Sorted(btree_set::Iter<'a, Fact>),
+}
+
+impl<'a> Iterator for BucketIter<'a> {
+    type Item = &'a Fact;
+
+    fn next(&mut self) -> Option<&'a Fact> {
+        match self {
+            BucketIter::Hashed(facts, collisions) => facts.next().or_else(|| collisions.next().map(|(_, f)| f)),
+            BucketIter::Sorted(it) => it.next(),
+        }
+    }
+}
+
+/// sets are equal if they contain the same facts, whatever their storage
+impl PartialEq for FactSet {
+    fn eq(&self, other: &FactSet) -> bool {
+        self.len == other.len && self.iter().all(|fact| other.contains(fact))
+    }
+}
+
+impl Eq for FactSet {}
+
+impl FactSet {
+    pub fn new() -> Self {
+        FactSet::default()
+    }
+
+    /// creates a set iterating over facts in their sort order
+    pub fn sorted() -> Self {
+        FactSet { sorted: true, ..FactSet::default() }
+    }
+
+    pub fn is_sorted(&self) -> bool {
+        self.sorted
+    }
+
+    /// creates an empty set with the same storage as this one
+    pub fn empty(&self) -> Self {
+        FactSet { sorted: self.sorted, ..FactSet::default() }
+    }
+
+    /// sorted buckets do not use the hash
+    fn hash(&self, fact: &Fact) -> u64 {
+        if self.sorted {
+            return 0;
+        }
+        #[cfg(test)]
+        FACT_HASHES.with(|c| c.set(c.get() + 1));
+        self.hasher.hash_one(fact)
+    }
+
+    /// returns true if the fact was not present
+    pub fn insert(&mut self, fact: Fact) -> bool {
+        let hash = self.hash(&fact);
+        if self.contains_hashed(hash, &fact) {
+            return false;
+        }
+
+        let key = (fact.predicate.name, fact.predicate.ids.len());
+        let sorted = self.sorted;
+        let bucket = self.facts.entry(key).or_insert_with(|| Arc::new(Bucket::new(sorted)));
+        self.size += fact.estimated_size();
+        Arc::make_mut(bucket).insert(hash, fact);
+        self.len += 1;
+        true
+    }
+
+    /// returns true if the fact was present
+    pub fn remove(&mut self, fact: &Fact) -> bool {
+        let hash = self.hash(fact);
+        if !self.contains_hashed(hash, fact) {
+            return false;
+        }
+
+        let key = (fact.predicate.name, fact.predicate.ids.len());
+        if let Some(bucket) = self.facts.get_mut(&key) {
+            if bucket.len() == 1 {
+                self.facts.remove(&key);
+            } else {
+                Arc::make_mut(bucket).remove(hash, fact);
+            }
+        }
+        self.len -= 1;
+        self.size -= fact.estimated_size();
+        true
+    }
+
+    pub fn contains(&self, fact: &Fact) -> bool {
+        self.contains_hashed(self.hash(fact), fact)
+    }
+
+    fn contains_hashed(&self, hash: u64, fact: &Fact) -> bool {
+        self.facts
+            .get(&(fact.predicate.name, fact.predicate.ids.len()))
+            .map(|bucket| bucket.contains(hash, fact))
+            .unwrap_or(false)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// sum of the estimated sizes of the facts, see `Fact::estimated_size`
+    pub fn estimated_size(&self) -> usize {
+        self.size
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Fact> {
+        self.facts.values().flat_map(|bucket| bucket.iter())
+    }
+
+    /// iterates over the facts with this predicate name, whatever their arity
+    pub fn with_name(&self, name: Symbol) -> impl Iterator<Item = &Fact> {
+        self.facts.iter()
+            .filter(move |((n, _), _)| *n == name)
+            .flat_map(|(_, bucket)| bucket.iter())
+    }
+
+    /// number of facts for each predicate name
+    pub fn count_by_name(&self) -> HashMap<Symbol, usize> {
+        let mut counts = HashMap::new();
+        for ((name, _), bucket) in self.facts.iter() {
+            *counts.entry(*name).or_insert(0) += bucket.len();
+        }
+        counts
+    }
+
+    /// number of facts with the same name and arity as `predicate`
+    pub fn bucket_len(&self, predicate: &Predicate) -> usize {
+        self.facts
+            .get(&(predicate.name, predicate.ids.len()))
+            .map(|bucket| bucket.len())
+            .unwrap_or(0)
+    }
+
+    /// iterates over the facts with the same name and arity as `predicate`
+    /// and whose terms can be unified with it
+    pub fn matching<P: Borrow<Predicate>>(&self, predicate: P) -> Matching<'_, P> {
+        let key = (predicate.borrow().name, predicate.borrow().ids.len());
+        Matching {
+            facts: self.facts.get(&key).map(|bucket| bucket.iter()),
+            predicate,
+        }
+    }
+
+    /// the buckets with these names and arities, shared with this set until
+    /// one of them modifies them
+    pub(crate) fn buckets(&self, keys: &BTreeSet<(Symbol, usize)>) -> FactSet {
+        // cloned to keep the hasher, see `hasher`
+        let mut set = self.clone();
+        set.facts.retain(|key, _| keys.contains(key));
+        set.len = set.facts.values().map(|bucket| bucket.len()).sum();
+        set.size = set.iter().map(|fact| fact.estimated_size()).sum();
+        set
+    }
+
+    /// the facts with the name and arity of `predicate` that are in this
+    /// set and not in `other`, then the ones in `other` and not in this set.
+    /// A bucket shared by both sets is not compared
+    pub(crate) fn changes<'a>(&'a self, other: &'a FactSet, predicate: &Predicate) -> (Vec<&'a Fact>, Vec<&'a Fact>) {
+        let key = (predicate.name, predicate.ids.len());
+        match (self.facts.get(&key), other.facts.get(&key)) {
+            (Some(a), Some(b)) if Arc::ptr_eq(a, b) => (Vec::new(), Vec::new()),
+            (a, b) => (
+                a.into_iter().flat_map(|a| a.iter()).filter(|fact| !other.contains(fact)).collect(),
+                b.into_iter().flat_map(|b| b.iter()).filter(|fact| !self.contains(fact)).collect(),
+            ),
+        }
+    }
+
+    pub fn retain<F: FnMut(&Fact) -> bool>(&mut self, mut f: F) {
+        for bucket in self.facts.values_mut() {
+            Arc::make_mut(bucket).retain(|fact| f(fact));
+        }
+        self.facts.retain(|_, bucket| !bucket.is_empty());
+        self.len = self.facts.values().map(|bucket| bucket.len()).sum();
+        self.size = self.iter().map(|fact| fact.estimated_size()).sum();
+    }
+}
+
+/// iterator returned by `FactSet::matching`
+pub struct Matching<'a, P> {
+    facts: Option<BucketIter<'a>>,
+    predicate: P,
+}
+
+impl<'a, P: Borrow<Predicate>> Iterator for Matching<'a, P> {
+    type Item = &'a Fact;
+
+    fn next(&mut self) -> Option<&'a Fact> {
+        let facts = self.facts.as_mut()?;
+        let predicate = self.predicate.borrow();
+        facts.find(|fact| {
+            #[cfg(test)]
+            CANDIDATES.with(|c| c.set(c.get() + 1));
+            match_preds(&fact.predicate, predicate)
+        })
+    }
+}
+
+#[cfg(test)]
+impl FactSet {
+    /// number of buckets stored in the same place as in `other`
+    pub(crate) fn shared_buckets(&self, other: &FactSet) -> usize {
+        self.facts.iter()
+            .filter(|(key, bucket)| other.facts.get(key).map(|b| Arc::ptr_eq(b, bucket)).unwrap_or(false))
+            .count()
+    }
+}
+
+impl core::iter::FromIterator<Fact> for FactSet {
+    fn from_iter<T: IntoIterator<Item = Fact>>(iter: T) -> Self {
+        let mut set = FactSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+/// sorted, to serialize the same set in the same way
+impl From<FactSet> for Vec<Fact> {
+    fn from(set: FactSet) -> Vec<Fact> {
+        let mut facts = set.facts.into_values()
+            .flat_map(|bucket| Arc::try_unwrap(bucket).unwrap_or_else(|bucket| (*bucket).clone()))
+            .collect::<Vec<_>>();
+        facts.sort();
+        facts
+    }
+}
+
+impl From<Vec<Fact>> for FactSet {
+    fn from(facts: Vec<Fact>) -> FactSet {
+        facts.into_iter().collect()
+    }
+}
+
+impl Extend<Fact> for FactSet {
+    fn extend<T: IntoIterator<Item = Fact>>(&mut self, iter: T) {
+        for fact in iter {
+            self.insert(fact);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn index() {
+        let mut syms = SymbolTable::new();
+        let names = (0..20).map(|i| syms.insert(&format!("pred{}", i))).collect::<Vec<_>>();
+
+        let mut w = World::new();
+        let mut flat = HashSet::new();
+        for i in 0..10_000i64 {
+            let f = fact(names[i as usize % 20], &[&int(i / 20), &int(i % 7)]);
+            flat.insert(f.clone());
+            w.add_fact(f);
+        }
+        assert_eq!(w.facts.len(), 10_000);
+        assert_eq!(w.facts.iter().cloned().collect::<HashSet<_>>(), flat);
+
+        let join = syms.insert("join");
+        let r = rule(
+            join,
+            &[var(&mut syms, "a"), var(&mut syms, "b")],
+            &[
+                pred(names[0], &[var(&mut syms, "a"), int(3)]),
+                pred(names[1], &[var(&mut syms, "a"), var(&mut syms, "b")]),
+            ],
+        );
+
+        CANDIDATES.with(|c| c.set(0));
+        let res = w.query_rule(r).drain(..).collect::<HashSet<_>>();
+        let examined = CANDIDATES.with(|c| c.get());
+
+        // same results as a scan over all facts
+        let (p0, p1) = (names[0], names[1]);
+        let expected = flat.iter()
+            .filter(|f| f.predicate.name == p0 && f.predicate.ids[1] == int(3))
+            .flat_map(|f0| flat.iter()
+                .filter(move |f1| f1.predicate.name == p1 && f1.predicate.ids[0] == f0.predicate.ids[0])
+                .map(move |f1| fact(join, &[&f0.predicate.ids[0], &f1.predicate.ids[1]])))
+            .collect::<HashSet<_>>();
+        assert!(!expected.is_empty());
+        assert_eq!(res, expected);
+
+        // 500 facts for the first predicate, then 500 for the second one
+        // for each of the matching facts
+        assert_eq!(examined, 500 + expected.len() * 500);
+        let unindexed = 10_000 + expected.len() * 10_000;
+        assert!(examined * 20 <= unindexed, "examined {} facts instead of {}", examined, unindexed);
+
+        let mut set = w.facts.clone();
+        assert!(set.remove(&fact(names[0], &[&int(0), &int(0)])));
+        assert!(!set.remove(&fact(names[0], &[&int(0), &int(0)])));
+        assert_eq!(set.len(), 9_999);
+        set.retain(|f| f.predicate.name != names[0]);
+        assert_eq!(set.len(), 9_500);
+        assert_ne!(set, w.facts);
+    }
+
+    thread_local! {
+        static REFERENCE_HASHES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    /// counts the hashes computed by a `HashSet`
+    #[derive(Default)]
+    struct Counting(std::collections::hash_map::DefaultHasher);
+
+    impl Hasher for Counting {
+        fn finish(&self) -> u64 {
+            REFERENCE_HASHES.with(|c| c.set(c.get() + 1));
+            self.0.finish()
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            self.0.write(bytes)
+        }
+    }
+
+    #[test]
+    fn hash_once() {
+        let mut syms = SymbolTable::new();
+        let blob = syms.insert("blob");
+        let facts = (0..200u32)
+            .map(|i| fact(blob, &[&ID::Bytes([&i.to_be_bytes()[..], &[0u8; 4096][..]].concat())]))
+            .collect::<Vec<_>>();
+
+        // the previous storage: a lookup, then an insertion
+        let mut reference = std::collections::HashSet::<Fact, BuildHasherDefault<Counting>>::default();
+        REFERENCE_HASHES.with(|c| c.set(0));
+        for f in facts.iter().chain(facts.iter()) {
+            if !reference.contains(f) {
+                reference.insert(f.clone());
+            }
+        }
+        let reference_hashes = REFERENCE_HASHES.with(|c| c.get());
+
+        let mut set = FactSet::new();
+        FACT_HASHES.with(|c| c.set(0));
+        for f in facts.iter().chain(facts.iter()) {
+            set.insert(f.clone());
+        }
+        let hashes = FACT_HASHES.with(|c| c.get());
+        assert_eq!(hashes, 400);
+        assert!(hashes < reference_hashes, "hashed {} facts instead of {}", hashes, reference_hashes);
+
+        assert_eq!(set.len(), 200);
+        assert_eq!(set.iter().cloned().collect::<Vec<_>>().len(), reference.len());
+        assert!(reference.iter().all(|f| set.contains(f)));
+        assert_eq!(set, facts.iter().cloned().collect::<FactSet>());
+
+        FACT_HASHES.with(|c| c.set(0));
+        assert!(set.remove(&facts[0]));
+        assert!(!set.contains(&facts[0]));
+        assert_eq!(FACT_HASHES.with(|c| c.get()), 2);
+    }
+
+    #[test]
+    fn hash_collisions() {
+        let mut syms = SymbolTable::new();
+        let a = fact(syms.insert("a"), &[&int(0)]);
+        let b = fact(syms.insert("b"), &[&int(1)]);
+        let c = fact(syms.insert("c"), &[&int(2)]);
+
+        let mut bucket = Hashed::default();
+        assert!(bucket.insert(1, a.clone()));
+        assert!(bucket.insert(1, b.clone()));
+        assert!(!bucket.insert(1, b.clone()));
+        assert!(bucket.insert(1, c.clone()));
+        assert!(bucket.contains(1, &a) && bucket.contains(1, &b) && bucket.contains(1, &c));
+        assert!(!bucket.contains(2, &a));
+
+        assert!(bucket.remove(1, &a));
+        assert!(!bucket.contains(1, &a));
+        assert!(bucket.contains(1, &b) && bucket.contains(1, &c));
+        assert_eq!(bucket.facts.len() + bucket.collisions.len(), 2);
+
+        bucket.retain(|f| *f == c);
+        assert_eq!(bucket.facts.get(&1), Some(&c));
+        assert!(bucket.collisions.is_empty());
+    }
+}
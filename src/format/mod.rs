@@ -0,0 +1,463 @@
+//! conversion of facts, rules and caveats to the protobuf messages
+//! embedded in biscuit tokens, see `schema.proto`
+//!
+//! the `token_*` functions convert to the messages, the `proto_*_to_token`
+//! ones convert back and fail on missing fields or unknown enum values
+use std::convert::TryFrom;
+use prost::Message;
+
+use super::{AggregateOp, Binary, BytesConstraint, Caveat, Constraint, ConstraintKind, DateConstraint,
+  Expression, Fact, IntConstraint, Op, Predicate, Rule, SetHandle, StrConstraint, SymbolConstraint, SymbolTable,
+  Unary, ID};
+use crate::error;
+
+pub mod schema;
+
+/// the content of a token block
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Block {
+    pub symbols: SymbolTable,
+    pub facts: Vec<Fact>,
+    pub rules: Vec<Rule>,
+    pub caveats: Vec<Caveat>,
+}
+
+impl Block {
+    pub fn to_proto(&self) -> schema::Block {
+        schema::Block {
+            symbols: self.symbols.symbols.clone(),
+            facts_v1: self.facts.iter().map(token_fact).collect(),
+            rules_v1: self.rules.iter().map(token_rule).collect(),
+            caveats_v1: self.caveats.iter().map(token_caveat).collect(),
+        }
+    }
+
+    pub fn from_proto(block: &schema::Block) -> Result<Self, error::Format> {
+        Ok(Block {
+            symbols: SymbolTable::from_symbols(block.symbols.clone()).map_err(error::Format::Symbol)?,
+            facts: block.facts_v1.iter().map(proto_fact_to_token).collect::<Result<_, _>>()?,
+            rules: block.rules_v1.iter().map(proto_rule_to_token).collect::<Result<_, _>>()?,
+            caveats: block.caveats_v1.iter().map(proto_caveat_to_token).collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        self.to_proto().encode_to_vec()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, error::Format> {
+        let block = schema::Block::decode(bytes).map_err(|e| error::Format::Decode(e.to_string()))?;
+        Block::from_proto(&block)
+    }
+}
+
+pub fn token_fact(f: &Fact) -> schema::FactV1 {
+    schema::FactV1 { predicate: token_predicate(&f.predicate) }
+}
+
+pub fn proto_fact_to_token(f: &schema::FactV1) -> Result<Fact, error::Format> {
+    Ok(Fact { predicate: proto_predicate_to_token(&f.predicate)? })
+}
+
+pub fn token_rule(r: &Rule) -> schema::RuleV1 {
+    schema::RuleV1 {
+        head: token_predicate(&r.head),
+        body: r.body.iter().map(token_predicate).collect(),
+        constraints: r.constraints.iter().map(token_constraint).collect(),
+        expressions: r.expressions.iter().map(token_expression).collect(),
+        negated: r.negated.iter().map(token_predicate).collect(),
+        aggregate: r.aggregate.as_ref().map(|(op, variable)| {
+            let kind = match op {
+                AggregateOp::Count => schema::aggregate::Kind::Count,
+                AggregateOp::Sum => schema::aggregate::Kind::Sum,
+                AggregateOp::Min => schema::aggregate::Kind::Min,
+                AggregateOp::Max => schema::aggregate::Kind::Max,
+            };
+            schema::Aggregate { kind: kind as i32, variable: *variable }
+        }),
+    }
+}
+
+pub fn proto_rule_to_token(r: &schema::RuleV1) -> Result<Rule, error::Format> {
+    let aggregate = match &r.aggregate {
+        None => None,
+        Some(a) => {
+            let op = match schema::aggregate::Kind::try_from(a.kind) {
+                Ok(schema::aggregate::Kind::Count) => AggregateOp::Count,
+                Ok(schema::aggregate::Kind::Sum) => AggregateOp::Sum,
+                Ok(schema::aggregate::Kind::Min) => AggregateOp::Min,
+                Ok(schema::aggregate::Kind::Max) => AggregateOp::Max,
+                Err(_) => return Err(error::Format::UnknownEnumValue { field: "Aggregate.kind", value: a.kind }),
+            };
+            Some((op, a.variable))
+        },
+    };
+
+    Ok(Rule {
+        head: proto_predicate_to_token(&r.head)?,
+        body: r.body.iter().map(proto_predicate_to_token).collect::<Result<_, _>>()?,
+        constraints: r.constraints.iter().map(proto_constraint_to_token).collect::<Result<_, _>>()?,
+        expressions: r.expressions.iter().map(proto_expression_to_token).collect::<Result<_, _>>()?,
+        negated: r.negated.iter().map(proto_predicate_to_token).collect::<Result<_, _>>()?,
+        aggregate,
+    })
+}
+
+pub fn token_caveat(c: &Caveat) -> schema::CaveatV1 {
+    schema::CaveatV1 { queries: c.queries.iter().map(token_rule).collect() }
+}
+
+pub fn proto_caveat_to_token(c: &schema::CaveatV1) -> Result<Caveat, error::Format> {
+    Ok(Caveat { queries: c.queries.iter().map(proto_rule_to_token).collect::<Result<_, _>>()? })
+}
+
+pub fn token_predicate(p: &Predicate) -> schema::PredicateV1 {
+    schema::PredicateV1 { name: p.name, ids: p.ids.iter().map(token_id).collect() }
+}
+
+pub fn proto_predicate_to_token(p: &schema::PredicateV1) -> Result<Predicate, error::Format> {
+    let ids = p.ids.iter().map(proto_id_to_token).collect::<Result<Vec<_>, _>>()?;
+    Ok(Predicate::new(p.name, &ids))
+}
+
+pub fn token_id(id: &ID) -> schema::Idv1 {
+    use schema::idv1::Content;
+
+    let content = match id {
+        ID::Symbol(s) => Content::Symbol(*s),
+        ID::Variable(v) => Content::Variable(*v),
+        ID::Integer(i) => Content::Integer(*i),
+        ID::Str(s) => Content::Str(s.clone()),
+        ID::Date(d) => Content::Date(*d),
+        ID::Bytes(b) => Content::Bytes(b.clone()),
+        ID::Bool(b) => Content::Bool(*b),
+        ID::Set(s) => Content::Set(schema::IdSet { set: s.iter().map(token_id).collect() }),
+        ID::Wildcard => Content::Wildcard(schema::Empty {}),
+    };
+    schema::Idv1 { content: Some(content) }
+}
+
+pub fn proto_id_to_token(id: &schema::Idv1) -> Result<ID, error::Format> {
+    use schema::idv1::Content;
+
+    Ok(match &id.content {
+        None => return Err(error::Format::MissingField("IDV1.Content")),
+        Some(Content::Symbol(s)) => ID::Symbol(*s),
+        Some(Content::Variable(v)) => ID::Variable(*v),
+        Some(Content::Integer(i)) => ID::Integer(*i),
+        Some(Content::Str(s)) => ID::Str(s.clone()),
+        Some(Content::Date(d)) => ID::Date(*d),
+        Some(Content::Bytes(b)) => ID::Bytes(b.clone()),
+        Some(Content::Bool(b)) => ID::Bool(*b),
+        Some(Content::Set(s)) => ID::Set(s.set.iter().map(proto_id_to_token).collect::<Result<_, _>>()?),
+        Some(Content::Wildcard(_)) => ID::Wildcard,
+    })
+}
+
+pub fn token_constraint(c: &Constraint) -> schema::ConstraintV1 {
+    use schema::constraint_v1::Constraint as Kind;
+    use schema::{bytes_constraint_v1 as b, date_constraint_v1 as d, int_constraint_v1 as i,
+      string_constraint_v1 as s, symbol_constraint_v1 as y};
+
+    // the sets of named sets are sorted so that the same constraint is
+    // always encoded the same way
+    fn sorted<T: Ord + Clone>(set: &std::collections::HashSet<T>) -> Vec<T> {
+        let mut v = set.iter().cloned().collect::<Vec<_>>();
+        v.sort();
+        v
+    }
+
+    let constraint = match &c.kind {
+        ConstraintKind::Int(c) => Kind::Int(schema::IntConstraintV1 { constraint: Some(match c {
+            IntConstraint::LessThan(v) => i::Constraint::LessThan(*v),
+            IntConstraint::GreaterThan(v) => i::Constraint::GreaterThan(*v),
+            IntConstraint::LessOrEqual(v) => i::Constraint::LessOrEqual(*v),
+            IntConstraint::GreaterOrEqual(v) => i::Constraint::GreaterOrEqual(*v),
+            IntConstraint::Equal(v) => i::Constraint::Equal(*v),
+            IntConstraint::In(set) => i::Constraint::InSet(schema::IntSet { set: set.iter().cloned().collect() }),
+            IntConstraint::NotIn(set) => i::Constraint::NotInSet(schema::IntSet { set: set.iter().cloned().collect() }),
+        }) }),
+        ConstraintKind::Str(c) => Kind::Str(schema::StringConstraintV1 { constraint: Some(match c {
+            StrConstraint::Prefix(v) => s::Constraint::Prefix(v.clone()),
+            StrConstraint::Suffix(v) => s::Constraint::Suffix(v.clone()),
+            StrConstraint::Equal(v) => s::Constraint::Equal(v.clone()),
+            StrConstraint::Regex(v) => s::Constraint::Regex(v.clone()),
+            StrConstraint::In(set) => s::Constraint::InSet(schema::StringSet { set: set.iter().cloned().collect() }),
+            StrConstraint::NotIn(set) => s::Constraint::NotInSet(schema::StringSet { set: set.iter().cloned().collect() }),
+        }) }),
+        ConstraintKind::Date(c) => Kind::Date(schema::DateConstraintV1 { constraint: Some(match c {
+            DateConstraint::Before(v) => d::Constraint::Before(*v),
+            DateConstraint::After(v) => d::Constraint::After(*v),
+        }) }),
+        ConstraintKind::Symbol(c) => Kind::Symbol(schema::SymbolConstraintV1 { constraint: Some(match c {
+            SymbolConstraint::In(set) => y::Constraint::InSet(schema::SymbolSet { set: set.iter().cloned().collect() }),
+            SymbolConstraint::NotIn(set) => y::Constraint::NotInSet(schema::SymbolSet { set: set.iter().cloned().collect() }),
+        }) }),
+        ConstraintKind::Bytes(c) => Kind::Bytes(schema::BytesConstraintV1 { constraint: Some(match c {
+            BytesConstraint::Equal(v) => b::Constraint::Equal(v.clone()),
+            BytesConstraint::In(set) => b::Constraint::InSet(schema::BytesSet { set: set.iter().cloned().collect() }),
+            BytesConstraint::NotIn(set) => b::Constraint::NotInSet(schema::BytesSet { set: set.iter().cloned().collect() }),
+            BytesConstraint::InSet(h) => b::Constraint::InNamedSet(schema::NamedBytesSet { name: h.name(), set: sorted(h.set()) }),
+            BytesConstraint::NotInSet(h) => {
+                b::Constraint::NotInNamedSet(schema::NamedBytesSet { name: h.name(), set: sorted(h.set()) })
+            },
+        }) }),
+    };
+
+    schema::ConstraintV1 { id: c.id, constraint: Some(constraint) }
+}
+
+pub fn proto_constraint_to_token(c: &schema::ConstraintV1) -> Result<Constraint, error::Format> {
+    use schema::constraint_v1::Constraint as Kind;
+    use schema::{bytes_constraint_v1 as b, date_constraint_v1 as d, int_constraint_v1 as i,
+      string_constraint_v1 as s, symbol_constraint_v1 as y};
+
+    let missing = |field| error::Format::MissingField(field);
+    let kind = match c.constraint.as_ref().ok_or(missing("ConstraintV1.Constraint"))? {
+        Kind::Int(c) => ConstraintKind::Int(match c.constraint.as_ref().ok_or(missing("IntConstraintV1.Constraint"))? {
+            i::Constraint::LessThan(v) => IntConstraint::LessThan(*v),
+            i::Constraint::GreaterThan(v) => IntConstraint::GreaterThan(*v),
+            i::Constraint::LessOrEqual(v) => IntConstraint::LessOrEqual(*v),
+            i::Constraint::GreaterOrEqual(v) => IntConstraint::GreaterOrEqual(*v),
+            i::Constraint::Equal(v) => IntConstraint::Equal(*v),
+            i::Constraint::InSet(set) => IntConstraint::In(set.set.iter().cloned().collect()),
+            i::Constraint::NotInSet(set) => IntConstraint::NotIn(set.set.iter().cloned().collect()),
+        }),
+        Kind::Str(c) => ConstraintKind::Str(match c.constraint.as_ref().ok_or(missing("StringConstraintV1.Constraint"))? {
+            s::Constraint::Prefix(v) => StrConstraint::Prefix(v.clone()),
+            s::Constraint::Suffix(v) => StrConstraint::Suffix(v.clone()),
+            s::Constraint::Equal(v) => StrConstraint::Equal(v.clone()),
+            s::Constraint::Regex(v) => StrConstraint::Regex(v.clone()),
+            s::Constraint::InSet(set) => StrConstraint::In(set.set.iter().cloned().collect()),
+            s::Constraint::NotInSet(set) => StrConstraint::NotIn(set.set.iter().cloned().collect()),
+        }),
+        Kind::Date(c) => ConstraintKind::Date(match c.constraint.as_ref().ok_or(missing("DateConstraintV1.Constraint"))? {
+            d::Constraint::Before(v) => DateConstraint::Before(*v),
+            d::Constraint::After(v) => DateConstraint::After(*v),
+        }),
+        Kind::Symbol(c) => ConstraintKind::Symbol(match c.constraint.as_ref().ok_or(missing("SymbolConstraintV1.Constraint"))? {
+            y::Constraint::InSet(set) => SymbolConstraint::In(set.set.iter().cloned().collect()),
+            y::Constraint::NotInSet(set) => SymbolConstraint::NotIn(set.set.iter().cloned().collect()),
+        }),
+        Kind::Bytes(c) => ConstraintKind::Bytes(match c.constraint.as_ref().ok_or(missing("BytesConstraintV1.Constraint"))? {
+            b::Constraint::Equal(v) => BytesConstraint::Equal(v.clone()),
+            b::Constraint::InSet(set) => BytesConstraint::In(set.set.iter().cloned().collect()),
+            b::Constraint::NotInSet(set) => BytesConstraint::NotIn(set.set.iter().cloned().collect()),
+            b::Constraint::InNamedSet(set) => BytesConstraint::InSet(SetHandle::new(set.name, set.set.iter().cloned())),
+            b::Constraint::NotInNamedSet(set) => BytesConstraint::NotInSet(SetHandle::new(set.name, set.set.iter().cloned())),
+        }),
+    };
+
+    Ok(Constraint { id: c.id, kind })
+}
+
+pub fn token_expression(e: &Expression) -> schema::ExpressionV1 {
+    use schema::op::Content;
+    use schema::op_binary::Kind;
+
+    let ops = e.ops.iter().map(|op| {
+        let content = match op {
+            Op::Value(id) => Content::Value(token_id(id)),
+            Op::Unary(Unary::Negate) => Content::Unary(schema::OpUnary { kind: schema::op_unary::Kind::Negate as i32 }),
+            Op::Binary(binary) => {
+                let kind = match binary {
+                    Binary::LessThan => Kind::LessThan,
+                    Binary::GreaterThan => Kind::GreaterThan,
+                    Binary::LessOrEqual => Kind::LessOrEqual,
+                    Binary::GreaterOrEqual => Kind::GreaterOrEqual,
+                    Binary::Equal => Kind::Equal,
+                    Binary::In => Kind::In,
+                    Binary::NotIn => Kind::NotIn,
+                    Binary::Prefix => Kind::Prefix,
+                    Binary::Suffix => Kind::Suffix,
+                    Binary::Regex => Kind::Regex,
+                    Binary::Add => Kind::Add,
+                    Binary::And => Kind::And,
+                };
+                Content::Binary(schema::OpBinary { kind: kind as i32 })
+            },
+            Op::Bind(v) => Content::Bind(*v),
+        };
+        schema::Op { content: Some(content) }
+    }).collect();
+
+    schema::ExpressionV1 { ops }
+}
+
+pub fn proto_expression_to_token(e: &schema::ExpressionV1) -> Result<Expression, error::Format> {
+    use schema::op::Content;
+    use schema::op_binary::Kind;
+
+    let ops = e.ops.iter().map(|op| Ok(match &op.content {
+        None => return Err(error::Format::MissingField("Op.Content")),
+        Some(Content::Value(id)) => Op::Value(proto_id_to_token(id)?),
+        Some(Content::Unary(u)) => match schema::op_unary::Kind::try_from(u.kind) {
+            Ok(schema::op_unary::Kind::Negate) => Op::Unary(Unary::Negate),
+            Err(_) => return Err(error::Format::UnknownEnumValue { field: "OpUnary.kind", value: u.kind }),
+        },
+        Some(Content::Binary(b)) => Op::Binary(match Kind::try_from(b.kind) {
+            Ok(Kind::LessThan) => Binary::LessThan,
+            Ok(Kind::GreaterThan) => Binary::GreaterThan,
+            Ok(Kind::LessOrEqual) => Binary::LessOrEqual,
+            Ok(Kind::GreaterOrEqual) => Binary::GreaterOrEqual,
+            Ok(Kind::Equal) => Binary::Equal,
+            Ok(Kind::In) => Binary::In,
+            Ok(Kind::NotIn) => Binary::NotIn,
+            Ok(Kind::Prefix) => Binary::Prefix,
+            Ok(Kind::Suffix) => Binary::Suffix,
+            Ok(Kind::Regex) => Binary::Regex,
+            Ok(Kind::Add) => Binary::Add,
+            Ok(Kind::And) => Binary::And,
+            Err(_) => return Err(error::Format::UnknownEnumValue { field: "OpBinary.kind", value: b.kind }),
+        }),
+        Some(Content::Bind(v)) => Op::Bind(*v),
+    })).collect::<Result<_, _>>()?;
+
+    Ok(Expression { ops })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_caveat, parse_fact, parse_rule};
+    use std::collections::BTreeSet;
+
+    /// a caveat and a fact, encoded by hand from `schema.proto`
+    const FIXTURE: &[u8] = &[
+        // symbols: "authority", "right", "read", "caveat", "0"
+        0x12, 0x09, 0x61, 0x75, 0x74, 0x68, 0x6f, 0x72, 0x69, 0x74, 0x79, 0x12, 0x05, 0x72, 0x69, 0x67,
+        0x68, 0x74, 0x12, 0x04, 0x72, 0x65, 0x61, 0x64, 0x12, 0x06, 0x63, 0x61, 0x76, 0x65, 0x61, 0x74,
+        0x12, 0x01, 0x30,
+        // facts_v1: right(#authority, "file1", #read)
+        0x42, 0x15, 0x0a, 0x13, 0x08, 0x01, 0x12, 0x02, 0x08, 0x00, 0x12, 0x07, 0x22,
+        0x05, 0x66, 0x69, 0x6c, 0x65, 0x31, 0x12, 0x02, 0x08, 0x02,
+        // caveats_v1: one query, with its head, body, constraint and expression
+        0x52, 0x3f, 0x0a, 0x3d, 0x0a, 0x06,
+        0x08, 0x03, 0x12, 0x02, 0x10, 0x04, 0x12, 0x0e, 0x08, 0x01, 0x12, 0x02, 0x08, 0x00, 0x12, 0x02,
+        0x10, 0x04, 0x12, 0x02, 0x08, 0x02, 0x1a, 0x0a, 0x08, 0x04, 0x1a, 0x06, 0x0a, 0x04, 0x66, 0x69,
+        0x6c, 0x65, 0x22, 0x17, 0x0a, 0x04, 0x0a, 0x02, 0x10, 0x04, 0x0a, 0x09, 0x0a, 0x07, 0x22, 0x05,
+        0x66, 0x69, 0x6c, 0x65, 0x31, 0x0a, 0x04, 0x1a, 0x02, 0x08, 0x04,
+    ];
+
+    fn round_trip_rule(r: &Rule) {
+        let proto = token_rule(r);
+        assert_eq!(&proto_rule_to_token(&proto).unwrap(), r);
+        let decoded = schema::RuleV1::decode(proto.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(&proto_rule_to_token(&decoded).unwrap(), r);
+    }
+
+    #[test]
+    fn fixture() {
+        let symbols = ["authority", "right", "read", "caveat", "0"].iter().map(|s| s.to_string()).collect();
+        let mut syms = SymbolTable::from_symbols(symbols).unwrap();
+        let fact = parse_fact(r#"right(#authority, "file1", #read)"#, &mut syms).unwrap();
+        let caveat = parse_caveat(
+            r#"caveat($0) <- right(#authority, $0, #read) @ $0 matches "file"* | $0 == "file1""#,
+            &mut syms,
+        ).unwrap();
+        assert_eq!(syms.len(), 5);
+
+        let block = Block { symbols: syms, facts: vec![fact], rules: vec![], caveats: vec![caveat] };
+        assert_eq!(block.encode(), FIXTURE);
+        assert_eq!(Block::decode(FIXTURE), Ok(block));
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut syms = SymbolTable::with_defaults();
+
+        let terms = parse_fact(
+            r#"terms(#read, -12, "str", 2020-12-21T09:23:12+00:00, hex:00ff, true, false, [#read, 1, "a", hex:01], [], _)"#,
+            &mut syms,
+        ).unwrap();
+        assert_eq!(proto_fact_to_token(&token_fact(&terms)), Ok(terms.clone()));
+
+        let rules = [
+            "grandparent($a, $c) <- parent($a, $b), parent($b, $c)",
+            "orphan($p) <- person($p), !parent(_, $p)",
+            "children($p, count($c)) <- parent($p, $c)",
+            "total(sum($amount)) <- payment(_, $amount)",
+            "first(min($d)) <- event($d)",
+            "last(max($d)) <- event($d)",
+            "ints($i) <- int($i) @ $i < 1, $i > -2, $i <= 3, $i >= 4, $i == 5, $i in [-1, 0, 2], $i not in [7]",
+            "strs($s) <- str($s) @ $s matches \"pre\"*, $s matches *\"suf\", $s == \"eq\", $s matches /^a+$/",
+            "strs($s) <- str($s) @ $s in [\"a\", \"b\"], $s not in [\"c\"]",
+            "dates($d) <- date($d) @ $d <= 2020-12-21T09:23:12+00:00, $d >= 1970-01-01T00:00:00+00:00",
+            "syms($s) <- sym($s) @ $s in [#read, #write], $s not in [#authority]",
+            "bytes($b) <- data($b) @ $b == hex:0102, $b in [hex:, hex:ff], $b not in [hex:00]",
+            "exprs($a) <- x($a, $b) | -(5 + -4) < $a, $a > 0, $a <= 1, $a >= 2, $a in [1, 2] && $b not in [3]",
+            "strs($s) <- x($s) | $s matches \"pre\"*, $s matches *\"suf\", $s matches /\"^a+$\"/, $s == \"a\" + \"b\"",
+            "computed($c) <- x($a, $b) | $c = $a + $b",
+        ];
+        for input in rules.iter() {
+            round_trip_rule(&parse_rule(input, &mut syms).unwrap());
+        }
+
+        let caveat = parse_caveat("c1($0) <- resource(#ambient, $0) || c2() <- operation(#ambient, #read)", &mut syms).unwrap();
+        assert_eq!(proto_caveat_to_token(&token_caveat(&caveat)), Ok(caveat.clone()));
+
+        let block = Block {
+            symbols: syms.clone(),
+            facts: vec![terms],
+            rules: rules.iter().map(|r| parse_rule(r, &mut syms.clone()).unwrap()).collect(),
+            caveats: vec![caveat],
+        };
+        assert_eq!(Block::decode(&block.encode()), Ok(block));
+
+        // sets are encoded in order
+        let c = |set: &[i64]| Constraint { id: 0, kind: ConstraintKind::Int(IntConstraint::In(set.iter().cloned().collect::<BTreeSet<_>>())) };
+        assert_eq!(token_constraint(&c(&[3, 1, 2])).encode_to_vec(), token_constraint(&c(&[1, 2, 3])).encode_to_vec());
+    }
+
+    #[test]
+    fn invalid() {
+        let binary = |kind| schema::ExpressionV1 { ops: vec![schema::Op {
+            content: Some(schema::op::Content::Binary(schema::OpBinary { kind })),
+        }] };
+        assert_eq!(
+            proto_expression_to_token(&binary(12)),
+            Err(error::Format::UnknownEnumValue { field: "OpBinary.kind", value: 12 })
+        );
+        assert!(proto_expression_to_token(&binary(11)).is_ok());
+
+        let unary = schema::ExpressionV1 { ops: vec![schema::Op {
+            content: Some(schema::op::Content::Unary(schema::OpUnary { kind: -1 })),
+        }] };
+        assert_eq!(
+            proto_expression_to_token(&unary),
+            Err(error::Format::UnknownEnumValue { field: "OpUnary.kind", value: -1 })
+        );
+
+        let mut rule = token_rule(&parse_rule("total(sum($a)) <- payment($a)", &mut SymbolTable::new()).unwrap());
+        rule.aggregate.as_mut().unwrap().kind = 4;
+        assert_eq!(
+            proto_rule_to_token(&rule),
+            Err(error::Format::UnknownEnumValue { field: "Aggregate.kind", value: 4 })
+        );
+
+        // unknown values survive decoding and are reported by the conversion
+        let bytes = binary(42).encode_to_vec();
+        let decoded = schema::ExpressionV1::decode(bytes.as_slice()).unwrap();
+        assert_eq!(
+            proto_expression_to_token(&decoded),
+            Err(error::Format::UnknownEnumValue { field: "OpBinary.kind", value: 42 })
+        );
+
+        let fact = schema::FactV1 { predicate: schema::PredicateV1 { name: 0, ids: vec![schema::Idv1 { content: None }] } };
+        assert_eq!(proto_fact_to_token(&fact), Err(error::Format::MissingField("IDV1.Content")));
+        let constraint = schema::ConstraintV1 { id: 0, constraint: None };
+        assert_eq!(proto_constraint_to_token(&constraint), Err(error::Format::MissingField("ConstraintV1.Constraint")));
+        let constraint = schema::ConstraintV1 {
+            id: 0,
+            constraint: Some(schema::constraint_v1::Constraint::Date(schema::DateConstraintV1 { constraint: None })),
+        };
+        assert_eq!(proto_constraint_to_token(&constraint), Err(error::Format::MissingField("DateConstraintV1.Constraint")));
+
+        assert!(matches!(Block::decode(&FIXTURE[..FIXTURE.len() - 1]), Err(error::Format::Decode(_))));
+        let duplicate = schema::Block { symbols: vec!["a".to_string(), "a".to_string()], ..Default::default() };
+        assert_eq!(
+            Block::decode(&duplicate.encode_to_vec()),
+            Err(error::Format::Symbol(error::Symbol::Duplicate("a".to_string())))
+        );
+    }
+}
@@ -0,0 +1,334 @@
+//! messages of `schema.proto`, as generated by prost
+//!
+//! enumerations are stored as `i32` so that unknown values can be
+//! reported by the conversion functions
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Block {
+    #[prost(string, repeated, tag = "2")]
+    pub symbols: Vec<String>,
+    #[prost(message, repeated, tag = "8")]
+    pub facts_v1: Vec<FactV1>,
+    #[prost(message, repeated, tag = "9")]
+    pub rules_v1: Vec<RuleV1>,
+    #[prost(message, repeated, tag = "10")]
+    pub caveats_v1: Vec<CaveatV1>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FactV1 {
+    #[prost(message, required, tag = "1")]
+    pub predicate: PredicateV1,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RuleV1 {
+    #[prost(message, required, tag = "1")]
+    pub head: PredicateV1,
+    #[prost(message, repeated, tag = "2")]
+    pub body: Vec<PredicateV1>,
+    #[prost(message, repeated, tag = "3")]
+    pub constraints: Vec<ConstraintV1>,
+    #[prost(message, repeated, tag = "4")]
+    pub expressions: Vec<ExpressionV1>,
+    #[prost(message, repeated, tag = "5")]
+    pub negated: Vec<PredicateV1>,
+    #[prost(message, optional, tag = "6")]
+    pub aggregate: Option<Aggregate>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Aggregate {
+    #[prost(enumeration = "aggregate::Kind", required, tag = "1")]
+    pub kind: i32,
+    #[prost(uint32, required, tag = "2")]
+    pub variable: u32,
+}
+
+pub mod aggregate {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Kind {
+        Count = 0,
+        Sum = 1,
+        Min = 2,
+        Max = 3,
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CaveatV1 {
+    #[prost(message, repeated, tag = "1")]
+    pub queries: Vec<RuleV1>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PredicateV1 {
+    #[prost(uint64, required, tag = "1")]
+    pub name: u64,
+    #[prost(message, repeated, tag = "2")]
+    pub ids: Vec<Idv1>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Idv1 {
+    #[prost(oneof = "idv1::Content", tags = "1, 2, 3, 4, 5, 6, 7, 8, 9")]
+    pub content: Option<idv1::Content>,
+}
+
+pub mod idv1 {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Content {
+        #[prost(uint64, tag = "1")]
+        Symbol(u64),
+        #[prost(uint32, tag = "2")]
+        Variable(u32),
+        #[prost(int64, tag = "3")]
+        Integer(i64),
+        #[prost(string, tag = "4")]
+        Str(String),
+        #[prost(uint64, tag = "5")]
+        Date(u64),
+        #[prost(bytes = "vec", tag = "6")]
+        Bytes(Vec<u8>),
+        #[prost(bool, tag = "7")]
+        Bool(bool),
+        #[prost(message, tag = "8")]
+        Set(super::IdSet),
+        #[prost(message, tag = "9")]
+        Wildcard(super::Empty),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct IdSet {
+    #[prost(message, repeated, tag = "1")]
+    pub set: Vec<Idv1>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Empty {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ConstraintV1 {
+    #[prost(uint32, required, tag = "1")]
+    pub id: u32,
+    #[prost(oneof = "constraint_v1::Constraint", tags = "2, 3, 4, 5, 6")]
+    pub constraint: Option<constraint_v1::Constraint>,
+}
+
+pub mod constraint_v1 {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Constraint {
+        #[prost(message, tag = "2")]
+        Int(super::IntConstraintV1),
+        #[prost(message, tag = "3")]
+        Str(super::StringConstraintV1),
+        #[prost(message, tag = "4")]
+        Date(super::DateConstraintV1),
+        #[prost(message, tag = "5")]
+        Symbol(super::SymbolConstraintV1),
+        #[prost(message, tag = "6")]
+        Bytes(super::BytesConstraintV1),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct IntConstraintV1 {
+    #[prost(oneof = "int_constraint_v1::Constraint", tags = "1, 2, 3, 4, 5, 6, 7")]
+    pub constraint: Option<int_constraint_v1::Constraint>,
+}
+
+pub mod int_constraint_v1 {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Constraint {
+        #[prost(int64, tag = "1")]
+        LessThan(i64),
+        #[prost(int64, tag = "2")]
+        GreaterThan(i64),
+        #[prost(int64, tag = "3")]
+        LessOrEqual(i64),
+        #[prost(int64, tag = "4")]
+        GreaterOrEqual(i64),
+        #[prost(int64, tag = "5")]
+        Equal(i64),
+        #[prost(message, tag = "6")]
+        InSet(super::IntSet),
+        #[prost(message, tag = "7")]
+        NotInSet(super::IntSet),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct IntSet {
+    #[prost(int64, repeated, packed = "true", tag = "1")]
+    pub set: Vec<i64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StringConstraintV1 {
+    #[prost(oneof = "string_constraint_v1::Constraint", tags = "1, 2, 3, 4, 5, 6")]
+    pub constraint: Option<string_constraint_v1::Constraint>,
+}
+
+pub mod string_constraint_v1 {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Constraint {
+        #[prost(string, tag = "1")]
+        Prefix(String),
+        #[prost(string, tag = "2")]
+        Suffix(String),
+        #[prost(string, tag = "3")]
+        Equal(String),
+        #[prost(message, tag = "4")]
+        InSet(super::StringSet),
+        #[prost(message, tag = "5")]
+        NotInSet(super::StringSet),
+        #[prost(string, tag = "6")]
+        Regex(String),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StringSet {
+    #[prost(string, repeated, tag = "1")]
+    pub set: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DateConstraintV1 {
+    #[prost(oneof = "date_constraint_v1::Constraint", tags = "1, 2")]
+    pub constraint: Option<date_constraint_v1::Constraint>,
+}
+
+pub mod date_constraint_v1 {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Constraint {
+        #[prost(uint64, tag = "1")]
+        Before(u64),
+        #[prost(uint64, tag = "2")]
+        After(u64),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SymbolConstraintV1 {
+    #[prost(oneof = "symbol_constraint_v1::Constraint", tags = "1, 2")]
+    pub constraint: Option<symbol_constraint_v1::Constraint>,
+}
+
+pub mod symbol_constraint_v1 {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Constraint {
+        #[prost(message, tag = "1")]
+        InSet(super::SymbolSet),
+        #[prost(message, tag = "2")]
+        NotInSet(super::SymbolSet),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SymbolSet {
+    #[prost(uint64, repeated, packed = "true", tag = "1")]
+    pub set: Vec<u64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BytesConstraintV1 {
+    #[prost(oneof = "bytes_constraint_v1::Constraint", tags = "1, 2, 3, 4, 5")]
+    pub constraint: Option<bytes_constraint_v1::Constraint>,
+}
+
+pub mod bytes_constraint_v1 {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Constraint {
+        #[prost(bytes = "vec", tag = "1")]
+        Equal(Vec<u8>),
+        #[prost(message, tag = "2")]
+        InSet(super::BytesSet),
+        #[prost(message, tag = "3")]
+        NotInSet(super::BytesSet),
+        #[prost(message, tag = "4")]
+        InNamedSet(super::NamedBytesSet),
+        #[prost(message, tag = "5")]
+        NotInNamedSet(super::NamedBytesSet),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BytesSet {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub set: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct NamedBytesSet {
+    #[prost(uint64, required, tag = "1")]
+    pub name: u64,
+    #[prost(bytes = "vec", repeated, tag = "2")]
+    pub set: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExpressionV1 {
+    #[prost(message, repeated, tag = "1")]
+    pub ops: Vec<Op>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Op {
+    #[prost(oneof = "op::Content", tags = "1, 2, 3, 4")]
+    pub content: Option<op::Content>,
+}
+
+pub mod op {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Content {
+        #[prost(message, tag = "1")]
+        Value(super::Idv1),
+        #[prost(message, tag = "2")]
+        Unary(super::OpUnary),
+        #[prost(message, tag = "3")]
+        Binary(super::OpBinary),
+        #[prost(uint32, tag = "4")]
+        Bind(u32),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct OpUnary {
+    #[prost(enumeration = "op_unary::Kind", required, tag = "1")]
+    pub kind: i32,
+}
+
+pub mod op_unary {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Kind {
+        Negate = 0,
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct OpBinary {
+    #[prost(enumeration = "op_binary::Kind", required, tag = "1")]
+    pub kind: i32,
+}
+
+pub mod op_binary {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Kind {
+        LessThan = 0,
+        GreaterThan = 1,
+        LessOrEqual = 2,
+        GreaterOrEqual = 3,
+        Equal = 4,
+        In = 5,
+        NotIn = 6,
+        Prefix = 7,
+        Suffix = 8,
+        Regex = 9,
+        Add = 10,
+        And = 11,
+    }
+}
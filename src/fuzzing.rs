@@ -0,0 +1,404 @@
+//! generation of values for fuzzing, with the `arbitrary` feature
+//!
+//! the `Arbitrary` implementations use the default `Bounds`, which keep
+//! the values small and the symbols and variables in a narrow range, so
+//! that generated rules often match the generated facts. A `cargo fuzz`
+//! target only needs to call `evaluate_fuzz_input`:
+//!
+//! ```ignore
+//! fuzz_target!(|data: &[u8]| {
+//!     let _ = datalog::fuzzing::evaluate_fuzz_input(data);
+//! });
+//! ```
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use arbitrary::{Arbitrary, Unstructured};
+
+use super::{
+    AggregateOp, Binary, BytesConstraint, Constraint, ConstraintKind, DateConstraint, Expression, Fact,
+    IntConstraint, Op, Predicate, Rule, RuleErrors, RunLimits, RunStats, StrConstraint, Symbol, SymbolConstraint,
+    Unary, World, ID,
+};
+use crate::error;
+
+/// sizes and ranges of the generated values
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bounds {
+    /// symbols are taken from `0..symbols`, for values and predicate names
+    pub symbols: Symbol,
+    /// variables are taken from `0..variables`
+    pub variables: u32,
+    /// maximum number of terms of a predicate
+    pub terms: usize,
+    /// maximum number of elements of sets, strings and byte arrays
+    pub elements: usize,
+    /// maximum number of body and negated predicates of a rule
+    pub body: usize,
+    /// maximum number of constraints and expressions of a rule
+    pub constraints: usize,
+    /// maximum number of operations of an expression
+    pub ops: usize,
+    /// maximum number of facts and rules of a `FuzzInput`
+    pub world: usize,
+}
+
+impl Default for Bounds {
+    fn default() -> Self {
+        Bounds {
+            symbols: 4,
+            variables: 4,
+            terms: 3,
+            elements: 3,
+            body: 3,
+            constraints: 2,
+            ops: 5,
+            world: 8,
+        }
+    }
+}
+
+/// characters of the generated strings, they make both valid and
+/// invalid regexes
+const CHARS: [char; 6] = ['a', 'b', '(', '[', '*', '.'];
+
+impl Bounds {
+    fn collect<T>(
+        &self,
+        u: &mut Unstructured,
+        max: usize,
+        mut f: impl FnMut(&Self, &mut Unstructured) -> arbitrary::Result<T>,
+    ) -> arbitrary::Result<Vec<T>> {
+        let len = u.int_in_range(0..=max)?;
+        (0..len).map(|_| f(self, u)).collect()
+    }
+
+    pub fn symbol(&self, u: &mut Unstructured) -> arbitrary::Result<Symbol> {
+        u.int_in_range(0..=self.symbols.saturating_sub(1))
+    }
+
+    pub fn variable(&self, u: &mut Unstructured) -> arbitrary::Result<u32> {
+        u.int_in_range(0..=self.variables.saturating_sub(1))
+    }
+
+    /// small integers, and the extremes to find overflows
+    pub fn integer(&self, u: &mut Unstructured) -> arbitrary::Result<i64> {
+        Ok(*u.choose(&[i64::MIN, -1, 0, 1, 2, i64::MAX])?)
+    }
+
+    pub fn string(&self, u: &mut Unstructured) -> arbitrary::Result<String> {
+        self.collect(u, self.elements, |_, u| u.choose(&CHARS).copied()).map(|chars| chars.into_iter().collect())
+    }
+
+    pub fn bytes(&self, u: &mut Unstructured) -> arbitrary::Result<Vec<u8>> {
+        self.collect(u, self.elements, |_, u| u.arbitrary())
+    }
+
+    fn set<T: Ord>(
+        &self,
+        u: &mut Unstructured,
+        f: impl FnMut(&Self, &mut Unstructured) -> arbitrary::Result<T>,
+    ) -> arbitrary::Result<BTreeSet<T>> {
+        self.collect(u, self.elements, f).map(|values| values.into_iter().collect())
+    }
+
+    /// a value that can be stored in a fact, without variables
+    pub fn value(&self, u: &mut Unstructured) -> arbitrary::Result<ID> {
+        if u.ratio(1, 5)? {
+            let set = self.collect(u, self.elements, Bounds::leaf)?;
+            Ok(ID::Set(set.into_iter().collect::<BTreeSet<_>>()))
+        } else {
+            self.leaf(u)
+        }
+    }
+
+    fn leaf(&self, u: &mut Unstructured) -> arbitrary::Result<ID> {
+        Ok(match u.int_in_range(0..=5)? {
+            0 => ID::Symbol(self.symbol(u)?),
+            1 => ID::Integer(self.integer(u)?),
+            2 => ID::Str(self.string(u)?),
+            3 => ID::Date(*u.choose(&[0, 1, u64::MAX])?),
+            4 => ID::Bytes(self.bytes(u)?),
+            _ => ID::Bool(u.arbitrary()?),
+        })
+    }
+
+    /// a value, a variable or a wildcard
+    pub fn term(&self, u: &mut Unstructured) -> arbitrary::Result<ID> {
+        Ok(match u.int_in_range(0..=4)? {
+            0 | 1 => self.value(u)?,
+            2 | 3 => ID::Variable(self.variable(u)?),
+            _ => ID::Wildcard,
+        })
+    }
+
+    pub fn predicate(&self, u: &mut Unstructured) -> arbitrary::Result<Predicate> {
+        let name = self.symbol(u)?;
+        let ids = self.collect(u, self.terms, Bounds::term)?;
+        Ok(Predicate::new(name, &ids))
+    }
+
+    pub fn fact(&self, u: &mut Unstructured) -> arbitrary::Result<Fact> {
+        let name = self.symbol(u)?;
+        let ids = self.collect(u, self.terms, Bounds::value)?;
+        Ok(Fact::new(name, &ids))
+    }
+
+    pub fn constraint(&self, u: &mut Unstructured) -> arbitrary::Result<Constraint> {
+        let id = self.variable(u)?;
+        let kind = match u.int_in_range(0..=4)? {
+            0 => ConstraintKind::Int(match u.int_in_range(0..=6)? {
+                0 => IntConstraint::LessThan(self.integer(u)?),
+                1 => IntConstraint::GreaterThan(self.integer(u)?),
+                2 => IntConstraint::LessOrEqual(self.integer(u)?),
+                3 => IntConstraint::GreaterOrEqual(self.integer(u)?),
+                4 => IntConstraint::Equal(self.integer(u)?),
+                5 => IntConstraint::In(self.set(u, Bounds::integer)?),
+                _ => IntConstraint::NotIn(self.set(u, Bounds::integer)?),
+            }),
+            1 => ConstraintKind::Str(match u.int_in_range(0..=5)? {
+                0 => StrConstraint::Prefix(self.string(u)?),
+                1 => StrConstraint::Suffix(self.string(u)?),
+                2 => StrConstraint::Equal(self.string(u)?),
+                3 => StrConstraint::In(self.set(u, Bounds::string)?),
+                4 => StrConstraint::NotIn(self.set(u, Bounds::string)?),
+                _ => StrConstraint::Regex(self.string(u)?),
+            }),
+            2 => ConstraintKind::Date(if u.arbitrary()? {
+                DateConstraint::Before(u.arbitrary()?)
+            } else {
+                DateConstraint::After(u.arbitrary()?)
+            }),
+            3 => ConstraintKind::Symbol(if u.arbitrary()? {
+                SymbolConstraint::In(self.set(u, Bounds::symbol)?)
+            } else {
+                SymbolConstraint::NotIn(self.set(u, Bounds::symbol)?)
+            }),
+            _ => ConstraintKind::Bytes(match u.int_in_range(0..=2)? {
+                0 => BytesConstraint::Equal(self.bytes(u)?),
+                1 => BytesConstraint::In(self.set(u, Bounds::bytes)?),
+                _ => BytesConstraint::NotIn(self.set(u, Bounds::bytes)?),
+            }),
+        };
+        Ok(Constraint { id, kind })
+    }
+
+    /// any sequence of operations, most of them leave an invalid stack
+    pub fn op(&self, u: &mut Unstructured) -> arbitrary::Result<Op> {
+        Ok(match u.int_in_range(0..=7)? {
+            0..=3 => Op::Value(self.term(u)?),
+            4 => Op::Unary(Unary::Negate),
+            5 | 6 => Op::Binary(u.arbitrary()?),
+            _ => Op::Bind(self.variable(u)?),
+        })
+    }
+
+    pub fn expression(&self, u: &mut Unstructured) -> arbitrary::Result<Expression> {
+        Ok(Expression { ops: self.collect(u, self.ops, Bounds::op)? })
+    }
+
+    pub fn rule(&self, u: &mut Unstructured) -> arbitrary::Result<Rule> {
+        Ok(Rule {
+            head: self.predicate(u)?,
+            body: self.collect(u, self.body, Bounds::predicate)?,
+            constraints: self.collect(u, self.constraints, Bounds::constraint)?,
+            expressions: self.collect(u, self.constraints, Bounds::expression)?,
+            negated: self.collect(u, self.body, Bounds::predicate)?,
+            aggregate: if u.ratio(1, 4)? { Some((u.arbitrary()?, self.variable(u)?)) } else { None },
+        })
+    }
+
+    pub fn input(&self, u: &mut Unstructured) -> arbitrary::Result<FuzzInput> {
+        Ok(FuzzInput {
+            facts: self.collect(u, self.world, Bounds::fact)?,
+            rules: self.collect(u, self.world, Bounds::rule)?,
+            rule: self.rule(u)?,
+            rule_errors: if u.arbitrary()? { RuleErrors::Fatal } else { RuleErrors::Skip },
+        })
+    }
+}
+
+macro_rules! arbitrary_with_bounds {
+    ($($t:ty => $f:ident),*) => {
+        $(
+            impl<'a> Arbitrary<'a> for $t {
+                fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+                    Bounds::default().$f(u)
+                }
+            }
+        )*
+    };
+}
+
+arbitrary_with_bounds!(
+    ID => term,
+    Predicate => predicate,
+    Fact => fact,
+    Constraint => constraint,
+    Op => op,
+    Expression => expression,
+    Rule => rule,
+    FuzzInput => input
+);
+
+impl<'a> Arbitrary<'a> for Binary {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(u.choose(&[
+            Binary::LessThan,
+            Binary::GreaterThan,
+            Binary::LessOrEqual,
+            Binary::GreaterOrEqual,
+            Binary::Equal,
+            Binary::In,
+            Binary::NotIn,
+            Binary::Prefix,
+            Binary::Suffix,
+            Binary::Regex,
+            Binary::Add,
+            Binary::And,
+        ])?.clone())
+    }
+}
+
+impl<'a> Arbitrary<'a> for AggregateOp {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(u.choose(&[AggregateOp::Count, AggregateOp::Sum, AggregateOp::Min, AggregateOp::Max])?.clone())
+    }
+}
+
+/// a world and a rule decoded by `evaluate_fuzz_input`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuzzInput {
+    pub facts: Vec<Fact>,
+    pub rules: Vec<Rule>,
+    /// applied on its own to the facts of the world after the run
+    pub rule: Rule,
+    pub rule_errors: RuleErrors,
+}
+
+/// limits of the runs of `evaluate_fuzz_input`, they are reached quickly
+/// so that the fuzzer does not mistake a large run for a hang
+pub fn fuzz_limits() -> RunLimits {
+    RunLimits {
+        max_facts: Some(256),
+        max_iterations: Some(16),
+        max_time: Some(Duration::from_secs(1)),
+        max_candidates: 10_000,
+        ..Default::default()
+    }
+}
+
+/// decodes a `FuzzInput` from `data`, runs its world with `fuzz_limits`,
+/// then applies its rule to the resulting facts
+///
+/// errors are expected: the goal is to find panics and runs that do not
+/// stop within the limits. Inputs too short to be decoded make an empty
+/// world
+pub fn evaluate_fuzz_input(data: &[u8]) -> Result<RunStats, error::RunStopped> {
+    let input = match FuzzInput::arbitrary_take_rest(Unstructured::new(data)) {
+        Ok(input) => input,
+        Err(_) => return Ok(RunStats::default()),
+    };
+    let limits = RunLimits { rule_errors: input.rule_errors, ..fuzz_limits() };
+
+    let mut world = World::new();
+    for fact in input.facts {
+        world.add_fact(fact);
+    }
+    for rule in input.rules {
+        world.add_rule(rule);
+    }
+    let res = world.run_with_stats(limits.clone());
+
+    let mut new_facts = Vec::new();
+    let _ = input.rule.apply_with_limits(&world.facts, &mut new_facts, &limits);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Caveat, HashMap, SymbolTable};
+    use proptest::prelude::*;
+    use proptest::test_runner::Config;
+
+    proptest! {
+        #![proptest_config(Config { cases: 2000, failure_persistence: None, ..Config::default() })]
+
+        /// no input makes the engine panic or go over its limits
+        #[test]
+        fn within_limits(data in proptest::collection::vec(any::<u8>(), 0..1024)) {
+            let limits = fuzz_limits();
+            let stats = match evaluate_fuzz_input(&data) {
+                Ok(stats) => stats,
+                Err(stopped) => *stopped.stats,
+            };
+            prop_assert!(stats.iterations.len() <= limits.max_iterations.unwrap() as usize);
+            prop_assert!(stats.rules.iter().map(|r| r.new).sum::<usize>() <= limits.max_facts.unwrap() as usize);
+        }
+    }
+
+    proptest! {
+        #![proptest_config(Config { cases: 256, failure_persistence: None, ..Config::default() })]
+
+        /// malformed rules and facts make typed errors in the whole
+        /// pipeline, from printing and parsing to the serialization of
+        /// the world
+        #[test]
+        fn no_panics(data in proptest::collection::vec(any::<u8>(), 0..1024)) {
+            let input = FuzzInput::arbitrary_take_rest(Unstructured::new(&data)).unwrap();
+            // the last generated symbol is out of the table
+            let mut syms = SymbolTable::from_symbols(vec!["p0".to_string(), "p1".to_string(), "a".to_string()]).unwrap();
+            let mut w = World::new();
+            for fact in input.facts {
+                w.add_fact(fact);
+            }
+            let rules = input.rules.iter().chain(Some(&input.rule)).collect::<Vec<_>>();
+            for r in rules.iter() {
+                w.add_rule((*r).clone());
+                let printed = syms.print_rule(r);
+                let _ = crate::parser::with_symbols(&mut syms, || printed.parse::<Rule>());
+            }
+            let _ = syms.check_world(&w);
+
+            let limits = RunLimits { rule_errors: input.rule_errors, ..fuzz_limits() };
+            let caveats = rules.iter().map(|r| Caveat { queries: vec![(*r).clone()] }).collect::<Vec<_>>();
+            if let Err(failed) = w.check_caveats(&caveats, Some(&limits), &HashMap::new(), &syms) {
+                for f in failed {
+                    let _ = syms.print_error(&f);
+                }
+            }
+
+            let mut run = w.clone();
+            if let Err(e) = run.run_with_limits(limits) {
+                let fatal = input.rule_errors == RuleErrors::Fatal;
+                prop_assert!(fatal || !matches!(e, error::Error::RuleFailed(_)), "{}", e);
+                let _ = syms.print_error(&e);
+            }
+            let _ = syms.print_world(&run);
+
+            let mut bytes = Vec::new();
+            run.serialize_into(&syms, &mut bytes).unwrap();
+            prop_assert!(World::deserialize_from(&mut bytes.as_slice()).is_ok());
+        }
+    }
+
+    #[test]
+    fn bounds() {
+        let data = (0..=255u8).cycle().take(4096).collect::<Vec<_>>();
+        let bounds = Bounds { symbols: 1, variables: 1, terms: 2, ..Bounds::default() };
+        let mut u = Unstructured::new(&data);
+        for _ in 0..64 {
+            let rule = bounds.rule(&mut u).unwrap();
+            for p in rule.body.iter().chain(Some(&rule.head)) {
+                assert_eq!(p.name, 0);
+                assert!(p.ids.len() <= 2);
+                assert!(p.ids.iter().all(|id| !matches!(id, ID::Variable(v) if *v != 0)));
+            }
+        }
+
+        // exhausted data still makes values
+        let mut u = Unstructured::new(&[]);
+        assert_eq!(FuzzInput::arbitrary(&mut u).unwrap().facts, Vec::new());
+    }
+}
@@ -1,27 +1,114 @@
 //! Logic language implementation for caveats
-use std::collections::{HashMap, HashSet, BTreeSet};
-use std::convert::AsRef;
-use std::fmt;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use regex::Regex;
+//!
+//! without the default `std` feature, the crate is `no_std` and only needs
+//! `alloc`: regexes never match (see the `regex` feature), the binary
+//! format and the thread-local symbol table of the `parser` are not
+//! available, and time limits need a `time::Clock`
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::convert::{AsRef, TryFrom};
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::ops::ControlFlow;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+use smallvec::SmallVec;
+use sync::{Mutex, OnceLock};
+use time::Instant;
+
+#[cfg(feature = "std")]
+pub(crate) use std::collections::{hash_map, hash_map::RandomState, HashMap, HashSet};
+#[cfg(not(feature = "std"))]
+pub(crate) use hashbrown::{hash_map, DefaultHashBuilder as RandomState, HashMap, HashSet};
 
 pub type Symbol = u64;
+mod sync;
+pub mod time;
+mod trace;
 mod symbol;
 mod expression;
+mod builder;
+mod macros;
+mod factset;
+mod results;
+mod report;
+mod evaluation_trace;
+pub mod parser;
+pub mod workloads;
+#[cfg(feature = "protobuf")]
+pub mod format;
+#[cfg(feature = "serde")]
+pub mod testvectors;
+#[cfg(feature = "serde")]
+mod serialization;
+#[cfg(feature = "std")]
+mod binary;
+#[cfg(feature = "arbitrary")]
+pub mod fuzzing;
 pub mod error;
+pub use factset::{FactSet, Matching};
+pub use results::{ResultSet, Row, Rows};
+pub use report::{CaveatFailureReport, QueryReport};
+pub use evaluation_trace::{EvaluationTrace, TraceOutcome, TraceStep};
+#[cfg(feature = "serde")]
+pub use serialization::JSON_VERSION;
+#[cfg(feature = "std")]
+pub use binary::BINARY_VERSION;
 pub use symbol::*;
 pub use expression::*;
+pub use builder::*;
 
 #[derive(Debug, Clone, PartialEq, Hash, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ID {
     Symbol(Symbol),
     Variable(u32),
     Integer(i64),
     Str(String),
-    Date(u64),
-    Bytes(Vec<u8>),
+    Date(#[cfg_attr(feature = "serde", serde(with = "serialization::date"))] u64),
+    Bytes(#[cfg_attr(feature = "serde", serde(with = "serialization::bytes"))] Vec<u8>),
     Bool(bool),
     Set(BTreeSet<ID>),
+    /// matches any term in a body predicate, without binding it
+    Wildcard,
+}
+
+/// type of a term, see `World::declare_schema`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TermType {
+    Symbol,
+    Integer,
+    Str,
+    Date,
+    Bytes,
+    Bool,
+    Set,
+}
+
+impl TermType {
+    /// as returned by `ID::type_name`
+    pub fn name(&self) -> &'static str {
+        match self {
+            TermType::Symbol => "symbol",
+            TermType::Integer => "integer",
+            TermType::Str => "string",
+            TermType::Date => "date",
+            TermType::Bytes => "bytes",
+            TermType::Bool => "boolean",
+            TermType::Set => "set",
+        }
+    }
 }
 
 impl From<&ID> for ID {
@@ -35,29 +122,157 @@ impl From<&ID> for ID {
             ID::Bytes(ref b) => ID::Bytes(b.clone()),
             ID::Bool(ref b) => ID::Bool(*b),
             ID::Set(ref s) => ID::Set(s.clone()),
+            ID::Wildcard => ID::Wildcard,
+        }
+    }
+}
+
+impl From<i64> for ID {
+    fn from(i: i64) -> Self {
+        ID::Integer(i)
+    }
+}
+
+impl From<&str> for ID {
+    fn from(s: &str) -> Self {
+        ID::Str(s.to_string())
+    }
+}
+
+impl From<bool> for ID {
+    fn from(b: bool) -> Self {
+        ID::Bool(b)
+    }
+}
+
+impl<const N: usize> From<&[u8; N]> for ID {
+    fn from(b: &[u8; N]) -> Self {
+        ID::Bytes(b.to_vec())
+    }
+}
+
+/// implements `TryFrom<&ID>` for the Rust type of a variant, see
+/// `ResultSet::typed_column`
+macro_rules! try_from_id {
+    ($($ty:ty, $name:literal: $variant:ident($value:ident) => $converted:expr;)*) => {
+        $(
+            impl TryFrom<&ID> for $ty {
+                type Error = crate::error::Conversion;
+
+                fn try_from(id: &ID) -> Result<Self, Self::Error> {
+                    match id {
+                        ID::$variant($value) => Ok($converted),
+                        id => Err(crate::error::Conversion::InvalidType { expected: $name, found: id.type_name() }),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+try_from_id!(
+    i64, "integer": Integer(i) => *i;
+    String, "string": Str(s) => s.clone();
+    bool, "boolean": Bool(b) => *b;
+    Vec<u8>, "bytes": Bytes(b) => b.clone();
+    BTreeSet<ID>, "set": Set(s) => s.clone();
+);
+
+impl ID {
+    /// `None` for variables and wildcards
+    pub fn term_type(&self) -> Option<TermType> {
+        match self {
+            ID::Symbol(_) => Some(TermType::Symbol),
+            ID::Integer(_) => Some(TermType::Integer),
+            ID::Str(_) => Some(TermType::Str),
+            ID::Date(_) => Some(TermType::Date),
+            ID::Bytes(_) => Some(TermType::Bytes),
+            ID::Bool(_) => Some(TermType::Bool),
+            ID::Set(_) => Some(TermType::Set),
+            ID::Variable(_) | ID::Wildcard => None,
+        }
+    }
+
+    /// name of the variant, as used in conversion errors
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ID::Symbol(_) => "symbol",
+            ID::Variable(_) => "variable",
+            ID::Integer(_) => "integer",
+            ID::Str(_) => "string",
+            ID::Date(_) => "date",
+            ID::Bytes(_) => "bytes",
+            ID::Bool(_) => "boolean",
+            ID::Set(_) => "set",
+            ID::Wildcard => "wildcard",
+        }
+    }
+
+    /// size in bytes of the strings and byte arrays contained in this term
+    pub fn payload_size(&self) -> usize {
+        match self {
+            ID::Str(s) => s.len(),
+            ID::Bytes(b) => b.len(),
+            ID::Set(s) => s.iter().map(|id| id.payload_size()).sum(),
+            _ => 0,
+        }
+    }
+
+    /// replaces the symbols and variables found in `table`, see
+    /// `World::merge_translated`
+    pub fn translate(&self, table: &HashMap<Symbol, Symbol>) -> ID {
+        match self {
+            ID::Symbol(s) => ID::Symbol(translate_symbol(table, *s)),
+            ID::Variable(v) => ID::Variable(translate_variable(table, *v)),
+            ID::Set(s) => ID::Set(s.iter().map(|id| id.translate(table)).collect()),
+            id => id.clone(),
         }
     }
 }
 
+pub(crate) fn translate_symbol(table: &HashMap<Symbol, Symbol>, s: Symbol) -> Symbol {
+    table.get(&s).cloned().unwrap_or(s)
+}
+
+/// variables are named with symbols, so they are translated with the same table
+pub(crate) fn translate_variable(table: &HashMap<Symbol, Symbol>, v: u32) -> u32 {
+    translate_symbol(table, v as Symbol) as u32
+}
+
 impl AsRef<ID> for ID {
     fn as_ref(&self) -> &ID {
         self
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+#[derive(Debug, Clone, PartialEq, Hash, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Predicate {
     pub name: Symbol,
-    pub ids: Vec<ID>,
+    /// stored inline up to 4 terms, which covers most predicates
+    pub ids: SmallVec<[ID; 4]>,
 }
 
 impl Predicate {
     pub fn new(name: Symbol, ids: &[ID]) -> Predicate {
         Predicate {
             name,
-            ids: ids.to_vec(),
+            ids: SmallVec::from(ids),
+        }
+    }
+
+    pub fn translate(&self, table: &HashMap<Symbol, Symbol>) -> Predicate {
+        Predicate {
+            name: translate_symbol(table, self.name),
+            ids: self.ids.iter().map(|id| id.translate(table)).collect(),
         }
     }
+
+    /// the same predicate named `ns::name`, the terms are unchanged
+    pub fn namespaced(&self, symbols: &mut SymbolTable, ns: &str) -> Predicate {
+        let name = symbols.print_symbol(self.name);
+        Predicate { name: symbols.insert_namespaced(ns, &name), ids: self.ids.clone() }
+    }
 }
 
 impl AsRef<Predicate> for Predicate {
@@ -66,7 +281,8 @@ impl AsRef<Predicate> for Predicate {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+#[derive(Debug, Clone, PartialEq, Hash, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Fact {
     pub predicate: Predicate,
 }
@@ -77,16 +293,149 @@ impl Fact {
             predicate: Predicate::new(name, ids),
         }
     }
+
+    pub fn translate(&self, table: &HashMap<Symbol, Symbol>) -> Fact {
+        Fact { predicate: self.predicate.translate(table) }
+    }
+
+    /// see `Predicate::namespaced`
+    pub fn namespaced(&self, symbols: &mut SymbolTable, ns: &str) -> Fact {
+        Fact { predicate: self.predicate.namespaced(symbols, ns) }
+    }
+
+    /// moves the fact to the table the remapping was built for, see
+    /// `SymbolTable::extend`
+    pub fn remap_symbols(&self, remapping: &SymbolRemapping) -> Fact {
+        self.translate(remapping.table())
+    }
+
+    /// like `remap_symbols`, but fails if the fact uses a symbol missing
+    /// from the remapping's source table, instead of keeping it as is
+    pub fn try_remap_symbols(&self, remapping: &SymbolRemapping) -> Result<Fact, crate::error::Merge> {
+        remapping.check_fact(self)?;
+        Ok(self.remap_symbols(remapping))
+    }
+
+    /// checks that the fact can be stored: its terms, and the values of
+    /// its sets, must not be variables or wildcards
+    pub fn validate(&self) -> Result<(), crate::error::InvalidFact> {
+        fn check(id: &ID) -> Result<(), crate::error::InvalidFact> {
+            match id {
+                ID::Variable(v) => Err(crate::error::InvalidFact::Variable(*v)),
+                ID::Wildcard => Err(crate::error::InvalidFact::Wildcard),
+                ID::Set(s) => s.iter().try_for_each(check),
+                _ => Ok(()),
+            }
+        }
+        self.predicate.ids.iter().try_for_each(check)
+    }
+
+    /// checks the fact against the schema of its predicate, if there is
+    /// one in `schemas`, see `World::declare_schema`
+    pub fn validate_schemas(&self, schemas: &HashMap<Symbol, Vec<TermType>>) -> Result<(), crate::error::Schema> {
+        check_schema(&self.predicate, schemas, &mut HashMap::new())
+    }
+
+    /// size in bytes of the strings and byte arrays in the fact's terms
+    pub fn weight(&self) -> usize {
+        self.predicate.ids.iter().map(|id| id.payload_size()).sum()
+    }
+
+    /// approximate memory used by the fact: the size of its strings and
+    /// byte arrays, plus the size of each term
+    pub fn estimated_size(&self) -> usize {
+        self.predicate.ids.iter().map(|id| core::mem::size_of::<ID>() + id.payload_size()).sum()
+    }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// measures of a rule's evaluation cost and size, see `Rule::complexity`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RuleComplexity {
+    /// body and negated predicates
+    pub predicates: usize,
+    pub constraints: usize,
+    /// ops across all expressions
+    pub expression_ops: usize,
+    /// size in bytes of the strings and byte arrays used in the rule
+    pub payload_size: usize,
+}
+
+impl RuleComplexity {
+    /// true if any of the measures is above the one in `max`
+    pub fn exceeds(&self, max: &RuleComplexity) -> bool {
+        self.predicates > max.predicates
+            || self.constraints > max.constraints
+            || self.expression_ops > max.expression_ops
+            || self.payload_size > max.payload_size
+    }
+}
+
+/// rules are compared structurally: the order of body predicates,
+/// constraints and expressions matters
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Rule {
     pub head: Predicate,
     pub body: Vec<Predicate>,
+    /// checked as soon as the variable they apply to is bound
+    pub constraints: Vec<Constraint>,
     pub expressions: Vec<Expression>,
+    /// the rule only matches if no fact matches these predicates, once
+    /// the variables from the body are bound
+    pub negated: Vec<Predicate>,
+    /// aggregates the values bound to a variable: the head's other
+    /// variables form the grouping key, and the result replaces the
+    /// aggregated variable in the head
+    pub aggregate: Option<(AggregateOp, u32)>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AggregateOp {
+    Count,
+    Sum,
+    Min,
+    Max,
+}
+
+impl AggregateOp {
+    /// aggregates the values of a group, returns None if the operation
+    /// is not supported for the values' types
+    pub fn aggregate(&self, values: &[ID]) -> Option<ID> {
+        match self {
+            AggregateOp::Count => Some(ID::Integer(values.len() as i64)),
+            AggregateOp::Sum => values.iter().try_fold(0i64, |sum, id| match id {
+                ID::Integer(i) => sum.checked_add(*i),
+                _ => None,
+            }).map(ID::Integer),
+            AggregateOp::Min | AggregateOp::Max => {
+                let same_type = values.iter().all(|id| matches!(id, ID::Integer(_)))
+                    || values.iter().all(|id| matches!(id, ID::Date(_)));
+                if !same_type {
+                    return None;
+                }
+
+                if *self == AggregateOp::Min {
+                    values.iter().min().cloned()
+                } else {
+                    values.iter().max().cloned()
+                }
+            },
+        }
+    }
+
+    pub fn print(&self, value: String) -> String {
+        match self {
+            AggregateOp::Count => format!("count({})", value),
+            AggregateOp::Sum => format!("sum({})", value),
+            AggregateOp::Min => format!("min({})", value),
+            AggregateOp::Max => format!("max({})", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Constraint {
     pub id: u32,
     pub kind: ConstraintKind,
@@ -98,7 +447,8 @@ impl AsRef<Constraint> for Constraint {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ConstraintKind {
     Int(IntConstraint),
     Str(StrConstraint),
@@ -107,54 +457,141 @@ pub enum ConstraintKind {
     Bytes(BytesConstraint),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum IntConstraint {
     LessThan(i64),
     GreaterThan(i64),
     LessOrEqual(i64),
     GreaterOrEqual(i64),
     Equal(i64),
-    In(HashSet<i64>),
-    NotIn(HashSet<i64>),
+    In(BTreeSet<i64>),
+    NotIn(BTreeSet<i64>),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum StrConstraint {
     Prefix(String),
     Suffix(String),
     Equal(String),
-    In(HashSet<String>),
-    NotIn(HashSet<String>),
+    In(BTreeSet<String>),
+    NotIn(BTreeSet<String>),
     Regex(String),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DateConstraint {
-    Before(u64),
-    After(u64),
+    Before(#[cfg_attr(feature = "serde", serde(with = "serialization::date"))] u64),
+    After(#[cfg_attr(feature = "serde", serde(with = "serialization::date"))] u64),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SymbolConstraint {
-    In(HashSet<u64>),
-    NotIn(HashSet<u64>),
+    In(BTreeSet<u64>),
+    NotIn(BTreeSet<u64>),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BytesConstraint {
-    Equal(Vec<u8>),
-    In(HashSet<Vec<u8>>),
-    NotIn(HashSet<Vec<u8>>),
+    Equal(#[cfg_attr(feature = "serde", serde(with = "serialization::bytes"))] Vec<u8>),
+    In(#[cfg_attr(feature = "serde", serde(with = "serialization::bytes_set"))] BTreeSet<Vec<u8>>),
+    NotIn(#[cfg_attr(feature = "serde", serde(with = "serialization::bytes_set"))] BTreeSet<Vec<u8>>),
+    /// like `In`, with a set shared with a world, see `World::add_revocation_set`
+    InSet(SetHandle),
+    NotInSet(SetHandle),
+}
+
+/// set of byte strings stored once in a world, and shared with the
+/// constraints that use it, see `World::add_revocation_set`
+///
+/// cloning it is cheap. It is serialized with its name and content, so a
+/// deserialized rule has its own copy of the set
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SetHandle {
+    name: Symbol,
+    #[cfg_attr(feature = "serde", serde(with = "serialization::shared_bytes_set"))]
+    set: Arc<HashSet<Vec<u8>>>,
+}
+
+impl SetHandle {
+    pub fn new<I: IntoIterator<Item = Vec<u8>>>(name: Symbol, ids: I) -> Self {
+        SetHandle { name, set: Arc::new(ids.into_iter().collect()) }
+    }
+
+    pub fn name(&self) -> Symbol {
+        self.name
+    }
+
+    pub fn contains(&self, id: &[u8]) -> bool {
+        self.set.contains(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.set.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
+
+    /// the same set under another name
+    pub fn with_name(&self, name: Symbol) -> Self {
+        SetHandle { name, set: self.set.clone() }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.set.iter()
+    }
+
+    pub fn set(&self) -> &HashSet<Vec<u8>> {
+        &self.set
+    }
+}
+
+/// handles are equal if they have the same name and content
+impl PartialEq for SetHandle {
+    fn eq(&self, other: &SetHandle) -> bool {
+        self.name == other.name && (Arc::ptr_eq(&self.set, &other.set) || self.set == other.set)
+    }
+}
+
+impl Eq for SetHandle {}
+
+/// only hashes the name and size, to stay fast with large sets
+impl Hash for SetHandle {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.set.len().hash(state);
+    }
 }
 
 impl Constraint {
+    /// a value that `check` rejects because of its type, when it is likely
+    /// the same data with another representation: an integer timestamp
+    /// checked by a date constraint, or a date by an integer constraint.
+    /// Reported as a warning by the runs, see `World::run_with_diagnostics`
+    pub fn type_mismatch(&self, id: &ID) -> Option<crate::error::Evaluation> {
+        let (expected, found) = match (&self.kind, id) {
+            (ConstraintKind::Date(_), ID::Integer(_)) => (TermType::Date, TermType::Integer),
+            (ConstraintKind::Int(_), ID::Date(_)) => (TermType::Integer, TermType::Date),
+            _ => return None,
+        };
+        Some(crate::error::Evaluation::ConstraintType { variable: self.id, expected, found })
+    }
+
+    /// true if `name` is not the constrained variable. Values of the wrong
+    /// type, variables and wildcards never match
     pub fn check(&self, name: u32, id: &ID) -> bool {
         if name != self.id {
             return true;
         }
 
         match (id, &self.kind) {
-            (ID::Variable(_), _) => panic!("should not check constraint on a variable"),
             (ID::Integer(i), ConstraintKind::Int(c)) => match c {
                 IntConstraint::LessThan(j) => *i < *j,
                 IntConstraint::GreaterThan(j) => *i > *j,
@@ -168,14 +605,8 @@ impl Constraint {
                 StrConstraint::Prefix(pref) => s.as_str().starts_with(pref.as_str()),
                 StrConstraint::Suffix(suff) => s.as_str().ends_with(suff.as_str()),
                 StrConstraint::Equal(s2) => s == s2,
-                StrConstraint::Regex(r) => {
-                  if let Some(re) = Regex::new(r).ok() {
-                    re.is_match(s)
-                  } else {
-                    // an invalid regex will never match
-                    false
-                  }
-                },
+                // an invalid regex will never match
+                StrConstraint::Regex(r) => regex_match(r, s).unwrap_or(false),
                 StrConstraint::In(h) => h.contains(s),
                 StrConstraint::NotIn(h) => !h.contains(s),
             },
@@ -191,6 +622,8 @@ impl Constraint {
                 BytesConstraint::Equal(s2) => s == s2,
                 BytesConstraint::In(h) => h.contains(s),
                 BytesConstraint::NotIn(h) => !h.contains(s),
+                BytesConstraint::InSet(h) => h.contains(s),
+                BytesConstraint::NotInSet(h) => !h.contains(s),
             },
             _ => false,
         }
@@ -204,10 +637,95 @@ impl AsRef<Expression> for Expression {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Caveat {
     pub queries: Vec<Rule>,
 }
 
+/// facts seen by the caveat queries, see `World::check_caveat_with_scope`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum CaveatScope {
+    /// the facts currently in the world, the default: facts the rules
+    /// would derive are only seen if the world was run before
+    #[default]
+    AsIs,
+    /// runs a snapshot of the world to fixpoint first, the world itself
+    /// is not modified
+    AfterRun(RunLimits),
+}
+
+/// ordered allow or deny rule of an authorizer, see `World::evaluate_policies`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Policy {
+    /// the policy matches if any of the queries produces a fact
+    pub queries: Vec<Rule>,
+    pub kind: PolicyKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PolicyKind {
+    Allow,
+    Deny,
+}
+
+/// the first policy that matched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolicyDecision {
+    /// position of the policy in the list
+    pub index: usize,
+    pub kind: PolicyKind,
+}
+
+/// the query that verified a caveat, returned by `Caveat::check`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaveatSuccess {
+    /// index of the first query that produced facts
+    pub query: usize,
+    /// number of facts it produced
+    pub facts: usize,
+}
+
+impl Caveat {
+    /// caveat that succeeds if either one succeeds: the queries are a
+    /// disjunction, so the lists are concatenated
+    pub fn or(mut self, other: Caveat) -> Caveat {
+        self.queries.extend(other.queries);
+        self
+    }
+
+    /// both caveats must succeed. A conjunction of queries cannot be
+    /// expressed as a single caveat, so they are kept separate, to be
+    /// checked with `World::check_caveats`
+    pub fn and(self, other: Caveat) -> Vec<Caveat> {
+        vec![self, other]
+    }
+
+    /// checks the caveat against a set of facts, outside of a `World`. The
+    /// queries are tried in order until one of them produces a fact
+    ///
+    /// unlike `World::check_caveat`, the set is not checked for completeness
+    pub fn check(
+        &self,
+        facts: &FactSet,
+        limits: &RunLimits,
+        symbols: &SymbolTable,
+    ) -> Result<CaveatSuccess, crate::error::Caveat> {
+        for (query, rule) in self.queries.iter().enumerate() {
+            let mut found = Vec::new();
+            rule.apply_with_limits(facts, &mut found, limits)?;
+            if !found.is_empty() {
+                return Ok(CaveatSuccess { query, facts: found.len() });
+            }
+        }
+
+        Err(crate::error::Caveat::NoMatchingQuery(
+            self.queries.iter().map(|q| symbols.print_rule(q)).collect(),
+        ))
+    }
+}
+
 
 impl fmt::Display for Fact {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -215,212 +733,905 @@ impl fmt::Display for Fact {
     }
 }
 
+impl AsRef<Rule> for Rule {
+    fn as_ref(&self) -> &Rule {
+        self
+    }
+}
+
 impl Rule {
-    pub fn apply(&self, facts: &HashSet<Fact>, new_facts: &mut Vec<Fact>) {
-        // gather all of the variables used in that rule
-        let variables_set = self
-            .body
-            .iter()
-            .flat_map(|pred| {
-                pred.ids
-                    .iter()
-                    .filter_map(|id| match id {
-                        ID::Variable(i) => Some(*i),
-                        _ => None,
-                    })
-            })
-            .collect::<HashSet<_>>();
+    pub fn apply(&self, facts: &FactSet, new_facts: &mut Vec<Fact>) {
+        let mut derived = Vec::new();
+        self.apply_with_bindings(facts, &mut derived);
+        new_facts.extend(derived.into_iter().map(|(fact, _)| fact));
+    }
 
-        let variables = MatchedVariables::new(variables_set);
-
-        new_facts.extend(
-            CombineIt::new(variables, &self.body, &self.expressions, facts).map(|h| {
-                let mut p = self.head.clone();
-                for index in 0..p.ids.len() {
-                    let value = match &p.ids[index] {
-                        ID::Variable(i) => match h.get(i) {
-                          Some(val) => val,
-                          None => {
-                            println!("error: variables that appear in the head should appear in the body and constraints as well");
-                            continue;
-                          }
-                        },
-                        _ => continue,
-                    };
+    /// like `apply`, but returns the variable bindings that produced each fact
+    ///
+    /// facts generated by an aggregate come from multiple bindings, so they
+    /// are returned with empty bindings
+    pub fn apply_with_bindings(&self, facts: &FactSet, new_facts: &mut Vec<(Fact, HashMap<u32, ID>)>) {
+        self.apply_budgeted(facts, new_facts, None, true, None, None);
+    }
 
-                    p.ids[index] = value.clone();
-                }
+    /// like `apply`, but with the variables of `initial` fixed to their
+    /// value, as if they were replaced in the rule. Variables that do not
+    /// appear in the body are ignored
+    pub fn apply_with_initial_bindings(&self, facts: &FactSet, initial: &HashMap<u32, ID>, new_facts: &mut Vec<Fact>) {
+        let mut derived = Vec::new();
+        self.apply_budgeted(facts, &mut derived, None, true, Some(initial), None);
+        new_facts.extend(derived.into_iter().map(|(fact, _)| fact));
+    }
 
-                Fact { predicate: p }
-            }),
-        );
+    /// like `apply`, but aborts once the rule examined too many candidate
+    /// facts, generated too many facts, or ran out of time. Matches that
+    /// cannot produce a fact are skipped, whatever `limits.rule_errors` is
+    pub fn apply_with_limits(
+        &self,
+        facts: &FactSet,
+        new_facts: &mut Vec<Fact>,
+        limits: &RunLimits,
+    ) -> Result<(), crate::error::RunLimit> {
+        let start = Instant::now(time::default_clock());
+        let limits = &RunLimits { rule_errors: RuleErrors::Skip, ..limits.clone() };
+        let budget = Budget::new(limits.max_facts.map_or(usize::MAX, |max| max as usize), limits, start);
+        let mut derived = Vec::new();
+        self.apply_budgeted(facts, &mut derived, Some(&budget), limits.plan_joins, None, None);
+        budget.check().map_err(|e| e.with_context(limit_context(0, derived.len(), start, None)))?;
+
+        new_facts.extend(derived.into_iter().map(|(fact, _)| fact));
+        Ok(())
     }
-}
 
-/// recursive iterator for rule application
-pub struct CombineIt<'a> {
-    variables: MatchedVariables,
-    predicates: &'a [Predicate],
-    expressions: &'a [Expression],
-    all_facts: &'a HashSet<Fact>,
-    current_facts: Box<dyn Iterator<Item = &'a Fact> + 'a>,
-    current_it: Option<Box<CombineIt<'a>>>,
-}
+    /// like `apply_with_limits`, but only counts the facts the rule would
+    /// generate, up to `limit`, see `World::query_rule_count`
+    pub(crate) fn count_with_limits(&self, facts: &FactSet, limits: &RunLimits, limit: usize) -> Result<usize, crate::error::RunLimit> {
+        let start = Instant::now(time::default_clock());
+        let limits = &RunLimits { rule_errors: RuleErrors::Skip, ..limits.clone() };
+        let budget = Budget::new(limits.max_facts.map_or(usize::MAX, |max| max as usize), limits, start);
+        let count = self.count_budgeted(facts, Some(&budget), limits.plan_joins, limit);
+        if count < limit {
+            budget.check().map_err(|e| e.with_context(limit_context(0, count, start, None)))?;
+        }
+        Ok(count)
+    }
 
-impl<'a> CombineIt<'a> {
-    pub fn new(
-        variables: MatchedVariables,
-        predicates: &'a [Predicate],
-        expressions: &'a [Expression],
-        facts: &'a HashSet<Fact>,
-    ) -> Self {
-        let p = predicates[0].clone();
-        CombineIt {
-            variables,
-            predicates,
-            expressions,
-            all_facts: facts,
-            current_facts: Box::new(
-                facts
-                    .iter()
-                    .filter(move |fact| match_preds(&fact.predicate, &p)),
-            ),
-            current_it: None,
+    /// stops at the `limit`th match, and does not build the facts, except
+    /// for aggregates: a group only generates a fact if its aggregate can
+    /// be computed
+    fn count_budgeted(&self, facts: &FactSet, budget: Option<&Budget<'_>>, plan: bool, limit: usize) -> usize {
+        if self.body.is_empty() || limit == 0 {
+            return 0;
+        }
+
+        if self.aggregate.is_some() {
+            let mut generated = Vec::new();
+            self.apply_budgeted(facts, &mut generated, budget, plan, None, None);
+            return generated.len().min(limit);
         }
+
+        // matches leaving a head variable unbound do not generate a fact
+        let binds_head = |bindings: &HashMap<u32, ID>| self.head.ids.iter().all(|id| match id {
+            ID::Variable(v) => bindings.contains_key(v),
+            _ => true,
+        });
+        self.with_matches(facts, budget, plan, None, |combinations| {
+            combinations.filter(|h| binds_head(h)).take(limit).count()
+        })
     }
-}
 
-impl<'a> Iterator for CombineIt<'a> {
-    type Item = HashMap<u32, ID>;
+    /// checks the size of the head values computed by the expressions, see
+    /// `RunLimits::max_term_size`
+    fn check_computed(&self, bindings: &HashMap<u32, ID>, budget: Option<&Budget<'_>>) -> Result<(), crate::error::Evaluation> {
+        let budget = match budget {
+            Some(budget) => budget,
+            None => return Ok(()),
+        };
+        for v in self.expressions.iter().filter_map(Expression::binding) {
+            if let (true, Some(id)) = (self.head.ids.contains(&ID::Variable(v)), bindings.get(&v)) {
+                budget.check_term_size(id)?;
+            }
+        }
+        Ok(())
+    }
 
-    fn next(&mut self) -> Option<HashMap<u32, ID>> {
-        // if we're the last iterator in the recursive chain, stop here
-        if self.predicates.is_empty() {
-            //return None;
-            //return self.variables.complete();
-            match self.variables.complete() {
-                None => return None,
-                // we got a complete set of variables, let's test the expressions
-                Some(variables) => {
-                    println!("predicates empty, will test variables: {:?}", variables);
-                    let mut valid = true;
-                    for e in self.expressions.iter() {
-                        match e.evaluate(&variables) {
-                            Some(ID::Bool(true)) => {},
-                            res => {
-                                println!("expr returned {:?}", res);
-                                valid = false;
-                                break;
-                            },
-                        }
-                    }
+    /// the head with its variables replaced by their value in `bindings`,
+    /// except for `aggregated`. Fails on unbound variables instead of
+    /// producing a fact containing them
+    fn bind_head(&self, bindings: &HashMap<u32, ID>, aggregated: Option<u32>) -> Result<Predicate, crate::error::Evaluation> {
+        let mut p = self.head.clone();
+        for id in p.ids.iter_mut() {
+            if let ID::Variable(i) = id {
+                if Some(*i) == aggregated {
+                    continue;
+                }
+                match bindings.get(i) {
+                    Some(value) => *id = value.clone(),
+                    None => return Err(crate::error::Evaluation::UnboundHeadVariable(*i)),
+                }
+            }
+        }
+        Ok(p)
+    }
 
-                    if valid {
-                        return Some(variables);
-                    } else {
-                        return None;
+    /// `plan` reorders the body predicates, see `Rule::join_order`, and
+    /// `initial` fixes the value of some variables
+    ///
+    /// with `known`, the facts it contains and the ones generated more than
+    /// once are left out of `new_facts`. Returns the number of facts left out
+    pub(crate) fn apply_budgeted(
+        &self,
+        facts: &FactSet,
+        new_facts: &mut Vec<(Fact, HashMap<u32, ID>)>,
+        budget: Option<&Budget<'_>>,
+        plan: bool,
+        initial: Option<&HashMap<u32, ID>>,
+        known: Option<&FactSet>,
+    ) -> usize {
+        // a rule without body predicates cannot match anything (see `Rule::validate`)
+        if self.body.is_empty() {
+            return 0;
+        }
+
+        if let Some(budget) = budget {
+            for c in self.constraints.iter() {
+                if let ConstraintKind::Str(StrConstraint::Regex(r)) = &c.kind {
+                    if regex_match(r, "").is_none() {
+                        budget.warn(crate::error::Evaluation::InvalidRegex(r.clone()));
                     }
-                },
+                }
             }
         }
 
-        loop {
-            if self.current_it.is_none() {
-                //fix the first predicate
-                let pred = &self.predicates[0];
-
-                loop {
-                    if let Some(current_fact) = self.current_facts.next() {
-                        // create a new MatchedVariables in which we fix variables we could unify
-                        // from our first predicate and the current fact
-                        let mut vars = self.variables.clone();
-                        let mut match_ids = true;
-                        for (key, id) in pred.ids.iter().zip(&current_fact.predicate.ids) {
-                            if let (ID::Variable(k), id) = (key, id) {
-                                if !vars.insert(*k, &id) {
-                                    match_ids = false;
-                                }
-
-                                if !match_ids {
-                                    break;
-                                }
+        self.with_matches(facts, budget, plan, initial, |combinations| {
+            let add_fact = || budget.map(|b| b.add_fact()).unwrap_or(true);
+
+            if let Some((op, variable)) = &self.aggregate {
+                // aggregates are computed over every distinct set of bindings
+                // sorted to generate the facts in a reproducible order
+                let mut groups: BTreeMap<Predicate, Vec<ID>> = BTreeMap::new();
+                for h in combinations {
+                    let value = match h.get(variable) {
+                        Some(value) => value.clone(),
+                        None => {
+                            if let Some(budget) = budget {
+                                budget.fail(crate::error::Evaluation::UnboundHeadVariable(*variable));
                             }
-                        }
+                            continue;
+                        },
+                    };
 
-                        if !match_ids {
+                    let p = match self.bind_head(&h, Some(*variable)) {
+                        Ok(p) => p,
+                        Err(e) => {
+                            if let Some(budget) = budget {
+                                budget.fail(e);
+                            }
                             continue;
-                        }
+                        },
+                    };
 
-                        if self.predicates.len() == 1 {
-                            match vars.complete() {
-                                None => {
-                                    println!("variables not complete, continue");
-                                    continue;
-                                },
-                                // we got a complete set of variables, let's test the expressions
-                                Some(variables) => {
-                                    println!("will test with variables: {:?}", variables);
-                                    let mut valid = true;
-                                    for e in self.expressions.iter() {
-                                        match e.evaluate(&variables) {
-                                            Some(ID::Bool(true)) => {println!("expression returned true");},
-                                            e => {
-                                                println!("expression returned {:?}", e);
-                                                valid = false;
-                                                break;
-                                            },
-                                        }
-                                    }
-
-                                    if valid {
-                                        return Some(variables);
-                                    } else {
-                                        continue;
-                                    }
-                                },
+                    groups.entry(p).or_default().push(value);
+                }
+
+                for (mut p, values) in groups {
+                    match op.aggregate(&values) {
+                        Some(result) => {
+                            for id in p.ids.iter_mut() {
+                                if *id == ID::Variable(*variable) {
+                                    *id = result.clone();
+                                }
                             }
-                        } else {
-                            // create a new iterator with the matched variables, the rest of the predicates,
-                            // and all of the facts
-                            self.current_it = Some(Box::new(CombineIt::new(
-                                vars,
-                                &self.predicates[1..],
-                                self.expressions,
-                                &self.all_facts,
-                            )));
-                        }
-                        break;
-                    } else {
-                        return None;
+                            if !add_fact() {
+                                return 0;
+                            }
+                            new_facts.push((Fact { predicate: p }, HashMap::new()));
+                        },
+                        None => {
+                            if let Some(budget) = budget {
+                                budget.fail(crate::error::Evaluation::Aggregate(op.clone()));
+                            }
+                        },
                     }
                 }
-            }
 
-            if self.current_it.is_none() {
-                break None;
+                return 0;
             }
 
-            if let Some(val) = self.current_it.as_mut().and_then(|it| it.next()) {
-                break Some(val);
-            } else {
-                self.current_it = None;
-            }
-        }
-    }
-}
+            let mut generated = HashSet::new();
+            let mut skipped = 0;
+            for h in combinations {
+                let p = match self.check_computed(&h, budget).and_then(|()| self.bind_head(&h, None)) {
+                    Ok(p) => p,
+                    Err(e) => {
+                        if let Some(budget) = budget {
+                            budget.fail(e);
+                        }
+                        continue;
+                    },
+                };
+
+                let fact = Fact { predicate: p };
+                if let Some(known) = known {
+                    if known.contains(&fact) || generated.contains(&fact) {
+                        skipped += 1;
+                        continue;
+                    }
+                    generated.insert(fact.clone());
+                }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct MatchedVariables(pub HashMap<u32, Option<ID>>);
+                if !add_fact() {
+                    return skipped;
+                }
+                new_facts.push((fact, h));
+            }
 
-impl MatchedVariables {
-    pub fn new(import: HashSet<u32>) -> Self {
-        MatchedVariables(import.iter().map(|key| (*key, None)).collect())
+            skipped
+        })
     }
 
-    pub fn insert(&mut self, key: u32, value: &ID) -> bool {
-        match self.0.get(&key) {
-            Some(None) => {
-                self.0.insert(key, Some(value.clone()));
+    /// runs `f` on the matches of the body, see `apply_budgeted`
+    fn with_matches<T>(
+        &self,
+        facts: &FactSet,
+        budget: Option<&Budget<'_>>,
+        plan: bool,
+        initial: Option<&HashMap<u32, ID>>,
+        f: impl FnOnce(CombineIt<'_>) -> T,
+    ) -> T {
+        // gather all of the variables used in that rule
+        let variables_set = self
+            .body
+            .iter()
+            .flat_map(|pred| {
+                pred.ids
+                    .iter()
+                    .filter_map(|id| match id {
+                        ID::Variable(i) => Some(*i),
+                        _ => None,
+                    })
+            })
+            .collect::<HashSet<_>>();
+
+        let planned;
+        let body = if plan {
+            planned = self.join_order(facts).into_iter().map(|i| self.body[i].clone()).collect::<Vec<_>>();
+            &planned
+        } else {
+            &self.body
+        };
+
+        let variables = match initial {
+            Some(initial) => MatchedVariables::with_bindings(variables_set, initial),
+            None => MatchedVariables::new(variables_set),
+        };
+        let mut combinations = CombineIt::new(variables, body, &self.constraints, &self.expressions, &self.negated, facts);
+        if let Some(budget) = budget {
+            combinations = combinations.with_budget(budget);
+        }
+        f(combinations)
+    }
+
+    /// order in which the body predicates are bound: the one with the
+    /// fewest facts first, then the ones sharing a variable with the
+    /// predicates already bound, fewest facts first, to avoid cross products
+    ///
+    /// the order does not change the generated facts, only the number of
+    /// combinations examined and the order of the results
+    pub fn join_order(&self, facts: &FactSet) -> Vec<usize> {
+        let sizes = self.body.iter().map(|p| facts.bucket_len(p)).collect::<Vec<_>>();
+        let mut bound = HashSet::new();
+        let mut remaining = (0..self.body.len()).collect::<Vec<_>>();
+        let mut order = Vec::with_capacity(self.body.len());
+
+        while !remaining.is_empty() {
+            let position = (0..remaining.len()).min_by_key(|&position| {
+                let p = &self.body[remaining[position]];
+                let connected = bound.is_empty() || p.ids.iter().any(|id| match id {
+                    ID::Variable(v) => bound.contains(v),
+                    _ => false,
+                });
+                (!connected, sizes[remaining[position]])
+            }).expect("remaining predicates");
+
+            let index = remaining.remove(position);
+            bound.extend(self.body[index].ids.iter().filter_map(|id| match id {
+                ID::Variable(v) => Some(*v),
+                _ => None,
+            }));
+            order.push(index);
+        }
+
+        order
+    }
+
+    /// checks that the rule can be evaluated: the body must not be empty,
+    /// and the variables used in the head, constraints and expressions must
+    /// be bound by the body or by a previous expression
+    pub fn validate(&self) -> Result<(), crate::error::Rule> {
+        if self.body.is_empty() {
+            return Err(crate::error::Rule::EmptyBody);
+        }
+
+        let mut bound = self.body.iter()
+            .flat_map(|p| p.ids.iter())
+            .filter_map(|id| match id {
+                ID::Variable(i) => Some(*i),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+
+        for c in self.constraints.iter() {
+            if !bound.contains(&c.id) {
+                return Err(crate::error::Rule::UnboundConstraintVariable(c.id));
+            }
+        }
+
+        for e in self.expressions.iter() {
+            if let Some(v) = e.variables().into_iter().find(|v| !bound.contains(v)) {
+                return Err(crate::error::Rule::UnboundExpressionVariable(v));
+            }
+
+            if let Some(v) = e.binding() {
+                bound.insert(v);
+            }
+        }
+
+        for id in self.head.ids.iter() {
+            match id {
+                ID::Variable(i) if !bound.contains(i) => {
+                    return Err(crate::error::Rule::UnboundHeadVariable(*i));
+                },
+                ID::Wildcard => return Err(crate::error::Rule::WildcardInHead),
+                _ => {},
+            }
+        }
+
+        Ok(())
+    }
+
+    /// checks the predicates of the rule against their schema in `schemas`,
+    /// if they have one (see `World::declare_schema`): their number of
+    /// terms, the type of the values, and the variables must not be used
+    /// at positions of different types
+    pub fn validate_schemas(&self, schemas: &HashMap<Symbol, Vec<TermType>>) -> Result<(), crate::error::Schema> {
+        // the body gives the variables their type, the head and negated
+        // predicates are checked against it
+        let mut variables = HashMap::new();
+        for p in self.body.iter().chain(self.negated.iter()).chain(core::iter::once(&self.head)) {
+            check_schema(p, schemas, &mut variables)?;
+        }
+        Ok(())
+    }
+
+    /// replaces the symbols and variables found in `table`, see
+    /// `World::merge_translated`
+    pub fn translate(&self, table: &HashMap<Symbol, Symbol>) -> Rule {
+        Rule {
+            head: self.head.translate(table),
+            body: self.body.iter().map(|p| p.translate(table)).collect(),
+            constraints: self.constraints.iter().map(|c| Constraint {
+                id: translate_variable(table, c.id),
+                kind: match &c.kind {
+                    ConstraintKind::Symbol(SymbolConstraint::In(h)) => ConstraintKind::Symbol(SymbolConstraint::In(
+                        h.iter().map(|s| translate_symbol(table, *s)).collect()
+                    )),
+                    ConstraintKind::Symbol(SymbolConstraint::NotIn(h)) => ConstraintKind::Symbol(SymbolConstraint::NotIn(
+                        h.iter().map(|s| translate_symbol(table, *s)).collect()
+                    )),
+                    ConstraintKind::Bytes(BytesConstraint::InSet(h)) => ConstraintKind::Bytes(BytesConstraint::InSet(
+                        h.with_name(translate_symbol(table, h.name()))
+                    )),
+                    ConstraintKind::Bytes(BytesConstraint::NotInSet(h)) => ConstraintKind::Bytes(BytesConstraint::NotInSet(
+                        h.with_name(translate_symbol(table, h.name()))
+                    )),
+                    kind => kind.clone(),
+                },
+            }).collect(),
+            expressions: self.expressions.iter().map(|e| e.translate(table)).collect(),
+            negated: self.negated.iter().map(|p| p.translate(table)).collect(),
+            aggregate: self.aggregate.as_ref().map(|(op, v)| (op.clone(), translate_variable(table, *v))),
+        }
+    }
+
+    /// the same rule with the names of the head, body and negated predicates
+    /// in the namespace `ns`, see `Predicate::namespaced`
+    pub fn namespaced(&self, symbols: &mut SymbolTable, ns: &str) -> Rule {
+        Rule {
+            head: self.head.namespaced(symbols, ns),
+            body: self.body.iter().map(|p| p.namespaced(symbols, ns)).collect(),
+            negated: self.negated.iter().map(|p| p.namespaced(symbols, ns)).collect(),
+            ..self.clone()
+        }
+    }
+
+    /// moves the rule to the table the remapping was built for, including
+    /// its variable names, see `SymbolTable::extend`
+    pub fn remap_symbols(&self, remapping: &SymbolRemapping) -> Rule {
+        self.translate(remapping.table())
+    }
+
+    /// like `Fact::try_remap_symbols`, for the symbols and variable names
+    /// of the rule
+    pub fn try_remap_symbols(&self, remapping: &SymbolRemapping) -> Result<Rule, crate::error::Merge> {
+        remapping.check_rule(self)?;
+        Ok(self.remap_symbols(remapping))
+    }
+
+    pub fn complexity(&self) -> RuleComplexity {
+        let predicates_payload: usize = core::iter::once(&self.head)
+            .chain(self.body.iter())
+            .chain(self.negated.iter())
+            .flat_map(|p| p.ids.iter())
+            .map(|id| id.payload_size())
+            .sum();
+
+        let constraints_payload: usize = self.constraints.iter().map(|c| match &c.kind {
+            ConstraintKind::Str(StrConstraint::Prefix(s))
+            | ConstraintKind::Str(StrConstraint::Suffix(s))
+            | ConstraintKind::Str(StrConstraint::Equal(s))
+            | ConstraintKind::Str(StrConstraint::Regex(s)) => s.len(),
+            ConstraintKind::Str(StrConstraint::In(h))
+            | ConstraintKind::Str(StrConstraint::NotIn(h)) => h.iter().map(|s| s.len()).sum(),
+            ConstraintKind::Bytes(BytesConstraint::Equal(b)) => b.len(),
+            ConstraintKind::Bytes(BytesConstraint::In(h))
+            | ConstraintKind::Bytes(BytesConstraint::NotIn(h)) => h.iter().map(|b| b.len()).sum(),
+            ConstraintKind::Bytes(BytesConstraint::InSet(h))
+            | ConstraintKind::Bytes(BytesConstraint::NotInSet(h)) => h.iter().map(|b| b.len()).sum(),
+            _ => 0,
+        }).sum();
+
+        let expressions_payload: usize = self.expressions.iter()
+            .flat_map(|e| e.ops.iter())
+            .map(|op| match op {
+                Op::Value(id) => id.payload_size(),
+                _ => 0,
+            })
+            .sum();
+
+        RuleComplexity {
+            predicates: self.body.len() + self.negated.len(),
+            constraints: self.constraints.len(),
+            expression_ops: self.expressions.iter().map(|e| e.ops.len()).sum(),
+            payload_size: predicates_payload + constraints_payload + expressions_payload,
+        }
+    }
+
+    /// renumbers variables from 0, in order of first occurrence (head,
+    /// body, negated predicates, constraints, expressions, aggregate)
+    pub fn normalize_variables(&self) -> Rule {
+        let mut variables: HashMap<u32, u32> = HashMap::new();
+        let mut rename = |v: u32| -> u32 {
+            let next = variables.len() as u32;
+            *variables.entry(v).or_insert(next)
+        };
+
+        let mut rule = self.clone();
+        for p in core::iter::once(&mut rule.head)
+            .chain(rule.body.iter_mut())
+            .chain(rule.negated.iter_mut()) {
+            for id in p.ids.iter_mut() {
+                if let ID::Variable(v) = id {
+                    *v = rename(*v);
+                }
+            }
+        }
+
+        for c in rule.constraints.iter_mut() {
+            c.id = rename(c.id);
+        }
+
+        for e in rule.expressions.iter_mut() {
+            for op in e.ops.iter_mut() {
+                match op {
+                    Op::Value(ID::Variable(v)) | Op::Bind(v) => *v = rename(*v),
+                    _ => {},
+                }
+            }
+        }
+
+        if let Some((_, v)) = rule.aggregate.as_mut() {
+            *v = rename(*v);
+        }
+
+        rule
+    }
+
+    /// two rules are equivalent if they are equal up to a renaming of
+    /// their variables
+    pub fn equivalent(&self, other: &Rule) -> bool {
+        self.normalize_variables() == other.normalize_variables()
+    }
+
+    /// an aggregate result is stale if a new result was computed for the
+    /// same group
+    pub fn is_stale_aggregate(&self, existing: &Fact, new: &Fact) -> bool {
+        let variable = match &self.aggregate {
+            Some((_, variable)) => ID::Variable(*variable),
+            None => return false,
+        };
+
+        existing.predicate.name == new.predicate.name
+            && existing.predicate.ids.len() == new.predicate.ids.len()
+            && existing != new
+            && self.head.ids.len() == new.predicate.ids.len()
+            && self.head.ids.iter()
+                .zip(existing.predicate.ids.iter().zip(&new.predicate.ids))
+                .all(|(head_id, (e, n))| *head_id == variable || e == n)
+    }
+}
+
+/// constraints and expressions of a rule, arranged to be tested as soon
+/// as possible during the join
+struct JoinPlan<'a> {
+    /// constraints of each variable, tested once when it is bound
+    constraints: HashMap<u32, Vec<&'a Constraint>>,
+    /// expressions that do not bind a variable, indexed by the number of
+    /// predicates that must be bound to evaluate them
+    early: Vec<Vec<&'a Expression>>,
+    /// expressions tested on complete sets of variables, in order
+    late: Vec<&'a Expression>,
+}
+
+impl<'a> JoinPlan<'a> {
+    fn new(
+        variables: &MatchedVariables,
+        predicates: &'a [Predicate],
+        constraints: &'a [Constraint],
+        expressions: &'a [Expression],
+    ) -> Self {
+        let mut by_variable: HashMap<u32, Vec<&'a Constraint>> = HashMap::new();
+        for c in constraints.iter() {
+            by_variable.entry(c.id).or_default().push(c);
+        }
+
+        // variables bound after each predicate
+        let mut bound = variables.0.iter()
+            .filter(|(_, value)| value.is_some())
+            .map(|(key, _)| *key)
+            .collect::<HashSet<_>>();
+        let mut stages = vec![bound.clone()];
+        for p in predicates.iter() {
+            bound.extend(p.ids.iter().filter_map(|id| match id {
+                ID::Variable(v) => Some(*v),
+                _ => None,
+            }));
+            stages.push(bound.clone());
+        }
+
+        let mut early = vec![Vec::new(); predicates.len()];
+        let mut late = Vec::new();
+        for e in expressions.iter() {
+            let variables = e.variables();
+            // after the last predicate, the expression is tested with the others
+            let stage = (1..predicates.len()).find(|i| variables.is_subset(&stages[*i]));
+            match stage {
+                Some(stage) if e.binding().is_none() => early[stage].push(e),
+                _ => late.push(e),
+            }
+        }
+
+        JoinPlan { constraints: by_variable, early, late }
+    }
+
+    /// tests a value against the constraints of the variable it is bound to
+    fn check(&self, variable: u32, id: &ID) -> bool {
+        self.constraints.get(&variable).into_iter().flatten().all(|c| {
+            #[cfg(test)]
+            CONSTRAINT_CHECKS.with(|c| c.set(c.get() + 1));
+            c.check(variable, id)
+        })
+    }
+
+    /// reports the constraints of the variable rejecting the value because
+    /// of its type, see `Constraint::type_mismatch`
+    fn report_mismatches(&self, variable: u32, id: &ID, budget: &Budget<'_>) {
+        for c in self.constraints.get(&variable).into_iter().flatten() {
+            if let Some(e) = c.type_mismatch(id) {
+                budget.warn(e);
+            }
+        }
+    }
+
+    /// false if some constraints can never hold: their variable is already
+    /// bound to a value they reject, or it does not appear in the body (see
+    /// `Rule::validate`), so nothing could be checked against them
+    fn satisfiable(&self, variables: &MatchedVariables) -> bool {
+        self.constraints.keys().all(|k| match variables.0.get(k) {
+            Some(Some(id)) => self.check(*k, id),
+            Some(None) => true,
+            None => false,
+        })
+    }
+
+    /// true if the complete set of variables satisfies every constraint
+    fn holds(&self, variables: &HashMap<u32, ID>) -> bool {
+        self.constraints.iter().all(|(k, constraints)| {
+            variables.get(k).is_some_and(|id| constraints.iter().all(|c| c.check(*k, id)))
+        })
+    }
+}
+
+/// iterator for rule application
+///
+/// the join is a loop over a stack of frames, one per bound body predicate,
+/// so deep bodies do not nest iterators or calls
+pub struct CombineIt<'a> {
+    variables: MatchedVariables,
+    predicates: &'a [Predicate],
+    plan: JoinPlan<'a>,
+    negated: &'a [Predicate],
+    all_facts: &'a FactSet,
+    // one frame per body predicate being bound, the last one is the deepest
+    frames: Vec<Frame<'a>>,
+    budget: Option<&'a Budget<'a>>,
+}
+
+/// candidate facts for a body predicate
+struct Frame<'a> {
+    facts: Matching<'a, Predicate>,
+    // variables bound by the current candidate fact, unset before the next one
+    undo: Vec<u32>,
+}
+
+impl<'a> CombineIt<'a> {
+    pub fn new(
+        variables: MatchedVariables,
+        predicates: &'a [Predicate],
+        constraints: &'a [Constraint],
+        expressions: &'a [Expression],
+        negated: &'a [Predicate],
+        facts: &'a FactSet,
+    ) -> Self {
+        let plan = JoinPlan::new(&variables, predicates, constraints, expressions);
+        let frames = predicates.first()
+            .filter(|_| plan.satisfiable(&variables))
+            .map(|p| Frame { facts: facts.matching(bind(p, &variables)), undo: Vec::new() })
+            .into_iter()
+            .collect();
+
+        CombineIt {
+            variables,
+            predicates,
+            plan,
+            negated,
+            all_facts: facts,
+            frames,
+            budget: None,
+        }
+    }
+
+    /// stops the iteration once the budget is exhausted
+    pub(crate) fn with_budget(mut self, budget: &'a Budget<'a>) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// tests the bound variables against the expressions that only need
+    /// the first `bound` predicates
+    fn check_early(&self, bound: usize) -> bool {
+        let expressions = match self.plan.early.get(bound) {
+            Some(expressions) if !expressions.is_empty() => expressions,
+            _ => return true,
+        };
+
+        let values = self.variables.bound();
+        expressions.iter().all(|e| {
+            if !self.budget.map(|b| b.evaluate()).unwrap_or(true) {
+                return false;
+            }
+            match e.try_evaluate(&values) {
+                Ok(value) => value == ID::Bool(true),
+                Err(err) => {
+                    if let Some(budget) = self.budget {
+                        budget.fail(err.into());
+                    }
+                    false
+                },
+            }
+        })
+    }
+
+    /// tests a complete set of variables against the remaining expressions,
+    /// then against the negated predicates
+    fn check(&self, variables: HashMap<u32, ID>) -> Option<HashMap<u32, ID>> {
+        let variables = evaluate_expressions(self.plan.late.iter().copied(), variables, self.budget)?;
+
+        if self.negated.iter().any(|negated| negation_matches(negated, &variables, self.all_facts)) {
+            return None;
+        }
+
+        Some(variables)
+    }
+}
+
+/// true if a fact matches the negated predicate once its variables are
+/// replaced by their value, the unbound ones match any term
+fn negation_matches(negated: &Predicate, variables: &HashMap<u32, ID>, facts: &FactSet) -> bool {
+    let p = Predicate {
+        name: negated.name,
+        ids: negated.ids.iter().map(|id| match id {
+            ID::Variable(i) => variables.get(i).cloned().unwrap_or(ID::Variable(*i)),
+            id => id.clone(),
+        }).collect(),
+    };
+
+    facts.matching(&p).next().is_some()
+}
+
+impl<'a> Iterator for CombineIt<'a> {
+    type Item = HashMap<u32, ID>;
+
+    fn next(&mut self) -> Option<HashMap<u32, ID>> {
+        loop {
+            // the body is exhausted once the first frame is
+            let depth = self.frames.len().checked_sub(1)?;
+            let frame = self.frames.last_mut().expect("at least one frame");
+            self.variables.unset(&frame.undo);
+            frame.undo.clear();
+
+            let current_fact = match frame.facts.next() {
+                Some(fact) => fact,
+                None => {
+                    // the previous frame moves to its next candidate
+                    self.frames.pop();
+                    continue;
+                },
+            };
+
+            if let Some(budget) = self.budget {
+                if !budget.examine() {
+                    self.frames.clear();
+                    return None;
+                }
+            }
+
+            // fix the variables we could unify from the predicate and the
+            // current fact, they are unset before the next candidate
+            let pred = &self.predicates[depth];
+            let mut match_ids = true;
+            for (key, id) in pred.ids.iter().zip(&current_fact.predicate.ids) {
+                if let (ID::Variable(k), id) = (key, id) {
+                    let unbound = self.variables.0.get(k) == Some(&None);
+                    if !self.variables.insert(*k, id) {
+                        match_ids = false;
+                        break;
+                    }
+
+                    // a variable that was already bound, by a previous
+                    // predicate or an earlier position of this one, has the
+                    // same value and its constraints were checked then
+                    if unbound {
+                        frame.undo.push(*k);
+                        if !self.plan.check(*k, id) {
+                            if let Some(budget) = self.budget {
+                                self.plan.report_mismatches(*k, id, budget);
+                            }
+                            match_ids = false;
+                            break;
+                        }
+                    }
+                }
+            }
+
+            if !match_ids || !self.check_early(depth + 1) {
+                continue;
+            }
+
+            if depth + 1 < self.predicates.len() {
+                // the next predicate only looks at the facts matching the
+                // variables bound so far
+                let facts = self.all_facts.matching(bind(&self.predicates[depth + 1], &self.variables));
+                self.frames.push(Frame { facts, undo: Vec::new() });
+                continue;
+            }
+
+            // we got a complete set of variables, let's test the expressions
+            if let Some(variables) = self.variables.complete() {
+                debug_assert!(self.plan.holds(&variables), "constraints checked on every binding");
+                if let Some(variables) = self.check(variables) {
+                    return Some(variables);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    /// number of calls to `Constraint::check` by `CombineIt`
+    static CONSTRAINT_CHECKS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    /// number of views computed from all the facts instead of updated
+    static VIEW_RECOMPUTATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// replaces the variables already bound in the predicate, to only look at
+/// the facts matching the previous predicates
+fn bind(predicate: &Predicate, variables: &MatchedVariables) -> Predicate {
+    Predicate {
+        name: predicate.name,
+        ids: predicate.ids.iter().map(|id| match id {
+            ID::Variable(v) => match variables.0.get(v) {
+                Some(Some(value)) => value.clone(),
+                _ => id.clone(),
+            },
+            id => id.clone(),
+        }).collect(),
+    }
+}
+
+/// tests the expressions in order against a complete set of variables
+///
+/// expressions ending with `Op::Bind` add their result to the variables
+/// (so later expressions and the rule's head can use it), the others
+/// must evaluate to `true`. Fails if the budget is exhausted, evaluation
+/// errors are reported to the budget
+fn evaluate_expressions<'a, I>(
+    expressions: I,
+    mut variables: HashMap<u32, ID>,
+    budget: Option<&Budget<'_>>,
+) -> Option<HashMap<u32, ID>>
+where
+    I: IntoIterator<Item = &'a Expression>,
+{
+    for e in expressions {
+        if budget.is_some_and(|b| !b.evaluate()) {
+            return None;
+        }
+        match (e.binding(), e.try_evaluate(&variables)) {
+            (Some(v), Ok(value)) => match variables.get(&v) {
+                // the variable was already bound, the computed value must match
+                Some(bound) if *bound != value => return None,
+                _ => {
+                    variables.insert(v, value);
+                }
+            },
+            (None, Ok(ID::Bool(true))) => {},
+            (_, Ok(_)) => return None,
+            (_, Err(err)) => {
+                if let Some(budget) = budget {
+                    budget.fail(err.into());
+                }
+                return None;
+            },
+        }
+    }
+
+    Some(variables)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchedVariables(pub HashMap<u32, Option<ID>>);
+
+impl MatchedVariables {
+    pub fn new(import: HashSet<u32>) -> Self {
+        MatchedVariables(import.iter().map(|key| (*key, None)).collect())
+    }
+
+    /// like `new`, with some of the variables already bound. Values for
+    /// variables that are not in `import` are ignored
+    pub fn with_bindings(import: HashSet<u32>, initial: &HashMap<u32, ID>) -> Self {
+        MatchedVariables(import.iter().map(|key| (*key, initial.get(key).cloned())).collect())
+    }
+
+    pub fn get(&self, key: u32) -> Option<&ID> {
+        self.0.get(&key).and_then(|value| value.as_ref())
+    }
+
+    /// iterates over the variables that are not bound yet
+    pub fn unbound(&self) -> impl Iterator<Item = u32> + '_ {
+        self.0.iter().filter(|(_, value)| value.is_none()).map(|(key, _)| *key)
+    }
+
+    /// iterates over the variables and their value, if they are bound
+    pub fn iter(&self) -> impl Iterator<Item = (u32, Option<&ID>)> {
+        self.0.iter().map(|(key, value)| (*key, value.as_ref()))
+    }
+
+    pub fn insert(&mut self, key: u32, value: &ID) -> bool {
+        match self.0.get(&key) {
+            Some(None) => {
+                self.0.insert(key, Some(value.clone()));
                 true
             }
             Some(Some(v)) => value == v,
@@ -432,6 +1643,22 @@ impl MatchedVariables {
         self.0.values().all(|v| v.is_some())
     }
 
+    /// values of the variables bound so far
+    pub fn bound(&self) -> HashMap<u32, ID> {
+        self.0.iter()
+            .filter_map(|(key, value)| value.as_ref().map(|value| (*key, value.clone())))
+            .collect()
+    }
+
+    /// removes the values of these variables
+    pub fn unset(&mut self, keys: &[u32]) {
+        for key in keys {
+            if let Some(value) = self.0.get_mut(key) {
+                *value = None;
+            }
+        }
+    }
+
     pub fn complete(&self) -> Option<HashMap<u32, ID>> {
         let mut result = HashMap::new();
         for (k, v) in self.0.iter() {
@@ -468,7 +1695,26 @@ pub fn rule<I: AsRef<ID>, P: AsRef<Predicate>>(
     Rule {
         head: pred(head_name, head_ids),
         body: predicates.iter().map(|p| p.as_ref().clone()).collect(),
+        constraints: Vec::new(),
+        expressions: Vec::new(),
+        negated: Vec::new(),
+        aggregate: None,
+    }
+}
+
+pub fn constrained_rule<I: AsRef<ID>, P: AsRef<Predicate>, C: AsRef<Constraint>>(
+    head_name: Symbol,
+    head_ids: &[I],
+    predicates: &[P],
+    constraints: &[C],
+) -> Rule {
+    Rule {
+        head: pred(head_name, head_ids),
+        body: predicates.iter().map(|p| p.as_ref().clone()).collect(),
+        constraints: constraints.iter().map(|c| c.as_ref().clone()).collect(),
         expressions: Vec::new(),
+        negated: Vec::new(),
+        aggregate: None,
     }
 }
 
@@ -481,7 +1727,36 @@ pub fn expressed_rule<I: AsRef<ID>, P: AsRef<Predicate>, C: AsRef<Expression>>(
     Rule {
         head: pred(head_name, head_ids),
         body: predicates.iter().map(|p| p.as_ref().clone()).collect(),
+        constraints: Vec::new(),
         expressions: expressions.iter().map(|c| c.as_ref().clone()).collect(),
+        negated: Vec::new(),
+        aggregate: None,
+    }
+}
+
+/// constraints are checked while binding the body's variables, and the
+/// expressions are evaluated once all of them are bound
+pub fn full_rule<I: AsRef<ID>, P: AsRef<Predicate>, C: AsRef<Constraint>, E: AsRef<Expression>>(
+    head_name: Symbol,
+    head_ids: &[I],
+    predicates: &[P],
+    constraints: &[C],
+    expressions: &[E],
+) -> Rule {
+    Rule {
+        head: pred(head_name, head_ids),
+        body: predicates.iter().map(|p| p.as_ref().clone()).collect(),
+        constraints: constraints.iter().map(|c| c.as_ref().clone()).collect(),
+        expressions: expressions.iter().map(|e| e.as_ref().clone()).collect(),
+        negated: Vec::new(),
+        aggregate: None,
+    }
+}
+
+/// the caveat succeeds if any of the queries produces a fact
+pub fn caveat<R: AsRef<Rule>>(queries: &[R]) -> Caveat {
+    Caveat {
+        queries: queries.iter().map(|q| q.as_ref().clone()).collect(),
     }
 }
 
@@ -493,645 +1768,5639 @@ pub fn string(s: &str) -> ID {
     ID::Str(s.to_string())
 }
 
+/// `None` if the regex is invalid. Without the `regex` feature, every
+/// regex is invalid
+#[cfg(feature = "regex")]
+pub(crate) fn regex_match(regex: &str, s: &str) -> Option<bool> {
+    regex::Regex::new(regex).ok().map(|re| re.is_match(s))
+}
+
+#[cfg(not(feature = "regex"))]
+pub(crate) fn regex_match(_regex: &str, _s: &str) -> Option<bool> {
+    None
+}
+
+/// dates before the unix epoch are set to the epoch, see `try_date`
+///
+/// the system clock is never read here, so it can be used on targets
+/// without one, like `wasm32-unknown-unknown`, with a time that comes
+/// from elsewhere
+#[cfg(feature = "std")]
 pub fn date(t: &SystemTime) -> ID {
-    let dur = t.duration_since(UNIX_EPOCH).unwrap();
-    ID::Date(dur.as_secs())
+    try_date(t).unwrap_or(ID::Date(0))
 }
 
+/// like `date`, but fails on times before the unix epoch
+#[cfg(feature = "std")]
+pub fn try_date(t: &SystemTime) -> Result<ID, crate::error::InvalidDate> {
+    t.duration_since(UNIX_EPOCH).map(|dur| ID::Date(dur.as_secs())).map_err(|_| crate::error::InvalidDate)
+}
+
+/// variable named `name`, its id is the symbol's index so it prints as
+/// `$name`, see `SymbolTable::print_variable`
 pub fn var(syms: &mut SymbolTable, name: &str) -> ID {
     let id = syms.insert(name);
     ID::Variable(id as u32)
 }
 
-pub fn match_preds(pred1: &Predicate, pred2: &Predicate) -> bool {
-    pred1.name == pred2.name
-        && pred1.ids.len() == pred2.ids.len()
-        && pred1
-            .ids
-            .iter()
+/// like `var`, but respects `SymbolTable::max_symbols`
+pub fn try_var(syms: &mut SymbolTable, name: &str) -> Result<ID, crate::error::Symbol> {
+    syms.try_insert(name).map(|id| ID::Variable(id as u32))
+}
+
+/// `variables` holds the type of the variables found in the previous
+/// predicates, the ones of `p` are added to it
+fn check_schema(
+    p: &Predicate,
+    schemas: &HashMap<Symbol, Vec<TermType>>,
+    variables: &mut HashMap<u32, TermType>,
+) -> Result<(), crate::error::Schema> {
+    let types = match schemas.get(&p.name) {
+        Some(types) => types,
+        None => return Ok(()),
+    };
+    if types.len() != p.ids.len() {
+        return Err(crate::error::Schema::Arity { predicate: p.name, expected: types.len(), found: p.ids.len() });
+    }
+
+    for (position, (id, expected)) in p.ids.iter().zip(types).enumerate() {
+        let found = match id {
+            ID::Variable(v) => match variables.get(v) {
+                Some(found) if found != expected => {
+                    return Err(crate::error::Schema::Variable {
+                        variable: *v,
+                        predicate: p.name,
+                        position,
+                        expected: *expected,
+                        found: *found,
+                    });
+                },
+                Some(_) => continue,
+                None => {
+                    variables.insert(*v, *expected);
+                    continue;
+                },
+            },
+            id => match id.term_type() {
+                Some(found) => found,
+                None => continue,
+            },
+        };
+        if found != *expected {
+            return Err(crate::error::Schema::TermType { predicate: p.name, position, expected: *expected, found });
+        }
+    }
+    Ok(())
+}
+
+/// checks that two predicates have the same name and arity, and that their
+/// terms can be unified. Zero-arity predicates match on their name only
+pub fn match_preds(pred1: &Predicate, pred2: &Predicate) -> bool {
+    pred1.name == pred2.name
+        && pred1.ids.len() == pred2.ids.len()
+        && pred1
+            .ids
+            .iter()
             .zip(&pred2.ids)
             .all(|(fid, pid)| match (fid, pid) {
                 (_, ID::Variable(_)) => true,
                 (ID::Variable(_), _) => true,
-                (ID::Symbol(i), ID::Symbol(ref j)) => i == j,
-                (ID::Integer(i), ID::Integer(j)) => i == j,
-                (ID::Str(i), ID::Str(j)) => i == j,
-                (ID::Date(i), ID::Date(j)) => i == j,
-                _ => false,
+                (_, ID::Wildcard) => true,
+                (ID::Wildcard, _) => true,
+                // values of different types are never equal
+                (fid, pid) => fid == pid,
             })
 }
 
 #[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct World {
-    pub facts: HashSet<Fact>,
-    pub rules: Vec<Rule>,
+    pub facts: FactSet,
+    /// shared by the clones of the world, and copied by the first one
+    /// that changes them, with `Arc::make_mut`. `World::rules()` lists
+    /// them with their origin
+    pub rules: Arc<Vec<Rule>>,
+    /// arities declared with `World::declare_predicate`
+    #[cfg_attr(feature = "serde", serde(with = "serialization::sorted_map"))]
+    pub arities: HashMap<Symbol, usize>,
+    /// how each fact was derived, filled by `World::run_with_provenance`
+    #[cfg_attr(feature = "serde", serde(with = "serialization::pairs"))]
+    pub derivations: HashMap<Fact, Derivation>,
+    /// blocks that provided each fact. Facts without an entry are visible
+    /// to all rules
+    #[cfg_attr(feature = "serde", serde(with = "serialization::pairs"))]
+    pub fact_origins: HashMap<Fact, BTreeSet<Origin>>,
+    /// block of each rule, indexed like `rules`. Rules without an entry
+    /// see all facts
+    #[cfg_attr(feature = "serde", serde(with = "serialization::sorted_map"))]
+    pub rule_origins: HashMap<usize, Origin>,
+    /// the last run stopped on a limit, see `World::is_complete`
+    #[cfg_attr(feature = "serde", serde(default))]
+    partial: bool,
+    /// checks of `World::try_add_fact` and `World::try_add_rule`, not
+    /// serialized
+    #[cfg_attr(feature = "serde", serde(skip))]
+    config: WorldConfig,
+    /// sets added with `World::add_revocation_set`, not serialized: the
+    /// constraints using them carry their content
+    #[cfg_attr(feature = "serde", serde(skip))]
+    revocation_sets: BTreeMap<Symbol, SetHandle>,
+    /// rules registered with `World::register_view`, not serialized
+    #[cfg_attr(feature = "serde", serde(skip))]
+    views: Vec<View>,
+    /// term types declared with `World::declare_schema`, not serialized
+    #[cfg_attr(feature = "serde", serde(skip))]
+    schemas: HashMap<Symbol, Vec<TermType>>,
+}
+
+/// index of a view in its world, see `World::register_view`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ViewId(usize);
+
+/// a rule and the facts it generates, updated after each run
+#[derive(Debug, Clone, PartialEq)]
+struct View {
+    rule: Rule,
+    facts: HashSet<Fact>,
+    /// buckets of the predicates of the rule when `facts` was computed,
+    /// shared with the world until it modifies them
+    seen: FactSet,
+}
+
+impl View {
+    fn new(rule: Rule, facts: &FactSet) -> Self {
+        let mut view = View { rule, facts: HashSet::new(), seen: FactSet::new() };
+        view.recompute(facts);
+        view
+    }
+
+    fn recompute(&mut self, facts: &FactSet) {
+        #[cfg(test)]
+        VIEW_RECOMPUTATIONS.with(|c| c.set(c.get() + 1));
+        let mut generated = Vec::new();
+        self.rule.apply_budgeted(facts, &mut generated, None, false, None, None);
+        self.facts = generated.into_iter().map(|(fact, _)| fact).collect();
+        self.seen = facts.buckets(&self.predicates());
+    }
+
+    fn predicates(&self) -> BTreeSet<(Symbol, usize)> {
+        self.rule.body.iter().chain(&self.rule.negated).map(|p| (p.name, p.ids.len())).collect()
+    }
+
+    /// only looks for the matches of the facts added to the body
+    /// predicates since the last update. Removed facts, changes to the
+    /// negated predicates and aggregates need a complete recomputation
+    fn update(&mut self, facts: &FactSet) {
+        let mut added = Vec::new();
+        for (index, p) in self.rule.body.iter().enumerate() {
+            let (new, removed) = facts.changes(&self.seen, p);
+            if !removed.is_empty() {
+                return self.recompute(facts);
+            }
+            added.extend(new.into_iter().map(|fact| (index, fact)));
+        }
+        let negated_changed = self.rule.negated.iter().any(|p| {
+            let (new, removed) = facts.changes(&self.seen, p);
+            !new.is_empty() || !removed.is_empty()
+        });
+        if negated_changed || (self.rule.aggregate.is_some() && !added.is_empty()) {
+            return self.recompute(facts);
+        }
+
+        for (index, fact) in added {
+            // binds the predicate to the new fact, the rest of the body
+            // can match any fact
+            let mut initial = HashMap::new();
+            let bound = self.rule.body[index].ids.iter().zip(&fact.predicate.ids).all(|(id, value)| match id {
+                ID::Variable(v) => initial.insert(*v, value.clone()).is_none_or(|previous| previous == *value),
+                _ => true,
+            });
+            if !bound || !match_preds(&fact.predicate, &self.rule.body[index]) {
+                continue;
+            }
+
+            let mut generated = Vec::new();
+            self.rule.apply_budgeted(facts, &mut generated, None, false, Some(&initial), None);
+            self.facts.extend(generated.into_iter().map(|(fact, _)| fact));
+        }
+        self.seen = facts.buckets(&self.predicates());
+    }
+}
+
+/// checks done by `World::try_add_fact` and `World::try_add_rule`, see
+/// `World::with_config`. The other adders and the runs ignore them
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct WorldConfig {
+    /// maximum number of facts in the world, including the generated ones
+    pub max_facts: Option<usize>,
+    pub max_rules: Option<usize>,
+    /// maximum size in bytes of the strings and byte arrays of a term (see
+    /// `ID::payload_size`), in facts, rule predicates and expressions
+    pub max_term_size: Option<usize>,
+    /// facts and rules are validated with `Fact::validate` and
+    /// `Rule::validate`
+    pub strict: bool,
+}
+
+/// where a fact or rule comes from in a token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Origin {
+    Authority,
+    /// facts and rules provided by the verifier
+    Ambient,
+    Block(u32),
+}
+
+impl Origin {
+    /// rules from the authority and ambient see the facts from the authority
+    /// and ambient, and rules from a block see those plus the block's own facts
+    pub fn can_see(&self, fact_origin: &Origin) -> bool {
+        match fact_origin {
+            Origin::Authority | Origin::Ambient => true,
+            Origin::Block(_) => self == fact_origin,
+        }
+    }
+}
+
+/// the rule application that produced a fact
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Derivation {
+    /// index of the rule in `World::rules`
+    pub rule: usize,
+    #[cfg_attr(feature = "serde", serde(with = "serialization::sorted_map"))]
+    pub bindings: HashMap<u32, ID>,
+    /// facts matched by the rule's body predicates, empty for aggregates
+    pub premises: Vec<Fact>,
+}
+
+/// recursive explanation of a fact, as returned by `World::explain`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProofTree {
+    pub fact: Fact,
+    /// rule index, or `None` if the fact was added directly
+    pub rule: Option<usize>,
+    pub premises: Vec<ProofTree>,
+}
+
+/// a rule of a world, as returned by `World::rules`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RuleEntry<'a> {
+    /// index of the rule in `World::rules`, as used in errors and
+    /// derivations
+    pub index: usize,
+    pub rule: &'a Rule,
+    /// `None` if the rule was added with `World::add_rule`
+    pub origin: Option<Origin>,
+}
+
+impl RuleEntry<'_> {
+    pub fn print(&self, symbols: &SymbolTable) -> String {
+        symbols.print_rule(self.rule)
+    }
 }
 
+/// a world can be shared between threads, and cloned in each of them
+const _: fn() = || {
+    fn send_sync<T: Send + Sync>() {}
+    send_sync::<World>();
+};
+
 impl World {
     pub fn new() -> Self {
         World::default()
     }
 
+    /// copy of the world that can be modified and run without changing
+    /// this one, for per request verification over a shared base
+    ///
+    /// this is a clone, but the facts are copied on write (see `FactSet`),
+    /// so it is cheap when the snapshot only adds a few facts
+    pub fn snapshot(&self) -> World {
+        self.clone()
+    }
+
+    /// creates a world storing its facts in a `FactSet::sorted`: `run`,
+    /// `query` and `query_rule` then iterate over facts in a reproducible
+    /// order, and a run stopped by a fact or candidate limit always ends
+    /// with the same facts
+    ///
+    /// each pass applies the rules of a stratum in the order they were
+    /// added, each rule examines the candidate facts in their sort order,
+    /// and the new facts are stored in the order they were generated. A
+    /// run stopped by `max_facts`, `max_memory`, `max_candidates` or
+    /// `max_iterations` then keeps the same facts whatever the order the
+    /// facts were added in, while time limits and cancellations depend on
+    /// when they happen. The mode is not serialized: a world read back
+    /// with serde stores its facts in a hashed `FactSet`
+    pub fn new_deterministic() -> Self {
+        World {
+            facts: FactSet::sorted(),
+            ..World::default()
+        }
+    }
+
+    /// creates a world whose `try_add_fact` and `try_add_rule` apply the
+    /// checks of `config`
+    pub fn with_config(config: WorldConfig) -> Self {
+        World { config, ..World::default() }
+    }
+
+    pub fn config(&self) -> &WorldConfig {
+        &self.config
+    }
+
+    /// adds a fact after checking it against the `WorldConfig` of the world
+    ///
+    /// returns true if the fact was not present. Facts already present are
+    /// not counted against the limits
+    pub fn try_add_fact(&mut self, fact: Fact) -> Result<bool, crate::error::WorldLimit> {
+        if self.config.strict {
+            fact.validate().map_err(crate::error::WorldLimit::InvalidFact)?;
+        }
+        fact.validate_schemas(&self.schemas).map_err(crate::error::WorldLimit::Schema)?;
+        self.check_terms(&fact.predicate.ids)?;
+        if self.facts.contains(&fact) {
+            return Ok(false);
+        }
+        if let Some(max) = self.config.max_facts.filter(|max| self.facts.len() >= *max) {
+            return Err(crate::error::WorldLimit::TooManyFacts(max));
+        }
+        self.add_fact(fact);
+        Ok(true)
+    }
+
+    /// adds a rule after checking it against the `WorldConfig` of the world
+    ///
+    /// returns true if the rule was added (see `add_rule`)
+    pub fn try_add_rule(&mut self, rule: Rule) -> Result<bool, crate::error::WorldLimit> {
+        if self.config.strict {
+            rule.validate().map_err(crate::error::WorldLimit::InvalidRule)?;
+        }
+        rule.validate_schemas(&self.schemas).map_err(crate::error::WorldLimit::Schema)?;
+        for p in core::iter::once(&rule.head).chain(rule.body.iter()).chain(rule.negated.iter()) {
+            self.check_terms(&p.ids)?;
+        }
+        for e in rule.expressions.iter() {
+            self.check_terms(e.ops.iter().filter_map(|op| match op {
+                Op::Value(id) => Some(id),
+                _ => None,
+            }))?;
+        }
+        if self.contains_rule(&rule) {
+            return Ok(false);
+        }
+        if let Some(max) = self.config.max_rules.filter(|max| self.rules.len() >= *max) {
+            return Err(crate::error::WorldLimit::TooManyRules(max));
+        }
+        Ok(self.add_rule(rule))
+    }
+
+    fn check_terms<'a, I: IntoIterator<Item = &'a ID>>(&self, ids: I) -> Result<(), crate::error::WorldLimit> {
+        let max = match self.config.max_term_size {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+        match ids.into_iter().map(|id| id.payload_size()).find(|size| *size > max) {
+            Some(size) => Err(crate::error::WorldLimit::TermTooLarge { size, max }),
+            None => Ok(()),
+        }
+    }
+
+    /// stores a set of byte strings, like revocation ids, under `name`,
+    /// replacing the previous set with that name
+    ///
+    /// the set is not stored as facts: rules check a value against it
+    /// with a `BytesConstraint::InSet` or `NotInSet` constraint holding the
+    /// returned handle, in constant time
+    pub fn add_revocation_set<I: IntoIterator<Item = Vec<u8>>>(&mut self, name: Symbol, ids: I) -> SetHandle {
+        let handle = SetHandle::new(name, ids);
+        self.revocation_sets.insert(name, handle.clone());
+        handle
+    }
+
+    pub fn revocation_set(&self, name: Symbol) -> Option<&SetHandle> {
+        self.revocation_sets.get(&name)
+    }
+
     pub fn add_fact(&mut self, fact: Fact) {
+        self.fact_origins.remove(&fact);
+        self.derivations.remove(&fact);
         self.facts.insert(fact);
     }
 
-    pub fn add_rule(&mut self, rule: Rule) {
-        self.rules.push(rule);
+    /// adds a fact only visible to the rules that can see its origin
+    pub fn add_fact_with_origin(&mut self, fact: Fact, origin: Origin) {
+        self.derivations.remove(&fact);
+        if self.facts.insert(fact.clone()) {
+            self.fact_origins.entry(fact).or_default().insert(origin);
+        } else if let Some(origins) = self.fact_origins.get_mut(&fact) {
+            origins.insert(origin);
+        }
     }
 
-    pub fn run(&mut self) -> Result<(), crate::error::RunLimit> {
-        self.run_with_limits(RunLimits::default())
+    /// adds the facts, rules and arity declarations of another world
+    ///
+    /// facts are deduplicated, and the rules of `other` are appended after
+    /// the existing ones in their original order, skipping the exact
+    /// duplicates (see `add_rule`). Both worlds must use the same symbol
+    /// table, otherwise use `merge_translated`
+    pub fn merge(&mut self, other: World) {
+        self.merge_translated(other, &HashMap::new())
     }
 
-    pub fn run_with_limits(&mut self, limits: RunLimits) -> Result<(), crate::error::RunLimit> {
-        let start = SystemTime::now();
-        let time_limit = start + limits.max_time;
-        let mut index = 0;
+    /// like `merge`, but first translates the symbols and variables of
+    /// `other` with `table`, for worlds built with different symbol tables.
+    /// Symbols missing from `table` are kept as is
+    pub fn merge_translated(&mut self, mut other: World, table: &HashMap<Symbol, Symbol>) {
+        let mut rule_indexes = Vec::with_capacity(other.rules.len());
+        for (index, rule) in other.rules.iter().enumerate() {
+            let rule = rule.translate(table);
+            let origin = other.rule_origins.get(&index).cloned();
+            let existing = self.rules.iter().enumerate()
+                .position(|(i, r)| *r == rule && self.rule_origins.get(&i) == origin.as_ref());
+
+            rule_indexes.push(match existing {
+                Some(i) => i,
+                None => {
+                    if let Some(origin) = origin {
+                        self.rule_origins.insert(self.rules.len(), origin);
+                    }
+                    Arc::make_mut(&mut self.rules).push(rule);
+                    self.rules.len() - 1
+                }
+            });
+        }
 
-        loop {
-            let mut new_facts: Vec<Fact> = Vec::new();
-            for rule in self.rules.iter() {
-                rule.apply(&self.facts, &mut new_facts);
-                //println!("new_facts after applying {:?}:\n{:#?}", rule, new_facts);
-            }
+        for fact in other.facts.iter() {
+            let translated = fact.translate(table);
+            let is_new = !self.facts.contains(&translated);
 
-            let len = self.facts.len();
-            self.facts.extend(new_facts.drain(..));
-            if self.facts.len() == len {
-                break;
+            match other.fact_origins.get(fact) {
+                None => self.add_fact(translated.clone()),
+                Some(origins) => for origin in origins.iter() {
+                    self.add_fact_with_origin(translated.clone(), *origin);
+                },
             }
 
-            index += 1;
-            if index == limits.max_iterations {
-                return Err(crate::error::RunLimit::TooManyIterations);
+            if let (true, Some(d)) = (is_new, other.derivations.get(fact)) {
+                self.derivations.insert(translated, Derivation {
+                    rule: rule_indexes[d.rule],
+                    bindings: d.bindings.iter()
+                        .map(|(k, v)| (translate_variable(table, *k), v.translate(table)))
+                        .collect(),
+                    premises: d.premises.iter().map(|p| p.translate(table)).collect(),
+                });
             }
+        }
 
-            if self.facts.len() >= limits.max_facts as usize {
-                return Err(crate::error::RunLimit::TooManyFacts);
-            }
+        for (name, arity) in other.arities.drain() {
+            self.arities.entry(translate_symbol(table, name)).or_insert(arity);
+        }
+    }
 
-            let now = SystemTime::now();
-            if now >= time_limit {
-                return Err(crate::error::RunLimit::Timeout);
-            }
+    /// like `merge`, but first moves the predicates of `other` to the
+    /// namespace `ns` (see `Rule::namespaced`), so that its facts and rules
+    /// stay apart from the ones of this world and of other namespaces. Both
+    /// worlds must use `symbols`
+    pub fn merge_namespaced(&mut self, mut other: World, ns: &str, symbols: &mut SymbolTable) {
+        other.rules = Arc::new(other.rules.iter().map(|r| r.namespaced(symbols, ns)).collect());
+        other.facts = other.facts.iter().map(|f| f.namespaced(symbols, ns)).collect();
+        other.fact_origins = other.fact_origins.into_iter().map(|(f, o)| (f.namespaced(symbols, ns), o)).collect();
+        other.derivations = other.derivations.into_iter().map(|(f, d)| {
+            let premises = d.premises.iter().map(|p| p.namespaced(symbols, ns)).collect();
+            (f.namespaced(symbols, ns), Derivation { premises, ..d })
+        }).collect();
+        other.arities = other.arities.into_iter().map(|(name, arity)| {
+            let name = symbols.print_symbol(name);
+            (symbols.insert_namespaced(ns, &name), arity)
+        }).collect();
+        self.merge(other);
+    }
+
+    /// like `merge`, but fails if `other` declares a predicate with another
+    /// arity than this world, or uses it with another number of terms.
+    /// The world is left unchanged on failure
+    pub fn try_merge(&mut self, other: World) -> Result<(), crate::error::Merge> {
+        self.check_merge(&other, &HashMap::new())?;
+        self.merge(other);
+        Ok(())
+    }
+
+    /// like `try_merge`, for a world built with the source table of
+    /// `remapping`. Also fails if `other` uses symbols missing from it
+    pub fn try_merge_remapped(&mut self, other: World, remapping: &SymbolRemapping) -> Result<(), crate::error::Merge> {
+        for fact in other.facts.iter() {
+            remapping.check_fact(fact)?;
+        }
+        for rule in other.rules.iter() {
+            remapping.check_rule(rule)?;
+        }
+        if let Some(name) = other.arities.keys().find(|name| remapping.get(**name).is_none()) {
+            return Err(crate::error::Merge::MissingSymbol(*name));
         }
 
+        self.check_merge(&other, remapping.table())?;
+        self.merge_translated(other, remapping.table());
         Ok(())
     }
 
-    pub fn query(&self, pred: Predicate) -> Vec<&Fact> {
-        self.facts
-            .iter()
-            .filter(|f| {
-                f.predicate.name == pred.name
-                    && f.predicate
-                        .ids
-                        .iter()
-                        .zip(&pred.ids)
-                        .all(|(fid, pid)| match (fid, pid) {
-                            (ID::Symbol(_), ID::Variable(_)) => true,
-                            (ID::Symbol(i), ID::Symbol(ref j)) => i == j,
-                            (ID::Integer(i), ID::Integer(ref j)) => i == j,
-                            (ID::Str(i), ID::Str(ref j)) => i == j,
-                            (ID::Date(i), ID::Date(ref j)) => i == j,
-                            _ => false,
-                        })
-            })
-            .collect::<Vec<_>>()
+    /// the arities of `other`, translated with `table`, against the
+    /// declarations of this world
+    fn check_merge(&self, other: &World, table: &HashMap<Symbol, Symbol>) -> Result<(), crate::error::Merge> {
+        let declarations = other.arities.iter().map(|(name, arity)| (*name, *arity));
+        let predicates = other.facts.iter().map(|f| &f.predicate)
+            .chain(other.rules.iter().flat_map(|r| {
+                core::iter::once(&r.head).chain(r.body.iter()).chain(r.negated.iter())
+            }))
+            .map(|p| (p.name, p.ids.len()));
+
+        for (name, found) in declarations.chain(predicates) {
+            let predicate = translate_symbol(table, name);
+            match self.arities.get(&predicate) {
+                Some(expected) if *expected != found => {
+                    return Err(crate::error::Merge::ArityConflict { predicate, expected: *expected, found });
+                },
+                _ => {},
+            }
+        }
+        Ok(())
     }
 
-    pub fn query_rule(&self, rule: Rule) -> Vec<Fact> {
-        let mut new_facts: Vec<Fact> = Vec::new();
-        rule.apply(&self.facts, &mut new_facts);
-        new_facts
+    /// iterates over the current facts with this predicate name, including
+    /// the ones derived by previous runs
+    pub fn facts_for(&self, name: Symbol) -> impl Iterator<Item = &Fact> {
+        self.facts.with_name(name)
     }
-}
 
-pub fn sym(syms: &mut SymbolTable, name: &str) -> ID {
-    let id = syms.insert(name);
-    ID::Symbol(id)
-}
+    /// iterates over the rules in order, with their index and origin
+    pub fn rules(&self) -> impl Iterator<Item = RuleEntry<'_>> {
+        self.rules.iter().enumerate().map(move |(index, rule)| RuleEntry {
+            index,
+            rule,
+            origin: self.rule_origins.get(&index).copied(),
+        })
+    }
 
-pub struct RunLimits {
-    pub max_facts: u32,
-    pub max_iterations: u32,
-    pub max_time: Duration,
-}
+    /// checks if the fact is currently present, added directly or derived
+    pub fn contains(&self, fact: &Fact) -> bool {
+        self.facts.contains(fact)
+    }
 
-impl std::default::Default for RunLimits {
-    fn default() -> Self {
-        RunLimits {
-            max_facts: 1000,
-            max_iterations: 100,
-            max_time: Duration::from_millis(1),
-        }
+    /// current number of facts for each predicate name, including derived facts
+    pub fn fact_count_by_predicate(&self) -> HashMap<Symbol, usize> {
+        self.facts.count_by_name()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// removes a fact, returns true if it was present
+    ///
+    /// the facts that were derived from it are not retracted: use
+    /// `reset_derived` before running the rules again
+    pub fn remove_fact(&mut self, fact: &Fact) -> bool {
+        self.fact_origins.remove(fact);
+        self.derivations.remove(fact);
+        self.facts.remove(fact)
+    }
 
-    #[test]
-    fn family() {
-        let mut w = World::new();
-        let mut syms = SymbolTable::new();
+    /// removes all the facts matching a predicate, which can contain
+    /// variables and wildcards, and returns how many were removed
+    ///
+    /// as with `remove_fact`, derived facts are not retracted
+    pub fn retract_matching(&mut self, pred: &Predicate) -> usize {
+        let matching = self.facts.matching(pred).cloned().collect::<Vec<_>>();
+        for fact in matching.iter() {
+            self.remove_fact(fact);
+        }
+        matching.len()
+    }
 
-        let a = syms.add("A");
-        let b = syms.add("B");
-        let c = syms.add("C");
-        let d = syms.add("D");
-        let e = syms.add("e");
-        let parent = syms.insert("parent");
-        let grandparent = syms.insert("grandparent");
+    /// removes the facts generated by `run_with_provenance`, keeping the
+    /// facts that were added directly
+    ///
+    /// facts generated by `run` or `run_with_limits` are not tracked and
+    /// stay in the world
+    pub fn reset_derived(&mut self) {
+        for (fact, _) in self.derivations.drain() {
+            self.facts.remove(&fact);
+            self.fact_origins.remove(&fact);
+        }
+    }
 
-        w.add_fact(fact(parent, &[&a, &b]));
-        w.add_fact(fact(parent, &[&b, &c]));
-        w.add_fact(fact(parent, &[&c, &d]));
+    /// declares the number of terms of a predicate, checked by
+    /// `add_fact_checked` and `add_rule_checked`
+    pub fn declare_predicate(&mut self, name: Symbol, arity: usize) {
+        self.arities.insert(name, arity);
+    }
 
-        let r1 = rule(
-            grandparent,
-            &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")],
-            &[
-                pred(parent, &[var(&mut syms, "grandparent"), var(&mut syms, "parent")]),
-                pred(parent, &[var(&mut syms, "parent"), var(&mut syms, "grandchild")]),
-            ],
-        );
+    /// declares the type of each term of a predicate, and its arity (see
+    /// `declare_predicate`). Facts and rules that do not follow it are
+    /// rejected by `try_add_fact` and `try_add_rule`
+    ///
+    /// the schemas are not serialized
+    pub fn declare_schema(&mut self, name: Symbol, types: &[TermType]) {
+        self.declare_predicate(name, types.len());
+        self.schemas.insert(name, types.to_vec());
+    }
 
-        println!("symbols: {:?}", syms);
-        println!("testing r1: {}", syms.print_rule(&r1));
-        let query_rule_result = w.query_rule(r1);
-        println!("grandparents query_rules: {:?}", query_rule_result);
-        println!("current facts: {:?}", w.facts);
+    pub fn schemas(&self) -> &HashMap<Symbol, Vec<TermType>> {
+        &self.schemas
+    }
 
-        let r2 = rule(
-            grandparent,
-            &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")],
-            &[
-                pred(parent, &[var(&mut syms, "grandparent"), var(&mut syms, "parent")]),
-                pred(parent, &[var(&mut syms, "parent"), var(&mut syms, "grandchild")]),
-            ],
-        );
+    /// adds a fact after verifying it against the declared arities, and
+    /// the world's size against the memory limit
+    pub fn add_fact_checked(&mut self, fact: Fact, limits: &RunLimits) -> Result<(), crate::error::Check> {
+        self.check_arity(&fact.predicate)?;
+        if !self.facts.contains(&fact) && self.exceeds_memory(&fact, limits) {
+            let context = limit_context(0, self.facts.len(), Instant::now(None), None);
+            return Err(crate::error::RunLimit::TooMuchMemory(context).into());
+        }
+        self.add_fact(fact);
+        Ok(())
+    }
 
-        println!("adding r2: {}", syms.print_rule(&r2));
-        w.add_rule(r2);
+    /// adds a rule after verifying its head, body and negated predicates
+    /// against the declared arities, and its complexity and number of body
+    /// predicates against the limits
+    ///
+    /// returns true if the rule was added (see `add_rule`)
+    pub fn add_rule_checked(&mut self, rule: Rule, limits: &RunLimits) -> Result<bool, crate::error::Check> {
+        if let Some(max) = limits.max_body_predicates {
+            if rule.body.len() > max {
+                return Err(crate::error::Check::TooManyBodyPredicates { found: rule.body.len(), max });
+            }
+        }
 
-        w.run().unwrap();
+        if let Some(max) = &limits.max_rule_complexity {
+            let complexity = rule.complexity();
+            if complexity.exceeds(max) {
+                return Err(crate::error::Check::RuleTooComplex(complexity));
+            }
+        }
 
-        println!("parents:");
-        let res = w.query(pred(parent, &[var(&mut syms, "parent"), var(&mut syms, "child")]));
-        for fact in res {
-            println!("\t{}", syms.print_fact(fact));
+        for p in core::iter::once(&rule.head).chain(rule.body.iter()).chain(rule.negated.iter()) {
+            self.check_arity(p)?;
         }
+        Ok(self.add_rule(rule))
+    }
 
-        println!(
-            "parents of B: {:?}",
-            w.query(pred(parent, &[&var(&mut syms, "parent"), &b]))
-        );
-        println!(
-            "grandparents: {:?}",
-            w.query(pred(grandparent, &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")]))
-        );
-        w.add_fact(fact(parent, &[&c, &e]));
-        w.run().unwrap();
-        let mut res = w.query(pred(grandparent, &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")]));
-        println!("grandparents after inserting parent(C, E): {:?}", res);
+    fn check_arity(&self, p: &Predicate) -> Result<(), crate::error::Check> {
+        match self.arities.get(&p.name) {
+            Some(expected) if *expected != p.ids.len() => Err(crate::error::Check::ArityMismatch {
+                predicate: p.name,
+                expected: *expected,
+                found: p.ids.len(),
+            }),
+            _ => Ok(()),
+        }
+    }
 
-        let res = res.drain(..).cloned().collect::<HashSet<_>>();
-        let compared = (vec![
-            fact(grandparent, &[&a, &c]),
-            fact(grandparent, &[&b, &d]),
-            fact(grandparent, &[&b, &e]),
-        ])
-        .drain(..)
-        .collect::<HashSet<_>>();
-        assert_eq!(res, compared);
+    /// lists the predicates used with multiple arities in facts and rules
+    pub fn arity_report(&self) -> BTreeMap<Symbol, BTreeSet<usize>> {
+        let mut arities: BTreeMap<Symbol, BTreeSet<usize>> = BTreeMap::new();
+        let predicates = self.facts.iter().map(|f| &f.predicate)
+            .chain(self.rules.iter().flat_map(|r| {
+                core::iter::once(&r.head).chain(r.body.iter()).chain(r.negated.iter())
+            }));
 
-        /*w.add_rule(rule("siblings", &[var("A"), var("B")], &[
-          pred(parent, &[var(parent), var("A")]),
-          pred(parent, &[var(parent), var("B")])
-        ]));
+        for p in predicates {
+            arities.entry(p.name).or_default().insert(p.ids.len());
+        }
 
-        w.run();
-        println!("siblings: {:#?}", w.query(pred("siblings", &[var("A"), var("B")])));
-        */
+        arities.retain(|_, a| a.len() > 1);
+        arities
     }
 
-    #[test]
-    fn numbers() {
-        let mut w = World::new();
-        let mut syms = SymbolTable::new();
+    /// adds a rule, unless the exact same rule is already present
+    ///
+    /// returns true if the rule was added
+    pub fn add_rule(&mut self, rule: Rule) -> bool {
+        if self.contains_rule(&rule) {
+            false
+        } else {
+            Arc::make_mut(&mut self.rules).push(rule);
+            true
+        }
+    }
 
-        let abc = syms.add("abc");
-        let def = syms.add("def");
-        let ghi = syms.add("ghi");
-        let jkl = syms.add("jkl");
-        let mno = syms.add("mno");
-        let aaa = syms.add("AAA");
-        let bbb = syms.add("BBB");
-        let ccc = syms.add("CCC");
-        let t1 = syms.insert("t1");
-        let t2 = syms.insert("t2");
-        let join = syms.insert("join");
+    /// true if the rule was added without an origin
+    fn contains_rule(&self, rule: &Rule) -> bool {
+        self.rules.iter().enumerate().any(|(i, r)| r == rule && !self.rule_origins.contains_key(&i))
+    }
 
-        w.add_fact(fact(t1, &[&int(0), &abc]));
-        w.add_fact(fact(t1, &[&int(1), &def]));
-        w.add_fact(fact(t1, &[&int(2), &ghi]));
-        w.add_fact(fact(t1, &[&int(3), &jkl]));
-        w.add_fact(fact(t1, &[&int(4), &mno]));
+    /// adds a rule that only sees the facts visible from its origin, and
+    /// whose generated facts get the same origin
+    ///
+    /// returns true if the rule was added (see `add_rule`)
+    pub fn add_rule_with_origin(&mut self, rule: Rule, origin: Origin) -> bool {
+        if self.rules.iter().enumerate().any(|(i, r)| *r == rule && self.rule_origins.get(&i) == Some(&origin)) {
+            false
+        } else {
+            self.rule_origins.insert(self.rules.len(), origin);
+            Arc::make_mut(&mut self.rules).push(rule);
+            true
+        }
+    }
+
+    /// facts that a rule from `origin` can use
+    pub fn visible_facts(&self, origin: &Origin) -> FactSet {
+        let mut visible = self.facts.empty();
+        visible.extend(self.facts.iter().filter(|f| match self.fact_origins.get(*f) {
+            None => true,
+            Some(origins) => origins.iter().any(|o| origin.can_see(o)),
+        }).cloned());
+        visible
+    }
+
+    /// records that `origin` produced a fact already present
+    ///
+    /// returns true if the fact became visible to more rules
+    fn add_origin(&mut self, fact: &Fact, origin: Option<Origin>) -> bool {
+        match (self.fact_origins.get_mut(fact), origin) {
+            // the fact is already visible everywhere
+            (None, _) => false,
+            (Some(_), None) => {
+                self.fact_origins.remove(fact);
+                true
+            },
+            (Some(origins), Some(o)) => origins.insert(o),
+        }
+    }
+
+    pub fn run(&mut self) -> Result<(), crate::error::Error> {
+        self.run_with_limits(RunLimits::default())
+    }
+
+    /// applies the rules until no new facts are generated
+    ///
+    /// rules are evaluated stratum by stratum (see `World::strata`), so a
+    /// negated predicate is fully derived before any rule negating it runs
+    pub fn run_with_limits(&mut self, limits: RunLimits) -> Result<(), crate::error::Error> {
+        self.evaluate(limits, Evaluation::Plain, &mut |_| ControlFlow::Continue(()), &mut RunStats::default())
+    }
+
+    /// like `run_with_limits`, but `max_time` and the durations in the
+    /// stats are measured with `clock` instead of the default one, see
+    /// the `time` module
+    pub fn run_with_limits_and_clock(&mut self, limits: RunLimits, clock: &impl time::Clock) -> Result<(), crate::error::Error> {
+        self.evaluate_with_clock(limits, Evaluation::Plain, &mut |_| ControlFlow::Continue(()), &mut RunStats::default(), Some(clock))
+    }
+
+    /// like `run_with_limits`, but adds to `diagnostics` the errors that
+    /// did not stop the run, see `RuleErrors`
+    pub fn run_with_diagnostics(
+        &mut self,
+        limits: RunLimits,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Result<(), crate::error::Error> {
+        let mut stats = RunStats::default();
+        let res = self.evaluate(limits, Evaluation::Plain, &mut |_| ControlFlow::Continue(()), &mut stats);
+        diagnostics.append(&mut stats.diagnostics);
+        res
+    }
+
+    /// like `run_with_limits`, but returns counters on the rules and
+    /// iterations, also available in the error if the run stopped early
+    pub fn run_with_stats(&mut self, limits: RunLimits) -> Result<RunStats, crate::error::RunStopped> {
+        let mut stats = RunStats::default();
+        match self.evaluate(limits, Evaluation::Timed, &mut |_| ControlFlow::Continue(()), &mut stats) {
+            Ok(()) => Ok(stats),
+            Err(error) => Err(crate::error::RunStopped { error, stats: Box::new(stats) }),
+        }
+    }
+
+    /// like `run_with_limits`, but calls `callback` after each iteration.
+    /// If it returns `ControlFlow::Break`, the run stops with a
+    /// `RunLimit::Cancelled` error, keeping the facts generated so far
+    pub fn run_with_callback<F>(&mut self, limits: RunLimits, mut callback: F) -> Result<(), crate::error::Error>
+    where
+        F: FnMut(RunProgress) -> ControlFlow<()>,
+    {
+        self.evaluate(limits, Evaluation::Plain, &mut callback, &mut RunStats::default())
+    }
+
+    /// like `run_with_limits`, but records in `derivations` the rule and
+    /// bindings that produced each new fact
+    pub fn run_with_provenance(&mut self, limits: RunLimits) -> Result<(), crate::error::Error> {
+        self.evaluate(limits, Evaluation::Provenance, &mut |_| ControlFlow::Continue(()), &mut RunStats::default())
+    }
+
+    /// like `run_with_stats`, but a run stopped by a limit returns
+    /// `RunOutcome::Partial` instead of an error
+    pub fn run_partial(&mut self, limits: RunLimits) -> Result<RunOutcome, crate::error::Error> {
+        let mut stats = RunStats::default();
+        match self.evaluate(limits, Evaluation::Plain, &mut |_| ControlFlow::Continue(()), &mut stats) {
+            Ok(()) => Ok(RunOutcome::Complete(stats)),
+            Err(crate::error::Error::RunLimit(limit)) => Ok(RunOutcome::Partial { stats, limit }),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// false if the last run stopped on a limit: the world then misses
+    /// some of the facts the rules would generate, and `check_caveat`
+    /// always fails
+    pub fn is_complete(&self) -> bool {
+        !self.partial
+    }
+
+    fn evaluate(
+        &mut self,
+        limits: RunLimits,
+        evaluation: Evaluation,
+        callback: &mut dyn FnMut(RunProgress) -> ControlFlow<()>,
+        stats: &mut RunStats,
+    ) -> Result<(), crate::error::Error> {
+        self.evaluate_with_clock(limits, evaluation, callback, stats, time::default_clock())
+    }
+
+    fn evaluate_with_clock(
+        &mut self,
+        limits: RunLimits,
+        evaluation: Evaluation,
+        callback: &mut dyn FnMut(RunProgress) -> ControlFlow<()>,
+        stats: &mut RunStats,
+        clock: Option<&dyn time::Clock>,
+    ) -> Result<(), crate::error::Error> {
+        let res = self.saturate(limits, evaluation, callback, stats, clock);
+        self.partial = matches!(res, Err(crate::error::Error::RunLimit(_)));
+        self.refresh_views();
+        res
+    }
+
+    fn saturate(
+        &mut self,
+        limits: RunLimits,
+        evaluation: Evaluation,
+        callback: &mut dyn FnMut(RunProgress) -> ControlFlow<()>,
+        stats: &mut RunStats,
+        clock: Option<&dyn time::Clock>,
+    ) -> Result<(), crate::error::Error> {
+        stats.rules = vec![RuleStats::default(); self.rules.len()];
+        let provenance = evaluation == Evaluation::Provenance;
+        let strata = self.strata()?;
+
+        // rules that need predicates no fact or rule can provide will never match
+        let reachable = self.reachable_predicates(
+            &self.facts.iter().map(|f| f.predicate.name).collect()
+        );
+
+        let start = Instant::now(clock);
+        let time_limit = limits.deadline(start);
+        let budget = Budget::new(usize::MAX, &limits, start);
+        let no_facts = self.facts.empty();
+        let mut index = 0;
+
+        for stratum in strata.iter() {
+            loop {
+                // another pass is needed to generate new facts, or to
+                // verify that there are none left
+                if limits.max_iterations == Some(index) {
+                    let context = limit_context(index, self.facts.len(), start, None);
+                    return Err(crate::error::RunLimit::TooManyIterations(context).into());
+                }
+
+                stats.iterations.push(IterationStats::default());
+                let iteration_span = trace::IterationSpan::enter(index);
+                let iteration_start = Instant::now(clock);
+                let mut new_facts: Vec<Fact> = Vec::new();
+                let mut producers: Vec<usize> = Vec::new();
+                let mut bindings: Vec<HashMap<u32, ID>> = Vec::new();
+                let mut stale_facts: Vec<Fact> = Vec::new();
+                for rule_index in stratum.iter() {
+                    let rule = &self.rules[*rule_index];
+                    if !rule.body.iter().all(|p| reachable.contains(&p.name)) {
+                        continue;
+                    }
+
+                    let visible;
+                    let facts = match self.rule_origins.get(rule_index) {
+                        Some(origin) => {
+                            visible = self.visible_facts(origin);
+                            &visible
+                        },
+                        None => &self.facts,
+                    };
+
+                    // facts with origins can get another one when generated
+                    // again (see `add_origin`), so they are only left out of
+                    // the generated facts without origins
+                    let known = if self.fact_origins.is_empty() { &self.facts } else { &no_facts };
+
+                    let first_new = new_facts.len();
+                    let mut derived = Vec::new();
+                    let rule_start = (evaluation == Evaluation::Timed).then(|| Instant::now(clock));
+                    let rule_span = trace::RuleSpan::enter(*rule_index, &budget, clock);
+                    let skipped = rule.apply_budgeted(facts, &mut derived, Some(&budget), limits.plan_joins, None, Some(known));
+                    rule_span.exit(&budget, derived.len() + skipped);
+                    let rule_stats = &mut stats.rules[*rule_index];
+                    rule_stats.applications += 1;
+                    rule_stats.emitted += derived.len() + skipped;
+                    rule_stats.skipped += skipped;
+                    if let Some(rule_start) = rule_start {
+                        rule_stats.total_time += rule_start.elapsed();
+                    }
+                    if let Err(e) = budget.check() {
+                        stats.finish(iteration_start, start);
+                        let context = limit_context(index, self.facts.len(), start, Some(*rule_index));
+                        return Err(e.with_context(context).into());
+                    }
+                    for e in budget.take_diagnostics() {
+                        let diagnostic = Diagnostic::new(*rule_index, e);
+                        if !stats.diagnostics.contains(&diagnostic) {
+                            stats.diagnostics.push(diagnostic);
+                        }
+                    }
+                    if let Some(e) = budget.failure() {
+                        stats.finish(iteration_start, start);
+                        return Err(crate::error::RuleFailed { rule: *rule_index, error: e.clone() }.into());
+                    }
+                    for (fact, b) in derived {
+                        new_facts.push(fact);
+                        if provenance {
+                            bindings.push(b);
+                        }
+                    }
+                    producers.resize(new_facts.len(), *rule_index);
+                    //println!("new_facts after applying {:?}:\n{:#?}", rule, new_facts);
+
+                    if rule.aggregate.is_some() {
+                        // aggregate results replace the ones computed in previous runs
+                        stale_facts.extend(self.facts.iter().filter(|f| {
+                            new_facts[first_new..].iter().any(|new| rule.is_stale_aggregate(f, new))
+                        }).cloned());
+                    }
+                }
+
+                for fact in stale_facts.iter() {
+                    self.facts.remove(fact);
+                    self.derivations.remove(fact);
+                    self.fact_origins.remove(fact);
+                }
+
+                let mut changed = false;
+                let mut bindings = bindings.into_iter();
+                // new facts stored by each rule in this iteration
+                let mut contributed = vec![0usize; self.rules.len()];
+                let mut pending = new_facts.drain(..).zip(producers.drain(..));
+                while let Some((fact, rule)) = pending.next() {
+                    let origin = self.rule_origins.get(&rule).cloned();
+                    if self.facts.contains(&fact) {
+                        changed |= self.add_origin(&fact, origin);
+                        bindings.next();
+                        continue;
+                    }
+
+                    if limits.max_facts.is_some_and(|max| self.facts.len() >= max as usize) {
+                        stats.finish(iteration_start, start);
+                        // blame the rule with the most new facts, including
+                        // the ones that could not be stored
+                        contributed[rule] += 1;
+                        for (fact, rule) in pending {
+                            if !self.facts.contains(&fact) {
+                                contributed[rule] += 1;
+                            }
+                        }
+                        let top = contributed.iter().enumerate().max_by_key(|(_, count)| **count).map(|(i, _)| i);
+                        let context = limit_context(index, self.facts.len(), start, top);
+                        return Err(crate::error::RunLimit::TooManyFacts(context).into());
+                    }
+                    if self.exceeds_memory(&fact, &limits) {
+                        stats.finish(iteration_start, start);
+                        let context = limit_context(index, self.facts.len(), start, Some(rule));
+                        return Err(crate::error::RunLimit::TooMuchMemory(context).into());
+                    }
+
+                    if provenance {
+                        let b = bindings.next().expect("one set of bindings per new fact");
+                        let derivation = self.derivation(rule, b);
+                        self.derivations.insert(fact.clone(), derivation);
+                    }
+                    if let Some(origin) = origin {
+                        self.fact_origins.entry(fact.clone()).or_default().insert(origin);
+                    }
+                    self.facts.insert(fact);
+                    contributed[rule] += 1;
+                    stats.rules[rule].new += 1;
+                    if let Some(iteration) = stats.iterations.last_mut() {
+                        iteration.new += 1;
+                    }
+                    changed = true;
+                }
+                stats.finish(iteration_start, start);
+                iteration_span.exit(stats.iterations.last().map_or(0, |iteration| iteration.new));
+                index += 1;
+
+                let progress = RunProgress {
+                    iteration: index,
+                    facts: self.facts.len(),
+                    elapsed: start.elapsed(),
+                };
+                if callback(progress).is_break() {
+                    return Err(crate::error::RunLimit::Cancelled.into());
+                }
+
+                if !changed {
+                    break;
+                }
+
+                if time_limit.is_some_and(|limit| limit.has_passed()) {
+                    let context = limit_context(index, self.facts.len(), start, None);
+                    return Err(crate::error::RunLimit::Timeout(context).into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// true if adding `fact` would go over `limits.max_memory`
+    fn exceeds_memory(&self, fact: &Fact, limits: &RunLimits) -> bool {
+        limits.max_memory.is_some_and(|max| self.facts.estimated_size() + fact.estimated_size() > max)
+    }
+
+    fn derivation(&self, rule: usize, bindings: HashMap<u32, ID>) -> Derivation {
+        let r = &self.rules[rule];
+        let premises = if r.aggregate.is_some() {
+            Vec::new()
+        } else {
+            r.body.iter().filter_map(|p| {
+                let mut p = p.clone();
+                for id in p.ids.iter_mut() {
+                    if let ID::Variable(i) = id {
+                        if let Some(value) = bindings.get(i) {
+                            *id = value.clone();
+                        }
+                    }
+                }
+
+                // wildcards can match multiple facts, any of them is a valid premise
+                let premise = self.facts.matching(&p).next().cloned();
+                premise
+            }).collect()
+        };
+
+        Derivation { rule, bindings, premises }
+    }
+
+    /// returns how a fact was derived by `run_with_provenance`, or `None`
+    /// if it was added directly
+    pub fn origin_of(&self, fact: &Fact) -> Option<&Derivation> {
+        self.derivations.get(fact)
+    }
+
+    /// recursively expands the premises of a fact down to the facts that
+    /// were added directly
+    pub fn explain(&self, fact: &Fact) -> ProofTree {
+        let mut visited = HashSet::new();
+        self.explain_inner(fact, &mut visited)
+    }
+
+    fn explain_inner(&self, fact: &Fact, visited: &mut HashSet<Fact>) -> ProofTree {
+        let derivation = match self.derivations.get(fact) {
+            // a fact cannot be used to prove itself
+            Some(d) if visited.insert(fact.clone()) => d,
+            _ => return ProofTree { fact: fact.clone(), rule: None, premises: Vec::new() },
+        };
+
+        let premises = derivation.premises.iter().map(|p| self.explain_inner(p, visited)).collect();
+        visited.remove(fact);
+
+        ProofTree { fact: fact.clone(), rule: Some(derivation.rule), premises }
+    }
+
+    /// lists the predicates each rule depends on, in the same order as `rules`
+    pub fn dependency_graph(&self) -> Vec<RuleDependencies> {
+        self.rules.iter().map(|rule| RuleDependencies {
+            head: rule.head.name,
+            body: rule.body.iter().map(|p| p.name).collect(),
+            negated: rule.negated.iter().map(|p| p.name).collect(),
+        }).collect()
+    }
+
+    /// computes the predicates that can be derived by the rules, starting
+    /// from the `seed` predicates (usually the ones of the known facts)
+    ///
+    /// negated predicates are not taken into account since they do not
+    /// prevent a rule from firing
+    pub fn reachable_predicates(&self, seed: &HashSet<Symbol>) -> HashSet<Symbol> {
+        let mut reachable = seed.clone();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for rule in self.rules.iter() {
+                if !reachable.contains(&rule.head.name)
+                    && rule.body.iter().all(|p| reachable.contains(&p.name)) {
+                    reachable.insert(rule.head.name);
+                    changed = true;
+                }
+            }
+        }
+
+        reachable
+    }
+
+    /// groups the rule indexes by stratum, in evaluation order
+    ///
+    /// a rule's stratum is the one of its head predicate, which must be
+    /// at least the stratum of its body predicates, and strictly above
+    /// the stratum of its negated predicates (and of its body predicates
+    /// for aggregates). If a predicate depends negatively on itself,
+    /// directly or transitively, the strata cannot be computed and the
+    /// rule set is rejected
+    pub fn strata(&self) -> Result<Vec<Vec<usize>>, crate::error::Stratification> {
+        let mut strata: HashMap<Symbol, usize> = HashMap::new();
+        for rule in self.rules.iter() {
+            strata.insert(rule.head.name, 0);
+            for p in rule.body.iter().chain(rule.negated.iter()) {
+                strata.insert(p.name, 0);
+            }
+        }
+
+        let max_stratum = strata.len();
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for rule in self.rules.iter() {
+                let mut stratum = strata[&rule.head.name];
+                // aggregates need their body predicates to be fully derived
+                let offset = if rule.aggregate.is_some() { 1 } else { 0 };
+                for p in rule.body.iter() {
+                    stratum = stratum.max(strata[&p.name] + offset);
+                }
+                for p in rule.negated.iter() {
+                    stratum = stratum.max(strata[&p.name] + 1);
+                }
+
+                if stratum > max_stratum {
+                    return Err(crate::error::Stratification { predicate: rule.head.name });
+                }
+
+                if stratum != strata[&rule.head.name] {
+                    strata.insert(rule.head.name, stratum);
+                    changed = true;
+                }
+            }
+        }
+
+        let mut res: Vec<Vec<usize>> = Vec::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            let stratum = strata[&rule.head.name];
+            if res.len() <= stratum {
+                res.resize(stratum + 1, Vec::new());
+            }
+            res[stratum].push(index);
+        }
+        res.retain(|rules| !rules.is_empty());
+
+        Ok(res)
+    }
+
+    /// facts with the name and number of terms of `pred`, where variables
+    /// match symbols, see `query_with_constraints`
+    pub fn query(&self, pred: Predicate) -> Vec<&Fact> {
+        self.query_with_constraints(pred, &[])
+    }
+
+    /// like `query`, but a variable with constraints matches any value
+    /// satisfying them
+    pub fn query_with_constraints(&self, pred: Predicate, constraints: &[Constraint]) -> Vec<&Fact> {
+        self.facts
+            .matching(&pred)
+            .filter(|f| {
+                f.predicate.ids.iter().zip(&pred.ids).all(|(fid, pid)| match (fid, pid) {
+                    (_, ID::Variable(v)) if constraints.iter().any(|c| c.id == *v) => {
+                        constraints.iter().all(|c| c.check(*v, fid))
+                    },
+                    (ID::Symbol(_), ID::Variable(_)) => true,
+                    (_, ID::Wildcard) => true,
+                    (ID::Symbol(i), ID::Symbol(ref j)) => i == j,
+                    (ID::Integer(i), ID::Integer(ref j)) => i == j,
+                    (ID::Str(i), ID::Str(ref j)) => i == j,
+                    (ID::Date(i), ID::Date(ref j)) => i == j,
+                    _ => false,
+                })
+            })
+            .collect::<Vec<_>>()
+    }
+
+    /// unifies the variables of `pred` with the matching facts, and returns
+    /// one set of bindings per match satisfying the constraints
+    pub fn query_bindings(&self, pred: &Predicate, constraints: &[Constraint]) -> Vec<HashMap<u32, ID>> {
+        let variables = pred.ids.iter()
+            .filter_map(|id| match id {
+                ID::Variable(i) => Some(*i),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+
+        CombineIt::new(
+            MatchedVariables::new(variables),
+            core::slice::from_ref(pred),
+            constraints,
+            &[],
+            &[],
+            &self.facts,
+        ).collect()
+    }
+
+    /// facts matching `pred` whose values for its variables satisfy the
+    /// constraints and expressions, as in the body of a rule with `pred`
+    /// as its only predicate
+    ///
+    /// constraints on variables that are not in `pred` never hold, and
+    /// expressions binding a variable add it for the next ones
+    pub fn query_constrained(&self, pred: &Predicate, constraints: &[Constraint], expressions: &[Expression]) -> Vec<&Fact> {
+        let variables = pred.ids.iter()
+            .filter_map(|id| match id {
+                ID::Variable(i) => Some(*i),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+
+        self.facts.matching(pred).filter(|fact| {
+            let mut matched = MatchedVariables::new(variables.clone());
+            let unified = pred.ids.iter().zip(&fact.predicate.ids).all(|(id, value)| match id {
+                ID::Variable(i) => matched.insert(*i, value),
+                _ => true,
+            });
+            let bindings = match matched.complete() {
+                Some(bindings) if unified => bindings,
+                _ => return false,
+            };
+
+            constraints.iter().all(|c| bindings.get(&c.id).is_some_and(|value| c.check(c.id, value)))
+                && evaluate_expressions(expressions, bindings, None).is_some()
+        }).collect()
+    }
+
+    /// like `query_bindings`, as a `ResultSet` to read the values by column
+    pub fn query_results(&self, pred: &Predicate, constraints: &[Constraint]) -> ResultSet {
+        ResultSet::from(self.query_bindings(pred, constraints))
+    }
+
+    pub fn query_rule(&self, rule: Rule) -> Vec<Fact> {
+        let mut new_facts: Vec<Fact> = Vec::new();
+        rule.apply(&self.facts, &mut new_facts);
+        new_facts
+    }
+
+    /// like `query_rule`, with a trace of each fact tried for each body
+    /// predicate and why it was rejected. The body predicates are joined
+    /// in order, without the indexes and join order of `query_rule`, and
+    /// the trace stops at the `RunLimits::default` number of candidates
+    ///
+    /// see `SymbolTable::print_evaluation_trace` to print it
+    pub fn query_rule_traced(&self, rule: &Rule) -> (Vec<Fact>, EvaluationTrace) {
+        let limits = RunLimits { max_time: None, ..RunLimits::default() };
+        (self.query_rule(rule.clone()), evaluation_trace::trace(self, rule, &limits))
+    }
+
+    /// like `query_rule`, with some variables fixed to a value, see
+    /// `Rule::apply_with_initial_bindings`
+    pub fn query_rule_with_bindings(&self, rule: &Rule, initial: &HashMap<u32, ID>) -> Vec<Fact> {
+        let mut new_facts: Vec<Fact> = Vec::new();
+        rule.apply_with_initial_bindings(&self.facts, initial, &mut new_facts);
+        new_facts
+    }
+
+    /// succeeds if at least one of the caveat's queries generates a fact,
+    /// and the world is complete (see `World::is_complete`)
+    ///
+    /// the queries run with `RunLimits::caveat_default` unless `limits` is set.
+    /// They only see the facts currently in the world (`CaveatScope::AsIs`):
+    /// run the world first, or use `check_caveat_with_scope`, to include the
+    /// facts derived by the rules
+    pub fn check_caveat(&self, caveat: &Caveat, limits: Option<&RunLimits>) -> Result<(), crate::error::Caveat> {
+        self.check_queries(caveat, limits, None)
+    }
+
+    /// `symbols` are only used to print the failed queries, see the
+    /// `trace` module
+    fn check_queries(
+        &self,
+        caveat: &Caveat,
+        limits: Option<&RunLimits>,
+        symbols: Option<&SymbolTable>,
+    ) -> Result<(), crate::error::Caveat> {
+        if !self.is_complete() {
+            return Err(crate::error::Caveat::Incomplete);
+        }
+
+        let limits = limits.cloned().unwrap_or_else(RunLimits::caveat_default);
+        for (index, query) in caveat.queries.iter().enumerate() {
+            if query.count_with_limits(&self.facts, &limits, 1)? > 0 {
+                return Ok(());
+            }
+            trace::failed_query(index, query, symbols);
+        }
+
+        Err(crate::error::Caveat::NoMatch)
+    }
+
+    /// evaluates each query of the caveat again, counting the facts matching
+    /// each body predicate and the candidates rejected by each constraint,
+    /// expression and negated predicate, to find why the caveat failed. The
+    /// queries run with `RunLimits::caveat_default`, and see the facts
+    /// currently in the world, like `check_caveat`
+    ///
+    /// see `SymbolTable::print_caveat_failure_report` to print it
+    pub fn explain_caveat_failure(&self, caveat: &Caveat) -> CaveatFailureReport {
+        report::explain(self, caveat, &RunLimits::caveat_default())
+    }
+
+    /// like `check_caveat`, which uses `CaveatScope::AsIs`, but can see the
+    /// facts derived by the rules
+    pub fn check_caveat_with_scope(
+        &self,
+        caveat: &Caveat,
+        limits: Option<&RunLimits>,
+        scope: &CaveatScope,
+    ) -> Result<(), crate::error::Caveat> {
+        self.scoped(scope)?.check_caveat(caveat, limits)
+    }
+
+    /// like `check_caveats`, the world is run once for all the caveats. If
+    /// that run fails, all the caveats fail with its error
+    pub fn check_caveats_with_scope(
+        &self,
+        caveats: &[Caveat],
+        limits: Option<&RunLimits>,
+        overrides: &HashMap<usize, RunLimits>,
+        symbols: &SymbolTable,
+        scope: &CaveatScope,
+    ) -> Result<(), Vec<crate::error::FailedCaveat>> {
+        match self.scoped(scope) {
+            Ok(world) => world.check_caveats(caveats, limits, overrides, symbols),
+            Err(_) if caveats.is_empty() => Ok(()),
+            Err(error) => Err(caveats.iter().enumerate().map(|(index, caveat)| crate::error::FailedCaveat {
+                index,
+                caveat: symbols.print_caveat(caveat),
+                error: error.clone(),
+            }).collect()),
+        }
+    }
+
+    fn scoped(&self, scope: &CaveatScope) -> Result<Cow<'_, World>, crate::error::Caveat> {
+        match scope {
+            CaveatScope::AsIs => Ok(Cow::Borrowed(self)),
+            CaveatScope::AfterRun(limits) => {
+                let mut snapshot = self.clone();
+                snapshot.run_with_limits(limits.clone()).map_err(|e| match e {
+                    crate::error::Error::RunLimit(e) => crate::error::Caveat::RunLimit(e),
+                    crate::error::Error::Stratification(e) => crate::error::Caveat::Stratification(e),
+                    crate::error::Error::RuleFailed(e) => crate::error::Caveat::RuleFailed(e),
+                    e => crate::error::Caveat::Run(Box::new(e)),
+                })?;
+                Ok(Cow::Owned(snapshot))
+            },
+        }
+    }
+
+    /// checks all the caveats, and reports every failure
+    ///
+    /// `overrides` replaces `limits` for the caveats at those positions
+    pub fn check_caveats(
+        &self,
+        caveats: &[Caveat],
+        limits: Option<&RunLimits>,
+        overrides: &HashMap<usize, RunLimits>,
+        symbols: &SymbolTable,
+    ) -> Result<(), Vec<crate::error::FailedCaveat>> {
+        let failed = caveats.iter().enumerate().filter_map(|(index, caveat)| {
+            let limits = overrides.get(&index).or(limits);
+            self.check_queries(caveat, limits, Some(symbols)).err().map(|error| crate::error::FailedCaveat {
+                index,
+                caveat: symbols.print_caveat(caveat),
+                error,
+            })
+        }).collect::<Vec<_>>();
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(failed)
+        }
+    }
+
+    /// sets the current time of the world to `t`, as the ambient fact
+    /// `time(#ambient, t)`, replacing the previous ones. Times before the
+    /// unix epoch are set to the epoch, like in `date`
+    ///
+    /// facts that the rules derived from the previous time are kept, so
+    /// the time should be set before running the world, or on a snapshot
+    /// of a world that was not run, like `check_at` does
+    #[cfg(feature = "std")]
+    pub fn with_time(mut self, t: &SystemTime, symbols: &mut SymbolTable) -> World {
+        let time = symbols.insert("time");
+        let ambient = symbols.add("ambient");
+        let previous = self.query(pred(time, &[ambient.clone(), ID::Wildcard])).into_iter().cloned().collect::<Vec<_>>();
+        for fact in previous.iter() {
+            self.remove_fact(fact);
+        }
+        self.add_fact(fact(time, &[ambient, date(t)]));
+        self
+    }
+
+    /// checks the caveats as they would have been at time `t`: a snapshot
+    /// of the world gets that time (see `with_time`), is run with the
+    /// limits, then the caveats are checked on it
+    ///
+    /// the world itself is not modified. If the run fails, all the caveats
+    /// fail with its error
+    #[cfg(feature = "std")]
+    pub fn check_at(
+        &self,
+        caveats: &[Caveat],
+        t: &SystemTime,
+        limits: &RunLimits,
+        symbols: &mut SymbolTable,
+    ) -> Result<(), Vec<crate::error::FailedCaveat>> {
+        let world = self.snapshot().with_time(t, symbols);
+        world.check_caveats_with_scope(caveats, Some(limits), &HashMap::new(), symbols, &CaveatScope::AfterRun(limits.clone()))
+    }
+
+    /// evaluates the policies in order, and returns the first one with a
+    /// query that produces a fact
+    pub fn evaluate_policies(
+        &self,
+        policies: &[Policy],
+        limits: &RunLimits,
+    ) -> Result<PolicyDecision, crate::error::Policy> {
+        if !self.is_complete() {
+            return Err(crate::error::Policy::Incomplete);
+        }
+
+        for (index, policy) in policies.iter().enumerate() {
+            for query in policy.queries.iter() {
+                if !self.query_rule_with_limits(query.clone(), limits.clone())?.is_empty() {
+                    return Ok(PolicyDecision { index, kind: policy.kind });
+                }
+            }
+        }
+
+        Err(crate::error::Policy::NoMatch)
+    }
+
+    /// checks all the caveats, then evaluates the policies. Returns the
+    /// index of the allow policy that matched
+    ///
+    /// the policies are evaluated even if caveats failed, so the error
+    /// reports both
+    pub fn authorize(
+        &self,
+        caveats: &[Caveat],
+        policies: &[Policy],
+        limits: &RunLimits,
+        symbols: &SymbolTable,
+    ) -> Result<usize, crate::error::Authorization> {
+        let caveats = self.check_caveats(caveats, Some(limits), &HashMap::new(), symbols);
+        let policy = self.evaluate_policies(policies, limits);
+
+        match (caveats, policy) {
+            (Err(failed), policy) => Err(crate::error::Authorization::FailedCaveats { failed, policy }),
+            (Ok(()), Ok(PolicyDecision { index, kind: PolicyKind::Allow })) => Ok(index),
+            (Ok(()), Ok(PolicyDecision { index, kind: PolicyKind::Deny })) => {
+                Err(crate::error::Authorization::Denied(index))
+            },
+            (Ok(()), Err(e)) => Err(crate::error::Authorization::Policy(e)),
+        }
+    }
+
+    /// like `!query_rule(rule).is_empty()`, but stops at the first match
+    /// without building the facts
+    pub fn query_rule_any(&self, rule: &Rule) -> bool {
+        self.query_rule_count(rule, 1) > 0
+    }
+
+    /// number of facts `query_rule` would return, counting at most `limit`
+    /// of them. Like `query_rule`, the same fact is counted once per match
+    pub fn query_rule_count(&self, rule: &Rule, limit: usize) -> usize {
+        rule.count_budgeted(&self.facts, None, false, limit)
+    }
+
+    /// like `query_rule`, but aborts as soon as one of the limits is reached
+    pub fn query_rule_with_limits(&self, rule: Rule, limits: RunLimits) -> Result<Vec<Fact>, crate::error::RunLimit> {
+        let mut new_facts: Vec<Fact> = Vec::new();
+        rule.apply_with_limits(&self.facts, &mut new_facts, &limits)?;
+        Ok(new_facts)
+    }
+
+    /// applies a single rule until it generates no new fact, and returns
+    /// the number of facts added. The rule is not added to the world, and
+    /// the world's rules are not applied
+    ///
+    /// the rule sees all the facts, and the ones it generates are visible
+    /// to every rule. The limits are counted like in `run_with_limits`, and
+    /// matches that cannot produce a fact are skipped, whatever
+    /// `limits.rule_errors` is
+    pub fn run_rule(&mut self, rule: &Rule, limits: &RunLimits) -> Result<usize, crate::error::RunLimit> {
+        let start = Instant::now(time::default_clock());
+        let limits = &RunLimits { rule_errors: RuleErrors::Skip, ..limits.clone() };
+        let time_limit = limits.deadline(start);
+        let budget = Budget::new(usize::MAX, limits, start);
+        let mut added = 0;
+        let mut index = 0;
+
+        loop {
+            if limits.max_iterations == Some(index) {
+                return Err(crate::error::RunLimit::TooManyIterations(limit_context(index, self.facts.len(), start, None)));
+            }
+
+            let mut derived = Vec::new();
+            rule.apply_budgeted(&self.facts, &mut derived, Some(&budget), limits.plan_joins, None, Some(&self.facts));
+            budget.check().map_err(|e| e.with_context(limit_context(index, self.facts.len(), start, None)))?;
+
+            if rule.aggregate.is_some() {
+                // aggregate results replace the ones computed before
+                let stale = self.facts.iter()
+                    .filter(|f| derived.iter().any(|(new, _)| rule.is_stale_aggregate(f, new)))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for fact in stale.iter() {
+                    self.remove_fact(fact);
+                }
+            }
+
+            let mut changed = false;
+            for (fact, _) in derived {
+                if self.facts.contains(&fact) {
+                    continue;
+                }
+                if limits.max_facts.is_some_and(|max| self.facts.len() >= max as usize) {
+                    return Err(crate::error::RunLimit::TooManyFacts(limit_context(index, self.facts.len(), start, None)));
+                }
+                if self.exceeds_memory(&fact, limits) {
+                    return Err(crate::error::RunLimit::TooMuchMemory(limit_context(index, self.facts.len(), start, None)));
+                }
+                self.facts.insert(fact);
+                added += 1;
+                changed = true;
+            }
+            index += 1;
+
+            if !changed {
+                return Ok(added);
+            }
+            if time_limit.is_some_and(|limit| limit.has_passed()) {
+                return Err(crate::error::RunLimit::Timeout(limit_context(index, self.facts.len(), start, None)));
+            }
+        }
+    }
+
+    /// like `query_rule`, but only with the facts visible from `origin`
+    pub fn query_rule_with_origin(&self, rule: Rule, origin: Origin) -> Vec<Fact> {
+        let mut new_facts: Vec<Fact> = Vec::new();
+        rule.apply(&self.visible_facts(&origin), &mut new_facts);
+        new_facts
+    }
+
+    /// keeps the facts generated by `rule` up to date, see `view_facts`
+    ///
+    /// the rule is not added to the world, and sees all of its facts, like
+    /// `query_rule`. Its facts are computed now, then updated at the end
+    /// of each run, or by `refresh_views`: only the matches of the facts
+    /// added since the last update are looked for, unless facts of its
+    /// body predicates were removed, facts of its negated predicates
+    /// changed, or it is an aggregate
+    pub fn register_view(&mut self, rule: Rule) -> ViewId {
+        self.views.push(View::new(rule, &self.facts));
+        ViewId(self.views.len() - 1)
+    }
+
+    /// facts generated by the rule of a view, as of the last run or
+    /// `refresh_views`
+    pub fn view_facts(&self, id: ViewId) -> Option<&HashSet<Fact>> {
+        self.views.get(id.0).map(|view| &view.facts)
+    }
+
+    /// updates the views with the facts changed since the last run, for
+    /// facts added or removed without running the rules
+    pub fn refresh_views(&mut self) {
+        for view in self.views.iter_mut() {
+            view.update(&self.facts);
+        }
+    }
+}
+
+/// what a run records besides the new facts
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Evaluation {
+    Plain,
+    /// derivations, see `World::run_with_provenance`
+    Provenance,
+    /// time spent in each rule, see `World::run_with_stats`
+    Timed,
+}
+
+/// counters returned by `World::run_with_stats`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RunStats {
+    /// one entry per pass over the rules of a stratum, including the last
+    /// one that did not find new facts
+    pub iterations: Vec<IterationStats>,
+    /// indexed like `World::rules`
+    pub rules: Vec<RuleStats>,
+    pub duration: Duration,
+    /// in the order they were found, each one reported once
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl RunStats {
+    fn finish(&mut self, iteration_start: Instant, run_start: Instant) {
+        if let Some(iteration) = self.iterations.last_mut() {
+            iteration.duration = iteration_start.elapsed();
+        }
+        self.duration = run_start.elapsed();
+    }
+}
+
+/// a problem found while applying a rule that did not stop the run, see
+/// `World::run_with_diagnostics`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// index of the rule in `World::rules`
+    pub rule: usize,
+    pub error: crate::error::Evaluation,
+    /// the error and its sources, with variables printed as their id
+    pub description: String,
+}
+
+impl Diagnostic {
+    fn new(rule: usize, error: crate::error::Evaluation) -> Self {
+        let severity = match error {
+            crate::error::Evaluation::InvalidRegex(_) | crate::error::Evaluation::ConstraintType { .. } => {
+                Severity::Warning
+            },
+            _ => Severity::Error,
+        };
+        let mut description = error.to_string();
+        let mut source = core::error::Error::source(&error);
+        while let Some(e) = source {
+            description.push_str(": ");
+            description.push_str(&e.to_string());
+            source = e.source();
+        }
+        Diagnostic { severity, rule, error, description }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: rule {}: {}", severity, self.rule, self.description)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// the rule works as documented, but probably not as intended, like
+    /// a constraint with an invalid regex that never matches
+    Warning,
+    /// some matches of the rule's body were skipped, see `RuleErrors`
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IterationStats {
+    /// facts that were not already in the world
+    pub new: usize,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RuleStats {
+    /// times the rule was applied, once per iteration of its stratum
+    /// unless it could not match anything
+    pub applications: usize,
+    /// facts generated by the rule, including the ones already known
+    pub emitted: usize,
+    /// facts that were not already in the world
+    pub new: usize,
+    /// generated facts dropped by the rule application, because they were
+    /// already in the world or generated twice. Included in `emitted`
+    pub skipped: usize,
+    /// time spent applying the rule
+    pub total_time: Duration,
+}
+
+impl RuleStats {
+    pub fn duplicates(&self) -> usize {
+        self.emitted - self.new
+    }
+}
+
+/// result of `World::run_partial`
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunOutcome {
+    Complete(RunStats),
+    /// the run stopped on `limit`, keeping the facts generated so far
+    Partial { stats: RunStats, limit: crate::error::RunLimit },
+}
+
+/// state of a run, passed to the callback of `World::run_with_callback`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunProgress {
+    /// number of iterations done, starting from 1
+    pub iteration: u32,
+    pub facts: usize,
+    pub elapsed: Duration,
+}
+
+/// predicates used by a rule, as returned by `World::dependency_graph`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleDependencies {
+    pub head: Symbol,
+    pub body: Vec<Symbol>,
+    pub negated: Vec<Symbol>,
+}
+
+pub fn sym(syms: &mut SymbolTable, name: &str) -> ID {
+    let id = syms.insert(name);
+    ID::Symbol(id)
+}
+
+/// like `sym`, but respects `SymbolTable::max_symbols`
+pub fn try_sym(syms: &mut SymbolTable, name: &str) -> Result<ID, crate::error::Symbol> {
+    syms.try_add(name)
+}
+
+/// limits of a run, `None` meaning unlimited
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunLimits {
+    /// maximum number of facts in the world, including the ones present
+    /// before the run. The run stops before storing the fact that would go
+    /// over the limit
+    pub max_facts: Option<u32>,
+    /// maximum number of passes over the rules, across all strata. A
+    /// stratum needs one more pass than the ones generating new facts,
+    /// to verify it reached a fixpoint. With 0, rules are never applied
+    pub max_iterations: Option<u32>,
+    pub max_time: Option<Duration>,
+    /// number of facts examined and expressions evaluated while matching
+    /// rule bodies, counted across all the rule applications of a run
+    pub max_candidates: u64,
+    /// sum of the estimated sizes of the facts in the world (see
+    /// `Fact::estimated_size`), checked like `max_facts`
+    pub max_memory: Option<usize>,
+    /// reorders the body of each rule to bind the most selective predicates
+    /// first (see `Rule::join_order`). Without it, predicates are bound in
+    /// the order they are written, and the results are generated in the
+    /// same order as before the planning was introduced
+    pub plan_joins: bool,
+    /// the time limit is checked after examining this many facts while
+    /// matching rule bodies, and between iterations
+    pub time_check_interval: u32,
+    /// rules above this complexity are rejected by `World::add_rule_checked`
+    pub max_rule_complexity: Option<RuleComplexity>,
+    /// rules with more body predicates are rejected by `World::add_rule_checked`
+    pub max_body_predicates: Option<usize>,
+    /// what the run does when a match of a rule's body cannot produce a fact
+    pub rule_errors: RuleErrors,
+    /// maximum size in bytes (see `ID::payload_size`) of a value computed by
+    /// an expression, in the head of a generated fact. The
+    /// matches computing a larger one do not produce a fact and report
+    /// `error::Evaluation::TermTooLarge`, so a rule growing a value at
+    /// each iteration stops before the other limits
+    pub max_term_size: Option<usize>,
+}
+
+/// handling of the matches that cannot produce a fact, like an expression
+/// applied to values of the wrong type, see `error::Evaluation`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RuleErrors {
+    /// the match is ignored, as if the body did not match
+    #[default]
+    Skip,
+    /// the run stops with `error::RuleFailed`
+    Fatal,
+}
+
+impl Default for RunLimits {
+    fn default() -> Self {
+        RunLimits {
+            max_facts: Some(1000),
+            max_iterations: Some(100),
+            max_time: Some(Duration::from_millis(1)),
+            max_candidates: 1_000_000,
+            max_memory: None,
+            plan_joins: true,
+            time_check_interval: 100,
+            max_rule_complexity: None,
+            max_body_predicates: Some(256),
+            rule_errors: RuleErrors::Skip,
+            max_term_size: Some(64 * 1024),
+        }
+    }
+}
+
+impl RunLimits {
+    /// no limit on facts, iterations, time or candidates
+    pub fn unlimited() -> Self {
+        RunLimits {
+            max_facts: None,
+            max_iterations: None,
+            max_time: None,
+            max_candidates: u64::MAX,
+            ..RunLimits::default()
+        }
+    }
+
+    /// limits for a single query, like the ones of a caveat: they only
+    /// allow a small join and a very short time
+    pub fn caveat_default() -> Self {
+        RunLimits {
+            max_facts: Some(1000),
+            max_iterations: Some(1),
+            max_time: Some(Duration::from_micros(500)),
+            max_candidates: 5_000,
+            ..RunLimits::default()
+        }
+    }
+
+    /// starts from the default limits
+    pub fn builder() -> RunLimitsBuilder {
+        RunLimitsBuilder { limits: RunLimits::default(), allow_unlimited: false }
+    }
+
+    /// `None` if there is no time limit, or if it is too far away to be
+    /// represented
+    fn deadline<'c>(&self, start: Instant<'c>) -> Option<Instant<'c>> {
+        self.max_time.and_then(|max| start.checked_add(max))
+    }
+}
+
+/// builds `RunLimits`, see `RunLimits::builder`
+#[derive(Debug, Clone)]
+pub struct RunLimitsBuilder {
+    limits: RunLimits,
+    allow_unlimited: bool,
+}
+
+impl RunLimitsBuilder {
+    pub fn max_facts(mut self, max: Option<u32>) -> Self {
+        self.limits.max_facts = max;
+        self
+    }
+
+    pub fn max_iterations(mut self, max: Option<u32>) -> Self {
+        self.limits.max_iterations = max;
+        self
+    }
+
+    pub fn max_time(mut self, max: Option<Duration>) -> Self {
+        self.limits.max_time = max;
+        self
+    }
+
+    pub fn max_candidates(mut self, max: u64) -> Self {
+        self.limits.max_candidates = max;
+        self
+    }
+
+    pub fn max_memory(mut self, max: Option<usize>) -> Self {
+        self.limits.max_memory = max;
+        self
+    }
+
+    pub fn plan_joins(mut self, plan: bool) -> Self {
+        self.limits.plan_joins = plan;
+        self
+    }
+
+    pub fn time_check_interval(mut self, interval: u32) -> Self {
+        self.limits.time_check_interval = interval;
+        self
+    }
+
+    pub fn max_rule_complexity(mut self, max: Option<RuleComplexity>) -> Self {
+        self.limits.max_rule_complexity = max;
+        self
+    }
+
+    pub fn max_body_predicates(mut self, max: Option<usize>) -> Self {
+        self.limits.max_body_predicates = max;
+        self
+    }
+
+    pub fn rule_errors(mut self, errors: RuleErrors) -> Self {
+        self.limits.rule_errors = errors;
+        self
+    }
+
+    pub fn max_term_size(mut self, max: Option<usize>) -> Self {
+        self.limits.max_term_size = max;
+        self
+    }
+
+    /// accepts limits without any fact, memory, iteration or time limit
+    pub fn allow_unlimited(mut self) -> Self {
+        self.allow_unlimited = true;
+        self
+    }
+
+    /// fails if there is no fact, memory, iteration or time limit, unless
+    /// `allow_unlimited` was called
+    pub fn build(self) -> Result<RunLimits, crate::error::Unbounded> {
+        let limits = self.limits;
+        if !self.allow_unlimited
+            && limits.max_facts.is_none()
+            && limits.max_memory.is_none()
+            && limits.max_iterations.is_none()
+            && limits.max_time.is_none()
+        {
+            return Err(crate::error::Unbounded);
+        }
+
+        Ok(limits)
+    }
+}
+
+fn limit_context(iterations: u32, facts: usize, start: Instant, rule: Option<usize>) -> crate::error::LimitContext {
+    crate::error::LimitContext { iterations, facts, elapsed: start.elapsed(), rule }
+}
+
+/// work done while applying rules, shared by the nested `CombineIt`
+///
+/// the counters are atomic so that a `CombineIt` can be sent to another
+/// thread. They are `usize` as some targets have no 64 bit atomics
+pub(crate) struct Budget<'c> {
+    max_facts: usize,
+    max_candidates: u64,
+    time_check_interval: u64,
+    deadline: Option<Instant<'c>>,
+    facts: AtomicUsize,
+    candidates: AtomicUsize,
+    expressions: AtomicUsize,
+    exceeded: OnceLock<crate::error::RunLimit>,
+    rule_errors: RuleErrors,
+    max_term_size: Option<usize>,
+    failed: OnceLock<crate::error::Evaluation>,
+    /// errors that did not stop the iteration, without duplicates
+    diagnostics: Mutex<Vec<crate::error::Evaluation>>,
+}
+
+impl<'c> Budget<'c> {
+    pub(crate) fn new(max_facts: usize, limits: &RunLimits, start: Instant<'c>) -> Self {
+        Budget {
+            max_facts,
+            max_candidates: limits.max_candidates,
+            time_check_interval: u64::from(limits.time_check_interval.max(1)),
+            deadline: limits.deadline(start),
+            facts: AtomicUsize::new(0),
+            candidates: AtomicUsize::new(0),
+            expressions: AtomicUsize::new(0),
+            exceeded: OnceLock::new(),
+            rule_errors: limits.rule_errors,
+            max_term_size: limits.max_term_size,
+            failed: OnceLock::new(),
+            diagnostics: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// counts a candidate fact, returns false if the budget is exhausted
+    ///
+    /// the errors reported by `check` are completed by the caller with a
+    /// `LimitContext`
+    pub(crate) fn examine(&self) -> bool {
+        self.spend(&self.candidates)
+    }
+
+    /// counts an expression evaluation, like `examine`
+    pub(crate) fn evaluate(&self) -> bool {
+        self.spend(&self.expressions)
+    }
+
+    /// candidates and expression evaluations share the same limit
+    fn spend(&self, counter: &AtomicUsize) -> bool {
+        if self.is_exceeded() {
+            return false;
+        }
+
+        counter.fetch_add(1, Ordering::Relaxed);
+        let work = self.candidates.load(Ordering::Relaxed) as u64 + self.expressions.load(Ordering::Relaxed) as u64;
+        if work > self.max_candidates {
+            self.exceed(crate::error::RunLimit::TooManyCandidates(Default::default()));
+            false
+        } else if work.is_multiple_of(self.time_check_interval)
+            && self.deadline.is_some_and(|deadline| deadline.has_passed())
+        {
+            self.exceed(crate::error::RunLimit::Timeout(Default::default()));
+            false
+        } else {
+            true
+        }
+    }
+
+    /// counts a generated fact, returns false if the budget is exhausted
+    pub(crate) fn add_fact(&self) -> bool {
+        if self.is_exceeded() {
+            return false;
+        }
+
+        if self.facts.fetch_add(1, Ordering::Relaxed) + 1 > self.max_facts {
+            self.exceed(crate::error::RunLimit::TooManyFacts(Default::default()));
+            false
+        } else {
+            true
+        }
+    }
+
+    #[cfg(any(test, feature = "tracing"))]
+    pub(crate) fn candidates(&self) -> u64 {
+        self.candidates.load(Ordering::Relaxed) as u64
+    }
+
+    #[cfg(test)]
+    pub(crate) fn expressions(&self) -> u64 {
+        self.expressions.load(Ordering::Relaxed) as u64
+    }
+
+    /// only the first limit reached is kept
+    fn exceed(&self, limit: crate::error::RunLimit) {
+        let _ = self.exceeded.set(limit);
+    }
+
+    /// with `RuleErrors::Fatal`, keeps the first error and stops the
+    /// iteration like an exhausted budget, otherwise reports it like `warn`
+    pub(crate) fn fail(&self, e: crate::error::Evaluation) {
+        if self.rule_errors == RuleErrors::Fatal {
+            let _ = self.failed.set(e);
+        } else {
+            self.warn(e);
+        }
+    }
+
+    /// fails if a computed value is over `RunLimits::max_term_size`
+    pub(crate) fn check_term_size(&self, id: &ID) -> Result<(), crate::error::Evaluation> {
+        match (self.max_term_size, id.payload_size()) {
+            (Some(max), size) if size > max => Err(crate::error::Evaluation::TermTooLarge { size, max }),
+            _ => Ok(()),
+        }
+    }
+
+    /// reports an error without stopping the iteration, see `take_diagnostics`
+    pub(crate) fn warn(&self, e: crate::error::Evaluation) {
+        let mut diagnostics = self.diagnostics.lock();
+        if !diagnostics.contains(&e) {
+            diagnostics.push(e);
+        }
+    }
+
+    /// the errors reported since the last call
+    pub(crate) fn take_diagnostics(&self) -> Vec<crate::error::Evaluation> {
+        core::mem::take(&mut *self.diagnostics.lock())
+    }
+
+    fn is_exceeded(&self) -> bool {
+        self.exceeded.get().is_some() || self.failed.get().is_some()
+    }
+
+    pub(crate) fn failure(&self) -> Option<&crate::error::Evaluation> {
+        self.failed.get()
+    }
+
+    pub(crate) fn check(&self) -> Result<(), crate::error::RunLimit> {
+        match self.exceeded.get() {
+            Some(e) => Err(*e),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn family() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let a = syms.add("A");
+        let b = syms.add("B");
+        let c = syms.add("C");
+        let d = syms.add("D");
+        let e = syms.add("e");
+        let parent = syms.insert("parent");
+        let grandparent = syms.insert("grandparent");
+
+        w.add_fact(fact(parent, &[&a, &b]));
+        w.add_fact(fact(parent, &[&b, &c]));
+        w.add_fact(fact(parent, &[&c, &d]));
+
+        let r1 = rule(
+            grandparent,
+            &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")],
+            &[
+                pred(parent, &[var(&mut syms, "grandparent"), var(&mut syms, "parent")]),
+                pred(parent, &[var(&mut syms, "parent"), var(&mut syms, "grandchild")]),
+            ],
+        );
+
+        println!("symbols: {:?}", syms);
+        println!("testing r1: {}", syms.print_rule(&r1));
+        let query_rule_result = w.query_rule(r1);
+        println!("grandparents query_rules: {:?}", query_rule_result);
+        println!("current facts: {:?}", w.facts);
+
+        let r2 = rule(
+            grandparent,
+            &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")],
+            &[
+                pred(parent, &[var(&mut syms, "grandparent"), var(&mut syms, "parent")]),
+                pred(parent, &[var(&mut syms, "parent"), var(&mut syms, "grandchild")]),
+            ],
+        );
+
+        println!("adding r2: {}", syms.print_rule(&r2));
+        w.add_rule(r2);
+
+        w.run().unwrap();
+
+        println!("parents:");
+        let res = w.query(pred(parent, &[var(&mut syms, "parent"), var(&mut syms, "child")]));
+        for fact in res {
+            println!("\t{}", syms.print_fact(fact));
+        }
+
+        println!(
+            "parents of B: {:?}",
+            w.query(pred(parent, &[&var(&mut syms, "parent"), &b]))
+        );
+        println!(
+            "grandparents: {:?}",
+            w.query(pred(grandparent, &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")]))
+        );
+        w.add_fact(fact(parent, &[&c, &e]));
+        w.run().unwrap();
+        let mut res = w.query(pred(grandparent, &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")]));
+        println!("grandparents after inserting parent(C, E): {:?}", res);
+
+        let res = res.drain(..).cloned().collect::<HashSet<_>>();
+        let compared = (vec![
+            fact(grandparent, &[&a, &c]),
+            fact(grandparent, &[&b, &d]),
+            fact(grandparent, &[&b, &e]),
+        ])
+        .drain(..)
+        .collect::<HashSet<_>>();
+        assert_eq!(res, compared);
+        assert_eq!(syms.print_world(&w), r#"World {
+  facts: [
+    "parent(#A, #B)",
+    "parent(#B, #C)",
+    "parent(#C, #D)",
+    "parent(#C, #e)",
+    "grandparent(#A, #C)",
+    "grandparent(#B, #D)",
+    "grandparent(#B, #e)",
+]
+  rules: [
+    "grandparent($grandparent, $grandchild) <- parent($grandparent, $parent), parent($parent, $grandchild)",
+]
+}"#);
+
+        /*w.add_rule(rule("siblings", &[var("A"), var("B")], &[
+          pred(parent, &[var(parent), var("A")]),
+          pred(parent, &[var(parent), var("B")])
+        ]));
+
+        w.run();
+        println!("siblings: {:#?}", w.query(pred("siblings", &[var("A"), var("B")])));
+        */
+    }
+
+    #[test]
+    fn constraints_and_expressions() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+        let order = syms.insert("order");
+        let price = syms.insert("price");
+        let cheap = syms.insert("cheap");
+        let item = var(&mut syms, "item");
+        let quantity = var(&mut syms, "quantity");
+        let amount = var(&mut syms, "amount");
+        let total = var(&mut syms, "total");
+        let [quantity_id, total_id] = [syms.insert("quantity") as u32, syms.insert("total") as u32];
+        for (name, q, p) in [("pen", 2, 3), ("book", 1, 20), ("ink", 10, 4), ("paper", 4, 1)] {
+            let i = syms.add(name);
+            w.add_fact(fact(order, &[&i, &int(q)]));
+            w.add_fact(fact(price, &[&i, &int(p)]));
+        }
+
+        // the constraint is on $quantity, the expression on $amount and
+        // binds $total
+        let r = full_rule(
+            cheap,
+            &[&item, &total],
+            &[pred(order, &[&item, &quantity]), pred(price, &[&item, &amount])],
+            &[Constraint { id: quantity_id, kind: ConstraintKind::Int(IntConstraint::LessThan(5)) }],
+            &[
+                Expression { ops: vec![Op::Value(amount.clone()), Op::Value(int(10)), Op::Binary(Binary::LessThan)] },
+                Expression {
+                    ops: vec![Op::Value(amount.clone()), Op::Value(quantity.clone()), Op::Binary(Binary::Add), Op::Bind(total_id)],
+                },
+            ],
+        );
+        assert_eq!(
+            syms.print_rule(&r),
+            "cheap($item, $total) <- order($item, $quantity), price($item, $amount) @ $quantity < 5 \
+             | $amount < 10, $total = $amount + $quantity"
+        );
+        w.add_rule(r.clone());
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
+
+        // ink is filtered by the constraint, book by the expression
+        let mut res = w.query(pred(cheap, &[ID::Wildcard, ID::Wildcard])).into_iter().map(|f| syms.print_fact(f)).collect::<Vec<_>>();
+        res.sort();
+        assert_eq!(res, ["cheap(#paper, 5)", "cheap(#pen, 5)"]);
+        assert_eq!(w.query_rule(r).len(), 2);
+    }
+
+    #[test]
+    fn query_arity() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+        let a = syms.add("A");
+        let b = syms.add("B");
+        let c = syms.add("C");
+        let parent = syms.insert("parent");
+        let route = syms.insert("route");
+        w.add_fact(fact(parent, &[&a, &b]));
+        w.add_fact(fact(parent, &[&b, &c]));
+        w.add_fact(fact(route, &[&int(1), &a, &string("example.com")]));
+        w.add_fact(fact(route, &[&int(2), &b, &string("test.com")]));
+        w.add_fact(fact(route, &[&int(3), &a, &string("www.example.com")]));
+
+        // too few or too many terms
+        assert!(w.query(pred(parent, &[var(&mut syms, "parent")])).is_empty());
+        assert!(w.query(pred(parent, &[ID::Wildcard, ID::Wildcard, ID::Wildcard])).is_empty());
+        assert_eq!(w.query(pred(parent, &[var(&mut syms, "parent"), var(&mut syms, "child")])).len(), 2);
+        assert_eq!(w.query(pred(parent, &[ID::Wildcard, b.clone()])), [&fact(parent, &[&a, &b])]);
+
+        let id = syms.insert("id") as u32;
+        let domain = syms.insert("domain") as u32;
+        let query = pred(route, &[ID::Variable(id), a.clone(), ID::Variable(domain)]);
+        // unconstrained variables only match symbols
+        assert!(w.query(query.clone()).is_empty());
+        let constraints = [
+            Constraint { id, kind: ConstraintKind::Int(IntConstraint::GreaterThan(1)) },
+            Constraint { id: domain, kind: ConstraintKind::Str(StrConstraint::Suffix("example.com".to_string())) },
+        ];
+        assert_eq!(w.query_with_constraints(query.clone(), &constraints), [
+            &fact(route, &[&int(3), &a, &string("www.example.com")])
+        ]);
+        let query = pred(route, &[ID::Wildcard, a.clone(), ID::Variable(domain)]);
+        let mut matched = w.query_with_constraints(query, &constraints[1..]);
+        matched.sort_by_key(|f| syms.print_fact(f));
+        assert_eq!(matched, [
+            &fact(route, &[&int(1), &a, &string("example.com")]),
+            &fact(route, &[&int(3), &a, &string("www.example.com")]),
+        ]);
+    }
+
+    /// spans and events recorded by `tracing`, with their fields
+    #[cfg(feature = "tracing")]
+    mod capture {
+        use std::collections::HashMap;
+        use std::fmt::Debug;
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Subscriber};
+        use tracing_subscriber::layer::Context;
+
+        #[derive(Debug, Clone, Default)]
+        pub struct Recorded {
+            pub name: &'static str,
+            pub fields: HashMap<&'static str, String>,
+        }
+
+        impl Visit for Recorded {
+            fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                self.fields.insert(field.name(), format!("{:?}", value));
+            }
+        }
+
+        #[derive(Clone, Default)]
+        pub struct Capture {
+            pub spans: Arc<Mutex<Vec<Recorded>>>,
+            pub events: Arc<Mutex<Vec<Recorded>>>,
+            /// index in `spans` of the open spans, ids are reused
+            ids: Arc<Mutex<HashMap<u64, usize>>>,
+        }
+
+        impl<S: Subscriber> tracing_subscriber::Layer<S> for Capture {
+            fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _: Context<'_, S>) {
+                let mut spans = self.spans.lock().unwrap();
+                let mut span = Recorded { name: attrs.metadata().name(), ..Default::default() };
+                attrs.record(&mut span);
+                self.ids.lock().unwrap().insert(id.into_u64(), spans.len());
+                spans.push(span);
+            }
+
+            fn on_record(&self, id: &Id, values: &Record<'_>, _: Context<'_, S>) {
+                let index = self.ids.lock().unwrap()[&id.into_u64()];
+                values.record(&mut self.spans.lock().unwrap()[index]);
+            }
+
+            fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+                let mut recorded = Recorded { name: event.metadata().name(), ..Default::default() };
+                event.record(&mut recorded);
+                self.events.lock().unwrap().push(recorded);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "tracing")]
+    fn tracing() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let mut syms = SymbolTable::new();
+        let a = syms.add("A");
+        let b = syms.add("B");
+        let c = syms.add("C");
+        let parent = syms.insert("parent");
+        let grandparent = syms.insert("grandparent");
+
+        let mut w = World::new();
+        w.add_fact(fact(parent, &[&a, &b]));
+        w.add_fact(fact(parent, &[&b, &c]));
+        w.add_rule(rule(grandparent, &[var(&mut syms, "x"), var(&mut syms, "z")], &[
+            pred(parent, &[var(&mut syms, "x"), var(&mut syms, "y")]),
+            pred(parent, &[var(&mut syms, "y"), var(&mut syms, "z")]),
+        ]));
+        let caveat = Caveat {
+            queries: vec![rule(grandparent, &[var(&mut syms, "x")], &[pred(grandparent, &[&var(&mut syms, "x"), &a])])],
+        };
+
+        let capture = capture::Capture::default();
+        let subscriber = tracing_subscriber::registry().with(capture.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            w.run_with_limits(RunLimits::unlimited()).unwrap();
+            assert!(w.check_caveats(&[caveat], None, &HashMap::new(), &syms).is_err());
+        });
+
+        let spans = capture.spans.lock().unwrap();
+        let fields = |name: &str, field: &str| {
+            spans.iter().filter(|s| s.name == name).map(|s| s.fields[field].clone()).collect::<Vec<_>>()
+        };
+        // the second iteration verifies that there are no new facts
+        assert_eq!(fields("iteration", "iteration"), ["0", "1"]);
+        assert_eq!(fields("iteration", "new"), ["1", "0"]);
+        assert_eq!(fields("rule", "rule"), ["0", "0"]);
+        assert_eq!(fields("rule", "emitted"), ["1", "1"]);
+        assert!(fields("rule", "candidates").iter().all(|c| c.parse::<u64>().unwrap() > 0));
+        assert!(spans.iter().filter(|s| s.name == "rule").all(|s| s.fields.contains_key("duration")));
+
+        let events = capture.events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].fields["query"], "0");
+        assert_eq!(events[0].fields["rule"], "grandparent($x) <- grandparent($x, #A)");
+        assert_eq!(events[0].fields["message"], "caveat query failed");
+    }
+
+    #[test]
+    fn numbers() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let abc = syms.add("abc");
+        let def = syms.add("def");
+        let ghi = syms.add("ghi");
+        let jkl = syms.add("jkl");
+        let mno = syms.add("mno");
+        let aaa = syms.add("AAA");
+        let bbb = syms.add("BBB");
+        let ccc = syms.add("CCC");
+        let t1 = syms.insert("t1");
+        let t2 = syms.insert("t2");
+        let join = syms.insert("join");
+
+        w.add_fact(fact(t1, &[&int(0), &abc]));
+        w.add_fact(fact(t1, &[&int(1), &def]));
+        w.add_fact(fact(t1, &[&int(2), &ghi]));
+        w.add_fact(fact(t1, &[&int(3), &jkl]));
+        w.add_fact(fact(t1, &[&int(4), &mno]));
+
+        w.add_fact(fact(t2, &[&int(0), &aaa, &int(0)]));
+        w.add_fact(fact(t2, &[&int(1), &bbb, &int(0)]));
+        w.add_fact(fact(t2, &[&int(2), &ccc, &int(1)]));
+
+        let (res, trace) = w.query_rule_traced(&rule(
+            join,
+            &[var(&mut syms, "left"), var(&mut syms, "right")],
+            &[
+                pred(t1, &[var(&mut syms, "id"), var(&mut syms, "left")]),
+                pred(t2, &[var(&mut syms, "t2_id"), var(&mut syms, "right"), var(&mut syms, "id")]),
+            ],
+        ));
+        assert_eq!(trace.counts(0), (5, 5));
+        assert_eq!(trace.counts(1), (15, 3));
+        assert_eq!(trace.produced().len(), res.len());
+
+        let res2 = res.iter().cloned().collect::<HashSet<_>>();
+        let compared = (vec![
+            fact(join, &[&abc, &aaa]),
+            fact(join, &[&abc, &bbb]),
+            fact(join, &[&def, &ccc]),
+        ])
+        .drain(..)
+        .collect::<HashSet<_>>();
+        assert_eq!(res2, compared);
+
+        // test constraints
+        /*
+        let res = w.query_rule(constrained_rule(
+            join,
+            &[var(&mut syms, "left"), var(&mut syms, "right")],
+            &[
+                pred(t1, &[var(&mut syms, "id"), var(&mut syms, "left")]),
+                pred(t2, &[var(&mut syms, "t2_id"), var(&mut syms, "right"), var(&mut syms, "id")]),
+            ],
+            &[Constraint {
+                id: syms.insert("id") as u32,
+                kind: ConstraintKind::Int(IntConstraint::LessThan(1)),
+            }],
+        ));
+        */
+
+        let r = expressed_rule(
+            join,
+            &[var(&mut syms, "left"), var(&mut syms, "right")],
+            &[
+                pred(t1, &[var(&mut syms, "id"), var(&mut syms, "left")]),
+                pred(t2, &[var(&mut syms, "t2_id"), var(&mut syms, "right"), var(&mut syms, "id")]),
+            ],
+            &[ Expression { ops: vec![
+                Op::Value(var(&mut syms, "id")),
+                Op::Value(ID::Integer(1)),
+                Op::Binary(Binary::LessThan),
+            ] } ],
+        );
+        assert_eq!(syms.print_rule(&r), "join($left, $right) <- t1($id, $left), t2($t2_id, $right, $id) | $id < 1");
+        let res = w.query_rule(r);
+        for fact in &res {
+            println!("\t{}", syms.print_fact(fact));
+        }
+
+        let res2 = res.iter().cloned().collect::<HashSet<_>>();
+        let compared = (vec![fact(join, &[&abc, &aaa]), fact(join, &[&abc, &bbb])])
+            .drain(..)
+            .collect::<HashSet<_>>();
+        assert_eq!(res2, compared);
+    }
+
+    #[test]
+    fn str() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let app_0 = syms.add("app_0");
+        let app_1 = syms.add("app_1");
+        let app_2 = syms.add("app_2");
+        let route = syms.insert("route");
+        let suff = syms.insert("route suffix");
+
+        w.add_fact(fact(route, &[&int(0), &app_0, &string("example.com")]));
+        w.add_fact(fact(route, &[&int(1), &app_1, &string("test.com")]));
+        w.add_fact(fact(route, &[&int(2), &app_2, &string("test.fr")]));
+        w.add_fact(fact(route, &[&int(3), &app_0, &string("www.example.com")]));
+        w.add_fact(fact(route, &[&int(4), &app_1, &string("mx.example.com")]));
+
+        fn test_suffix(w: &World, syms: &mut SymbolTable, suff: Symbol, route: Symbol, suffix: &str) -> Vec<Fact> {
+            /*
+            w.query_rule(constrained_rule(
+                suff,
+                &[var(syms, "app_id"), var(syms, "domain_name")],
+                &[pred(
+                    route,
+                    &[var(syms, "route_id"), var(syms, "app_id"), var(syms, "domain_name")],
+                )],
+                &[Constraint {
+                    id: syms.insert("domain_name") as u32,
+                    kind: ConstraintKind::Str(StrConstraint::Suffix(suffix.to_string())),
+                }],
+            ))
+            */
+            w.query_rule(expressed_rule(
+                suff,
+                &[var(syms, "app_id"), var(syms, "domain_name")],
+                &[pred(
+                    route,
+                    &[var(syms, "route_id"), var(syms, "app_id"), var(syms, "domain_name")],
+                )],
+                &[ Expression { ops: vec![
+                    Op::Value(var(syms, "domain_name")),
+                    Op::Value(ID::Str(suffix.to_string())),
+                    Op::Binary(Binary::Suffix),
+                ] } ],
+            ))
+        }
+
+        let res = test_suffix(&w, &mut syms, suff, route, ".fr");
+        for fact in &res {
+            println!("\t{}", syms.print_fact(fact));
+        }
+
+        let res2 = res.iter().cloned().collect::<HashSet<_>>();
+        let compared = (vec![fact(suff, &[&app_2, &string("test.fr")])])
+            .drain(..)
+            .collect::<HashSet<_>>();
+        assert_eq!(res2, compared);
+
+        let res = test_suffix(&w, &mut syms, suff, route, "example.com");
+        for fact in &res {
+            println!("\t{}", syms.print_fact(fact));
+        }
+
+        let res2 = res.iter().cloned().collect::<HashSet<_>>();
+        let compared = (vec![
+            fact(suff, &[&app_0, &string("example.com")]),
+            fact(suff, &[&app_0, &string("www.example.com")]),
+            fact(suff, &[&app_1, &string("mx.example.com")]),
+        ])
+        .drain(..)
+        .collect::<HashSet<_>>();
+        assert_eq!(res2, compared);
+
+        // the same filters without a rule, on the route facts themselves
+        let domain_name = var(&mut syms, "domain_name");
+        let domain_id = syms.get("domain_name").unwrap() as u32;
+        let routes = pred(route, &[var(&mut syms, "route_id"), var(&mut syms, "app_id"), domain_name.clone()]);
+        for suffix in [".fr", "example.com"] {
+            let expected = test_suffix(&w, &mut syms, suff, route, suffix).into_iter()
+                .map(|f| f.predicate.ids.to_vec())
+                .collect::<HashSet<_>>();
+            let constraint = Constraint { id: domain_id, kind: ConstraintKind::Str(StrConstraint::Suffix(suffix.to_string())) };
+            let expression = Expression { ops: vec![
+                Op::Value(domain_name.clone()),
+                Op::Value(ID::Str(suffix.to_string())),
+                Op::Binary(Binary::Suffix),
+            ] };
+
+            let queried = [
+                w.query_constrained(&routes, &[constraint], &[]),
+                w.query_constrained(&routes, &[], &[expression]),
+            ];
+            for facts in queried {
+                let found = facts.into_iter().map(|f| f.predicate.ids[1..].to_vec()).collect::<HashSet<_>>();
+                assert_eq!(found, expected, "suffix {}", suffix);
+            }
+        }
+        assert_eq!(w.query_constrained(&routes, &[], &[]).len(), 5);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn check_at() {
+        let mut syms = SymbolTable::new();
+        let parse = |syms: &mut SymbolTable, r: &str| crate::parser::parse_rule(r, syms).unwrap();
+        let expiry = UNIX_EPOCH + Duration::from_secs(1_893_456_000); // 2030-01-01T00:00:00Z
+        let before = expiry - Duration::from_secs(1);
+        let after = expiry + Duration::from_secs(1);
+
+        let mut w = World::new();
+        w.add_fact(crate::parser::parse_fact("operation(#ambient, #read)", &mut syms).unwrap());
+        w.add_rule(parse(&mut syms, "expired(#ambient) <- time(#ambient, $t) @ $t >= 2030-01-01T00:00:00Z"));
+        let caveats = [
+            crate::caveat(&[parse(&mut syms, "check($t) <- time(#ambient, $t) @ $t <= 2030-01-01T00:00:00Z")]),
+            crate::caveat(&[parse(&mut syms, "check(#read) <- operation(#ambient, #read), !expired(#ambient)")]),
+        ];
+        let limits = RunLimits::unlimited();
+
+        assert_eq!(w.check_at(&caveats, &before, &limits, &mut syms), Ok(()));
+        let failed = w.check_at(&caveats, &after, &limits, &mut syms).unwrap_err();
+        assert_eq!(failed.iter().map(|f| f.index).collect::<Vec<_>>(), [0, 1]);
+        assert!(failed.iter().all(|f| f.error == error::Caveat::NoMatch));
+        // the world itself has no time
+        assert_eq!(w.facts.len(), 1);
+
+        // the time fact is replaced
+        let time = syms.insert("time");
+        let pinned = w.clone().with_time(&after, &mut syms).with_time(&before, &mut syms);
+        assert_eq!(pinned.query(pred(time, &[ID::Wildcard, ID::Wildcard])), [&fact(time, &[syms.add("ambient"), date(&before)])]);
+        assert_eq!(pinned.facts.len(), 2);
+    }
+
+    #[test]
+    fn views() {
+        let mut syms = SymbolTable::new();
+        let parse_fact = |syms: &mut SymbolTable, f: &str| crate::parser::parse_fact(f, syms).unwrap();
+        let parse_rule = |syms: &mut SymbolTable, r: &str| crate::parser::parse_rule(r, syms).unwrap();
+        let recomputations = || VIEW_RECOMPUTATIONS.with(|c| c.get());
+
+        let mut w = World::new();
+        for i in 0..200 {
+            w.add_fact(parse_fact(&mut syms, &format!("parent(\"p{}\", \"p{}\")", i, i + 1)));
+        }
+        let grandparent = parse_rule(&mut syms, "grandparent($a, $c) <- parent($a, $b), parent($b, $c)");
+        let orphan = parse_rule(&mut syms, "orphan($a) <- person($a), !parent($p, $a)");
+        let before = recomputations();
+        let view = w.register_view(grandparent.clone());
+        let orphans = w.register_view(orphan);
+        assert_eq!(recomputations(), before + 2);
+        assert_eq!(w.view_facts(view).map(HashSet::len), Some(199));
+        assert_eq!(w.view_facts(ViewId(2)), None);
+
+        // a new fact only joins with the facts it shares a variable with,
+        // instead of every pair of facts
+        w.add_fact(parse_fact(&mut syms, "parent(\"p200\", \"p201\")"));
+        w.add_fact(parse_fact(&mut syms, "person(\"p0\")"));
+        factset::CANDIDATES.with(|c| c.set(0));
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
+        let updated = factset::CANDIDATES.with(|c| c.replace(0));
+        w.query_rule(grandparent.clone());
+        assert!(updated * 10 < factset::CANDIDATES.with(|c| c.get()), "{} candidates", updated);
+        assert_eq!(recomputations(), before + 3, "only the view with a negation is recomputed");
+        let expected = parse_fact(&mut syms, "grandparent(\"p199\", \"p201\")");
+        assert!(w.view_facts(view).unwrap().contains(&expected));
+        assert_eq!(w.view_facts(view).map(HashSet::len), Some(200));
+        assert_eq!(w.view_facts(orphans).map(HashSet::len), Some(1));
+
+        // unchanged predicates do not update the view
+        w.add_fact(parse_fact(&mut syms, "other(1)"));
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
+        assert_eq!(recomputations(), before + 3);
+
+        // rules of the world, and removed facts
+        w.add_rule(parse_rule(&mut syms, "parent(\"root\", $a) <- person($a)"));
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
+        assert!(w.view_facts(view).unwrap().contains(&parse_fact(&mut syms, "grandparent(\"root\", \"p1\")")));
+        assert_eq!(w.view_facts(orphans).map(HashSet::len), Some(0));
+        assert_eq!(recomputations(), before + 4);
+        let removed = parse_fact(&mut syms, "parent(\"p100\", \"p101\")");
+        w.remove_fact(&removed);
+        w.refresh_views();
+        assert_eq!(recomputations(), before + 6);
+        assert_eq!(w.view_facts(view).map(HashSet::len), Some(199));
+        let mut expected = HashSet::new();
+        for f in w.query_rule(grandparent) {
+            expected.insert(f);
+        }
+        assert_eq!(w.view_facts(view), Some(&expected));
+    }
+
+    #[test]
+    fn query_rule_any() {
+        let mut syms = SymbolTable::new();
+        let mut w = World::new();
+        for i in 0..10_000 {
+            w.add_fact(crate::parser::parse_fact(&format!("value({})", i), &mut syms).unwrap());
+        }
+        let parse = |syms: &mut SymbolTable, r: &str| crate::parser::parse_rule(r, syms).unwrap();
+        let all = parse(&mut syms, "found($x) <- value($x)");
+        let large = parse(&mut syms, "found($x) <- value($x) @ $x > 9997");
+        let none = parse(&mut syms, "found($x) <- value($x) @ $x < 0");
+        let unbound = parse(&mut syms, "found($y) <- value($x)");
+
+        assert!(w.query_rule_any(&all));
+        assert!(w.query_rule_any(&large));
+        assert!(!w.query_rule_any(&none));
+        assert!(!w.query_rule_any(&unbound));
+        assert_eq!(w.query_rule_count(&all, 5), 5);
+        assert_eq!(w.query_rule_count(&all, usize::MAX), 10_000);
+        assert_eq!(w.query_rule_count(&large, 5), 2);
+        assert_eq!(w.query_rule_count(&all, 0), 0);
+
+        // the first match stops the iteration: two candidates are enough,
+        // while building all the facts goes over the limit
+        let limits = RunLimits { max_candidates: 2, ..RunLimits::default() };
+        assert_eq!(all.count_with_limits(&w.facts, &limits, 1), Ok(1));
+        assert!(w.query_rule_with_limits(all.clone(), limits.clone()).is_err());
+        assert_eq!(w.check_caveat(&caveat(&[all]), Some(&limits)), Ok(()));
+        assert!(matches!(
+            w.check_caveat(&caveat(&[none]), Some(&limits)),
+            Err(error::Caveat::RunLimit(error::RunLimit::TooManyCandidates(_)))
+        ));
+
+        // aggregates only count the groups that can be computed
+        let sum = parse(&mut syms, "total(sum($x)) <- value($x)");
+        assert!(w.query_rule_any(&sum));
+        assert_eq!(w.query_rule_count(&sum, 10), 1);
+    }
+
+    #[test]
+    fn merge_namespaced() {
+        let mut syms = SymbolTable::new();
+        let parse_fact = |syms: &mut SymbolTable, f: &str| crate::parser::parse_fact(f, syms).unwrap();
+        let parse_rule = |syms: &mut SymbolTable, r: &str| crate::parser::parse_rule(r, syms).unwrap();
+        let service = |syms: &mut SymbolTable, file: &str| {
+            let mut w = World::new();
+            w.add_fact(parse_fact(syms, &format!("right(#authority, \"{}\", #read)", file)));
+            w.add_rule(parse_rule(syms, "readable($f) <- right(#authority, $f, #read), !right(#authority, $f, #revoked)"));
+            w
+        };
+        let a = service(&mut syms, "a.txt");
+        let b = service(&mut syms, "b.txt");
+
+        let mut w = World::new();
+        w.add_fact(parse_fact(&mut syms, "right(#authority, \"local.txt\", #read)"));
+        w.merge_namespaced(a.clone(), "a", &mut syms);
+        w.merge_namespaced(b, "b", &mut syms);
+        assert_eq!(w.facts.len(), 3);
+        assert_eq!(w.rules.len(), 2);
+        assert_eq!(
+            syms.print_rule(&w.rules[0]),
+            "a::readable($f) <- a::right(#authority, $f, #read), !a::right(#authority, $f, #revoked)"
+        );
+        // the terms and variables are not namespaced
+        assert_eq!(syms.get("a::authority"), None);
+        assert_eq!(syms.get("a::f"), None);
+
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
+        let query = |syms: &mut SymbolTable, w: &World, p: &str| {
+            let mut files = w.query_results(&crate::parser::parse_predicate(p, syms).unwrap(), &[])
+                .typed_column::<String>(syms.insert("f") as u32)
+                .unwrap();
+            files.sort();
+            files
+        };
+        assert_eq!(query(&mut syms, &w, "a::readable($f)"), ["a.txt"]);
+        assert_eq!(query(&mut syms, &w, "b::readable($f)"), ["b.txt"]);
+        assert_eq!(query(&mut syms, &w, "b::right(#authority, $f, #read)"), ["b.txt"]);
+        assert_eq!(query(&mut syms, &w, "right(#authority, $f, #read)"), ["local.txt"]);
+        assert!(query(&mut syms, &w, "readable($f)").is_empty());
+
+        // the printed names parse back
+        let rule = a.rules[0].namespaced(&mut syms, "a");
+        let printed = syms.print_rule(&rule);
+        assert_eq!(parse_rule(&mut syms, &printed), rule);
+        let fact = parse_fact(&mut syms, "c::d::right(#authority, \"c.txt\", #read)");
+        assert_eq!(syms.print_fact(&fact), "c::d::right(#authority, \"c.txt\", #read)");
+        assert_eq!(fact.predicate.name, syms.insert_namespaced("c", "d::right"));
+    }
+
+    #[test]
+    fn revocation_set() {
+        let mut syms = SymbolTable::new();
+        let revocation_id = syms.insert("revocation_id");
+        let valid = syms.insert("valid");
+        let revoked = syms.insert("revoked");
+        let id = var(&mut syms, "id");
+        let id_var = syms.get("id").unwrap() as u32;
+
+        let mut w = World::new();
+        let set = w.add_revocation_set(revoked, (0..100_000u32).map(|i| i.to_be_bytes().to_vec()));
+        assert_eq!(set.len(), 100_000);
+        assert_eq!(w.revocation_set(revoked), Some(&set));
+        assert_eq!(w.revocation_set(valid), None);
+        // the set is not stored as facts
+        assert!(w.facts.is_empty());
+
+        let rule = constrained_rule(valid, &[&id], &[pred(revocation_id, &[&id])], &[Constraint {
+            id: id_var,
+            kind: ConstraintKind::Bytes(BytesConstraint::NotInSet(set.clone())),
+        }]);
+        assert_eq!(syms.print_rule(&rule), "valid($id) <- revocation_id($id) @ $id not in set #revoked");
+        let limits = RunLimits { max_candidates: 2, max_facts: Some(2), max_iterations: Some(2), ..RunLimits::default() };
+
+        let checked = |id: &[u8]| {
+            let mut w = w.clone();
+            w.add_fact(fact(revocation_id, &[&ID::Bytes(id.to_vec())]));
+            w.query_rule_with_limits(rule.clone(), limits.clone()).unwrap().len() == 1
+        };
+        assert!(!checked(&42u32.to_be_bytes()));
+        assert!(checked(&100_000u32.to_be_bytes()));
+        assert!(checked(b"other"));
+
+        // the opposite constraint, and a set replaced under the same name
+        let in_set = Constraint { id: id_var, kind: ConstraintKind::Bytes(BytesConstraint::InSet(set.clone())) };
+        assert!(in_set.check(id_var, &ID::Bytes(99_999u32.to_be_bytes().to_vec())));
+        assert!(!in_set.check(id_var, &ID::Bytes(vec![])));
+        let replaced = w.add_revocation_set(revoked, [vec![1]]);
+        assert_eq!(w.revocation_set(revoked).map(SetHandle::len), Some(1));
+        assert_ne!(replaced, set);
+        assert_eq!(set.with_name(revoked), set);
+        assert_ne!(set.with_name(valid), set);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn date_constraint() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let t1 = SystemTime::now();
+        println!("t1 = {:?}", t1);
+        let t2 = t1 + Duration::from_secs(10);
+        println!("t2 = {:?}", t2);
+        let t3 = t2 + Duration::from_secs(30);
+        println!("t3 = {:?}", t3);
+
+        let t2_timestamp = t2.duration_since(UNIX_EPOCH).unwrap().as_secs();
+
+        let abc = syms.add("abc");
+        let def = syms.add("def");
+        let x = syms.insert("x");
+        let before = syms.insert("before");
+        let after = syms.insert("after");
+
+        w.add_fact(fact(x, &[&date(&t1), &abc]));
+        w.add_fact(fact(x, &[&date(&t3), &def]));
+
+        /*
+        let r1 = constrained_rule(
+            before,
+            &[var(&mut syms, "date"), var(&mut syms, "val")],
+            &[pred(x, &[var(&mut syms, "date"), var(&mut syms, "val")])],
+            &[
+                Constraint {
+                    id: syms.insert("date") as u32,
+                    kind: ConstraintKind::Date(DateConstraint::Before(t2_timestamp)),
+                },
+                Constraint {
+                    id: syms.insert("date") as u32,
+                    kind: ConstraintKind::Date(DateConstraint::After(0)),
+                },
+            ],
+        );
+        */
+        let r1 = expressed_rule(
+            before,
+            &[var(&mut syms, "date"), var(&mut syms, "val")],
+            &[pred(x, &[var(&mut syms, "date"), var(&mut syms, "val")])],
+            &[
+                Expression { ops: vec![
+                    Op::Value(var(&mut syms, "date")),
+                    Op::Value(ID::Date(t2_timestamp)),
+                    Op::Binary(Binary::LessOrEqual),
+                ] },
+                Expression { ops: vec![
+                    Op::Value(var(&mut syms, "date")),
+                    Op::Value(ID::Date(0)),
+                    Op::Binary(Binary::GreaterOrEqual),
+                ] },
+            ],
+        );
+
+
+        println!("testing r1: {}", syms.print_rule(&r1));
+        let t2_printed = chrono::DateTime::<chrono::Utc>::from_timestamp(t2_timestamp as i64, 0).unwrap().to_rfc3339();
+        assert_eq!(syms.print_rule(&r1), format!(
+            "before($date, $val) <- x($date, $val) | $date <= {}, $date >= 1970-01-01T00:00:00+00:00",
+            t2_printed
+        ));
+        let res = w.query_rule(r1);
+        for fact in &res {
+            println!("\t{}", syms.print_fact(fact));
+        }
+
+        let res2 = res.iter().cloned().collect::<HashSet<_>>();
+        let compared = (vec![fact(before, &[&date(&t1), &abc])])
+            .drain(..)
+            .collect::<HashSet<_>>();
+        assert_eq!(res2, compared);
+
+        /*
+        let r2 = constrained_rule(
+            after,
+            &[var(&mut syms, "date"), var(&mut syms, "val")],
+            &[pred(x, &[var(&mut syms, "date"), var(&mut syms, "val")])],
+            &[
+                Constraint {
+                    id: syms.insert("date") as u32,
+                    kind: ConstraintKind::Date(DateConstraint::After(t2_timestamp)),
+                },
+                Constraint {
+                    id: syms.insert("date") as u32,
+                    kind: ConstraintKind::Date(DateConstraint::After(0)),
+                },
+            ],
+        );
+        */
+        let r2 = expressed_rule(
+            after,
+            &[var(&mut syms, "date"), var(&mut syms, "val")],
+            &[pred(x, &[var(&mut syms, "date"), var(&mut syms, "val")])],
+            &[
+                Expression { ops: vec![
+                    Op::Value(var(&mut syms, "date")),
+                    Op::Value(ID::Date(t2_timestamp)),
+                    Op::Binary(Binary::GreaterOrEqual),
+                ] },
+                Expression { ops: vec![
+                    Op::Value(var(&mut syms, "date")),
+                    Op::Value(ID::Date(0)),
+                    Op::Binary(Binary::GreaterOrEqual),
+                ] },
+            ],
+        );
+
+        println!("testing r2: {}", syms.print_rule(&r2));
+        assert_eq!(syms.print_rule(&r2), format!(
+            "after($date, $val) <- x($date, $val) | $date >= {}, $date >= 1970-01-01T00:00:00+00:00",
+            t2_printed
+        ));
+        let res = w.query_rule(r2);
+        for fact in &res {
+            println!("\t{}", syms.print_fact(fact));
+        }
+
+        let res2 = res.iter().cloned().collect::<HashSet<_>>();
+        let compared = (vec![fact(after, &[&date(&t3), &def])])
+            .drain(..)
+            .collect::<HashSet<_>>();
+        assert_eq!(res2, compared);
+    }
+
+    #[test]
+    fn set_constraint() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let abc = syms.add("abc");
+        let def = syms.add("def");
+        let x = syms.insert("x");
+        let int_set = syms.insert("int_set");
+        let symbol_set = syms.insert("symbol_set");
+        let string_set = syms.insert("string_set");
+
+        w.add_fact(fact(x, &[&abc, &int(0), &string("test")]));
+        w.add_fact(fact(x, &[&def, &int(2), &string("hello")]));
+
+        /*
+        let res = w.query_rule(constrained_rule(
+            int_set,
+            &[var(&mut syms, "sym"), var(&mut syms, "str")],
+            &[pred(x, &[var(&mut syms, "sym"), var(&mut syms, "int"), var(&mut syms, "str")])],
+            &[Constraint {
+                id: syms.insert("int") as u32,
+                kind: ConstraintKind::Int(IntConstraint::In([0, 1].iter().cloned().collect())),
+            }],
+        ));
+        */
+
+        let res = w.query_rule(expressed_rule(
+            int_set,
+            &[var(&mut syms, "sym"), var(&mut syms, "str")],
+            &[pred(x, &[var(&mut syms, "sym"), var(&mut syms, "int"), var(&mut syms, "str")])],
+            &[
+                Expression { ops: vec![
+                    Op::Value(var(&mut syms, "int")),
+                    Op::Value(ID::Set([ID::Integer(0), ID::Integer(1)].iter().cloned().collect())),
+                    Op::Binary(Binary::In),
+                ] },
+            ],
+        ));
+
+        for fact in &res {
+            println!("\t{}", syms.print_fact(fact));
+        }
+
+        let res2 = res.iter().cloned().collect::<HashSet<_>>();
+        let compared = (vec![fact(int_set, &[&abc, &string("test")])])
+            .drain(..)
+            .collect::<HashSet<_>>();
+        assert_eq!(res2, compared);
+
+        let abc_sym_id = syms.insert("abc");
+        let ghi_sym_id = syms.insert("ghi");
+
+        /*
+        let res = w.query_rule(constrained_rule(
+            symbol_set,
+            &[var(&mut syms, "symbol"), var(&mut syms, "int"), var(&mut syms, "str")],
+            &[pred(x, &[var(&mut syms, "symbol"), var(&mut syms, "int"), var(&mut syms, "str")])],
+            &[Constraint {
+                id: syms.insert("symbol") as u32,
+                kind: ConstraintKind::Symbol(SymbolConstraint::NotIn(
+                    [abc_sym_id, ghi_sym_id].iter().cloned().collect(),
+                )),
+            }],
+        ));
+        */
+
+        let res = w.query_rule(expressed_rule(
+            symbol_set,
+            &[var(&mut syms, "symbol"), var(&mut syms, "int"), var(&mut syms, "str")],
+            &[pred(x, &[var(&mut syms, "symbol"), var(&mut syms, "int"), var(&mut syms, "str")])],
+            &[
+                Expression { ops: vec![
+                    Op::Value(var(&mut syms, "symbol")),
+                    Op::Value(ID::Set([ID::Symbol(abc_sym_id), ID::Symbol(ghi_sym_id)].iter().cloned().collect())),
+                    Op::Binary(Binary::NotIn),
+                ] },
+            ],
+        ));
+
+        for fact in &res {
+            println!("\t{}", syms.print_fact(fact));
+        }
+
+        let res2 = res.iter().cloned().collect::<HashSet<_>>();
+        let compared = (vec![fact(symbol_set, &[&def, &int(2), &string("hello")])])
+            .drain(..)
+            .collect::<HashSet<_>>();
+        assert_eq!(res2, compared);
+
+        /*
+        let res = w.query_rule(constrained_rule(
+            string_set,
+            &[var(&mut syms, "sym"), var(&mut syms, "int"), var(&mut syms, "str")],
+            &[pred(x, &[var(&mut syms, "sym"), var(&mut syms, "int"), var(&mut syms, "str")])],
+            &[Constraint {
+                id: syms.insert("str") as u32,
+                kind: ConstraintKind::Str(StrConstraint::In(
+                    ["test".to_string(), "aaa".to_string()]
+                        .iter()
+                        .cloned()
+                        .collect(),
+                )),
+            }],
+        ));
+        */
+
+        let res = w.query_rule(expressed_rule(
+            string_set,
+            &[var(&mut syms, "sym"), var(&mut syms, "int"), var(&mut syms, "str")],
+            &[pred(x, &[var(&mut syms, "sym"), var(&mut syms, "int"), var(&mut syms, "str")])],
+            &[
+                Expression { ops: vec![
+                    Op::Value(var(&mut syms, "str")),
+                    Op::Value(ID::Set([ID::Str("test".to_string()), ID::Str("aaa".to_string())].iter().cloned().collect())),
+                    Op::Binary(Binary::In),
+                ] },
+            ],
+        ));
+        for fact in &res {
+            println!("\t{}", syms.print_fact(fact));
+        }
+
+        let res2 = res.iter().cloned().collect::<HashSet<_>>();
+        let compared = (vec![fact(string_set, &[&abc, &int(0), &string("test")])])
+            .drain(..)
+            .collect::<HashSet<_>>();
+        assert_eq!(res2, compared);
+    }
+
+    #[test]
+    fn resource() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let authority = syms.add("authority");
+        let ambient = syms.add("ambient");
+        let resource = syms.insert("resource");
+        let operation = syms.insert("operation");
+        let right = syms.insert("right");
+        let file1 = syms.add("file1");
+        let file2 = syms.add("file2");
+        let read = syms.add("read");
+        let write = syms.add("write");
+        let caveat1 = syms.insert("caveat1");
+        let caveat2 = syms.insert("caveat2");
+
+        w.add_fact(fact(resource, &[&ambient, &file2]));
+        w.add_fact(fact(operation, &[&ambient, &write]));
+        w.add_fact(fact(right, &[&authority, &file1, &read]));
+        w.add_fact(fact(right, &[&authority, &file2, &read]));
+        w.add_fact(fact(right, &[&authority, &file1, &write]));
+
+        let res = w.query_rule(rule(
+            caveat1,
+            &[&file1],
+            &[pred(resource, &[&ambient, &file1])],
+        ));
+
+        for fact in &res {
+            println!("\t{}", syms.print_fact(fact));
+        }
+
+        assert!(res.is_empty());
+
+        let res = w.query_rule(rule(
+            caveat2,
+            &[ID::Variable(0)],
+            &[
+                pred(resource, &[&ambient, &ID::Variable(0)]),
+                pred(operation, &[&ambient, &read]),
+                pred(right, &[&authority, &ID::Variable(0), &read]),
+            ],
+        ));
+
+        for fact in &res {
+            println!("\t{}", syms.print_fact(fact));
+        }
+
+        assert!(res.is_empty());
+
+        assert_eq!(syms.print_world(&w), r#"World {
+  facts: [
+    "resource(#ambient, #file2)",
+    "operation(#ambient, #write)",
+    "right(#authority, #file1, #read)",
+    "right(#authority, #file1, #write)",
+    "right(#authority, #file2, #read)",
+]
+  rules: []
+}"#);
+
+        let caveat = Caveat { queries: vec![
+            rule(caveat1, &[&file1], &[pred(resource, &[&ambient, &file1])]),
+            rule(caveat2, &[&file2], &[pred(operation, &[&ambient, &write])]),
+        ] };
+        assert_eq!(
+            syms.print_caveat(&caveat),
+            "caveat1(#file1) <- resource(#ambient, #file1) || caveat2(#file2) <- operation(#ambient, #write)"
+        );
+    }
+
+    #[test]
+    fn query_bindings() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let authority = syms.add("authority");
+        let ambient = syms.add("ambient");
+        let resource = syms.insert("resource");
+        let right = syms.insert("right");
+        let file1 = syms.add("file1");
+        let file2 = syms.add("file2");
+        let read = syms.add("read");
+        let write = syms.add("write");
+
+        w.add_fact(fact(resource, &[&ambient, &file2]));
+        w.add_fact(fact(right, &[&authority, &file1, &read]));
+        w.add_fact(fact(right, &[&authority, &file2, &read]));
+        w.add_fact(fact(right, &[&authority, &file1, &write]));
+
+        let file = syms.insert("file") as u32;
+        let op = syms.insert("op") as u32;
+        let query = pred(right, &[authority.clone(), ID::Variable(file), ID::Variable(op)]);
+
+        let bindings = |file_id: &ID, op_id: &ID| -> HashMap<u32, ID> {
+            [(file, file_id.clone()), (op, op_id.clone())].iter().cloned().collect()
+        };
+
+        let mut res = w.query_bindings(&query, &[]);
+        res.sort_by_key(|h| (h[&file].clone(), h[&op].clone()));
+        assert_eq!(res, vec![
+            bindings(&file1, &read),
+            bindings(&file1, &write),
+            bindings(&file2, &read),
+        ]);
+
+        let read_only = Constraint {
+            id: op,
+            kind: ConstraintKind::Symbol(SymbolConstraint::In(
+                [syms.insert("read")].iter().cloned().collect(),
+            )),
+        };
+        let mut res = w.query_bindings(&query, &[read_only]);
+        res.sort_by_key(|h| h[&file].clone());
+        assert_eq!(res, vec![bindings(&file1, &read), bindings(&file2, &read)]);
+
+        assert!(w.query_bindings(&pred(right, &[&ambient, &ID::Variable(file), &read]), &[]).is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn query_limits() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let left = syms.insert("left");
+        let right = syms.insert("right");
+        let pair = syms.insert("pair");
+
+        for i in 0..1000 {
+            w.add_fact(fact(left, &[&int(i)]));
+            w.add_fact(fact(right, &[&int(i)]));
+        }
+
+        let cross_product = rule(
+            pair,
+            &[var(&mut syms, "a"), var(&mut syms, "b")],
+            &[
+                pred(left, &[var(&mut syms, "a")]),
+                pred(right, &[var(&mut syms, "b")]),
+            ],
+        );
+
+        let limits = || RunLimits { max_time: Some(Duration::from_secs(10)), ..Default::default() };
+
+        let start = std::time::Instant::now();
+        let res = w.query_rule_with_limits(cross_product.clone(), RunLimits { max_candidates: 5000, max_facts: None, ..limits() });
+        assert!(matches!(res, Err(error::RunLimit::TooManyCandidates(_))));
+
+        let res = w.query_rule_with_limits(cross_product.clone(), RunLimits { max_facts: Some(100), ..limits() });
+        assert!(matches!(res, Err(error::RunLimit::TooManyFacts(_))));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let start = std::time::Instant::now();
+        let res = w.query_rule_with_limits(cross_product, RunLimits {
+            max_time: Some(Duration::from_millis(5)),
+            max_candidates: u64::MAX,
+            max_facts: None,
+            ..Default::default()
+        });
+        assert!(matches!(res, Err(error::RunLimit::Timeout(_))));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let small = constrained_rule(
+            pair,
+            &[var(&mut syms, "a"), var(&mut syms, "b")],
+            &[
+                pred(left, &[var(&mut syms, "a")]),
+                pred(right, &[var(&mut syms, "b")]),
+            ],
+            &[Constraint {
+                id: syms.insert("a") as u32,
+                kind: ConstraintKind::Int(IntConstraint::LessThan(3)),
+            }],
+        );
+        let res = w.query_rule_with_limits(small.clone(), RunLimits { max_facts: Some(5000), ..limits() }).unwrap();
+        assert_eq!(res.len(), 3000);
+        assert_eq!(res.len(), w.query_rule(small).len());
+    }
+
+    #[test]
+    fn caveats() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let authority = syms.add("authority");
+        let ambient = syms.add("ambient");
+        let resource = syms.insert("resource");
+        let operation = syms.insert("operation");
+        let right = syms.insert("right");
+        let file1 = syms.add("file1");
+        let file2 = syms.add("file2");
+        let read = syms.add("read");
+        let write = syms.add("write");
+        let caveat1 = syms.insert("caveat1");
+        let caveat2 = syms.insert("caveat2");
+
+        w.add_fact(fact(resource, &[&ambient, &file2]));
+        w.add_fact(fact(operation, &[&ambient, &write]));
+        w.add_fact(fact(right, &[&authority, &file1, &read]));
+        w.add_fact(fact(right, &[&authority, &file2, &read]));
+        w.add_fact(fact(right, &[&authority, &file1, &write]));
+
+        let c1 = Caveat {
+            queries: vec![rule(caveat1, &[&file1], &[pred(resource, &[&ambient, &file1])])],
+        };
+        let c2 = Caveat {
+            queries: vec![rule(
+                caveat2,
+                &[var(&mut syms, "file")],
+                &[
+                    pred(resource, &[&ambient, &var(&mut syms, "file")]),
+                    pred(operation, &[&ambient, &read]),
+                    pred(right, &[&authority, &var(&mut syms, "file"), &read]),
+                ],
+            )],
+        };
+        let c3 = Caveat {
+            queries: vec![
+                c1.queries[0].clone(),
+                rule(caveat1, &[&file2], &[pred(resource, &[&ambient, &file2])]),
+            ],
+        };
+
+        let limits = RunLimits::unlimited();
+        assert_eq!(w.check_caveat(&c1, Some(&limits)), Err(error::Caveat::NoMatch));
+        assert_eq!(w.check_caveat(&c3, Some(&limits)), Ok(()));
+
+        let limited = RunLimits { max_candidates: 0, ..Default::default() };
+        assert!(matches!(
+            w.check_caveat(&c3, Some(&limited)),
+            Err(error::Caveat::RunLimit(error::RunLimit::TooManyCandidates(_)))
+        ));
+
+        let caveats = [c1, c3, c2];
+        let overrides = HashMap::from([(1, limited)]);
+        let failed = w.check_caveats(&caveats, None, &overrides, &syms).unwrap_err();
+        assert_eq!(failed.iter().map(|f| f.index).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert!(matches!(
+            failed[1].error,
+            error::Caveat::RunLimit(error::RunLimit::TooManyCandidates(_))
+        ));
+
+        let failed = w.check_caveats(&caveats, Some(&limits), &HashMap::new(), &syms).unwrap_err();
+        assert_eq!(failed, vec![
+            error::FailedCaveat {
+                index: 0,
+                caveat: "caveat1(#file1) <- resource(#ambient, #file1)".to_string(),
+                error: error::Caveat::NoMatch,
+            },
+            error::FailedCaveat {
+                index: 2,
+                caveat: "caveat2($file) <- resource(#ambient, $file), operation(#ambient, #read), right(#authority, $file, #read)".to_string(),
+                error: error::Caveat::NoMatch,
+            },
+        ]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn caveat_limits() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let left = syms.insert("left");
+        let right = syms.insert("right");
+        let both = syms.insert("both");
+
+        for i in 0..100 {
+            w.add_fact(fact(left, &[&int(i)]));
+            w.add_fact(fact(right, &[&int(i)]));
+        }
+
+        // only generates 100 facts, but examines every pair of facts
+        let query = rule(both, &[var(&mut syms, "a")], &[
+            pred(left, &[var(&mut syms, "a")]),
+            pred(right, &[var(&mut syms, "a")]),
+        ]);
+        // the caveat stops at the first match, this one has none and
+        // examines every pair of facts
+        let unmatched = crate::parser::parse_rule("both($a) <- left($a), right($b) | $a == $b + 100", &mut syms).unwrap();
+        let caveat = Caveat { queries: vec![unmatched] };
+
+        let res = w.check_caveat(&caveat, None);
+        assert!(matches!(
+            res,
+            Err(error::Caveat::RunLimit(error::RunLimit::TooManyCandidates(_) | error::RunLimit::Timeout(_)))
+        ));
+
+        let limits = RunLimits { max_time: Some(Duration::from_secs(10)), ..Default::default() };
+        assert_eq!(w.check_caveat(&caveat, Some(&limits)), Err(error::Caveat::NoMatch));
+        assert_eq!(w.check_caveat(&Caveat { queries: vec![query.clone()] }, Some(&limits)), Ok(()));
+
+        w.add_rule(query);
+        w.run_with_limits(limits).unwrap();
+        assert_eq!(w.facts.len(), 300);
+    }
+
+    #[test]
+    fn caveat_check() {
+        let mut syms = SymbolTable::new();
+        let right = syms.insert("right");
+        let allowed = syms.insert("allowed");
+        let read = syms.add("read");
+        let write = syms.add("write");
+
+        let facts = [
+            fact(right, &[&string("file1"), &read]),
+            fact(right, &[&string("file2"), &read]),
+        ].iter().cloned().collect::<FactSet>();
+
+        let caveat = Caveat { queries: vec![
+            rule(allowed, &[var(&mut syms, "f")], &[pred(right, &[&var(&mut syms, "f"), &write])]),
+            rule(allowed, &[var(&mut syms, "f")], &[pred(right, &[&var(&mut syms, "f"), &read])]),
+        ] };
+        let limits = RunLimits::caveat_default();
+        assert_eq!(caveat.check(&facts, &limits, &syms), Ok(CaveatSuccess { query: 1, facts: 2 }));
+
+        let failing = Caveat { queries: vec![caveat.queries[0].clone(), caveat.queries[0].clone()] };
+        assert_eq!(
+            failing.check(&facts, &limits, &syms),
+            Err(error::Caveat::NoMatchingQuery(vec!["allowed($f) <- right($f, #write)".to_string(); 2]))
+        );
+
+        let limited = RunLimits { max_candidates: 0, ..Default::default() };
+        assert!(matches!(
+            caveat.check(&facts, &limited, &syms),
+            Err(error::Caveat::RunLimit(error::RunLimit::TooManyCandidates(_)))
+        ));
+    }
+
+    #[test]
+    fn caveat_scope() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+        let parent = syms.insert("parent");
+        let grandparent = syms.insert("grandparent");
+        let check = syms.insert("check");
+        let a = sym(&mut syms, "A");
+        let b = sym(&mut syms, "B");
+        let c = sym(&mut syms, "C");
+
+        w.add_fact(fact(parent, &[&a, &b]));
+        w.add_fact(fact(parent, &[&b, &c]));
+        w.add_rule(rule(grandparent, &[var(&mut syms, "x"), var(&mut syms, "z")], &[
+            pred(parent, &[var(&mut syms, "x"), var(&mut syms, "y")]),
+            pred(parent, &[var(&mut syms, "y"), var(&mut syms, "z")]),
+        ]));
+
+        // only succeeds with the derived fact
+        let caveats = [Caveat { queries: vec![rule(check, &[&a], &[pred(grandparent, &[&a, &c])])] }];
+        let caveat = &caveats[0];
+        let limits = RunLimits { max_time: Some(Duration::from_secs(10)), ..Default::default() };
+        let after_run = CaveatScope::AfterRun(limits.clone());
+
+        assert_eq!(CaveatScope::default(), CaveatScope::AsIs);
+        assert_eq!(w.check_caveat(caveat, None), Err(error::Caveat::NoMatch));
+        assert_eq!(w.check_caveat_with_scope(caveat, None, &CaveatScope::AsIs), Err(error::Caveat::NoMatch));
+        assert_eq!(w.check_caveat_with_scope(caveat, None, &after_run), Ok(()));
+        assert_eq!(
+            w.check_caveats_with_scope(&caveats, None, &HashMap::new(), &syms, &after_run),
+            Ok(())
+        );
+
+        // the caller's world was not run
+        assert_eq!(w.facts.len(), 2);
+        assert!(w.check_caveats(&caveats, None, &HashMap::new(), &syms).is_err());
+
+        let limited = CaveatScope::AfterRun(RunLimits { max_iterations: Some(0), ..limits });
+        let failed = w.check_caveats_with_scope(&caveats, None, &HashMap::new(), &syms, &limited).unwrap_err();
+        assert!(matches!(failed[0].error, error::Caveat::RunLimit(error::RunLimit::TooManyIterations(_))));
+        assert_eq!(w.check_caveats_with_scope(&[], None, &HashMap::new(), &syms, &limited), Ok(()));
+
+        w.run_with_limits(RunLimits { max_time: Some(Duration::from_secs(10)), ..Default::default() }).unwrap();
+        assert_eq!(w.check_caveat(caveat, None), Ok(()));
+
+        // a rule error stopping the run fails the caveats instead of panicking
+        let n = syms.insert("n");
+        let negated = syms.insert("negated");
+        let x = var(&mut syms, "x");
+        let y_id = syms.insert("y") as u32;
+        w.add_fact(fact(n, &[string("one")]));
+        w.add_rule(expressed_rule(negated, &[var(&mut syms, "y")], &[pred(n, &[&x])], &[Expression {
+            ops: vec![Op::Value(x.clone()), Op::Unary(Unary::Negate), Op::Bind(y_id)],
+        }]));
+        let fatal = CaveatScope::AfterRun(RunLimits { rule_errors: RuleErrors::Fatal, ..RunLimits::unlimited() });
+        let e = w.check_caveat_with_scope(caveat, None, &fatal).unwrap_err();
+        let failed = error::RuleFailed {
+            rule: 1,
+            error: error::Evaluation::Expression(error::Expression::InvalidType {
+                op: Op::Unary(Unary::Negate),
+                operands: vec!["string"],
+            }),
+        };
+        assert_eq!(e, error::Caveat::RuleFailed(failed.clone()));
+        assert_eq!(
+            syms.print_error(&error::Error::from(e)),
+            "the world could not be run before the check: rule 1 failed: an expression failed: \
+             operator - cannot be applied to string"
+        );
+        let failed_caveats = w.check_caveats_with_scope(&caveats, None, &HashMap::new(), &syms, &fatal).unwrap_err();
+        assert_eq!(failed_caveats[0].error, error::Caveat::RuleFailed(failed));
+    }
+
+    #[test]
+    fn policies() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+        let right = syms.insert("right");
+        let admin = syms.insert("admin");
+        let allowed = syms.insert("allowed");
+        let user = syms.add("user");
+        let read = syms.add("read");
+        let write = syms.add("write");
+
+        w.add_fact(fact(right, &[&user, &read]));
+
+        let u = var(&mut syms, "u");
+        let can_read = rule(allowed, &[&u], &[pred(right, &[&u, &read])]);
+        let can_write = rule(allowed, &[&u], &[pred(right, &[&u, &write])]);
+        let is_admin = rule(allowed, &[&u], &[pred(admin, &[&u])]);
+        let policy = |kind, queries: &[&Rule]| Policy { queries: queries.iter().map(|q| (*q).clone()).collect(), kind };
+
+        let limits = RunLimits::caveat_default();
+        let allow_then_deny = [
+            policy(PolicyKind::Allow, &[&is_admin, &can_read]),
+            policy(PolicyKind::Deny, &[&can_read]),
+        ];
+        assert_eq!(
+            w.evaluate_policies(&allow_then_deny, &limits),
+            Ok(PolicyDecision { index: 0, kind: PolicyKind::Allow })
+        );
+
+        let deny_first = [
+            policy(PolicyKind::Allow, &[&can_write]),
+            policy(PolicyKind::Deny, &[&is_admin, &can_read]),
+            policy(PolicyKind::Allow, &[&can_read]),
+        ];
+        assert_eq!(
+            w.evaluate_policies(&deny_first, &limits),
+            Ok(PolicyDecision { index: 1, kind: PolicyKind::Deny })
+        );
+
+        let none = [policy(PolicyKind::Allow, &[&can_write]), policy(PolicyKind::Deny, &[&is_admin])];
+        assert_eq!(w.evaluate_policies(&none, &limits), Err(error::Policy::NoMatch));
+        assert_eq!(w.evaluate_policies(&[], &limits), Err(error::Policy::NoMatch));
+
+        let passing = [caveat(&[&can_read])];
+        let failing = [caveat(&[&can_write])];
+        assert_eq!(w.authorize(&passing, &allow_then_deny, &limits, &syms), Ok(0));
+        assert_eq!(
+            w.authorize(&passing, &deny_first, &limits, &syms),
+            Err(error::Authorization::Denied(1))
+        );
+        assert_eq!(
+            w.authorize(&passing, &none, &limits, &syms),
+            Err(error::Authorization::Policy(error::Policy::NoMatch))
+        );
+        match w.authorize(&failing, &allow_then_deny, &limits, &syms) {
+            Err(error::Authorization::FailedCaveats { failed, policy }) => {
+                assert_eq!(failed.len(), 1);
+                assert_eq!(failed[0].caveat, "allowed($u) <- right($u, #write)");
+                assert_eq!(policy, Ok(PolicyDecision { index: 0, kind: PolicyKind::Allow }));
+            },
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let mut partial = World::new();
+        partial.partial = true;
+        assert_eq!(partial.evaluate_policies(&allow_then_deny, &limits), Err(error::Policy::Incomplete));
+    }
+
+    #[test]
+    fn int_expr() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let abc = syms.add("abc");
+        let def = syms.add("def");
+        let x = syms.insert("x");
+        let less_than = syms.insert("less_than");
+
+        w.add_fact(fact(x, &[&int(-2), &abc]));
+        w.add_fact(fact(x, &[&int(0), &def]));
+
+        let r1 = expressed_rule(
+            less_than,
+            &[var(&mut syms, "nb"), var(&mut syms, "val")],
+            &[pred(x, &[var(&mut syms, "nb"), var(&mut syms, "val")])],
+            &[
+                Expression { ops: vec![
+                    Op::Value(ID::Integer(5)),
+                    Op::Value(ID::Integer(-4)),
+                    Op::Binary(Binary::Add),
+                    Op::Unary(Unary::Negate),
+                    Op::Value(var(&mut syms, "nb")),
+                    Op::Binary(Binary::LessThan),
+                ] },
+            ],
+        );
+
+        println!("world:\n{}\n", syms.print_world(&w));
+        println!("\ntesting r1: {}\n", syms.print_rule(&r1));
+        assert_eq!(syms.print_rule(&r1), "less_than($nb, $val) <- x($nb, $val) | -(5 + -4) < $nb");
+
+        // variables missing from the table are printed with their id
+        let unknown = Rule {
+            constraints: vec![Constraint { id: 100, kind: ConstraintKind::Int(IntConstraint::LessThan(1)) }],
+            expressions: vec![Expression { ops: vec![Op::Value(ID::Variable(101)), Op::Bind(100)] }],
+            ..rule(less_than, &[ID::Variable(100)], &[pred(x, &[ID::Variable(100), var(&mut syms, "val")])])
+        };
+        assert_eq!(
+            syms.print_rule(&unknown),
+            "less_than($<100?>) <- x($<100?>, $val) @ $<100?> < 1 | $<100?> = $<101?>"
+        );
+        assert_eq!(syms.print_fact(&fact(x, &[&ID::Variable(100)])), "x($<100?>)");
+        let res = w.query_rule(r1);
+        for fact in &res {
+            println!("\t{}", syms.print_fact(fact));
+        }
+
+        let res2 = res.iter().cloned().collect::<HashSet<_>>();
+        println!("got res: {:?}", res2);
+        let compared = (vec![fact(less_than, &[&int(0), &def])])
+            .drain(..)
+            .collect::<HashSet<_>>();
+        assert_eq!(res2, compared);
+
+    }
+
+    #[test]
+    fn computed_head() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let item = syms.insert("item");
+        let total = syms.insert("total");
+        let name = syms.insert("name");
+        let greeting = syms.insert("greeting");
+
+        w.add_fact(fact(item, &[&int(1)]));
+        w.add_fact(fact(item, &[&int(2)]));
+        w.add_fact(fact(name, &[&string("alice")]));
+        w.add_fact(fact(name, &[&string("bob")]));
+
+        let r1 = expressed_rule(
+            total,
+            &[var(&mut syms, "sum")],
+            &[
+                pred(item, &[var(&mut syms, "a")]),
+                pred(item, &[var(&mut syms, "b")]),
+            ],
+            &[
+                Expression { ops: vec![
+                    Op::Value(var(&mut syms, "a")),
+                    Op::Value(var(&mut syms, "b")),
+                    Op::Binary(Binary::Add),
+                    Op::Bind(syms.insert("sum") as u32),
+                ] },
+            ],
+        );
+
+        let r2 = expressed_rule(
+            greeting,
+            &[var(&mut syms, "message")],
+            &[pred(name, &[var(&mut syms, "name")])],
+            &[
+                Expression { ops: vec![
+                    Op::Value(string("hello ")),
+                    Op::Value(var(&mut syms, "name")),
+                    Op::Binary(Binary::Add),
+                    Op::Bind(syms.insert("message") as u32),
+                ] },
+            ],
+        );
+
+        w.add_rule(r1);
+        w.add_rule(r2);
+
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
+
+        let res = w.facts.iter().filter(|f| f.predicate.name == total)
+            .cloned().collect::<HashSet<_>>();
+        let compared = (vec![
+            fact(total, &[&int(2)]),
+            fact(total, &[&int(3)]),
+            fact(total, &[&int(4)]),
+        ])
+        .drain(..)
+        .collect::<HashSet<_>>();
+        assert_eq!(res, compared);
+
+        let res = w.facts.iter().filter(|f| f.predicate.name == greeting)
+            .cloned().collect::<HashSet<_>>();
+        let compared = (vec![
+            fact(greeting, &[&string("hello alice")]),
+            fact(greeting, &[&string("hello bob")]),
+        ])
+        .drain(..)
+        .collect::<HashSet<_>>();
+        assert_eq!(res, compared);
+
+        // running again after reaching the fixpoint does not derive anything new
+        let len = w.facts.len();
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
+        assert_eq!(w.facts.len(), len);
+    }
+
+    #[test]
+    fn negation() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let authority = syms.add("authority");
+        let ambient = syms.add("ambient");
+        let file1 = syms.add("file1");
+        let file2 = syms.add("file2");
+        let read = syms.add("read");
+        let right = syms.insert("right");
+        let resource = syms.insert("resource");
+        let revoked = syms.insert("revoked");
+        let allowed = syms.insert("allowed");
+
+        w.add_fact(fact(resource, &[&ambient, &file1]));
+        w.add_fact(fact(resource, &[&ambient, &file2]));
+        w.add_fact(fact(right, &[&authority, &file1, &read]));
+        w.add_fact(fact(right, &[&authority, &file2, &read]));
+        w.add_fact(fact(revoked, &[&file2]));
+
+        let r1 = Rule {
+            negated: vec![pred(revoked, &[var(&mut syms, "file")])],
+            ..rule(
+                allowed,
+                &[var(&mut syms, "file")],
+                &[
+                    pred(resource, &[&ambient, &var(&mut syms, "file")]),
+                    pred(right, &[&authority, &var(&mut syms, "file"), &read]),
+                ],
+            )
+        };
+
+        assert_eq!(syms.print_rule(&r1),
+          "allowed($file) <- resource(#ambient, $file), right(#authority, $file, #read), !revoked($file)");
+
+        let res = w.query_rule(r1);
+        let res2 = res.iter().cloned().collect::<HashSet<_>>();
+        let compared = (vec![fact(allowed, &[&file1])])
+            .drain(..)
+            .collect::<HashSet<_>>();
+        assert_eq!(res2, compared);
+    }
+
+    #[test]
+    fn negation_all_types() {
+        use crate::parser::{parse_fact, parse_rule};
+        let mut syms = SymbolTable::new();
+        let mut w = World::new();
+        for f in [
+            "token(hex:010203)",
+            "token(hex:0405)",
+            "revoked(hex:010203)",
+            "flag(true)",
+            "flag(false)",
+            "disabled(true)",
+            "group([1, 2])",
+            "group([3])",
+            "empty([3])",
+        ] {
+            w.add_fact(parse_fact(f, &mut syms).unwrap());
+        }
+        for r in [
+            "allow($id) <- token($id), !revoked($id)",
+            "enabled($f) <- flag($f), !disabled($f)",
+            "nonempty($g) <- group($g), !empty($g)",
+            // constants of these types match the facts too
+            "first($id) <- token($id), revoked(hex:010203)",
+        ] {
+            w.add_rule(parse_rule(r, &mut syms).unwrap());
+        }
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
+
+        let mut derived = ["allow", "enabled", "nonempty", "first"].iter()
+            .flat_map(|name| w.facts_for(syms.get(name).unwrap()))
+            .map(|f| syms.print_fact(f))
+            .collect::<Vec<_>>();
+        derived.sort();
+        assert_eq!(derived, [
+            "allow(hex:0405)",
+            "enabled(false)",
+            "first(hex:010203)",
+            "first(hex:0405)",
+            "nonempty([1, 2])",
+        ]);
+    }
+
+    #[test]
+    fn stratification() {
+        let mut syms = SymbolTable::new();
+
+        let a = syms.add("A");
+        let b = syms.add("B");
+        let c = syms.add("C");
+        let parent = syms.insert("parent");
+        let ancestor = syms.insert("ancestor");
+        let unrelated = syms.insert("unrelated");
+        let person = syms.insert("person");
+
+        // recursion through negation cannot be evaluated
+        let mut w = World::new();
+        w.add_fact(fact(person, &[&a]));
+        w.add_rule(Rule {
+            negated: vec![pred(ancestor, &[var(&mut syms, "x"), var(&mut syms, "y")])],
+            ..rule(
+                ancestor,
+                &[var(&mut syms, "x"), var(&mut syms, "y")],
+                &[
+                    pred(person, &[var(&mut syms, "x")]),
+                    pred(person, &[var(&mut syms, "y")]),
+                ],
+            )
+        });
+
+        match w.run() {
+            Err(error::Error::Stratification(e)) => assert_eq!(e.predicate, ancestor),
+            res => panic!("expected a stratification error, got {:?}", res),
+        }
+
+        // the negated predicate is derived by a recursive rule that appears after
+        // the rule using it, but strata make sure it is complete before use
+        let mut w = World::new();
+        for p in &[&a, &b, &c] {
+            w.add_fact(fact(person, &[p]));
+        }
+        w.add_fact(fact(parent, &[&a, &b]));
+        w.add_fact(fact(parent, &[&b, &c]));
+
+        w.add_rule(Rule {
+            negated: vec![pred(ancestor, &[var(&mut syms, "x"), var(&mut syms, "y")])],
+            ..rule(
+                unrelated,
+                &[var(&mut syms, "x"), var(&mut syms, "y")],
+                &[
+                    pred(person, &[var(&mut syms, "x")]),
+                    pred(person, &[var(&mut syms, "y")]),
+                ],
+            )
+        });
+        w.add_rule(rule(
+            ancestor,
+            &[var(&mut syms, "x"), var(&mut syms, "y")],
+            &[pred(parent, &[var(&mut syms, "x"), var(&mut syms, "y")])],
+        ));
+        w.add_rule(rule(
+            ancestor,
+            &[var(&mut syms, "x"), var(&mut syms, "z")],
+            &[
+                pred(ancestor, &[var(&mut syms, "x"), var(&mut syms, "y")]),
+                pred(parent, &[var(&mut syms, "y"), var(&mut syms, "z")]),
+            ],
+        ));
+
+        assert_eq!(w.strata().unwrap(), vec![vec![1, 2], vec![0]]);
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
+
+        assert!(w.facts.contains(&fact(ancestor, &[&a, &c])));
+        assert!(!w.facts.contains(&fact(unrelated, &[&a, &c])));
+        assert!(w.facts.contains(&fact(unrelated, &[&c, &a])));
+        assert_eq!(w.facts.iter().filter(|f| f.predicate.name == unrelated).count(), 6);
+    }
+
+    #[test]
+    fn aggregate() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let alice = syms.add("alice");
+        let bob = syms.add("bob");
+        let carol = syms.add("carol");
+        let user = syms.insert("user");
+        let session = syms.insert("session");
+        let sessions = syms.insert("sessions");
+        let total = syms.insert("total");
+        let first = syms.insert("first");
+        let last = syms.insert("last");
+
+        let date = |secs: u64| ID::Date(1_000_000 + secs);
+        w.add_fact(fact(user, &[&alice]));
+        w.add_fact(fact(user, &[&bob]));
+        w.add_fact(fact(user, &[&carol]));
+        w.add_fact(fact(session, &[&alice, &int(1), &date(0)]));
+        w.add_fact(fact(session, &[&alice, &int(2), &date(10)]));
+        w.add_fact(fact(session, &[&bob, &int(3), &date(20)]));
+
+        let mut aggregated = |name: Symbol, op: AggregateOp, variable: &str| {
+            let head = [var(&mut syms, "user"), var(&mut syms, variable)];
+            let body = [
+                pred(user, &[var(&mut syms, "user")]),
+                pred(session, &[var(&mut syms, "user"), var(&mut syms, "id"), var(&mut syms, "date")]),
+            ];
+            Rule {
+                aggregate: Some((op, syms.insert(variable) as u32)),
+                ..rule(name, &head, &body)
+            }
+        };
+
+        let r1 = aggregated(sessions, AggregateOp::Count, "id");
+        let r2 = aggregated(total, AggregateOp::Sum, "id");
+        let r3 = aggregated(first, AggregateOp::Min, "date");
+        let r4 = aggregated(last, AggregateOp::Max, "date");
+        assert_eq!(syms.print_rule(&r1),
+          "sessions($user, count($id)) <- user($user), session($user, $id, $date)");
+
+        for r in [r1, r2, r3, r4] {
+            w.add_rule(r);
+        }
+
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
+
+        assert!(w.facts.contains(&fact(sessions, &[&alice, &int(2)])));
+        assert!(w.facts.contains(&fact(sessions, &[&bob, &int(1)])));
+        assert!(w.facts.contains(&fact(total, &[&alice, &int(3)])));
+        assert!(w.facts.contains(&fact(total, &[&bob, &int(3)])));
+        assert!(w.facts.contains(&fact(first, &[&alice, &date(0)])));
+        assert!(w.facts.contains(&fact(last, &[&alice, &date(10)])));
+        // carol has no sessions, so there is no group for her
+        assert!(w.facts.iter().all(|f| f.predicate.name == user || f.predicate.ids[0] != carol));
+
+        // new facts update the aggregates instead of adding new results
+        w.add_fact(fact(session, &[&alice, &int(4), &date(30)]));
+        w.add_fact(fact(session, &[&carol, &int(5), &date(0)]));
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
+
+        let res = w.facts.iter().filter(|f| f.predicate.name == sessions)
+            .cloned().collect::<HashSet<_>>();
+        let compared = (vec![
+            fact(sessions, &[&alice, &int(3)]),
+            fact(sessions, &[&bob, &int(1)]),
+            fact(sessions, &[&carol, &int(1)]),
+        ])
+        .drain(..)
+        .collect::<HashSet<_>>();
+        assert_eq!(res, compared);
+        assert!(w.facts.contains(&fact(total, &[&alice, &int(7)])));
+        assert!(w.facts.contains(&fact(last, &[&alice, &date(30)])));
+        assert!(!w.facts.contains(&fact(last, &[&alice, &date(10)])));
+    }
+
+    #[test]
+    fn rule_dedup() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let a = syms.add("A");
+        let b = syms.add("B");
+        let c = syms.add("C");
+        let parent = syms.insert("parent");
+        let grandparent = syms.insert("grandparent");
+
+        w.add_fact(fact(parent, &[&a, &b]));
+        w.add_fact(fact(parent, &[&b, &c]));
+
+        let r1 = constrained_rule(
+            grandparent,
+            &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")],
+            &[
+                pred(parent, &[var(&mut syms, "grandparent"), var(&mut syms, "parent")]),
+                pred(parent, &[var(&mut syms, "parent"), var(&mut syms, "grandchild")]),
+            ],
+            &[Constraint {
+                id: syms.insert("grandchild") as u32,
+                kind: ConstraintKind::Symbol(SymbolConstraint::NotIn(
+                    [syms.insert("D"), syms.insert("E")].iter().cloned().collect(),
+                )),
+            }],
+        );
+
+        // same rule, but the set was built in a different order
+        let r2 = constrained_rule(
+            grandparent,
+            &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")],
+            &[
+                pred(parent, &[var(&mut syms, "grandparent"), var(&mut syms, "parent")]),
+                pred(parent, &[var(&mut syms, "parent"), var(&mut syms, "grandchild")]),
+            ],
+            &[Constraint {
+                id: syms.insert("grandchild") as u32,
+                kind: ConstraintKind::Symbol(SymbolConstraint::NotIn(
+                    [syms.insert("E"), syms.insert("D")].iter().cloned().collect(),
+                )),
+            }],
+        );
+
+        let r3 = rule(
+            grandparent,
+            &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")],
+            &[
+                pred(parent, &[var(&mut syms, "grandparent"), var(&mut syms, "parent")]),
+                pred(parent, &[var(&mut syms, "parent"), var(&mut syms, "grandchild")]),
+            ],
+        );
+
+        let rules = [r1.clone(), r2.clone(), r3.clone()].iter().cloned().collect::<HashSet<_>>();
+        assert_eq!(rules.len(), 2);
+
+        assert!(w.add_rule(r1));
+        assert!(!w.add_rule(r2));
+        assert!(w.add_rule(r3.clone()));
+        assert!(!w.add_rule(r3));
+        assert_eq!(w.rules.len(), 2);
+
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
+        assert!(w.facts.contains(&fact(grandparent, &[&a, &c])));
+    }
+
+    #[test]
+    fn constraint_and_expression_dedup() {
+        let ints = |values: &[i64]| ConstraintKind::Int(IntConstraint::In(values.iter().cloned().collect()));
+        let strs = |values: &[&str]| ConstraintKind::Str(StrConstraint::NotIn(values.iter().map(|s| s.to_string()).collect()));
+        let constraints = [
+            Constraint { id: 0, kind: ints(&[1, 2, 3]) },
+            Constraint { id: 0, kind: ints(&[3, 2, 1]) },
+            Constraint { id: 1, kind: ints(&[1, 2, 3]) },
+            Constraint { id: 0, kind: ints(&[1, 2]) },
+            Constraint { id: 0, kind: strs(&["a", "b"]) },
+            Constraint { id: 0, kind: strs(&["b", "a"]) },
+        ];
+        assert_eq!(constraints.iter().cloned().collect::<HashSet<_>>().len(), 4);
+
+        let add = |left: ID, right: ID| Expression { ops: vec![Op::Value(left), Op::Value(right), Op::Binary(Binary::Add)] };
+        let expressions = [
+            add(ID::Variable(0), int(1)),
+            add(ID::Variable(0), int(1)),
+            add(int(1), ID::Variable(0)),
+            Expression { ops: vec![Op::Value(ID::Variable(0)), Op::Unary(Unary::Negate)] },
+        ];
+        assert_eq!(expressions.iter().cloned().collect::<HashSet<_>>().len(), 3);
+    }
+
+    #[test]
+    fn variable_normalization() {
+        let mut syms = SymbolTable::new();
+
+        let parent = syms.insert("parent");
+        let grandparent = syms.insert("grandparent");
+
+        let r1 = rule(
+            grandparent,
+            &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")],
+            &[
+                pred(parent, &[var(&mut syms, "grandparent"), var(&mut syms, "parent")]),
+                pred(parent, &[var(&mut syms, "parent"), var(&mut syms, "grandchild")]),
+            ],
+        );
+
+        let r2 = rule(
+            grandparent,
+            &[var(&mut syms, "a"), var(&mut syms, "c")],
+            &[
+                pred(parent, &[var(&mut syms, "a"), var(&mut syms, "b")]),
+                pred(parent, &[var(&mut syms, "b"), var(&mut syms, "c")]),
+            ],
+        );
+
+        // the variables are not used in the same way
+        let r3 = rule(
+            grandparent,
+            &[var(&mut syms, "a"), var(&mut syms, "c")],
+            &[
+                pred(parent, &[var(&mut syms, "a"), var(&mut syms, "b")]),
+                pred(parent, &[var(&mut syms, "c"), var(&mut syms, "b")]),
+            ],
+        );
+
+        assert_ne!(r1, r2);
+        assert!(r1.equivalent(&r2));
+        assert!(!r1.equivalent(&r3));
+
+        let normalized = r2.normalize_variables();
+        assert_eq!(normalized.head.ids.to_vec(), vec![ID::Variable(0), ID::Variable(1)]);
+        assert_eq!(normalized.body[0].ids.to_vec(), vec![ID::Variable(0), ID::Variable(2)]);
+        assert_eq!(normalized.body[1].ids.to_vec(), vec![ID::Variable(2), ID::Variable(1)]);
+        assert_eq!(normalized, r1.normalize_variables());
+    }
+
+    #[test]
+    fn wildcard() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let app_0 = syms.add("app_0");
+        let app_1 = syms.add("app_1");
+        let route = syms.insert("route");
+        let app = syms.insert("app");
+
+        w.add_fact(fact(route, &[&int(0), &app_0, &string("example.com")]));
+        w.add_fact(fact(route, &[&int(1), &app_1, &string("test.com")]));
+        w.add_fact(fact(route, &[&int(2), &app_0, &string("test.fr")]));
+
+        let with_variables = rule(
+            app,
+            &[var(&mut syms, "app_id")],
+            &[pred(route, &[var(&mut syms, "route_id"), var(&mut syms, "app_id"), var(&mut syms, "domain_name")])],
+        );
+        let with_wildcards = rule(
+            app,
+            &[var(&mut syms, "app_id")],
+            &[pred(route, &[ID::Wildcard, var(&mut syms, "app_id"), ID::Wildcard])],
+        );
+        assert_eq!(syms.print_rule(&with_wildcards), "app($app_id) <- route(_, $app_id, _)");
+        assert_eq!(with_wildcards.validate(), Ok(()));
+
+        let res1 = w.query_rule(with_variables.clone()).drain(..).collect::<HashSet<_>>();
+        let res2 = w.query_rule(with_wildcards.clone()).drain(..).collect::<HashSet<_>>();
+        assert_eq!(res1, res2);
+        assert_eq!(res2, [fact(app, &[&app_0]), fact(app, &[&app_1])].iter().cloned().collect());
+
+        // wildcards are not recorded in the bindings
+        let variables = |r: &Rule| MatchedVariables::new(r.body.iter().flat_map(|p| p.ids.iter())
+            .filter_map(|id| match id {
+                ID::Variable(i) => Some(*i),
+                _ => None,
+            }).collect());
+        let bindings = CombineIt::new(variables(&with_wildcards), &with_wildcards.body, &[], &[], &[], &w.facts)
+            .collect::<Vec<_>>();
+        assert_eq!(bindings.len(), 3);
+        assert!(bindings.iter().all(|h| h.len() == 1));
+        let bindings = CombineIt::new(variables(&with_variables), &with_variables.body, &[], &[], &[], &w.facts)
+            .collect::<Vec<_>>();
+        assert!(bindings.iter().all(|h| h.len() == 3));
+
+        let invalid = rule(
+            app,
+            &[ID::Wildcard],
+            &[pred(route, &[ID::Wildcard, var(&mut syms, "app_id"), ID::Wildcard])],
+        );
+        assert_eq!(invalid.validate(), Err(error::Rule::WildcardInHead));
+    }
+
+    #[test]
+    fn zero_arity() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let file1 = syms.add("file1");
+        let file2 = syms.add("file2");
+        let resource = syms.insert("resource");
+        let maintenance = syms.insert("maintenance");
+        let readonly = syms.insert("readonly");
+        let locked = syms.insert("locked");
+        let no_ids: &[ID] = &[];
+
+        assert!(match_preds(&pred(maintenance, no_ids), &pred(maintenance, no_ids)));
+        assert!(!match_preds(&pred(maintenance, no_ids), &pred(readonly, no_ids)));
+        assert!(!match_preds(&pred(maintenance, no_ids), &pred(maintenance, &[&file1])));
+
+        w.add_fact(fact(resource, &[&file1]));
+        w.add_fact(fact(resource, &[&file2]));
+
+        // the flag fact gates the rule
+        let r1 = rule(
+            locked,
+            &[var(&mut syms, "file")],
+            &[
+                pred(maintenance, no_ids),
+                pred(resource, &[var(&mut syms, "file")]),
+            ],
+        );
+        // a rule whose body is only a zero-arity predicate
+        let r2 = rule(readonly, no_ids, &[pred(maintenance, no_ids)]);
+        assert_eq!(syms.print_rule(&r2), "readonly() <- maintenance()");
+        assert_eq!(r1.validate(), Ok(()));
+        assert_eq!(r2.validate(), Ok(()));
+
+        assert!(w.query_rule(r1.clone()).is_empty());
+        assert!(w.query_rule(r2.clone()).is_empty());
+
+        w.add_fact(fact(maintenance, no_ids));
+        assert_eq!(w.query(pred(maintenance, no_ids)), vec![&fact(maintenance, no_ids)]);
+
+        w.add_rule(r1);
+        w.add_rule(r2);
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
+
+        assert!(w.facts.contains(&fact(readonly, no_ids)));
+        assert!(w.facts.contains(&fact(locked, &[&file1])));
+        assert!(w.facts.contains(&fact(locked, &[&file2])));
+        assert_eq!(w.facts.len(), 6);
+    }
+
+    #[test]
+    fn arity() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let app_0 = syms.add("app_0");
+        let route = syms.insert("route");
+        let suff = syms.insert("route suffix");
+
+        w.declare_predicate(route, 3);
+        assert_eq!(w.add_fact_checked(fact(route, &[&int(0), &app_0, &string("example.com")]), &RunLimits::default()), Ok(()));
+
+        let res = w.add_fact_checked(fact(route, &[&int(1), &app_0, &string("test.com"), &string("typo")]), &RunLimits::default());
+        assert_eq!(res, Err(error::Check::ArityMismatch { predicate: route, expected: 3, found: 4 }));
+        assert_eq!(syms.print_check_error(&res.unwrap_err()),
+          "predicate route expects 3 terms, got 4");
+        assert_eq!(w.facts.len(), 1);
+
+        let invalid = rule(
+            suff,
+            &[var(&mut syms, "app_id")],
+            &[pred(route, &[var(&mut syms, "app_id"), var(&mut syms, "domain_name")])],
+        );
+        assert_eq!(w.add_rule_checked(invalid.clone(), &RunLimits::default()),
+          Err(error::Check::ArityMismatch { predicate: route, expected: 3, found: 2 }));
+        assert!(w.rules.is_empty());
+
+        let valid = rule(
+            suff,
+            &[var(&mut syms, "app_id")],
+            &[pred(route, &[ID::Wildcard, var(&mut syms, "app_id"), var(&mut syms, "domain_name")])],
+        );
+        assert_eq!(w.add_rule_checked(valid, &RunLimits::default()), Ok(true));
+        assert!(w.arity_report().is_empty());
+
+        // undeclared worlds can still find inconsistencies
+        w.add_fact(fact(route, &[&int(1), &app_0]));
+        w.add_rule(invalid);
+        w.add_fact(fact(suff, &[&app_0]));
+        let report = w.arity_report();
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[&route], [2, 3].iter().cloned().collect());
+    }
+
+    #[test]
+    fn world_config() {
+        let mut syms = SymbolTable::new();
+        let route = syms.insert("route");
+        let parse = |syms: &mut SymbolTable, r: &str| crate::parser::parse_rule(r, syms).unwrap();
+
+        // without a config, only the duplicates are reported
+        let mut w = World::new();
+        assert_eq!(w.try_add_fact(fact(route, &[int(0), string("example.com")])), Ok(true));
+        assert_eq!(w.try_add_fact(fact(route, &[int(0), string("example.com")])), Ok(false));
+        assert_eq!(w.try_add_fact(fact(route, &[var(&mut syms, "id"), ID::Wildcard])), Ok(true));
+        let suffix = parse(&mut syms, "suffix($d) <- route($i, $d)");
+        assert_eq!(w.try_add_rule(suffix.clone()), Ok(true));
+        assert_eq!(w.try_add_rule(suffix.clone()), Ok(false));
+        assert_eq!(w.rules.len(), 1);
+
+        let mut w = World::with_config(WorldConfig {
+            max_facts: Some(2),
+            max_rules: Some(1),
+            max_term_size: Some(16),
+            strict: true,
+        });
+        assert_eq!(w.config().max_facts, Some(2));
+        assert_eq!(w.try_add_fact(fact(route, &[int(0), string("example.com")])), Ok(true));
+        assert_eq!(
+            w.try_add_fact(fact(route, &[int(1), string("a.very.long.example.com")])),
+            Err(error::WorldLimit::TermTooLarge { size: 23, max: 16 })
+        );
+        assert_eq!(w.try_add_fact(fact(route, &[int(1), string("test.com")])), Ok(true));
+        assert_eq!(w.try_add_fact(fact(route, &[int(2), string("test.fr")])), Err(error::WorldLimit::TooManyFacts(2)));
+        // duplicates do not count against the limit
+        assert_eq!(w.try_add_fact(fact(route, &[int(1), string("test.com")])), Ok(false));
+        assert_eq!(w.facts.len(), 2);
+
+        let id = syms.insert("id") as u32;
+        let e = w.try_add_fact(fact(route, &[var(&mut syms, "id"), string("test.com")])).unwrap_err();
+        assert_eq!(e, error::WorldLimit::InvalidFact(error::InvalidFact::Variable(id)));
+        assert_eq!(syms.print_error(&error::Error::from(e)), "invalid fact: the fact contains variable $id");
+        let nested = ID::Set(BTreeSet::from([int(1), ID::Wildcard]));
+        assert_eq!(
+            w.try_add_fact(fact(route, &[int(3), nested])),
+            Err(error::WorldLimit::InvalidFact(error::InvalidFact::Wildcard))
+        );
+
+        let unbound = parse(&mut syms, "suffix($d) <- route($i, $e)");
+        assert_eq!(
+            w.try_add_rule(unbound),
+            Err(error::WorldLimit::InvalidRule(error::Rule::UnboundHeadVariable(syms.get("d").unwrap() as u32)))
+        );
+        let large = parse(&mut syms, r#"suffix($d) <- route($i, $d) | $d == "a.very.long.example.com""#);
+        assert_eq!(w.try_add_rule(large), Err(error::WorldLimit::TermTooLarge { size: 23, max: 16 }));
+        assert_eq!(w.try_add_rule(suffix.clone()), Ok(true));
+        assert_eq!(w.try_add_rule(suffix), Ok(false));
+        let other = parse(&mut syms, "other($i) <- route($i, $d)");
+        assert_eq!(w.try_add_rule(other.clone()), Err(error::WorldLimit::TooManyRules(1)));
+        assert_eq!(w.rules.len(), 1);
+
+        // the other adders do not apply the config
+        assert!(w.add_rule(other));
+        w.add_fact(fact(route, &[int(2), string("test.fr")]));
+        assert_eq!(w.facts.len(), 3);
+    }
+
+    #[test]
+    fn complexity() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let t1 = syms.insert("t1");
+        let t2 = syms.insert("t2");
+        let join = syms.insert("join");
+        let route = syms.insert("route");
+        let suff = syms.insert("route suffix");
+
+        let numbers = expressed_rule(
+            join,
+            &[var(&mut syms, "left"), var(&mut syms, "right")],
+            &[
+                pred(t1, &[var(&mut syms, "id"), var(&mut syms, "left")]),
+                pred(t2, &[var(&mut syms, "t2_id"), var(&mut syms, "right"), var(&mut syms, "id")]),
+            ],
+            &[ Expression { ops: vec![
+                Op::Value(var(&mut syms, "id")),
+                Op::Value(ID::Integer(1)),
+                Op::Binary(Binary::LessThan),
+            ] } ],
+        );
+        assert_eq!(numbers.complexity(), RuleComplexity {
+            predicates: 2,
+            constraints: 0,
+            expression_ops: 3,
+            payload_size: 0,
+        });
+
+        let suffix = Rule {
+            constraints: vec![Constraint {
+                id: syms.insert("route_id") as u32,
+                kind: ConstraintKind::Str(StrConstraint::In(
+                    ["test".to_string(), "aaa".to_string()].iter().cloned().collect(),
+                )),
+            }],
+            ..expressed_rule(
+                suff,
+                &[var(&mut syms, "app_id"), var(&mut syms, "domain_name")],
+                &[pred(
+                    route,
+                    &[var(&mut syms, "route_id"), var(&mut syms, "app_id"), var(&mut syms, "domain_name")],
+                )],
+                &[ Expression { ops: vec![
+                    Op::Value(var(&mut syms, "domain_name")),
+                    Op::Value(string("example.com")),
+                    Op::Binary(Binary::Suffix),
+                ] } ],
+            )
+        };
+        assert_eq!(suffix.complexity(), RuleComplexity {
+            predicates: 1,
+            constraints: 1,
+            expression_ops: 3,
+            payload_size: 18,
+        });
+
+        assert_eq!(fact(route, &[&int(0), &string("example.com"), &ID::Bytes(vec![0; 4])]).weight(), 15);
+
+        let limits = RunLimits {
+            max_rule_complexity: Some(RuleComplexity {
+                predicates: 4,
+                constraints: 4,
+                expression_ops: 3,
+                payload_size: 10,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(w.add_rule_checked(numbers, &limits), Ok(true));
+        assert_eq!(w.add_rule_checked(suffix.clone(), &limits),
+          Err(error::Check::RuleTooComplex(suffix.complexity())));
+        assert_eq!(w.rules.len(), 1);
+    }
+
+    #[test]
+    fn deep_body() {
+        let mut syms = SymbolTable::new();
+        let step = syms.insert("step");
+        let path = syms.insert("path");
+
+        let mut w = World::new();
+        for i in 0..100 {
+            w.add_fact(fact(step, &[&int(i), &int(i + 1)]));
+        }
+
+        let chain = |len: u32| {
+            let body = (0..len)
+                .map(|i| pred(step, &[ID::Variable(i), ID::Variable(i + 1)]))
+                .collect::<Vec<_>>();
+            rule(path, &[ID::Variable(0), ID::Variable(len)], &body)
+        };
+
+        let limits = RunLimits { max_time: Some(Duration::from_secs(10)), ..Default::default() };
+        assert_eq!(w.add_rule_checked(chain(100), &limits), Ok(true));
+        w.run_with_limits(limits.clone()).unwrap();
+        assert_eq!(w.facts.with_name(path).collect::<Vec<_>>(), vec![&fact(path, &[&int(0), &int(100)])]);
+
+        assert_eq!(w.add_rule_checked(chain(100_000), &limits),
+          Err(error::Check::TooManyBodyPredicates { found: 100_000, max: 256 }));
+        assert_eq!(w.rules.len(), 1);
+    }
+
+    #[test]
+    fn provenance() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let a = syms.add("A");
+        let b = syms.add("B");
+        let c = syms.add("C");
+        let d = syms.add("D");
+        let parent = syms.insert("parent");
+        let grandparent = syms.insert("grandparent");
+        let ancestor = syms.insert("ancestor");
+
+        w.add_fact(fact(parent, &[&a, &b]));
+        w.add_fact(fact(parent, &[&b, &c]));
+        w.add_fact(fact(parent, &[&c, &d]));
+
+        w.add_rule(rule(
+            grandparent,
+            &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")],
+            &[
+                pred(parent, &[var(&mut syms, "grandparent"), var(&mut syms, "parent")]),
+                pred(parent, &[var(&mut syms, "parent"), var(&mut syms, "grandchild")]),
+            ],
+        ));
+        w.add_rule(rule(
+            ancestor,
+            &[var(&mut syms, "a"), var(&mut syms, "b")],
+            &[pred(grandparent, &[var(&mut syms, "a"), var(&mut syms, "b")])],
+        ));
+
+        w.run_with_provenance(RunLimits::unlimited()).unwrap();
+
+        assert_eq!(w.origin_of(&fact(parent, &[&a, &b])), None);
+
+        let leaf = |f: Fact| ProofTree { fact: f, rule: None, premises: Vec::new() };
+
+        let origin = w.origin_of(&fact(grandparent, &[&a, &c])).unwrap();
+        assert_eq!(origin.rule, 0);
+        assert_eq!(origin.bindings.get(&(syms.insert("parent") as u32)), Some(&b));
+        assert_eq!(w.explain(&fact(grandparent, &[&a, &c])), ProofTree {
+            fact: fact(grandparent, &[&a, &c]),
+            rule: Some(0),
+            premises: vec![leaf(fact(parent, &[&a, &b])), leaf(fact(parent, &[&b, &c]))],
+        });
+        assert_eq!(w.explain(&fact(grandparent, &[&b, &d])), ProofTree {
+            fact: fact(grandparent, &[&b, &d]),
+            rule: Some(0),
+            premises: vec![leaf(fact(parent, &[&b, &c])), leaf(fact(parent, &[&c, &d]))],
+        });
+
+        assert_eq!(w.explain(&fact(ancestor, &[&a, &c])), ProofTree {
+            fact: fact(ancestor, &[&a, &c]),
+            rule: Some(1),
+            premises: vec![w.explain(&fact(grandparent, &[&a, &c]))],
+        });
+
+        // without provenance, nothing is recorded
+        let mut w2 = World { derivations: HashMap::new(), ..w.clone() };
+        w2.facts.retain(|f| f.predicate.name == parent);
+        w2.run_with_limits(RunLimits::unlimited()).unwrap();
+        assert!(w2.derivations.is_empty());
+        assert_eq!(w2.facts, w.facts);
+    }
+
+    #[test]
+    fn deterministic() {
+        let mut syms = SymbolTable::new();
+        let edge = syms.insert("edge");
+        let path = syms.insert("path");
+
+        let path_rules = [
+            rule(
+                path,
+                &[var(&mut syms, "a"), var(&mut syms, "b")],
+                &[pred(edge, &[var(&mut syms, "a"), var(&mut syms, "b")])],
+            ),
+            rule(
+                path,
+                &[var(&mut syms, "a"), var(&mut syms, "c")],
+                &[
+                    pred(edge, &[var(&mut syms, "a"), var(&mut syms, "b")]),
+                    pred(path, &[var(&mut syms, "b"), var(&mut syms, "c")]),
+                ],
+            ),
+        ];
+
+        let build = |reversed: bool| {
+            let mut w = World::new_deterministic();
+            let mut edges = (0..30).map(|i| fact(edge, &[&int(i), &int(i + 1)])).collect::<Vec<_>>();
+            if reversed {
+                edges.reverse();
+            }
+            for e in edges {
+                w.add_fact(e);
+            }
+            for r in path_rules.iter() {
+                w.add_rule(r.clone());
+            }
+            w
+        };
+
+        let limits = || RunLimits {
+            max_time: Some(Duration::from_secs(10)),
+            max_candidates: 2000,
+            ..Default::default()
+        };
+
+        let mut w1 = build(false);
+        let mut w2 = build(true);
+        let res1 = w1.run_with_limits(limits());
+        let res2 = w2.run_with_limits(limits());
+        assert!(matches!(res1, Err(error::Error::RunLimit(error::RunLimit::TooManyCandidates(_)))));
+        assert!(matches!(res2, Err(error::Error::RunLimit(error::RunLimit::TooManyCandidates(_)))));
+
+        assert!(w1.facts.len() > 30);
+        assert_eq!(syms.print_world(&w1), syms.print_world(&w2));
+        assert_eq!(w1.query(pred(path, &[int(0), ID::Wildcard])), w2.query(pred(path, &[int(0), ID::Wildcard])));
+        assert_eq!(w1.query_rule(path_rules[1].clone()), w2.query_rule(path_rules[1].clone()));
+
+        let facts = w1.facts.iter().collect::<Vec<_>>();
+        let mut sorted = facts.clone();
+        sorted.sort();
+        assert_eq!(facts, sorted);
+    }
+
+    #[test]
+    fn deterministic_truncation() {
+        let mut syms = SymbolTable::new();
+        let rules = [
+            "path($a, $b) <- edge($a, $b)",
+            "path($a, $c) <- path($a, $b), edge($b, $c)",
+            "pair($a, $b) <- node($a), node($b) | $a < $b",
+            "leaf($a) <- node($a), !edge($a, _)",
+        ].map(|r| crate::parser::parse_rule(r, &mut syms).unwrap());
+        let edge = syms.insert("edge");
+        let node = syms.insert("node");
+
+        // the facts are added in a different order for each run
+        let run = |seed: usize, limits: RunLimits| {
+            let mut facts = (0..20).flat_map(|i| [fact(edge, &[int(i), int(i + 1)]), fact(node, &[int(i)])]).collect::<Vec<_>>();
+            facts.rotate_left(seed);
+            if seed % 2 == 1 {
+                facts.reverse();
+            }
+
+            let mut w = World::new_deterministic();
+            for f in facts {
+                w.add_fact(f);
+            }
+            for r in rules.iter() {
+                w.add_rule(r.clone());
+            }
+            let res = w.run_with_limits(limits);
+            (res.map_err(|e| e.to_string().split(", ").next().map(str::to_string)), w.facts.iter().cloned().collect::<Vec<_>>())
+        };
+
+        let limits = [
+            RunLimits { max_facts: Some(150), ..RunLimits::unlimited() },
+            RunLimits { max_candidates: 3000, ..RunLimits::unlimited() },
+            RunLimits { max_memory: Some(3000), ..RunLimits::unlimited() },
+            RunLimits { max_iterations: Some(4), ..RunLimits::unlimited() },
+        ];
+        for limits in limits {
+            let (first_error, first_facts) = run(0, limits.clone());
+            assert!(first_error.is_err(), "{:?} did not stop the run", limits);
+            for seed in 1..20 {
+                let (error, facts) = run(seed, limits.clone());
+                assert_eq!(error, first_error);
+                assert_eq!(facts, first_facts, "run {} with {:?}", seed, limits);
+            }
+        }
+    }
 
-        w.add_fact(fact(t2, &[&int(0), &aaa, &int(0)]));
-        w.add_fact(fact(t2, &[&int(1), &bbb, &int(0)]));
-        w.add_fact(fact(t2, &[&int(2), &ccc, &int(1)]));
+    #[test]
+    fn stats() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
 
-        let res = w.query_rule(rule(
-            join,
-            &[var(&mut syms, "left"), var(&mut syms, "right")],
+        let a = syms.add("A");
+        let b = syms.add("B");
+        let c = syms.add("C");
+        let d = syms.add("D");
+        let parent = syms.insert("parent");
+        let grandparent = syms.insert("grandparent");
+
+        w.add_fact(fact(parent, &[&a, &b]));
+        w.add_fact(fact(parent, &[&b, &c]));
+        w.add_fact(fact(parent, &[&c, &d]));
+        w.add_rule(rule(
+            grandparent,
+            &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")],
             &[
-                pred(t1, &[var(&mut syms, "id"), var(&mut syms, "left")]),
-                pred(t2, &[var(&mut syms, "t2_id"), var(&mut syms, "right"), var(&mut syms, "id")]),
+                pred(parent, &[var(&mut syms, "grandparent"), var(&mut syms, "parent")]),
+                pred(parent, &[var(&mut syms, "parent"), var(&mut syms, "grandchild")]),
             ],
         ));
-        for fact in &res {
-            println!("\t{}", syms.print_fact(fact));
+
+        let limits = || RunLimits { max_time: Some(Duration::from_secs(10)), ..Default::default() };
+        let stats = w.clone().run_with_stats(limits()).unwrap();
+        assert_eq!(stats.iterations.iter().map(|i| i.new).collect::<Vec<_>>(), vec![2, 0]);
+        let counts = |stats: &RunStats| stats.rules.iter().map(|r| (r.applications, r.emitted, r.new)).collect::<Vec<_>>();
+        assert_eq!(counts(&stats), vec![(2, 4, 2)]);
+        assert_eq!(stats.rules[0].duplicates(), 2);
+        assert!(stats.duration >= stats.iterations[0].duration);
+
+        let err = w.run_with_stats(RunLimits { max_facts: Some(4), ..limits() }).unwrap_err();
+        assert!(matches!(err.error, error::Error::RunLimit(error::RunLimit::TooManyFacts(_))));
+        assert_eq!(err.stats.iterations.len(), 1);
+        assert_eq!(counts(&err.stats), vec![(1, 2, 1)]);
+    }
+
+    #[test]
+    fn deduplicated_rule() {
+        let mut syms = SymbolTable::new();
+        let linked = syms.insert("linked");
+        let hub = syms.insert("hub");
+
+        let mut w = World::new();
+        for a in 0..10 {
+            for b in 0..10 {
+                w.add_fact(fact(linked, &[&int(a), &int(b)]));
+            }
         }
+        // each fact is generated by 100 bindings of `b` and `c`
+        w.add_rule(rule(hub, &[var(&mut syms, "a")], &[
+            pred(linked, &[var(&mut syms, "a"), var(&mut syms, "b")]),
+            pred(linked, &[var(&mut syms, "b"), var(&mut syms, "c")]),
+        ]));
 
-        let res2 = res.iter().cloned().collect::<HashSet<_>>();
-        let compared = (vec![
-            fact(join, &[&abc, &aaa]),
-            fact(join, &[&abc, &bbb]),
-            fact(join, &[&def, &ccc]),
-        ])
-        .drain(..)
-        .collect::<HashSet<_>>();
-        assert_eq!(res2, compared);
+        let mut all = Vec::new();
+        assert_eq!(w.rules[0].apply_budgeted(&w.facts, &mut all, None, true, None, None), 0);
+        assert_eq!(all.len(), 1000);
+        let mut deduplicated = Vec::new();
+        assert_eq!(w.rules[0].apply_budgeted(&w.facts, &mut deduplicated, None, true, None, Some(&FactSet::new())), 990);
+        assert_eq!(deduplicated.len(), 10);
+        let mut unknown = Vec::new();
+        assert_eq!(w.rules[0].apply_budgeted(&w.facts, &mut unknown, None, true, None, Some(&w.facts)), 990);
+        assert_eq!(unknown.len(), 10);
+
+        let expected = all.into_iter().map(|(f, _)| f).chain(w.facts.iter().cloned()).collect::<FactSet>();
+        let limits = RunLimits { max_time: Some(Duration::from_secs(10)), ..Default::default() };
+        let mut res = w.clone();
+        let stats = res.run_with_stats(limits).unwrap();
+        assert_eq!(res.facts, expected);
+        // the second iteration only finds known facts
+        assert_eq!(stats.iterations.len(), 2);
+        assert_eq!(stats.rules[0].emitted, 2000);
+        assert_eq!(stats.rules[0].skipped, 1990);
+        assert_eq!(stats.rules[0].new, 10);
+    }
 
-        // test constraints
-        /*
-        let res = w.query_rule(constrained_rule(
-            join,
-            &[var(&mut syms, "left"), var(&mut syms, "right")],
+    #[test]
+    #[cfg(feature = "std")]
+    fn rule_stats() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let a = syms.add("A");
+        let b = syms.add("B");
+        let c = syms.add("C");
+        let d = syms.add("D");
+        let parent = syms.insert("parent");
+        let grandparent = syms.insert("grandparent");
+        let ancestor = syms.insert("ancestor");
+        let unknown = syms.insert("unknown");
+
+        w.add_fact(fact(parent, &[&a, &b]));
+        w.add_fact(fact(parent, &[&b, &c]));
+        w.add_fact(fact(parent, &[&c, &d]));
+        w.add_rule(rule(
+            grandparent,
+            &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")],
             &[
-                pred(t1, &[var(&mut syms, "id"), var(&mut syms, "left")]),
-                pred(t2, &[var(&mut syms, "t2_id"), var(&mut syms, "right"), var(&mut syms, "id")]),
+                pred(parent, &[var(&mut syms, "grandparent"), var(&mut syms, "parent")]),
+                pred(parent, &[var(&mut syms, "parent"), var(&mut syms, "grandchild")]),
             ],
-            &[Constraint {
-                id: syms.insert("id") as u32,
-                kind: ConstraintKind::Int(IntConstraint::LessThan(1)),
-            }],
         ));
-        */
+        w.add_rule(rule(ancestor, &[var(&mut syms, "a"), var(&mut syms, "b")], &[
+            pred(parent, &[var(&mut syms, "a"), var(&mut syms, "b")]),
+        ]));
+        w.add_rule(rule(ancestor, &[var(&mut syms, "a"), var(&mut syms, "c")], &[
+            pred(ancestor, &[var(&mut syms, "a"), var(&mut syms, "b")]),
+            pred(parent, &[var(&mut syms, "b"), var(&mut syms, "c")]),
+        ]));
+        // never applied, nothing generates `unknown` facts
+        w.add_rule(rule(ancestor, &[var(&mut syms, "a")], &[pred(unknown, &[var(&mut syms, "a")])]));
+
+        let limits = RunLimits { max_time: Some(Duration::from_secs(10)), ..Default::default() };
+        let stats = w.clone().run_with_stats(limits.clone()).unwrap();
+        assert_eq!(stats.rules.len(), w.rules.len());
+        assert!(stats.rules[..3].iter().all(|r| r.applications > 0 && r.emitted > 0));
+        assert_eq!(stats.rules[3], RuleStats::default());
+        assert_eq!(stats.rules.iter().map(|r| r.new).sum::<usize>(), 2 + 6);
+        let rule_time = stats.rules.iter().map(|r| r.total_time).sum::<Duration>();
+        assert!(rule_time > Duration::ZERO);
+        assert!(rule_time <= stats.duration);
+
+        // no timing outside of `run_with_stats`
+        let mut stats = RunStats::default();
+        w.evaluate(limits, Evaluation::Plain, &mut |_| ControlFlow::Continue(()), &mut stats).unwrap();
+        assert!(stats.rules.iter().all(|r| r.applications > 0 || r.emitted == 0));
+        assert!(stats.rules.iter().all(|r| r.total_time == Duration::ZERO));
+    }
 
-        let res = w.query_rule(expressed_rule(
-            join,
-            &[var(&mut syms, "left"), var(&mut syms, "right")],
+    #[test]
+    #[cfg(feature = "std")]
+    fn timeout_inside_rule() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let left = syms.insert("left");
+        let right = syms.insert("right");
+        let pair = syms.insert("pair");
+
+        for i in 0..2000 {
+            w.add_fact(fact(left, &[&int(i)]));
+            w.add_fact(fact(right, &[&int(i)]));
+        }
+        // a single application of this rule examines 4M combinations
+        w.add_rule(rule(
+            pair,
+            &[var(&mut syms, "a"), var(&mut syms, "b")],
             &[
-                pred(t1, &[var(&mut syms, "id"), var(&mut syms, "left")]),
-                pred(t2, &[var(&mut syms, "t2_id"), var(&mut syms, "right"), var(&mut syms, "id")]),
+                pred(left, &[var(&mut syms, "a")]),
+                pred(right, &[var(&mut syms, "b")]),
             ],
-            &[ Expression { ops: vec![
-                Op::Value(var(&mut syms, "id")),
-                Op::Value(ID::Integer(1)),
-                Op::Binary(Binary::LessThan),
-            ] } ],
         ));
-        for fact in &res {
-            println!("\t{}", syms.print_fact(fact));
+
+        let start = std::time::Instant::now();
+        let res = w.run_with_limits(RunLimits {
+            max_time: Some(Duration::from_millis(5)),
+            max_candidates: u64::MAX,
+            max_facts: None,
+            ..Default::default()
+        });
+        assert!(matches!(res, Err(error::Error::RunLimit(error::RunLimit::Timeout(_)))));
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert!(w.facts.len() < 4000 + 2000 * 2000);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn limits_builder() {
+        let limits = RunLimits::default();
+        assert_eq!(limits.max_facts, Some(1000));
+        assert_eq!(limits.max_iterations, Some(100));
+        assert_eq!(limits.max_time, Some(Duration::from_millis(1)));
+        assert_eq!(RunLimits::builder().build(), Ok(limits));
+
+        let unbounded = RunLimits::builder().max_facts(None).max_iterations(None).max_time(None);
+        assert_eq!(unbounded.clone().build(), Err(error::Unbounded));
+        let limits = unbounded.allow_unlimited().max_candidates(u64::MAX).build().unwrap();
+        assert_eq!(limits, RunLimits::unlimited());
+
+        let mut syms = SymbolTable::new();
+        let next = syms.insert("next");
+        let reach = syms.insert("reach");
+
+        // 60 iterations to derive 60 facts
+        let mut w = World::new();
+        w.add_fact(fact(reach, &[&int(0)]));
+        for i in 0..60 {
+            w.add_fact(fact(next, &[&int(i), &int(i + 1)]));
         }
+        w.add_rule(rule(reach, &[var(&mut syms, "b")], &[
+            pred(reach, &[var(&mut syms, "a")]),
+            pred(next, &[var(&mut syms, "a"), var(&mut syms, "b")]),
+        ]));
 
-        let res2 = res.iter().cloned().collect::<HashSet<_>>();
-        let compared = (vec![fact(join, &[&abc, &aaa]), fact(join, &[&abc, &bbb])])
-            .drain(..)
-            .collect::<HashSet<_>>();
-        assert_eq!(res2, compared);
+        let builder = || RunLimits::builder()
+            .max_facts(Some(1000))
+            .max_iterations(Some(1000))
+            .max_time(Some(Duration::from_secs(60)))
+            .max_candidates(u64::MAX);
+
+        let res = w.clone().run_with_limits(builder().max_facts(Some(100)).build().unwrap());
+        assert!(matches!(res, Err(error::Error::RunLimit(error::RunLimit::TooManyFacts(_)))));
+        let mut unlimited = w.clone();
+        unlimited.run_with_limits(builder().max_facts(None).build().unwrap()).unwrap();
+        assert!(unlimited.facts.contains(&fact(reach, &[&int(60)])));
+
+        let res = w.clone().run_with_limits(builder().max_iterations(Some(50)).build().unwrap());
+        assert!(matches!(res, Err(error::Error::RunLimit(error::RunLimit::TooManyIterations(_)))));
+        let mut unlimited = w.clone();
+        unlimited.run_with_limits(builder().max_iterations(None).build().unwrap()).unwrap();
+        assert!(unlimited.facts.contains(&fact(reach, &[&int(60)])));
+
+        let res = w.clone().run_with_limits(builder().max_time(Some(Duration::from_nanos(1))).build().unwrap());
+        assert!(matches!(res, Err(error::Error::RunLimit(error::RunLimit::Timeout(_)))));
+        let mut unlimited = w.clone();
+        unlimited.run_with_limits(builder().max_time(None).build().unwrap()).unwrap();
+        assert!(unlimited.facts.contains(&fact(reach, &[&int(60)])));
+
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
+        assert_eq!(w.facts.len(), 121);
     }
 
     #[test]
-    fn str() {
+    fn candidate_limit() {
         let mut w = World::new();
         let mut syms = SymbolTable::new();
 
-        let app_0 = syms.add("app_0");
-        let app_1 = syms.add("app_1");
-        let app_2 = syms.add("app_2");
-        let route = syms.insert("route");
-        let suff = syms.insert("route suffix");
+        let left = syms.insert("left");
+        let right = syms.insert("right");
+        let pair = syms.insert("pair");
 
-        w.add_fact(fact(route, &[&int(0), &app_0, &string("example.com")]));
-        w.add_fact(fact(route, &[&int(1), &app_1, &string("test.com")]));
-        w.add_fact(fact(route, &[&int(2), &app_2, &string("test.fr")]));
-        w.add_fact(fact(route, &[&int(3), &app_0, &string("www.example.com")]));
-        w.add_fact(fact(route, &[&int(4), &app_1, &string("mx.example.com")]));
+        for i in 0..1000 {
+            w.add_fact(fact(left, &[&int(i)]));
+            w.add_fact(fact(right, &[&int(i)]));
+        }
+        w.add_rule(rule(
+            pair,
+            &[var(&mut syms, "a"), var(&mut syms, "b")],
+            &[
+                pred(left, &[var(&mut syms, "a")]),
+                pred(right, &[var(&mut syms, "b")]),
+            ],
+        ));
 
-        fn test_suffix(w: &World, syms: &mut SymbolTable, suff: Symbol, route: Symbol, suffix: &str) -> Vec<Fact> {
-            /*
-            w.query_rule(constrained_rule(
-                suff,
-                &[var(syms, "app_id"), var(syms, "domain_name")],
-                &[pred(
-                    route,
-                    &[var(syms, "route_id"), var(syms, "app_id"), var(syms, "domain_name")],
-                )],
-                &[Constraint {
-                    id: syms.insert("domain_name") as u32,
-                    kind: ConstraintKind::Str(StrConstraint::Suffix(suffix.to_string())),
-                }],
-            ))
-            */
-            w.query_rule(expressed_rule(
-                suff,
-                &[var(syms, "app_id"), var(syms, "domain_name")],
-                &[pred(
-                    route,
-                    &[var(syms, "route_id"), var(syms, "app_id"), var(syms, "domain_name")],
-                )],
-                &[ Expression { ops: vec![
-                    Op::Value(var(syms, "domain_name")),
-                    Op::Value(ID::Str(suffix.to_string())),
-                    Op::Binary(Binary::Suffix),
-                ] } ],
-            ))
+        // no fact or time limit, only the join size stops the run
+        let limits = RunLimits { max_candidates: 10_000, ..RunLimits::unlimited() };
+        for _ in 0..3 {
+            let mut world = w.clone();
+            let err = world.run_with_stats(limits.clone()).unwrap_err();
+            assert!(matches!(err.error, error::Error::RunLimit(error::RunLimit::TooManyCandidates(_))));
+            // the first left fact, then 1000 right facts for each of them
+            assert_eq!(err.stats.rules[0].emitted, 9_990);
+            assert_eq!(world.facts, w.facts);
         }
 
-        let res = test_suffix(&w, &mut syms, suff, route, ".fr");
-        for fact in &res {
-            println!("\t{}", syms.print_fact(fact));
+        let res = w.query_rule_with_limits(w.rules[0].clone(), limits);
+        assert!(matches!(res, Err(error::RunLimit::TooManyCandidates(_))));
+    }
+
+    #[test]
+    fn memory_limit() {
+        let mut syms = SymbolTable::new();
+        let blob = syms.insert("blob");
+        let small = syms.insert("small");
+        let copy = syms.insert("copy");
+
+        let limits = RunLimits {
+            max_memory: Some(1 << 20),
+            max_time: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+
+        // few but huge facts
+        let mut w = World::new();
+        let huge = |i: u8| fact(blob, &[&ID::Bytes(vec![i; 300_000])]);
+        for i in 0..3 {
+            w.add_fact_checked(huge(i), &limits).unwrap();
+        }
+        assert!(matches!(
+            w.add_fact_checked(huge(3), &limits),
+            Err(error::Check::RunLimit(error::RunLimit::TooMuchMemory(_)))
+        ));
+        assert_eq!(w.facts.len(), 3);
+        // already present
+        w.add_fact_checked(huge(0), &limits).unwrap();
+
+        w.add_rule(rule(copy, &[var(&mut syms, "b")], &[pred(blob, &[var(&mut syms, "b")])]));
+        let res = w.run_with_limits(limits.clone());
+        assert!(matches!(res, Err(error::Error::RunLimit(error::RunLimit::TooMuchMemory(_)))));
+        assert!(w.facts.estimated_size() <= 1 << 20);
+        assert_eq!(w.facts.len(), 3);
+
+        // many tiny facts
+        let mut w = World::new();
+        for i in 0..400 {
+            w.add_fact_checked(fact(small, &[&int(i)]), &limits).unwrap();
+        }
+        w.add_rule(rule(copy, &[var(&mut syms, "i")], &[pred(small, &[var(&mut syms, "i")])]));
+        w.run_with_limits(limits).unwrap();
+        assert_eq!(w.facts.len(), 800);
+        assert_eq!(w.facts.estimated_size(), 800 * core::mem::size_of::<ID>());
+    }
+
+    #[test]
+    fn run_rule() {
+        let mut syms = SymbolTable::new();
+        let parent = syms.insert("parent");
+        let ancestor = syms.insert("ancestor");
+        let base = crate::parser::parse_rule("ancestor($a, $b) <- parent($a, $b)", &mut syms).unwrap();
+        let recursive = crate::parser::parse_rule("ancestor($a, $c) <- ancestor($a, $b), parent($b, $c)", &mut syms).unwrap();
+        let other = crate::parser::parse_rule("other($a) <- parent($a, $b)", &mut syms).unwrap();
+
+        let mut w = World::new();
+        for i in 0..9 {
+            w.add_fact(fact(parent, &[int(i), int(i + 1)]));
+        }
+        w.add_rule(other);
+        let limits = RunLimits::unlimited();
+        assert_eq!(w.run_rule(&base, &limits), Ok(9));
+        assert_eq!(w.run_rule(&recursive, &limits), Ok(36));
+        assert_eq!(w.run_rule(&recursive, &limits), Ok(0));
+        assert_eq!(w.query(pred(ancestor, &[ID::Wildcard, ID::Wildcard])).len(), 45);
+        assert_eq!(w.query(pred(ancestor, &[int(0), int(9)])).len(), 1);
+        // the rules of the world were not applied, and the rule was not added
+        assert_eq!(w.facts.len(), 9 + 45);
+        assert_eq!(w.rules.len(), 1);
+
+        // one pass per length of path, the limits apply to the whole fixpoint
+        let chain = || {
+            let mut w = World::new();
+            for i in 0..9 {
+                w.add_fact(fact(parent, &[int(i), int(i + 1)]));
+            }
+            w.run_rule(&base, &RunLimits::unlimited()).unwrap();
+            w
+        };
+        let e = chain().run_rule(&recursive, &RunLimits { max_iterations: Some(3), ..RunLimits::unlimited() }).unwrap_err();
+        assert!(matches!(e, error::RunLimit::TooManyIterations(ref c) if c.iterations == 3));
+        let mut truncated = chain();
+        let e = truncated.run_rule(&recursive, &RunLimits { max_facts: Some(30), ..RunLimits::unlimited() }).unwrap_err();
+        assert!(matches!(e, error::RunLimit::TooManyFacts(_)));
+        assert_eq!(truncated.facts.len(), 30);
+        let e = chain().run_rule(&recursive, &RunLimits { max_candidates: 10, ..RunLimits::unlimited() }).unwrap_err();
+        assert!(matches!(e, error::RunLimit::TooManyCandidates(_)));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn limit_context() {
+        let mut syms = SymbolTable::new();
+        let next = syms.insert("next");
+        let reach = syms.insert("reach");
+        let other = syms.insert("other");
+
+        // one new reach fact per iteration
+        let mut w = World::new();
+        w.add_fact(fact(reach, &[&int(0)]));
+        for i in 0..20 {
+            w.add_fact(fact(next, &[&int(i), &int(i + 1)]));
+        }
+        w.add_rule(rule(other, &[var(&mut syms, "a")], &[pred(next, &[var(&mut syms, "a"), int(0)])]));
+        w.add_rule(rule(reach, &[var(&mut syms, "b")], &[
+            pred(reach, &[var(&mut syms, "a")]),
+            pred(next, &[var(&mut syms, "a"), var(&mut syms, "b")]),
+        ]));
+        let limits = || RunLimits { max_time: Some(Duration::from_secs(10)), ..Default::default() };
+        let context = |res: Result<(), error::Error>| match res {
+            Err(error::Error::RunLimit(e)) => (e, *e.context().unwrap()),
+            res => panic!("unexpected result: {:?}", res),
+        };
+
+        let (e, c) = context(w.clone().run_with_limits(RunLimits { max_iterations: Some(5), ..limits() }));
+        assert!(matches!(e, error::RunLimit::TooManyIterations(_)));
+        assert_eq!((c.iterations, c.facts, c.rule), (5, 26, None));
+        assert!(e.to_string().starts_with("too many iterations after 5 iterations, 26 facts"));
+
+        let (e, c) = context(w.clone().run_with_limits(RunLimits { max_facts: Some(24), ..limits() }));
+        assert!(matches!(e, error::RunLimit::TooManyFacts(_)));
+        assert_eq!((c.iterations, c.facts, c.rule), (3, 24, Some(1)));
+        assert!(e.to_string().ends_with("while applying rule 1"));
+        assert_eq!(syms.print_rule(&w.rules[c.rule.unwrap()]), "reach($b) <- reach($a), next($a, $b)");
+
+        let max_memory = Some(43 * core::mem::size_of::<ID>());
+        let (e, c) = context(w.clone().run_with_limits(RunLimits { max_memory, ..limits() }));
+        assert!(matches!(e, error::RunLimit::TooMuchMemory(_)));
+        assert_eq!((c.iterations, c.facts, c.rule), (2, 23, Some(1)));
+
+        let (e, c) = context(w.clone().run_with_limits(RunLimits { max_candidates: 1, ..limits() }));
+        assert!(matches!(e, error::RunLimit::TooManyCandidates(_)));
+        assert_eq!((c.iterations, c.facts, c.rule), (0, 21, Some(1)));
+
+        let (e, c) = context(w.run_with_limits(RunLimits { max_time: Some(Duration::ZERO), ..limits() }));
+        assert!(matches!(e, error::RunLimit::Timeout(_)));
+        assert_eq!((c.iterations, c.facts), (1, 22));
+        assert!(c.elapsed > Duration::ZERO);
+    }
+
+    /// advances by one second every time it is read
+    struct Jumps(AtomicUsize);
+
+    impl time::Clock for Jumps {
+        fn now(&self) -> Duration {
+            Duration::from_secs(self.0.fetch_add(1, Ordering::Relaxed) as u64)
+        }
+    }
+
+    #[test]
+    fn fake_clock() {
+        let mut syms = SymbolTable::new();
+        let next = syms.insert("next");
+        let reach = syms.insert("reach");
+
+        // one new reach fact per iteration
+        let mut w = World::new();
+        w.add_fact(fact(reach, &[&int(0)]));
+        for i in 0..20 {
+            w.add_fact(fact(next, &[&int(i), &int(i + 1)]));
+        }
+        w.add_rule(rule(reach, &[var(&mut syms, "b")], &[
+            pred(reach, &[var(&mut syms, "a")]),
+            pred(next, &[var(&mut syms, "a"), var(&mut syms, "b")]),
+        ]));
+        let limits = || RunLimits { max_time: Some(Duration::from_secs(10)), ..Default::default() };
+
+        let timeouts = (0..2).map(|_| {
+            let res = w.clone().run_with_limits_and_clock(limits(), &Jumps(AtomicUsize::new(0)));
+            match res {
+                Err(error::Error::RunLimit(e @ error::RunLimit::Timeout(_))) => *e.context().unwrap(),
+                res => panic!("unexpected result: {:?}", res),
+            }
+        }).collect::<Vec<_>>();
+        assert_eq!(timeouts[0], timeouts[1]);
+        assert!(timeouts[0].iterations < 20);
+        // the clock is read 5 times per iteration, the deadline is reached
+        // at the end of the second one. The `tracing` spans read it too
+        #[cfg(not(feature = "tracing"))]
+        {
+            assert_eq!((timeouts[0].iterations, timeouts[0].facts), (2, 23));
+            assert_eq!(timeouts[0].elapsed, Duration::from_secs(11));
+        }
+
+        // a clock that does not move never reaches the deadline
+        struct Frozen;
+        impl time::Clock for Frozen {
+            fn now(&self) -> Duration {
+                Duration::ZERO
+            }
+        }
+        let mut run = w.clone();
+        run.run_with_limits_and_clock(RunLimits { max_time: Some(Duration::from_nanos(1)), ..limits() }, &Frozen).unwrap();
+        assert!(run.facts.contains(&fact(reach, &[&int(20)])));
+    }
+
+    #[test]
+    fn runaway_rule() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let user = syms.insert("user");
+        let group = syms.insert("group");
+        let member = syms.insert("member");
+        let admin = syms.insert("admin");
+        let root = syms.add("root");
+
+        for i in 0..50 {
+            w.add_fact(fact(user, &[&int(i)]));
+            w.add_fact(fact(group, &[&int(i), &int(100 + i)]));
+        }
+        w.add_fact(fact(admin, &[&int(0)]));
+
+        w.add_rule(rule(member, &[var(&mut syms, "u"), var(&mut syms, "g")], &[
+            pred(user, &[var(&mut syms, "u")]),
+            pred(group, &[var(&mut syms, "u"), var(&mut syms, "g")]),
+        ]));
+        // typo: `x` instead of `u`, joins every user with every group
+        w.add_rule(rule(member, &[var(&mut syms, "u"), var(&mut syms, "g")], &[
+            pred(user, &[var(&mut syms, "u")]),
+            pred(group, &[var(&mut syms, "x"), var(&mut syms, "g")]),
+        ]));
+        w.add_rule(rule(admin, &[&root], &[pred(admin, &[int(0)])]));
+
+        let limits = RunLimits { max_time: Some(Duration::from_secs(10)), ..Default::default() };
+        let e = match w.run_with_limits(limits) {
+            Err(error::Error::RunLimit(e)) => e,
+            res => panic!("unexpected result: {:?}", res),
+        };
+        assert!(matches!(e, error::RunLimit::TooManyFacts(_)));
+        assert_eq!(e.context().unwrap().rule, Some(1));
+        assert!(syms.print_run_limit(&e, &w.rules).ends_with(
+            ": member($u, $g) <- user($u), group($x, $g)"
+        ));
+    }
+
+    #[test]
+    fn iteration_limit() {
+        let mut syms = SymbolTable::new();
+        let parent = syms.insert("parent");
+        let grandparent = syms.insert("grandparent");
+
+        let mut w = World::new();
+        w.add_fact(fact(parent, &[&int(0), &int(1)]));
+        w.add_fact(fact(parent, &[&int(1), &int(2)]));
+        w.add_rule(rule(grandparent, &[var(&mut syms, "a"), var(&mut syms, "c")], &[
+            pred(parent, &[var(&mut syms, "a"), var(&mut syms, "b")]),
+            pred(parent, &[var(&mut syms, "b"), var(&mut syms, "c")]),
+        ]));
+        let limits = |max| RunLimits { max_iterations: Some(max), max_time: Some(Duration::from_secs(10)), ..Default::default() };
+        let iterations = |res: Result<(), error::Error>| match res {
+            Err(error::Error::RunLimit(error::RunLimit::TooManyIterations(c))) => c.iterations,
+            res => panic!("unexpected result: {:?}", res),
+        };
+
+        // no rule applied
+        let mut zero = w.clone();
+        assert_eq!(iterations(zero.run_with_limits(limits(0))), 0);
+        assert_eq!(zero.facts, w.facts);
+        World::new().run_with_limits(limits(0)).unwrap();
+
+        // the grandparent fact is generated, but the fixpoint is not verified
+        let mut one = w.clone();
+        assert_eq!(iterations(one.run_with_limits(limits(1))), 1);
+        assert_eq!(one.facts.len(), 3);
+
+        // fixpoint verified by the last allowed pass
+        let mut two = w.clone();
+        let stats = two.run_with_stats(limits(2)).unwrap();
+        assert_eq!(stats.iterations.len(), 2);
+        assert_eq!(two.facts.len(), 3);
+
+        // already at fixpoint, one pass verifies it
+        two.run_with_limits(limits(1)).unwrap();
+    }
+
+    #[test]
+    fn join_order() {
+        let mut syms = SymbolTable::new();
+        let generic = syms.insert("generic");
+        let selective = syms.insert("selective");
+        let joined = syms.insert("joined");
+
+        let mut facts = FactSet::new();
+        for i in 0..10_000 {
+            facts.insert(fact(generic, &[&int(i), &int(i % 1000)]));
+        }
+        facts.insert(fact(selective, &[&int(5)]));
+        facts.insert(fact(selective, &[&int(7)]));
+
+        // the generic predicate is written first
+        let r = rule(joined, &[var(&mut syms, "a"), var(&mut syms, "b")], &[
+            pred(generic, &[var(&mut syms, "a"), var(&mut syms, "b")]),
+            pred(selective, &[var(&mut syms, "b")]),
+        ]);
+        assert_eq!(r.join_order(&facts), vec![1, 0]);
+
+        let limits = RunLimits::unlimited();
+        let apply = |plan| {
+            let budget = Budget::new(usize::MAX, &limits, Instant::now(time::default_clock()));
+            let mut derived = Vec::new();
+            r.apply_budgeted(&facts, &mut derived, Some(&budget), plan, None, None);
+            let facts = derived.into_iter().map(|(fact, _)| fact).collect::<HashSet<_>>();
+            (facts, budget.candidates())
+        };
+
+        let (planned, planned_candidates) = apply(true);
+        let (unplanned, unplanned_candidates) = apply(false);
+        assert_eq!(planned, unplanned);
+        assert_eq!(planned.len(), 20);
+        assert!(
+            planned_candidates * 100 < unplanned_candidates,
+            "examined {} candidates instead of {}", planned_candidates, unplanned_candidates
+        );
+    }
+
+    #[test]
+    fn join_candidates() {
+        let mut syms = SymbolTable::new();
+        let left = syms.insert("left");
+        let right = syms.insert("right");
+        let pair = syms.insert("pair");
+
+        let mut facts = FactSet::new();
+        for i in 0..100 {
+            facts.insert(fact(left, &[&int(i)]));
+            facts.insert(fact(right, &[&int(i)]));
+        }
+
+        let y = var(&mut syms, "y");
+        let r = constrained_rule(
+            pair,
+            &[var(&mut syms, "x"), y.clone()],
+            &[pred(left, &[var(&mut syms, "x")]), pred(right, &[&y])],
+            &[Constraint {
+                id: syms.get("y").unwrap() as u32,
+                kind: ConstraintKind::Int(IntConstraint::LessThan(5)),
+            }],
+        );
+
+        factset::CANDIDATES.with(|c| c.set(0));
+        let mut res = Vec::new();
+        r.apply(&facts, &mut res);
+        let res = res.into_iter().collect::<HashSet<_>>();
+        let candidates = factset::CANDIDATES.with(|c| c.get());
+
+        let expected = (0..100)
+            .flat_map(|x| (0..5).map(move |y| fact(pair, &[&int(x), &int(y)])))
+            .collect::<HashSet<_>>();
+        assert_eq!(res, expected);
+        assert_eq!(candidates, 100 + 100 * 100);
+    }
+
+    #[test]
+    fn constraints_by_variable() {
+        let mut syms = SymbolTable::new();
+        let row = syms.insert("row");
+        let selected = syms.insert("selected");
+
+        let mut facts = FactSet::new();
+        for i in 0..1000i64 {
+            facts.insert(fact(row, &[&int(i), &int(i % 7), &int(i % 11), &int(i % 13), &int(i / 10)]));
         }
 
-        let res2 = res.iter().cloned().collect::<HashSet<_>>();
-        let compared = (vec![fact(suff, &[&app_2, &string("test.fr")])])
-            .drain(..)
+        let names = ["a", "b", "c", "d", "e"];
+        let vars = names.iter().map(|name| var(&mut syms, name)).collect::<Vec<_>>();
+        let ids = names.iter().map(|name| syms.get(name).unwrap() as u32).collect::<Vec<_>>();
+        // 4 constraints per variable
+        let constraints = ids.iter().flat_map(|id| {
+            [
+                IntConstraint::GreaterOrEqual(0),
+                IntConstraint::LessThan(2000),
+                IntConstraint::NotIn(BTreeSet::from([3])),
+                IntConstraint::NotIn(BTreeSet::from([5])),
+            ].iter().map(move |kind| Constraint { id: *id, kind: ConstraintKind::Int(kind.clone()) }).collect::<Vec<_>>()
+        }).collect::<Vec<_>>();
+        assert_eq!(constraints.len(), 20);
+        let r = constrained_rule(selected, &vars, &[pred(row, &vars)], &constraints);
+
+        CONSTRAINT_CHECKS.with(|c| c.set(0));
+        let mut res = Vec::new();
+        r.apply(&facts, &mut res);
+        let checks = CONSTRAINT_CHECKS.with(|c| c.get());
+
+        let expected = facts.iter()
+            .filter(|f| f.predicate.ids.iter().all(|id| *id != int(3) && *id != int(5)))
+            .map(|f| Fact { predicate: Predicate { name: selected, ids: f.predicate.ids.clone() } })
             .collect::<HashSet<_>>();
-        assert_eq!(res2, compared);
+        assert!(!expected.is_empty());
+        assert_eq!(res.into_iter().collect::<HashSet<_>>(), expected);
+        // for each fact, at most the 4 constraints of each of the 5
+        // variables, instead of the 20 constraints for each variable
+        assert!(checks <= 1000 * 5 * 4, "{} constraint checks", checks);
+    }
 
-        let res = test_suffix(&w, &mut syms, suff, route, "example.com");
-        for fact in &res {
-            println!("\t{}", syms.print_fact(fact));
+    #[test]
+    fn constraints_checked_once() {
+        let mut syms = SymbolTable::new();
+        let left = syms.insert("left");
+        let right = syms.insert("right");
+        let pair = syms.insert("pair");
+        let x = var(&mut syms, "x");
+        let y = var(&mut syms, "y");
+        let y_id = syms.get("y").unwrap() as u32;
+
+        let mut facts = FactSet::new();
+        for i in 0..10i64 {
+            facts.insert(fact(left, &[int(i)]));
+            facts.insert(fact(right, &[int(i), int(i * 10), int(i * 10)]));
         }
 
-        let res2 = res.iter().cloned().collect::<HashSet<_>>();
-        let compared = (vec![
-            fact(suff, &[&app_0, &string("example.com")]),
-            fact(suff, &[&app_0, &string("www.example.com")]),
-            fact(suff, &[&app_1, &string("mx.example.com")]),
-        ])
-        .drain(..)
-        .collect::<HashSet<_>>();
-        assert_eq!(res2, compared);
+        // `$y` is first bound by the second predicate, and appears twice in it
+        let y_small = Constraint { id: y_id, kind: ConstraintKind::Int(IntConstraint::LessThan(50)) };
+        let r = constrained_rule(pair, &[&x, &y], &[pred(left, &[&x]), pred(right, &[&x, &y, &y])], &[&y_small]);
+        assert_eq!(r.validate(), Ok(()));
+
+        CONSTRAINT_CHECKS.with(|c| c.set(0));
+        let mut res = Vec::new();
+        r.apply(&facts, &mut res);
+        let checks = CONSTRAINT_CHECKS.with(|c| c.get());
+        let expected = (0..5i64).map(|i| fact(pair, &[int(i), int(i * 10)])).collect::<HashSet<_>>();
+        assert_eq!(res.into_iter().collect::<HashSet<_>>(), expected);
+        // once per `right` fact, not once per occurrence of `$y`
+        assert_eq!(checks, 10);
+
+        // a variable fixed before the join is checked once, up front
+        let mut res = Vec::new();
+        CONSTRAINT_CHECKS.with(|c| c.set(0));
+        r.apply_with_initial_bindings(&facts, &HashMap::from([(y_id, int(60))]), &mut res);
+        assert_eq!(CONSTRAINT_CHECKS.with(|c| c.get()), 1);
+        assert!(res.is_empty());
+        r.apply_with_initial_bindings(&facts, &HashMap::from([(y_id, int(20))]), &mut res);
+        assert_eq!(res, vec![fact(pair, &[int(2), int(20)])]);
+
+        // a constraint on a variable that is not in the body is rejected by
+        // `validate`, and never holds when the rule is applied anyway
+        let unknown = syms.insert("unknown") as u32;
+        let always = Constraint { id: unknown, kind: ConstraintKind::Int(IntConstraint::NotIn(BTreeSet::new())) };
+        let r = constrained_rule(pair, &[&x, &y], &[pred(left, &[&x]), pred(right, &[&x, &y, &y])], &[&always]);
+        assert_eq!(r.validate(), Err(crate::error::Rule::UnboundConstraintVariable(unknown)));
+        let mut res = Vec::new();
+        r.apply(&facts, &mut res);
+        assert!(res.is_empty());
     }
 
     #[test]
-    fn date_constraint() {
-        let mut w = World::new();
+    fn early_expressions() {
         let mut syms = SymbolTable::new();
+        let left = syms.insert("left");
+        let right = syms.insert("right");
+        let pair = syms.insert("pair");
 
-        let t1 = SystemTime::now();
-        println!("t1 = {:?}", t1);
-        let t2 = t1 + Duration::from_secs(10);
-        println!("t2 = {:?}", t2);
-        let t3 = t2 + Duration::from_secs(30);
-        println!("t3 = {:?}", t3);
+        let mut facts = FactSet::new();
+        for i in 0..1000 {
+            facts.insert(fact(left, &[&int(i)]));
+            facts.insert(fact(right, &[&int(i)]));
+        }
 
-        let t2_timestamp = t2.duration_since(UNIX_EPOCH).unwrap().as_secs();
+        // rejects 99% of the left facts
+        let r = expressed_rule(
+            pair,
+            &[var(&mut syms, "a"), var(&mut syms, "b")],
+            &[pred(left, &[var(&mut syms, "a")]), pred(right, &[var(&mut syms, "b")])],
+            &[Expression { ops: vec![
+                Op::Value(var(&mut syms, "a")),
+                Op::Value(int(10)),
+                Op::Binary(Binary::LessThan),
+            ] }],
+        );
 
-        let abc = syms.add("abc");
-        let def = syms.add("def");
-        let x = syms.insert("x");
-        let before = syms.insert("before");
-        let after = syms.insert("after");
+        let limits = RunLimits::unlimited();
+        let budget = Budget::new(usize::MAX, &limits, Instant::now(time::default_clock()));
+        let mut derived = Vec::new();
+        r.apply_budgeted(&facts, &mut derived, Some(&budget), false, None, None);
+        let res = derived.into_iter().map(|(fact, _)| fact).collect::<HashSet<_>>();
 
-        w.add_fact(fact(x, &[&date(&t1), &abc]));
-        w.add_fact(fact(x, &[&date(&t3), &def]));
+        let expected = (0..10)
+            .flat_map(|a| (0..1000).map(move |b| fact(pair, &[&int(a), &int(b)])))
+            .collect::<HashSet<_>>();
+        assert_eq!(res, expected);
+        // instead of 1000 + 1000 * 1000
+        assert_eq!(budget.candidates(), 1000 + 10 * 1000);
+    }
 
-        /*
-        let r1 = constrained_rule(
-            before,
-            &[var(&mut syms, "date"), var(&mut syms, "val")],
-            &[pred(x, &[var(&mut syms, "date"), var(&mut syms, "val")])],
-            &[
-                Constraint {
-                    id: syms.insert("date") as u32,
-                    kind: ConstraintKind::Date(DateConstraint::Before(t2_timestamp)),
-                },
-                Constraint {
-                    id: syms.insert("date") as u32,
-                    kind: ConstraintKind::Date(DateConstraint::After(0)),
-                },
-            ],
-        );
-        */
-        let r1 = expressed_rule(
-            before,
-            &[var(&mut syms, "date"), var(&mut syms, "val")],
-            &[pred(x, &[var(&mut syms, "date"), var(&mut syms, "val")])],
-            &[
-                Expression { ops: vec![
-                    Op::Value(var(&mut syms, "date")),
-                    Op::Value(ID::Date(t2_timestamp)),
-                    Op::Binary(Binary::LessOrEqual),
-                ] },
-                Expression { ops: vec![
-                    Op::Value(var(&mut syms, "date")),
-                    Op::Value(ID::Date(0)),
-                    Op::Binary(Binary::GreaterOrEqual),
-                ] },
-            ],
-        );
+    #[test]
+    fn expression_budget() {
+        let mut syms = SymbolTable::new();
+        let left = syms.insert("left");
+        let right = syms.insert("right");
+        let small = syms.insert("small");
+        let pair = syms.insert("pair");
 
+        let mut w = World::new();
+        for i in 0..100 {
+            w.add_fact(fact(left, &[&int(i)]));
+            w.add_fact(fact(right, &[&int(i)]));
+        }
 
-        println!("testing r1: {}", syms.print_rule(&r1));
-        let res = w.query_rule(r1);
-        for fact in &res {
-            println!("\t{}", syms.print_fact(fact));
+        let a = var(&mut syms, "a");
+        let below_10 = || Expression { ops: vec![
+            Op::Value(a.clone()),
+            Op::Value(int(10)),
+            Op::Binary(Binary::LessThan),
+        ] };
+        let direct = expressed_rule(
+            pair,
+            &[var(&mut syms, "a"), var(&mut syms, "b")],
+            &[pred(left, &[var(&mut syms, "a")]), pred(right, &[var(&mut syms, "b")])],
+            &[below_10()],
+        );
+
+        // 100 + 10 * 100 facts examined, one expression per left fact
+        let unlimited = RunLimits::unlimited();
+        let budget = Budget::new(usize::MAX, &unlimited, Instant::now(time::default_clock()));
+        let mut derived = Vec::new();
+        direct.apply_budgeted(&w.facts, &mut derived, Some(&budget), false, None, None);
+        assert_eq!(derived.len(), 1000);
+        assert_eq!((budget.candidates(), budget.expressions()), (1100, 100));
+
+        // the budget runs out in the middle of the join
+        let limits = RunLimits { max_candidates: 600, ..RunLimits::unlimited() };
+        let budget = Budget::new(usize::MAX, &limits, Instant::now(time::default_clock()));
+        let mut derived = Vec::new();
+        direct.apply_budgeted(&w.facts, &mut derived, Some(&budget), false, None, None);
+        assert!(matches!(budget.check(), Err(error::RunLimit::TooManyCandidates(_))));
+        assert!(!derived.is_empty() && derived.len() < 1000);
+        assert!(derived.iter().all(|(f, _)| match &f.predicate.ids[0] {
+            ID::Integer(a) => *a < 10,
+            _ => false,
+        }));
+
+        // stops on the second rule of the second iteration, keeping the
+        // facts of the first one
+        w.add_rule(expressed_rule(small, &[var(&mut syms, "a")], &[pred(left, &[var(&mut syms, "a")])], &[below_10()]));
+        w.add_rule(rule(
+            pair,
+            &[var(&mut syms, "a"), var(&mut syms, "b")],
+            &[pred(small, &[var(&mut syms, "a")]), pred(right, &[var(&mut syms, "b")])],
+        ));
+        let limits = RunLimits { max_candidates: 600, max_time: Some(Duration::from_secs(10)), ..Default::default() };
+        match w.run_with_limits(limits) {
+            Err(error::Error::RunLimit(error::RunLimit::TooManyCandidates(c))) => {
+                assert_eq!((c.iterations, c.rule), (1, Some(1)));
+            },
+            res => panic!("unexpected result: {:?}", res),
         }
+        assert!(!w.is_complete());
+        assert_eq!(w.facts.with_name(small).count(), 10);
+        assert_eq!(w.facts.with_name(pair).count(), 0);
+    }
 
-        let res2 = res.iter().cloned().collect::<HashSet<_>>();
-        let compared = (vec![fact(before, &[&date(&t1), &abc])])
-            .drain(..)
-            .collect::<HashSet<_>>();
-        assert_eq!(res2, compared);
+    #[test]
+    fn combine_it_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<CombineIt<'static>>();
+        assert_send::<Matching<'static, Predicate>>();
+    }
 
-        /*
-        let r2 = constrained_rule(
-            after,
-            &[var(&mut syms, "date"), var(&mut syms, "val")],
-            &[pred(x, &[var(&mut syms, "date"), var(&mut syms, "val")])],
+    #[test]
+    fn initial_bindings() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let a = syms.add("A");
+        let b = syms.add("B");
+        let c = syms.add("C");
+        let d = syms.add("D");
+        let parent = syms.insert("parent");
+        let grandparent = syms.insert("grandparent");
+
+        w.add_fact(fact(parent, &[&a, &b]));
+        w.add_fact(fact(parent, &[&b, &c]));
+        w.add_fact(fact(parent, &[&c, &d]));
+        w.add_fact(fact(parent, &[&a, &d]));
+        let r = rule(
+            grandparent,
+            &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")],
             &[
-                Constraint {
-                    id: syms.insert("date") as u32,
-                    kind: ConstraintKind::Date(DateConstraint::After(t2_timestamp)),
-                },
-                Constraint {
-                    id: syms.insert("date") as u32,
-                    kind: ConstraintKind::Date(DateConstraint::After(0)),
-                },
+                pred(parent, &[var(&mut syms, "grandparent"), var(&mut syms, "parent")]),
+                pred(parent, &[var(&mut syms, "parent"), var(&mut syms, "grandchild")]),
             ],
         );
-        */
-        let r2 = expressed_rule(
-            after,
-            &[var(&mut syms, "date"), var(&mut syms, "val")],
-            &[pred(x, &[var(&mut syms, "date"), var(&mut syms, "val")])],
-            &[
-                Expression { ops: vec![
-                    Op::Value(var(&mut syms, "date")),
-                    Op::Value(ID::Date(t2_timestamp)),
-                    Op::Binary(Binary::GreaterOrEqual),
-                ] },
-                Expression { ops: vec![
-                    Op::Value(var(&mut syms, "date")),
-                    Op::Value(ID::Date(0)),
-                    Op::Binary(Binary::GreaterOrEqual),
-                ] },
-            ],
+        assert_eq!(w.query_rule(r.clone()).len(), 2);
+
+        let grandchild = syms.get("grandchild").unwrap() as u32;
+        let res = w.query_rule_with_bindings(&r, &HashMap::from([(grandchild, d.clone())]));
+        assert_eq!(res, vec![fact(grandparent, &[&b, &d])]);
+        let res = w.query_rule_with_bindings(&r, &HashMap::from([(grandchild, a.clone())]));
+        assert!(res.is_empty());
+
+        let variables = MatchedVariables::with_bindings(
+            HashSet::from([grandchild, 1000]),
+            &HashMap::from([(grandchild, d.clone()), (2000, a.clone())]),
         );
+        assert_eq!(variables.get(grandchild), Some(&d));
+        assert_eq!(variables.get(2000), None);
+        assert_eq!(variables.unbound().collect::<Vec<_>>(), vec![1000]);
+        assert_eq!(variables.iter().filter(|(_, value)| value.is_some()).count(), 1);
+    }
 
-        println!("testing r2: {}", syms.print_rule(&r2));
-        let res = w.query_rule(r2);
-        for fact in &res {
-            println!("\t{}", syms.print_fact(fact));
+    #[test]
+    fn partial_run() {
+        let mut syms = SymbolTable::new();
+        let next = syms.insert("next");
+        let reach = syms.insert("reach");
+        let done = syms.insert("done");
+
+        let mut w = World::new();
+        w.add_fact(fact(reach, &[&int(0)]));
+        for i in 0..20 {
+            w.add_fact(fact(next, &[&int(i), &int(i + 1)]));
         }
+        w.add_rule(rule(reach, &[var(&mut syms, "b")], &[
+            pred(reach, &[var(&mut syms, "a")]),
+            pred(next, &[var(&mut syms, "a"), var(&mut syms, "b")]),
+        ]));
+        assert!(w.is_complete());
 
-        let res2 = res.iter().cloned().collect::<HashSet<_>>();
-        let compared = (vec![fact(after, &[&date(&t3), &def])])
-            .drain(..)
-            .collect::<HashSet<_>>();
-        assert_eq!(res2, compared);
+        let limits = RunLimits { max_time: Some(Duration::from_secs(10)), ..Default::default() };
+        match w.run_partial(RunLimits { max_iterations: Some(5), ..limits.clone() }).unwrap() {
+            RunOutcome::Partial { stats, limit } => {
+                assert!(matches!(limit, error::RunLimit::TooManyIterations(_)));
+                assert_eq!(stats.iterations.len(), 5);
+            },
+            outcome => panic!("unexpected outcome: {:?}", outcome),
+        }
+        assert!(!w.is_complete());
+        assert!(w.facts.contains(&fact(reach, &[&int(5)])));
+
+        // reach(5) is there, but the world is partial
+        let caveat = Caveat { queries: vec![rule(done, &[int(5)], &[pred(reach, &[int(5)])])] };
+        assert_eq!(w.check_caveat(&caveat, Some(&limits)), Err(error::Caveat::Incomplete));
+
+        assert!(matches!(w.run_partial(limits.clone()).unwrap(), RunOutcome::Complete(_)));
+        assert!(w.is_complete());
+        assert_eq!(w.check_caveat(&caveat, Some(&limits)), Ok(()));
     }
 
     #[test]
-    fn set_constraint() {
+    fn fact_limit() {
         let mut w = World::new();
         let mut syms = SymbolTable::new();
 
-        let abc = syms.add("abc");
-        let def = syms.add("def");
-        let x = syms.insert("x");
-        let int_set = syms.insert("int_set");
-        let symbol_set = syms.insert("symbol_set");
-        let string_set = syms.insert("string_set");
-
-        w.add_fact(fact(x, &[&abc, &int(0), &string("test")]));
-        w.add_fact(fact(x, &[&def, &int(2), &string("hello")]));
+        let left = syms.insert("left");
+        let right = syms.insert("right");
+        let pair = syms.insert("pair");
 
-        /*
-        let res = w.query_rule(constrained_rule(
-            int_set,
-            &[var(&mut syms, "sym"), var(&mut syms, "str")],
-            &[pred(x, &[var(&mut syms, "sym"), var(&mut syms, "int"), var(&mut syms, "str")])],
-            &[Constraint {
-                id: syms.insert("int") as u32,
-                kind: ConstraintKind::Int(IntConstraint::In([0, 1].iter().cloned().collect())),
-            }],
+        for i in 0..100 {
+            w.add_fact(fact(left, &[&int(i)]));
+            w.add_fact(fact(right, &[&int(i)]));
+        }
+        w.add_rule(rule(
+            pair,
+            &[var(&mut syms, "a"), var(&mut syms, "b")],
+            &[
+                pred(left, &[var(&mut syms, "a")]),
+                pred(right, &[var(&mut syms, "b")]),
+            ],
         ));
-        */
 
-        let res = w.query_rule(expressed_rule(
-            int_set,
-            &[var(&mut syms, "sym"), var(&mut syms, "str")],
-            &[pred(x, &[var(&mut syms, "sym"), var(&mut syms, "int"), var(&mut syms, "str")])],
+        // the rule produces 10k facts in one iteration
+        let limits = RunLimits { max_facts: Some(300), max_time: Some(Duration::from_secs(10)), ..Default::default() };
+        let res = w.clone().run_with_limits(limits.clone());
+        assert!(matches!(res, Err(error::Error::RunLimit(error::RunLimit::TooManyFacts(_)))));
+
+        let err = w.run_with_stats(limits).unwrap_err();
+        assert_eq!(err.stats.rules[0].new, 100);
+        assert_eq!(w.facts.len(), 300);
+
+        let mut w = World::new();
+        w.add_fact(fact(left, &[&int(0)]));
+        w.add_fact(fact(right, &[&int(0)]));
+        w.add_rule(rule(pair, &[var(&mut syms, "a"), var(&mut syms, "b")], &[
+            pred(left, &[var(&mut syms, "a")]),
+            pred(right, &[var(&mut syms, "b")]),
+        ]));
+        // reaching the limit is not an error
+        w.run_with_limits(RunLimits { max_facts: Some(3), max_time: Some(Duration::from_secs(10)), ..Default::default() }).unwrap();
+        assert_eq!(w.facts.len(), 3);
+    }
+
+    #[test]
+    fn snapshot() {
+        let mut base = World::new();
+        let mut syms = SymbolTable::new();
+
+        let user = syms.insert("user");
+        let right = syms.insert("right");
+        let resource = syms.insert("resource");
+        let allowed = syms.insert("allowed");
+        let read = syms.add("read");
+
+        for i in 0..5000 {
+            base.add_fact(fact(user, &[&int(i)]));
+            base.add_fact(fact(right, &[&string(&format!("file{}", i)), &read]));
+        }
+        base.add_rule(rule(
+            allowed,
+            &[var(&mut syms, "file"), var(&mut syms, "op")],
             &[
-                Expression { ops: vec![
-                    Op::Value(var(&mut syms, "int")),
-                    Op::Value(ID::Set([ID::Integer(0), ID::Integer(1)].iter().cloned().collect())),
-                    Op::Binary(Binary::In),
-                ] },
+                pred(resource, &[var(&mut syms, "file")]),
+                pred(right, &[var(&mut syms, "file"), var(&mut syms, "op")]),
             ],
         ));
+        let copy = base.clone();
 
-        for fact in &res {
-            println!("\t{}", syms.print_fact(fact));
-        }
+        let limits = || RunLimits { max_facts: Some(20_000), max_time: Some(Duration::from_secs(10)), ..Default::default() };
 
-        let res2 = res.iter().cloned().collect::<HashSet<_>>();
-        let compared = (vec![fact(int_set, &[&abc, &string("test")])])
-            .drain(..)
-            .collect::<HashSet<_>>();
-        assert_eq!(res2, compared);
+        let mut s1 = base.snapshot();
+        s1.add_fact(fact(resource, &[&string("file1")]));
+        s1.run_with_limits(limits()).unwrap();
 
-        let abc_sym_id = syms.insert("abc");
-        let ghi_sym_id = syms.insert("ghi");
+        let mut s2 = base.snapshot();
+        s2.add_fact(fact(resource, &[&string("file2")]));
+        s2.add_fact(fact(resource, &[&string("unknown")]));
+        s2.run_with_limits(limits()).unwrap();
 
-        /*
-        let res = w.query_rule(constrained_rule(
-            symbol_set,
-            &[var(&mut syms, "symbol"), var(&mut syms, "int"), var(&mut syms, "str")],
-            &[pred(x, &[var(&mut syms, "symbol"), var(&mut syms, "int"), var(&mut syms, "str")])],
-            &[Constraint {
-                id: syms.insert("symbol") as u32,
-                kind: ConstraintKind::Symbol(SymbolConstraint::NotIn(
-                    [abc_sym_id, ghi_sym_id].iter().cloned().collect(),
-                )),
-            }],
-        ));
-        */
+        assert_eq!(base, copy);
+        assert_eq!(base.facts.len(), 10_000);
+        assert!(base.facts_for(allowed).next().is_none());
 
-        let res = w.query_rule(expressed_rule(
-            symbol_set,
-            &[var(&mut syms, "symbol"), var(&mut syms, "int"), var(&mut syms, "str")],
-            &[pred(x, &[var(&mut syms, "symbol"), var(&mut syms, "int"), var(&mut syms, "str")])],
+        assert_eq!(s1.facts_for(allowed).cloned().collect::<Vec<_>>(), vec![fact(allowed, &[&string("file1"), &read])]);
+        assert_eq!(s2.facts_for(allowed).cloned().collect::<Vec<_>>(), vec![fact(allowed, &[&string("file2"), &read])]);
+        assert!(s1.contains(&fact(user, &[&int(42)])));
+
+        // the base facts were not copied
+        assert_eq!(s1.facts.shared_buckets(&base.facts), 2);
+        assert_eq!(s2.facts.shared_buckets(&base.facts), 2);
+    }
+
+    #[test]
+    fn introspection() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let a = syms.add("A");
+        let b = syms.add("B");
+        let c = syms.add("C");
+        let d = syms.add("D");
+        let parent = syms.insert("parent");
+        let grandparent = syms.insert("grandparent");
+
+        w.add_fact(fact(parent, &[&a, &b]));
+        w.add_fact(fact(parent, &[&b, &c]));
+        w.add_fact(fact(parent, &[&c, &d]));
+        w.add_fact(fact(parent, &[&d]));
+        w.add_rule(rule(
+            grandparent,
+            &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")],
             &[
-                Expression { ops: vec![
-                    Op::Value(var(&mut syms, "symbol")),
-                    Op::Value(ID::Set([ID::Symbol(abc_sym_id), ID::Symbol(ghi_sym_id)].iter().cloned().collect())),
-                    Op::Binary(Binary::NotIn),
-                ] },
+                pred(parent, &[var(&mut syms, "grandparent"), var(&mut syms, "parent")]),
+                pred(parent, &[var(&mut syms, "parent"), var(&mut syms, "grandchild")]),
             ],
         ));
 
-        for fact in &res {
-            println!("\t{}", syms.print_fact(fact));
-        }
+        assert_eq!(w.facts_for(parent).count(), 4);
+        assert_eq!(w.facts_for(grandparent).count(), 0);
+        assert!(w.contains(&fact(parent, &[&a, &b])));
+        assert!(!w.contains(&fact(grandparent, &[&a, &c])));
+        assert_eq!(w.fact_count_by_predicate(), [(parent, 4)].iter().cloned().collect());
 
-        let res2 = res.iter().cloned().collect::<HashSet<_>>();
-        let compared = (vec![fact(symbol_set, &[&def, &int(2), &string("hello")])])
-            .drain(..)
-            .collect::<HashSet<_>>();
-        assert_eq!(res2, compared);
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
 
-        /*
-        let res = w.query_rule(constrained_rule(
-            string_set,
-            &[var(&mut syms, "sym"), var(&mut syms, "int"), var(&mut syms, "str")],
-            &[pred(x, &[var(&mut syms, "sym"), var(&mut syms, "int"), var(&mut syms, "str")])],
-            &[Constraint {
-                id: syms.insert("str") as u32,
-                kind: ConstraintKind::Str(StrConstraint::In(
-                    ["test".to_string(), "aaa".to_string()]
-                        .iter()
-                        .cloned()
-                        .collect(),
-                )),
-            }],
-        ));
-        */
+        let mut grandparents = w.facts_for(grandparent).cloned().collect::<Vec<_>>();
+        grandparents.sort_by(|f1, f2| f1.predicate.ids.cmp(&f2.predicate.ids));
+        assert_eq!(grandparents, vec![fact(grandparent, &[&a, &c]), fact(grandparent, &[&b, &d])]);
+        assert!(w.contains(&fact(grandparent, &[&a, &c])));
+        assert_eq!(w.fact_count_by_predicate(), [(parent, 4), (grandparent, 2)].iter().cloned().collect());
+    }
 
-        let res = w.query_rule(expressed_rule(
-            string_set,
-            &[var(&mut syms, "sym"), var(&mut syms, "int"), var(&mut syms, "str")],
-            &[pred(x, &[var(&mut syms, "sym"), var(&mut syms, "int"), var(&mut syms, "str")])],
+    #[test]
+    fn cancellation() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let a = syms.add("A");
+        let b = syms.add("B");
+        let c = syms.add("C");
+        let d = syms.add("D");
+        let parent = syms.insert("parent");
+        let ancestor = syms.insert("ancestor");
+
+        w.add_fact(fact(parent, &[&a, &b]));
+        w.add_fact(fact(parent, &[&b, &c]));
+        w.add_fact(fact(parent, &[&c, &d]));
+        w.add_rule(rule(
+            ancestor,
+            &[var(&mut syms, "a"), var(&mut syms, "b")],
+            &[pred(parent, &[var(&mut syms, "a"), var(&mut syms, "b")])],
+        ));
+        w.add_rule(rule(
+            ancestor,
+            &[var(&mut syms, "a"), var(&mut syms, "c")],
             &[
-                Expression { ops: vec![
-                    Op::Value(var(&mut syms, "str")),
-                    Op::Value(ID::Set([ID::Str("test".to_string()), ID::Str("aaa".to_string())].iter().cloned().collect())),
-                    Op::Binary(Binary::In),
-                ] },
+                pred(parent, &[var(&mut syms, "a"), var(&mut syms, "b")]),
+                pred(ancestor, &[var(&mut syms, "b"), var(&mut syms, "c")]),
             ],
         ));
-        for fact in &res {
-            println!("\t{}", syms.print_fact(fact));
-        }
 
-        let res2 = res.iter().cloned().collect::<HashSet<_>>();
-        let compared = (vec![fact(string_set, &[&abc, &int(0), &string("test")])])
-            .drain(..)
-            .collect::<HashSet<_>>();
-        assert_eq!(res2, compared);
+        let limits = || RunLimits { max_time: Some(Duration::from_secs(10)), ..Default::default() };
+        let mut complete = w.clone();
+        let mut progress = Vec::new();
+        complete.run_with_callback(limits(), |p| {
+            progress.push(p);
+            ControlFlow::Continue(())
+        }).unwrap();
+        assert_eq!(complete.facts.len(), 9);
+        assert_eq!(progress.iter().map(|p| (p.iteration, p.facts)).collect::<Vec<_>>(),
+          vec![(1, 6), (2, 8), (3, 9), (4, 9)]);
+
+        let mut calls = 0;
+        let res = w.run_with_callback(limits(), |_| {
+            calls += 1;
+            ControlFlow::Break(())
+        });
+        assert!(matches!(res, Err(error::Error::RunLimit(error::RunLimit::Cancelled))));
+        assert_eq!(calls, 1);
+
+        // only the direct parents were derived in the first iteration
+        assert_eq!(w.facts.len(), 6);
+        assert!(w.facts.contains(&fact(ancestor, &[&a, &b])));
+        assert!(!w.facts.contains(&fact(ancestor, &[&a, &c])));
     }
 
     #[test]
-    fn resource() {
+    fn retraction() {
         let mut w = World::new();
         let mut syms = SymbolTable::new();
 
@@ -1140,90 +7409,695 @@ mod tests {
         let resource = syms.insert("resource");
         let operation = syms.insert("operation");
         let right = syms.insert("right");
+        let allowed = syms.insert("allowed");
         let file1 = syms.add("file1");
         let file2 = syms.add("file2");
         let read = syms.add("read");
-        let write = syms.add("write");
-        let caveat1 = syms.insert("caveat1");
-        let caveat2 = syms.insert("caveat2");
 
-        w.add_fact(fact(resource, &[&ambient, &file2]));
-        w.add_fact(fact(operation, &[&ambient, &write]));
         w.add_fact(fact(right, &[&authority, &file1, &read]));
-        w.add_fact(fact(right, &[&authority, &file2, &read]));
-        w.add_fact(fact(right, &[&authority, &file1, &write]));
-
-        let res = w.query_rule(rule(
-            caveat1,
-            &[&file1],
-            &[pred(resource, &[&ambient, &file1])],
+        w.add_rule(rule(
+            allowed,
+            &[var(&mut syms, "file"), var(&mut syms, "op")],
+            &[
+                pred(resource, &[&ambient, &var(&mut syms, "file")]),
+                pred(operation, &[&ambient, &var(&mut syms, "op")]),
+                pred(right, &[&authority, &var(&mut syms, "file"), &var(&mut syms, "op")]),
+            ],
         ));
 
-        for fact in &res {
-            println!("\t{}", syms.print_fact(fact));
-        }
+        let ambient_facts = |w: &mut World, file: &ID| {
+            w.add_fact(fact(resource, &[&ambient, file]));
+            w.add_fact(fact(operation, &[&ambient, &read]));
+        };
+
+        ambient_facts(&mut w, &file1);
+        w.run_with_provenance(RunLimits::unlimited()).unwrap();
+        assert!(w.facts.contains(&fact(allowed, &[&file1, &read])));
+
+        // with reset, only the base facts remain
+        let mut reset = w.clone();
+        assert_eq!(reset.retract_matching(&pred(resource, &[&ambient, &ID::Wildcard])), 1);
+        assert!(reset.remove_fact(&fact(operation, &[&ambient, &read])));
+        assert!(!reset.remove_fact(&fact(operation, &[&ambient, &read])));
+        reset.reset_derived();
+        assert_eq!(reset.facts.len(), 1);
+
+        ambient_facts(&mut reset, &file2);
+        reset.run_with_provenance(RunLimits::unlimited()).unwrap();
+        assert!(!reset.facts.contains(&fact(allowed, &[&file1, &read])));
+        assert!(!reset.facts.contains(&fact(allowed, &[&file2, &read])));
+
+        // without reset, the previously derived facts stay
+        assert_eq!(w.retract_matching(&pred(resource, &[&ambient, &var(&mut syms, "file")])), 1);
+        assert_eq!(w.retract_matching(&pred(operation, &[&ambient, &ID::Wildcard])), 1);
+        ambient_facts(&mut w, &file2);
+        w.run_with_provenance(RunLimits::unlimited()).unwrap();
+        assert!(w.facts.contains(&fact(allowed, &[&file1, &read])));
+        assert!(!w.facts.contains(&fact(allowed, &[&file2, &read])));
+    }
 
-        assert!(res.is_empty());
+    #[test]
+    fn shared_rules() {
+        let mut syms = SymbolTable::new();
+        let parent = syms.insert("parent");
+        let grandparent = syms.insert("grandparent");
+        let mut base = World::new();
+        base.add_rule(rule(grandparent, &[var(&mut syms, "x"), var(&mut syms, "z")], &[
+            pred(parent, &[var(&mut syms, "x"), var(&mut syms, "y")]),
+            pred(parent, &[var(&mut syms, "y"), var(&mut syms, "z")]),
+        ]));
+        let ancestor = rule(grandparent, &[var(&mut syms, "x"), var(&mut syms, "y")], &[
+            pred(parent, &[var(&mut syms, "x"), var(&mut syms, "y")]),
+        ]);
+
+        // one request per thread, with its own facts
+        std::thread::scope(|scope| {
+            for i in 0..8 {
+                let (base, ancestor) = (&base, &ancestor);
+                scope.spawn(move || {
+                    let mut w = base.clone();
+                    assert!(Arc::ptr_eq(&w.rules, &base.rules));
+                    for j in 0..10 {
+                        w.add_fact(fact(parent, &[&int(i * 100 + j), &int(i * 100 + j + 1)]));
+                    }
+                    if i % 2 == 1 {
+                        w.add_rule(ancestor.clone());
+                        assert!(!Arc::ptr_eq(&w.rules, &base.rules));
+                    }
+                    w.run_with_limits(RunLimits::unlimited()).unwrap();
+                    let derived = w.facts.iter().filter(|f| f.predicate.name == grandparent).count();
+                    assert_eq!(derived, if i % 2 == 1 { 19 } else { 9 });
+                });
+            }
+        });
+        assert_eq!(base.rules.len(), 1);
+        assert_eq!(Arc::strong_count(&base.rules), 1);
+    }
 
-        let res = w.query_rule(rule(
-            caveat2,
-            &[ID::Variable(0)],
+    #[test]
+    fn merge() {
+        let mut syms = SymbolTable::new();
+        let a = syms.add("A");
+        let b = syms.add("B");
+        let c = syms.add("C");
+        let d = syms.add("D");
+        let parent = syms.insert("parent");
+        let grandparent = syms.insert("grandparent");
+
+        let grandparent_rule = rule(
+            grandparent,
+            &[var(&mut syms, "grandparent"), var(&mut syms, "grandchild")],
             &[
-                pred(resource, &[&ambient, &ID::Variable(0)]),
-                pred(operation, &[&ambient, &read]),
-                pred(right, &[&authority, &ID::Variable(0), &read]),
+                pred(parent, &[var(&mut syms, "grandparent"), var(&mut syms, "parent")]),
+                pred(parent, &[var(&mut syms, "parent"), var(&mut syms, "grandchild")]),
             ],
+        );
+
+        let mut single = World::new();
+        single.add_fact(fact(parent, &[&a, &b]));
+        single.add_fact(fact(parent, &[&b, &c]));
+        single.add_fact(fact(parent, &[&c, &d]));
+        single.add_rule(grandparent_rule.clone());
+
+        let mut w1 = World::new();
+        w1.add_fact(fact(parent, &[&a, &b]));
+        w1.add_fact(fact(parent, &[&b, &c]));
+        w1.add_rule(grandparent_rule.clone());
+
+        let mut w2 = World::new();
+        w2.add_fact(fact(parent, &[&b, &c]));
+        w2.add_fact(fact(parent, &[&c, &d]));
+        w2.add_rule(grandparent_rule.clone());
+
+        w1.merge(w2);
+        assert_eq!(w1.facts.len(), 3);
+        assert_eq!(w1.rules.len(), 1);
+        assert_eq!(w1, single);
+
+        w1.run_with_limits(RunLimits::unlimited()).unwrap();
+        single.run_with_limits(RunLimits::unlimited()).unwrap();
+        assert_eq!(w1.facts, single.facts);
+
+        // a world built with another symbol table, where the symbols are in
+        // a different order
+        let mut syms2 = SymbolTable::new();
+        let e = syms2.add("E");
+        let parent2 = syms2.insert("parent");
+        let d2 = syms2.add("D");
+        let mut w3 = World::new();
+        w3.add_fact(fact(parent2, &[&d2, &e]));
+        w3.add_rule(rule(
+            syms2.insert("ancestor"),
+            &[var(&mut syms2, "x"), var(&mut syms2, "y")],
+            &[pred(parent2, &[var(&mut syms2, "x"), var(&mut syms2, "y")])],
         ));
 
-        for fact in &res {
-            println!("\t{}", syms.print_fact(fact));
-        }
+        let table = syms2.symbols.iter().enumerate()
+            .map(|(i, name)| (i as Symbol, syms.insert(name)))
+            .collect::<HashMap<_, _>>();
+        w1.merge_translated(w3, &table);
+        w1.run_with_limits(RunLimits::unlimited()).unwrap();
+
+        let e = sym(&mut syms, "E");
+        let ancestor = syms.insert("ancestor");
+        assert!(w1.facts.contains(&fact(parent, &[&d, &e])));
+        assert!(w1.facts.contains(&fact(grandparent, &[&c, &e])));
+        assert!(w1.facts.contains(&fact(ancestor, &[&d, &e])));
+        assert_eq!(syms.print_rule(&w1.rules[1]), "ancestor($x, $y) <- parent($x, $y)");
+    }
 
-        assert!(res.is_empty());
+    #[test]
+    fn merge_errors() {
+        let mut syms = SymbolTable::new();
+        let parent = syms.insert("parent");
+        let a = syms.add("A");
+
+        // the same symbol at two indexes cannot be remapped
+        let duplicated = SymbolTable::from(vec!["parent".to_string(), "B".to_string(), "parent".to_string()]);
+        assert_eq!(
+            syms.clone().try_extend(&duplicated),
+            Err(error::Merge::Collision { symbol: "parent".to_string(), first: 0, second: 2 })
+        );
+
+        let mut other_syms = SymbolTable::new();
+        let other_parent = other_syms.insert("parent");
+        let b = other_syms.add("B");
+        let x = var(&mut other_syms, "x");
+
+        let mut full = syms.clone();
+        full.set_max_symbols(Some(3));
+        let e = full.try_extend(&other_syms).unwrap_err();
+        assert_eq!(e, error::Merge::Symbol(error::Symbol::TooManySymbols { max: 3 }));
+        assert_eq!(full, syms);
+        assert_eq!(syms.print_error(&e), "cannot add the symbols: the symbol table is limited to 3 symbols");
+
+        let remapping = syms.clone().try_extend(&other_syms).unwrap();
+        let unknown = fact(other_parent, &[ID::Symbol(10)]);
+        assert_eq!(unknown.try_remap_symbols(&remapping), Err(error::Merge::MissingSymbol(10)));
+        let r = rule(other_parent, &[&x], &[pred(other_parent, &[&x, &b])]);
+        assert!(r.try_remap_symbols(&remapping).is_ok());
+        let unknown_variable = Rule { aggregate: Some((AggregateOp::Count, 11)), ..r.clone() };
+        assert_eq!(unknown_variable.try_remap_symbols(&remapping), Err(error::Merge::MissingSymbol(11)));
+
+        let mut w = World::new();
+        w.declare_predicate(parent, 2);
+        w.add_fact(fact(parent, &[&a, &a]));
+
+        let mut other = World::new();
+        other.add_rule(r.clone());
+        let mut wrong = other.clone();
+        wrong.add_fact(unknown);
+        assert_eq!(w.clone().try_merge_remapped(wrong, &remapping), Err(error::Merge::MissingSymbol(10)));
+
+        // the rule's head has one term
+        let e = w.clone().try_merge_remapped(other.clone(), &remapping).unwrap_err();
+        assert_eq!(e, error::Merge::ArityConflict { predicate: parent, expected: 2, found: 1 });
+        assert_eq!(syms.print_error(&e), "predicate parent has 1 terms in the merged world, 2 expected");
+
+        let mut declared = World::new();
+        declared.declare_predicate(parent, 3);
+        let mut merged = w.clone();
+        assert_eq!(
+            merged.try_merge(declared),
+            Err(error::Merge::ArityConflict { predicate: parent, expected: 2, found: 3 })
+        );
+        assert_eq!(merged, w);
+
+        let mut valid = World::new();
+        valid.add_fact(fact(other_parent, &[&b, &b]));
+        w.try_merge_remapped(valid, &remapping).unwrap();
+        let remapped = ID::Symbol(remapping.get(1).unwrap());
+        assert!(w.facts.contains(&fact(parent, &[&remapped, &remapped])));
     }
 
     #[test]
-    fn int_expr() {
+    fn symbol_remapping() {
+        let mut authority = SymbolTable::new();
+        let right = authority.insert("right");
+        let read = authority.add("read");
+        let file1 = authority.add("file1");
+        let file2 = authority.add("file2");
+
         let mut w = World::new();
+        w.add_fact(fact(right, &[&file1, &read]));
+        w.add_fact(fact(right, &[&file2, &authority.add("write")]));
+
+        // the block's table starts with its own symbols, and shares some
+        // of the authority's
+        let mut block = SymbolTable::new();
+        let allowed = block.insert("allowed");
+        let block_right = block.insert("right");
+        let block_read = block.insert("read");
+        let r = constrained_rule(
+            allowed,
+            &[var(&mut block, "file")],
+            &[pred(block_right, &[var(&mut block, "file"), var(&mut block, "op")])],
+            &[Constraint {
+                id: block.get("op").unwrap() as u32,
+                kind: ConstraintKind::Symbol(SymbolConstraint::In(BTreeSet::from([block_read]))),
+            }],
+        );
+
+        let base = authority.symbols.len() as Symbol;
+        let remapping = authority.extend(&block);
+        assert_eq!(remapping.get(block_right), Some(right));
+        assert_eq!(remapping.get(block_read), authority.get("read"));
+        assert_eq!(remapping.get(allowed), Some(base));
+        assert_eq!(authority.get("allowed"), Some(base));
+        assert_eq!(authority.extend(&block), remapping);
+
+        let remapped = r.remap_symbols(&remapping);
+        assert_eq!(authority.print_rule(&remapped), "allowed($file) <- right($file, $op) @ $op in [#read]");
+        assert_eq!(
+            remapped.constraints[0].kind,
+            ConstraintKind::Symbol(SymbolConstraint::In(BTreeSet::from([authority.get("read").unwrap()]))),
+        );
+        w.add_rule(remapped);
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
+
+        let merged_allowed = authority.get("allowed").unwrap();
+        assert_eq!(w.facts.with_name(merged_allowed).collect::<Vec<_>>(), vec![&fact(merged_allowed, &[&file1])]);
+
+        let block_file1 = sym(&mut block, "file1");
+        assert_eq!(
+            fact(allowed, &[&block_file1]).remap_symbols(&authority.extend(&block)),
+            fact(merged_allowed, &[&file1]),
+        );
+    }
+
+    #[test]
+    fn rule_entries() {
+        use crate::parser::parse_rule;
         let mut syms = SymbolTable::new();
+        let mut w = World::new();
+        w.add_rule(parse_rule("allowed($f) <- right($f, #read)", &mut syms).unwrap());
+
+        let mut block = World::new();
+        block.add_rule_with_origin(parse_rule("valid($f) <- resource($f)", &mut syms).unwrap(), Origin::Block(1));
+        block.add_rule_with_origin(parse_rule("allowed($f) <- right($f, #read)", &mut syms).unwrap(), Origin::Block(1));
+        w.merge(block);
+        let mut block = World::new();
+        block.add_rule_with_origin(parse_rule("valid($f) <- resource($f)", &mut syms).unwrap(), Origin::Block(2));
+        w.merge(block);
+
+        let entries = w.rules().collect::<Vec<_>>();
+        assert_eq!(entries.iter().map(|e| e.index).collect::<Vec<_>>(), [0, 1, 2, 3]);
+        assert_eq!(
+            entries.iter().map(|e| e.origin).collect::<Vec<_>>(),
+            [None, Some(Origin::Block(1)), Some(Origin::Block(1)), Some(Origin::Block(2))]
+        );
+        assert_eq!(entries.iter().map(|e| e.print(&syms)).collect::<Vec<_>>(), [
+            "allowed($f) <- right($f, #read)",
+            "valid($f) <- resource($f)",
+            "allowed($f) <- right($f, #read)",
+            "valid($f) <- resource($f)",
+        ]);
+        assert!(core::ptr::eq(entries[3].rule, &w.rules[3]));
+    }
 
-        let abc = syms.add("abc");
-        let def = syms.add("def");
-        let x = syms.insert("x");
-        let less_than = syms.insert("less_than");
+    #[test]
+    fn origins() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
 
-        w.add_fact(fact(x, &[&int(-2), &abc]));
-        w.add_fact(fact(x, &[&int(0), &def]));
+        let right = syms.insert("right");
+        let resource = syms.insert("resource");
+        let operation = syms.insert("operation");
+        let allowed = syms.insert("allowed");
+        let file1 = syms.add("file1");
+        let file2 = syms.add("file2");
+        let read = syms.add("read");
 
-        let r1 = expressed_rule(
-            less_than,
-            &[var(&mut syms, "nb"), var(&mut syms, "val")],
-            &[pred(x, &[var(&mut syms, "nb"), var(&mut syms, "val")])],
+        w.add_fact_with_origin(fact(right, &[&file1, &read]), Origin::Authority);
+        w.add_fact_with_origin(fact(resource, &[&file2]), Origin::Ambient);
+        w.add_fact_with_origin(fact(operation, &[&read]), Origin::Ambient);
+        // a block cannot give itself rights on other blocks' rules
+        w.add_fact_with_origin(fact(right, &[&file2, &read]), Origin::Block(1));
+
+        let check = rule(
+            allowed,
+            &[var(&mut syms, "file")],
             &[
-                Expression { ops: vec![
-                    Op::Value(ID::Integer(5)),
-                    Op::Value(ID::Integer(-4)),
-                    Op::Binary(Binary::Add),
-                    Op::Unary(Unary::Negate),
-                    Op::Value(var(&mut syms, "nb")),
-                    Op::Binary(Binary::LessThan),
-                ] },
+                pred(resource, &[var(&mut syms, "file")]),
+                pred(operation, &[var(&mut syms, "op")]),
+                pred(right, &[var(&mut syms, "file"), var(&mut syms, "op")]),
             ],
         );
 
-        println!("world:\n{}\n", syms.print_world(&w));
-        println!("\ntesting r1: {}\n", syms.print_rule(&r1));
-        let res = w.query_rule(r1);
-        for fact in &res {
-            println!("\t{}", syms.print_fact(fact));
+        assert!(w.query_rule_with_origin(check.clone(), Origin::Block(2)).is_empty());
+        assert!(w.query_rule_with_origin(check.clone(), Origin::Ambient).is_empty());
+        assert_eq!(w.query_rule_with_origin(check.clone(), Origin::Block(1)), vec![fact(allowed, &[&file2])]);
+        // without origin, every fact is visible
+        assert_eq!(w.query_rule(check.clone()), vec![fact(allowed, &[&file2])]);
+
+        assert!(w.add_rule_with_origin(check.clone(), Origin::Block(2)));
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
+        assert!(!w.facts.contains(&fact(allowed, &[&file2])));
+
+        assert!(w.add_rule_with_origin(check.clone(), Origin::Block(1)));
+        assert!(!w.add_rule_with_origin(check.clone(), Origin::Block(1)));
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
+        assert!(w.facts.contains(&fact(allowed, &[&file2])));
+        assert_eq!(
+            w.fact_origins.get(&fact(allowed, &[&file2])),
+            Some(&[Origin::Block(1)].iter().cloned().collect())
+        );
+
+        // facts generated by block 1 are not visible to block 2
+        let r = rule(
+            syms.insert("seen"),
+            &[var(&mut syms, "file")],
+            &[pred(allowed, &[var(&mut syms, "file")])],
+        );
+        assert!(w.query_rule_with_origin(r.clone(), Origin::Block(2)).is_empty());
+        assert_eq!(w.query_rule_with_origin(r, Origin::Block(1)).len(), 1);
+    }
+
+    #[test]
+    fn reachability() {
+        let mut w = World::new();
+        let mut syms = SymbolTable::new();
+
+        let a = syms.insert("a");
+        let b = syms.insert("b");
+        let c = syms.insert("c");
+        let d = syms.insert("d");
+        let e = syms.insert("e");
+        let missing = syms.insert("missing");
+
+        for i in 0..100 {
+            w.add_fact(fact(a, &[&int(i)]));
         }
 
-        let res2 = res.iter().cloned().collect::<HashSet<_>>();
-        println!("got res: {:?}", res2);
-        let compared = (vec![fact(less_than, &[&int(0), &def])])
-            .drain(..)
-            .collect::<HashSet<_>>();
-        assert_eq!(res2, compared);
+        // a -> b -> c -> d is a chain that can fire, while e needs a
+        // predicate that has no facts and no rule generating it
+        w.add_rule(rule(b, &[var(&mut syms, "x")], &[pred(a, &[var(&mut syms, "x")])]));
+        w.add_rule(rule(c, &[var(&mut syms, "x")], &[pred(b, &[var(&mut syms, "x")])]));
+        w.add_rule(rule(d, &[var(&mut syms, "x")], &[pred(c, &[var(&mut syms, "x")])]));
+        w.add_rule(rule(
+            e,
+            &[var(&mut syms, "x"), var(&mut syms, "y")],
+            &[
+                pred(a, &[var(&mut syms, "x")]),
+                pred(a, &[var(&mut syms, "y")]),
+                pred(missing, &[var(&mut syms, "x")]),
+            ],
+        ));
+
+        assert_eq!(w.dependency_graph(), vec![
+            RuleDependencies { head: b, body: vec![a], negated: vec![] },
+            RuleDependencies { head: c, body: vec![b], negated: vec![] },
+            RuleDependencies { head: d, body: vec![c], negated: vec![] },
+            RuleDependencies { head: e, body: vec![a, a, missing], negated: vec![] },
+        ]);
+
+        let seed = [a].iter().cloned().collect::<HashSet<_>>();
+        let reachable = w.reachable_predicates(&seed);
+        assert_eq!(reachable, [a, b, c, d].iter().cloned().collect::<HashSet<_>>());
+
+        // skipping the unreachable rule yields the same facts as applying
+        // every rule until fixpoint
+        let mut expected = w.facts.clone();
+        loop {
+            let mut new_facts = Vec::new();
+            for rule in w.rules.iter() {
+                rule.apply(&expected, &mut new_facts);
+            }
+            let len = expected.len();
+            expected.extend(new_facts.drain(..));
+            if expected.len() == len {
+                break;
+            }
+        }
+
+        w.run_with_limits(RunLimits { max_time: Some(Duration::from_secs(10)), ..Default::default() }).unwrap();
+        assert_eq!(w.facts, expected);
+        assert_eq!(w.facts.len(), 400);
+    }
+
+    #[test]
+    fn rule_errors() {
+        let mut syms = SymbolTable::new();
+        let n = syms.insert("n");
+        let positive = syms.insert("positive");
+        let opposite = syms.insert("opposite");
+        let pair = syms.insert("pair");
+        let x = var(&mut syms, "x");
+        let y = var(&mut syms, "y");
+        let y_id = syms.insert("y") as u32;
+
+        let mut w = World::new();
+        w.add_fact(fact(n, &[int(1)]));
+        w.add_fact(fact(n, &[string("one")]));
+        w.add_fact(fact(n, &[int(i64::MIN)]));
+        // comparing a string to an integer is a type error
+        w.add_rule(expressed_rule(positive, &[&x], &[pred(n, &[&x])], &[Expression {
+            ops: vec![Op::Value(x.clone()), Op::Value(int(0)), Op::Binary(Binary::GreaterThan)],
+        }]));
+        // -i64::MIN overflows
+        w.add_rule(expressed_rule(opposite, &[&y], &[pred(n, &[&x])], &[Expression {
+            ops: vec![Op::Value(x.clone()), Op::Unary(Unary::Negate), Op::Bind(y_id)],
+        }]));
+        // $y is never bound
+        w.add_rule(rule(pair, &[&x, &y], &[pred(n, &[&x])]));
+
+        let mut skipped = w.clone();
+        skipped.run_with_limits(RunLimits::unlimited()).unwrap();
+        let derived = skipped.facts.iter().filter(|f| f.predicate.name != n).cloned().collect::<HashSet<_>>();
+        assert_eq!(derived, [fact(positive, &[int(1)]), fact(opposite, &[int(-1)])].iter().cloned().collect());
+
+        let fatal = RunLimits { rule_errors: RuleErrors::Fatal, ..RunLimits::unlimited() };
+        let e = w.clone().run_with_limits(fatal.clone()).unwrap_err();
+        assert_eq!(e, error::Error::RuleFailed(error::RuleFailed {
+            rule: 0,
+            error: error::Evaluation::Expression(error::Expression::InvalidType {
+                op: Op::Binary(Binary::GreaterThan),
+                operands: vec!["string", "integer"],
+            }),
+        }));
+        assert_eq!(
+            syms.print_error(&e),
+            "rule 0 failed: an expression failed: operator > cannot be applied to string and integer"
+        );
+
+        let mut overflow = w.clone();
+        Arc::make_mut(&mut overflow.rules).remove(0);
+        overflow.facts.remove(&fact(n, &[string("one")]));
+        let e = overflow.run_with_limits(fatal.clone()).unwrap_err();
+        assert_eq!(e, error::Error::RuleFailed(error::RuleFailed {
+            rule: 0,
+            error: error::Evaluation::Expression(error::Expression::Overflow),
+        }));
+
+        let mut unbound = w.clone();
+        Arc::make_mut(&mut unbound.rules).drain(..2);
+        let e = unbound.run_with_limits(fatal).unwrap_err();
+        assert_eq!(syms.print_error(&e), "rule 0 failed: variable $y of the head is not bound");
+
+        // values that used to panic
+        let c = Constraint { id: 0, kind: ConstraintKind::Int(IntConstraint::LessThan(1)) };
+        assert!(!c.check(0, &ID::Variable(1)));
+        #[cfg(feature = "std")]
+        {
+            let before_epoch = UNIX_EPOCH - Duration::from_secs(1);
+            assert_eq!(try_date(&before_epoch), Err(error::InvalidDate));
+            assert_eq!(date(&before_epoch), ID::Date(0));
+        }
+    }
+
+    #[test]
+    fn diagnostics() {
+        let mut syms = SymbolTable::new();
+        let s = syms.insert("s");
+        let n = syms.insert("n");
+        let valid = syms.insert("valid");
+        let positive = syms.insert("positive");
+        let pair = syms.insert("pair");
+        let total = syms.insert("total");
+        let x = var(&mut syms, "x");
+        let y = var(&mut syms, "y");
+        let x_id = syms.insert("x") as u32;
+        let y_id = syms.insert("y") as u32;
+
+        let mut w = World::new();
+        w.add_fact(fact(s, &[string("a")]));
+        w.add_fact(fact(n, &[int(1)]));
+        w.add_fact(fact(n, &[string("one")]));
+        w.add_rule(constrained_rule(valid, &[&x], &[pred(s, &[&x])], &[Constraint {
+            id: x_id,
+            kind: ConstraintKind::Str(StrConstraint::Regex("[".to_string())),
+        }]));
+        w.add_rule(expressed_rule(positive, &[&x], &[pred(n, &[&x])], &[Expression {
+            ops: vec![Op::Value(x.clone()), Op::Value(int(0)), Op::Binary(Binary::GreaterThan)],
+        }]));
+        w.add_rule(rule(pair, &[&x, &y], &[pred(n, &[&x])]));
+        w.add_rule(Rule { aggregate: Some((AggregateOp::Sum, x_id)), ..rule(total, &[&x], &[pred(n, &[&x])]) });
+
+        let mut diagnostics = Vec::new();
+        w.clone().run_with_diagnostics(RunLimits::unlimited(), &mut diagnostics).unwrap();
+        diagnostics.sort_by_key(|d| d.rule);
+        let reported = diagnostics.iter().map(|d| (d.severity, d.rule, d.error.clone())).collect::<Vec<_>>();
+        assert_eq!(reported, vec![
+            (Severity::Warning, 0, error::Evaluation::InvalidRegex("[".to_string())),
+            (Severity::Error, 1, error::Evaluation::Expression(error::Expression::InvalidType {
+                op: Op::Binary(Binary::GreaterThan),
+                operands: vec!["string", "integer"],
+            })),
+            (Severity::Error, 2, error::Evaluation::UnboundHeadVariable(y_id)),
+            (Severity::Error, 3, error::Evaluation::Aggregate(AggregateOp::Sum)),
+        ]);
+        assert_eq!(diagnostics[1].to_string(), "error: rule 1: an expression failed: operator > cannot be applied to string and integer");
+
+        // the other runs only report them in their stats
+        let stats = w.clone().run_with_stats(RunLimits::unlimited()).unwrap();
+        assert_eq!(stats.diagnostics.len(), 4);
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
+        assert!(w.facts.contains(&fact(positive, &[int(1)])));
+    }
+
+    #[test]
+    fn constraint_type_mismatch() {
+        use crate::parser::{parse_fact, parse_rule};
+        let mut syms = SymbolTable::new();
+        let mut w = World::new();
+        // a timestamp stored as an integer, and a date
+        w.add_fact(parse_fact("expires(#token, 1608542592)", &mut syms).unwrap());
+        w.add_fact(parse_fact("issued(#token, 2020-12-21T09:23:12+00:00)", &mut syms).unwrap());
+        w.add_fact(parse_fact("issued(#other, 12)", &mut syms).unwrap());
+        w.add_rule(parse_rule("expired($k) <- expires($k, $t) @ $t <= 2021-01-01T00:00:00+00:00", &mut syms).unwrap());
+        w.add_rule(parse_rule("recent($k) <- issued($k, $i) @ $i > 10", &mut syms).unwrap());
+
+        let mut diagnostics = Vec::new();
+        w.run_with_diagnostics(RunLimits::unlimited(), &mut diagnostics).unwrap();
+        diagnostics.sort_by_key(|d| d.rule);
+        let [t, i] = ["t", "i"].map(|name| syms.get(name).unwrap() as u32);
+        assert_eq!(diagnostics.iter().map(|d| (d.severity, d.rule, d.error.clone())).collect::<Vec<_>>(), vec![
+            (Severity::Warning, 0, error::Evaluation::ConstraintType {
+                variable: t,
+                expected: TermType::Date,
+                found: TermType::Integer,
+            }),
+            (Severity::Warning, 1, error::Evaluation::ConstraintType {
+                variable: i,
+                expected: TermType::Integer,
+                found: TermType::Date,
+            }),
+        ]);
+        assert_eq!(
+            syms.print_error(&diagnostics[0].error),
+            "the constraint on $t applies to date values, it never matches integer values: did you mean ID::Date?"
+        );
+        assert_eq!(
+            diagnostics[1].to_string(),
+            format!(
+                "warning: rule 1: the constraint on {} applies to integer values, it never matches date values: \
+                 did you mean ID::Integer?",
+                i
+            )
+        );
+        // the integer issue date matches
+        assert_eq!(w.facts_for(syms.get("recent").unwrap()).count(), 1);
+        assert_eq!(w.facts_for(syms.get("expired").unwrap()).count(), 0);
+
+        let c = &w.rules[0].constraints[0];
+        assert_eq!(c.type_mismatch(&ID::Str("2020".to_string())), None);
+        assert_eq!(c.type_mismatch(&ID::Date(0)), None);
+    }
+
+    #[test]
+    fn term_growth() {
+        let mut syms = SymbolTable::new();
+        let mut w = World::new();
+        w.add_fact(crate::parser::parse_fact("s(\"a\")", &mut syms).unwrap());
+        w.add_rule(crate::parser::parse_rule("s($y) <- s($x) | $y = $x + \"ab\"", &mut syms).unwrap());
+        // only the term size stops the growth
+        let limits = RunLimits {
+            max_facts: None,
+            max_iterations: Some(100_000),
+            max_time: Some(Duration::from_secs(60)),
+            max_term_size: Some(100),
+            ..RunLimits::default()
+        };
+
+        let mut diagnostics = Vec::new();
+        w.clone().run_with_diagnostics(limits.clone(), &mut diagnostics).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].error, error::Evaluation::TermTooLarge { size: 101, max: 100 });
+        assert_eq!(diagnostics[0].to_string(), "error: rule 0: computed term of 101 bytes, the maximum is 100");
+
+        w.run_with_limits(limits.clone()).unwrap();
+        assert_eq!(w.facts.len(), 50);
+        assert!(w.facts.iter().all(|f| f.predicate.ids[0].payload_size() <= 100));
+        assert!(w.facts.estimated_size() < 50 * (100 + 2 * core::mem::size_of::<Fact>()));
+
+        let fatal = RunLimits { rule_errors: RuleErrors::Fatal, ..limits };
+        let mut w = World::new();
+        w.add_fact(crate::parser::parse_fact("s(\"a\")", &mut syms).unwrap());
+        w.add_rule(crate::parser::parse_rule("s($y) <- s($x) | $y = $x + \"ab\"", &mut syms).unwrap());
+        assert_eq!(
+            w.run_with_limits(fatal),
+            Err(error::RuleFailed { rule: 0, error: error::Evaluation::TermTooLarge { size: 101, max: 100 } }.into())
+        );
+    }
 
+    #[test]
+    fn schemas() {
+        use crate::parser::{parse_fact, parse_rule};
+        let mut syms = SymbolTable::new();
+        let mut w = World::new();
+        let route = syms.insert("route");
+        let expires = syms.insert("expires");
+        w.declare_schema(route, &[TermType::Integer, TermType::Symbol, TermType::Str]);
+        w.declare_schema(expires, &[TermType::Integer, TermType::Date]);
+        assert_eq!(w.arities.get(&route), Some(&3));
+        assert_eq!(w.schemas().len(), 2);
+
+        assert_eq!(w.try_add_fact(parse_fact("route(1, #app, \"example.com\")", &mut syms).unwrap()), Ok(true));
+        assert_eq!(w.try_add_fact(parse_fact("expires(1, 2020-12-21T09:23:12+00:00)", &mut syms).unwrap()), Ok(true));
+        let e = w.try_add_fact(parse_fact("route(\"1\", #app, \"example.com\")", &mut syms).unwrap()).unwrap_err();
+        let schema = error::Schema::TermType {
+            predicate: route,
+            position: 0,
+            expected: TermType::Integer,
+            found: TermType::Str,
+        };
+        assert_eq!(e, error::WorldLimit::Schema(schema.clone()));
+        assert_eq!(
+            syms.print_error(&error::Error::from(schema)),
+            "term 0 of predicate route is a string, expected integer"
+        );
+        assert_eq!(w.facts.len(), 2);
+        assert_eq!(
+            w.try_add_fact(parse_fact("route(1, #app)", &mut syms).unwrap()),
+            Err(error::WorldLimit::Schema(error::Schema::Arity { predicate: route, expected: 3, found: 2 }))
+        );
+
+        // $t is a date in expires, and joined with an integer column
+        let rule = parse_rule("late($id) <- expires($id, $t), route($t, $app, $domain)", &mut syms).unwrap();
+        let t = syms.get("t").unwrap() as u32;
+        let variable = error::Schema::Variable {
+            variable: t,
+            predicate: route,
+            position: 0,
+            expected: TermType::Integer,
+            found: TermType::Date,
+        };
+        assert_eq!(rule.validate_schemas(w.schemas()), Err(variable.clone()));
+        assert_eq!(w.try_add_rule(rule), Err(error::WorldLimit::Schema(variable.clone())));
+        assert_eq!(
+            syms.print_error(&error::Error::from(variable)),
+            "variable $t is a date, term 0 of predicate route expects integer"
+        );
+        assert!(w.rules.is_empty());
+
+        // the head and constants are checked too, undeclared predicates
+        // are not
+        let rule = parse_rule("route($id, #app, $d) <- expires($id, $d)", &mut syms).unwrap();
+        assert!(matches!(w.try_add_rule(rule), Err(error::WorldLimit::Schema(error::Schema::Variable { .. }))));
+        let rule = parse_rule("late($id) <- expires($id, $t), route($id, \"app\", $domain)", &mut syms).unwrap();
+        assert!(matches!(w.try_add_rule(rule), Err(error::WorldLimit::Schema(error::Schema::TermType { .. }))));
+        let rule = parse_rule("domain($id, $d) <- route($id, $app, $d), other($d, _)", &mut syms).unwrap();
+        assert!(w.try_add_rule(rule).is_ok());
+        w.run_with_limits(RunLimits::unlimited()).unwrap();
     }
 }
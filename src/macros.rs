@@ -0,0 +1,132 @@
+//! declarative macros to write facts and rules
+//!
+//! predicate names are identifiers, terms can be integers, strings,
+//! booleans, byte strings (`b"..."`), symbols (`#name`), variables
+//! (`?name`) and wildcards (`_`). Names, symbols and variables are interned
+//! in the symbol table passed as first argument:
+//!
+//! ```ignore
+//! let f = fact!(syms, route(0, #app_0, "example.com"));
+//! let r = rule!(syms, suffix(?app, ?domain) <- route(?id, ?app, ?domain));
+//! ```
+
+/// creates a `Fact`, see the `macros` module documentation
+#[macro_export]
+macro_rules! fact {
+    ($syms:expr, $name:ident ( $($terms:tt)* )) => {
+        $crate::Fact { predicate: $crate::predicate!($syms, $name($($terms)*)) }
+    };
+}
+
+/// creates a `Predicate`, see the `macros` module documentation
+#[macro_export]
+macro_rules! predicate {
+    ($syms:expr, $name:ident ( $($terms:tt)* )) => {{
+        let name = $syms.insert(stringify!($name));
+        let ids: Vec<$crate::ID> = $crate::__terms!($syms, [] $($terms)*);
+        $crate::Predicate { name, ids: ids.into() }
+    }};
+}
+
+/// creates a `Rule`, see the `macros` module documentation
+#[macro_export]
+macro_rules! rule {
+    ($syms:expr, $head:ident ( $($head_terms:tt)* ) <- $( $name:ident ( $($terms:tt)* ) ),+ ) => {{
+        let head = $crate::predicate!($syms, $head($($head_terms)*));
+        let body = vec![$( $crate::predicate!($syms, $name($($terms)*)) ),+];
+        $crate::rule(head.name, &head.ids, &body)
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __terms {
+    ($syms:expr, [$($ids:expr),*]) => { vec![$($ids),*] };
+    ($syms:expr, [$($ids:expr),*] # $name:ident $(, $($rest:tt)*)?) => {
+        $crate::__terms!($syms, [$($ids,)* $crate::sym(&mut $syms, stringify!($name))] $($($rest)*)?)
+    };
+    ($syms:expr, [$($ids:expr),*] ? $name:ident $(, $($rest:tt)*)?) => {
+        $crate::__terms!($syms, [$($ids,)* $crate::var(&mut $syms, stringify!($name))] $($($rest)*)?)
+    };
+    ($syms:expr, [$($ids:expr),*] _ $(, $($rest:tt)*)?) => {
+        $crate::__terms!($syms, [$($ids,)* $crate::ID::Wildcard] $($($rest)*)?)
+    };
+    ($syms:expr, [$($ids:expr),*] $value:literal $(, $($rest:tt)*)?) => {
+        $crate::__terms!($syms, [$($ids,)* $crate::ID::from($value)] $($($rest)*)?)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn terms() {
+        let mut syms = SymbolTable::new();
+
+        let f = fact!(syms, route(0, -1, #app_0, "example.com", true, b"\x01\x02", ?var, _));
+        assert_eq!(f, fact(syms.get("route").unwrap(), &[
+            int(0),
+            int(-1),
+            ID::Symbol(syms.get("app_0").unwrap()),
+            string("example.com"),
+            ID::Bool(true),
+            ID::Bytes(vec![1, 2]),
+            ID::Variable(syms.get("var").unwrap() as u32),
+            ID::Wildcard,
+        ]));
+
+        let f = fact!(syms, maintenance());
+        assert!(f.predicate.ids.is_empty());
+    }
+
+    #[test]
+    fn family() {
+        let mut syms1 = SymbolTable::new();
+        let mut w1 = World::new();
+
+        let a = syms1.add("A");
+        let b = syms1.add("B");
+        let c = syms1.add("C");
+        let d = syms1.add("D");
+        let parent = syms1.insert("parent");
+        let grandparent = syms1.insert("grandparent");
+
+        w1.add_fact(fact(parent, &[&a, &b]));
+        w1.add_fact(fact(parent, &[&b, &c]));
+        w1.add_fact(fact(parent, &[&c, &d]));
+        let r1 = rule(
+            grandparent,
+            &[var(&mut syms1, "grandparent"), var(&mut syms1, "grandchild")],
+            &[
+                pred(parent, &[var(&mut syms1, "grandparent"), var(&mut syms1, "parent")]),
+                pred(parent, &[var(&mut syms1, "parent"), var(&mut syms1, "grandchild")]),
+            ],
+        );
+        w1.add_rule(r1.clone());
+
+        let mut syms2 = SymbolTable::new();
+        let mut w2 = World::new();
+
+        // intern the symbols in the same order to get the same indexes
+        for name in &["A", "B", "C", "D"] {
+            syms2.add(name);
+        }
+
+        w2.add_fact(fact!(syms2, parent(#A, #B)));
+        w2.add_fact(fact!(syms2, parent(#B, #C)));
+        w2.add_fact(fact!(syms2, parent(#C, #D)));
+        let r2 = rule!(syms2, grandparent(?grandparent, ?grandchild) <-
+            parent(?grandparent, ?parent), parent(?parent, ?grandchild));
+        assert_eq!(syms1.print_rule(&r1), syms2.print_rule(&r2));
+        w2.add_rule(r2);
+
+        w1.run_with_limits(RunLimits::unlimited()).unwrap();
+        w2.run_with_limits(RunLimits::unlimited()).unwrap();
+
+        assert_eq!(syms1, syms2);
+        assert_eq!(w1, w2);
+        assert!(w2.facts.contains(&fact!(syms2, grandparent(#A, #C))));
+        assert!(w2.facts.contains(&fact!(syms2, grandparent(#B, #D))));
+    }
+}
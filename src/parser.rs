@@ -0,0 +1,1101 @@
+//! text format for facts, rules and caveats, as printed by `SymbolTable`
+//!
+//! ```text
+//! right(#authority, "file1", #read)
+//! caveat1($0) <- resource(#ambient, $0), operation(#ambient, #read), right(#authority, $0, #read)
+//! valid($date) <- time(#ambient, $date) @ $date <= 2020-12-21T09:23:12+00:00 | $date >= 2019-01-01T00:00:00+00:00
+//! valid($id) <- revocation_id($id) @ $id not in set #revoked
+//! ```
+//!
+//! `in set #name` and `not in set #name` refer to a set added with
+//! `World::add_revocation_set`: they are only accepted by
+//! `parse_rule_with_sets` and `parse_caveat_with_sets`, which look the name
+//! up in the world's sets
+//!
+//! symbols and variable names are added to the table, errors carry the
+//! byte offset in the input where parsing stopped
+use alloc::collections::BTreeSet;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::vec;
+#[cfg(feature = "std")]
+use core::cell::RefCell;
+#[cfg(feature = "std")]
+use core::str::FromStr;
+use chrono::DateTime;
+
+use super::{AggregateOp, Binary, BytesConstraint, Caveat, Constraint, ConstraintKind, DateConstraint,
+  Expression, Fact, IntConstraint, Op, Predicate, Rule, StrConstraint, Symbol, SymbolConstraint,
+  SymbolTable, Unary, World, ID};
+use crate::error::{Parse, ParseKind};
+
+/// parses a fact like `right(#authority, "file1", #read)`, variables are
+/// not allowed
+pub fn parse_fact(input: &str, symbols: &mut SymbolTable) -> Result<Fact, Parse> {
+    let mut parser = Parser::new(input, symbols);
+    parser.variables = false;
+    let (predicate, _) = parser.predicate(false)?;
+    parser.end()?;
+    Ok(Fact { predicate })
+}
+
+/// parses a predicate like `right(#authority, $0, #read)`, as found in
+/// the body of a rule
+pub fn parse_predicate(input: &str, symbols: &mut SymbolTable) -> Result<Predicate, Parse> {
+    let mut parser = Parser::new(input, symbols);
+    let (predicate, _) = parser.predicate(false)?;
+    parser.end()?;
+    Ok(predicate)
+}
+
+/// parses a rule in the format of `SymbolTable::print_rule`
+pub fn parse_rule(input: &str, symbols: &mut SymbolTable) -> Result<Rule, Parse> {
+    let mut parser = Parser::new(input, symbols);
+    let rule = parser.rule()?;
+    parser.end()?;
+    Ok(rule)
+}
+
+/// parses queries separated by `||`, as printed by `SymbolTable::print_caveat`
+pub fn parse_caveat(input: &str, symbols: &mut SymbolTable) -> Result<Caveat, Parse> {
+    let mut parser = Parser::new(input, symbols);
+    let caveat = parser.caveat()?;
+    parser.end()?;
+    Ok(caveat)
+}
+
+/// like `parse_rule`, with the sets of `in set #name` constraints taken
+/// from the revocation sets of `world`
+pub fn parse_rule_with_sets(input: &str, symbols: &mut SymbolTable, world: &World) -> Result<Rule, Parse> {
+    let mut parser = Parser::new(input, symbols);
+    parser.world = Some(world);
+    let rule = parser.rule()?;
+    parser.end()?;
+    Ok(rule)
+}
+
+/// like `parse_caveat`, with the sets of `in set #name` constraints taken
+/// from the revocation sets of `world`
+pub fn parse_caveat_with_sets(input: &str, symbols: &mut SymbolTable, world: &World) -> Result<Caveat, Parse> {
+    let mut parser = Parser::new(input, symbols);
+    parser.world = Some(world);
+    let caveat = parser.caveat()?;
+    parser.end()?;
+    Ok(caveat)
+}
+
+#[cfg(feature = "std")]
+thread_local! {
+    static SYMBOLS: RefCell<Option<SymbolTable>> = const { RefCell::new(None) };
+}
+
+/// runs `f` with `symbols` as the table used by the `FromStr`
+/// implementations of `Fact` and `Rule`, on this thread
+///
+/// ```ignore
+/// let r: Rule = parser::with_symbols(&mut syms, || "a($x) <- b($x)".parse())?;
+/// ```
+///
+/// only available with the `std` feature, like the `FromStr` implementations
+#[cfg(feature = "std")]
+pub fn with_symbols<R, F: FnOnce() -> R>(symbols: &mut SymbolTable, f: F) -> R {
+    // gives the table back even if `f` panics
+    struct Restore<'a> {
+        symbols: &'a mut SymbolTable,
+        previous: Option<SymbolTable>,
+    }
+
+    impl Drop for Restore<'_> {
+        fn drop(&mut self) {
+            let table = SYMBOLS.with(|s| s.replace(self.previous.take()));
+            *self.symbols = table.unwrap_or_default();
+        }
+    }
+
+    let previous = SYMBOLS.with(|s| s.replace(Some(core::mem::take(symbols))));
+    let _restore = Restore { symbols, previous };
+    f()
+}
+
+#[cfg(feature = "std")]
+fn parse_with_context<T>(input: &str, parse: fn(&str, &mut SymbolTable) -> Result<T, Parse>) -> Result<T, Parse> {
+    SYMBOLS.with(|s| match s.borrow_mut().as_mut() {
+        Some(symbols) => parse(input, symbols),
+        None => Err(Parse { offset: 0, kind: ParseKind::NoSymbolTable }),
+    })
+}
+
+/// uses the table given to `with_symbols`
+#[cfg(feature = "std")]
+impl FromStr for Fact {
+    type Err = Parse;
+
+    fn from_str(s: &str) -> Result<Self, Parse> {
+        parse_with_context(s, parse_fact)
+    }
+}
+
+/// uses the table given to `with_symbols`
+#[cfg(feature = "std")]
+impl FromStr for Rule {
+    type Err = Parse;
+
+    fn from_str(s: &str) -> Result<Self, Parse> {
+        parse_with_context(s, parse_rule)
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    symbols: &'a mut SymbolTable,
+    variables: bool,
+    /// resolves `in set #name`, see `parse_rule_with_sets`
+    world: Option<&'a World>,
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str, symbols: &'a mut SymbolTable) -> Self {
+        Parser { input, pos: 0, symbols, variables: true, world: None }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn error<T>(&self, kind: ParseKind) -> Result<T, Parse> {
+        Err(Parse { offset: self.pos, kind })
+    }
+
+    fn error_at<T>(&self, offset: usize, kind: ParseKind) -> Result<T, Parse> {
+        Err(Parse { offset, kind })
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self, s: &str) -> bool {
+        self.skip_whitespace();
+        self.rest().starts_with(s)
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        let found = self.peek(s);
+        if found {
+            self.pos += s.len();
+        }
+        found
+    }
+
+    fn expect(&mut self, s: &'static str) -> Result<(), Parse> {
+        if self.eat(s) {
+            Ok(())
+        } else {
+            self.error(ParseKind::Expected(s))
+        }
+    }
+
+    /// like `eat`, but the word must not be followed by a name character
+    fn keyword(&mut self, word: &str) -> bool {
+        let found = self.peek(word) && !self.rest()[word.len()..].starts_with(is_name_char);
+        if found {
+            self.pos += word.len();
+        }
+        found
+    }
+
+    fn end(&mut self) -> Result<(), Parse> {
+        self.skip_whitespace();
+        if self.pos == self.input.len() {
+            Ok(())
+        } else {
+            self.error(ParseKind::TrailingInput)
+        }
+    }
+
+    fn name(&mut self) -> Result<&'a str, Parse> {
+        let rest = self.rest();
+        let len = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+        if len == 0 {
+            return self.error(ParseKind::Expected("a name"));
+        }
+        self.pos += len;
+        Ok(&rest[..len])
+    }
+
+    fn symbol(&mut self) -> Result<Symbol, Parse> {
+        let start = self.pos;
+        let name = self.name()?;
+        self.symbols.try_insert(name).or_else(|e| self.error_at(start, ParseKind::Symbol(e)))
+    }
+
+    /// `name`, or `ns::name` as created by `SymbolTable::insert_namespaced`
+    fn predicate_name(&mut self) -> Result<Symbol, Parse> {
+        let start = self.pos;
+        self.name()?;
+        while self.rest().starts_with("::") {
+            self.pos += 2;
+            self.name()?;
+        }
+        let name = &self.input[start..self.pos];
+        self.symbols.try_insert(name).or_else(|e| self.error_at(start, ParseKind::Symbol(e)))
+    }
+
+    /// `$name`
+    fn variable(&mut self) -> Result<u32, Parse> {
+        if !self.variables && self.peek("$") {
+            return self.error(ParseKind::VariableInFact);
+        }
+        self.expect("$")?;
+        Ok(self.symbol()? as u32)
+    }
+
+    fn caveat(&mut self) -> Result<Caveat, Parse> {
+        let mut queries = vec![self.rule()?];
+        while self.eat("||") {
+            queries.push(self.rule()?);
+        }
+        Ok(Caveat { queries })
+    }
+
+    fn rule(&mut self) -> Result<Rule, Parse> {
+        let (head, aggregate) = self.predicate(true)?;
+        self.expect("<-")?;
+
+        let mut body = Vec::new();
+        let mut negated = Vec::new();
+        self.skip_whitespace();
+        if !(self.rest().is_empty() || self.peek("@") || self.peek("|")) {
+            loop {
+                if self.eat("!") {
+                    negated.push(self.predicate(false)?.0);
+                } else {
+                    body.push(self.predicate(false)?.0);
+                }
+                if !self.eat(",") {
+                    break;
+                }
+            }
+        }
+
+        let mut constraints = Vec::new();
+        if self.eat("@") {
+            constraints.push(self.constraint()?);
+            while self.eat(",") {
+                constraints.push(self.constraint()?);
+            }
+        }
+
+        let mut expressions = Vec::new();
+        if self.peek("|") && !self.peek("||") {
+            self.pos += 1;
+            expressions.push(self.expression()?);
+            while self.eat(",") {
+                expressions.push(self.expression()?);
+            }
+        }
+
+        Ok(Rule { head, body, constraints, expressions, negated, aggregate })
+    }
+
+    /// `name(term, ...)`, the head of a rule can aggregate one of its
+    /// variables with `count($var)`, `sum`, `min` or `max`
+    fn predicate(&mut self, head: bool) -> Result<(Predicate, Option<(AggregateOp, u32)>), Parse> {
+        self.skip_whitespace();
+        let name = self.predicate_name()?;
+        self.expect("(")?;
+
+        let mut ids = Vec::new();
+        let mut aggregate = None;
+        if !self.eat(")") {
+            loop {
+                let op = if head && aggregate.is_none() { self.aggregate_op() } else { None };
+                match op {
+                    Some(op) => {
+                        self.expect("(")?;
+                        let v = self.variable()?;
+                        self.expect(")")?;
+                        aggregate = Some((op, v));
+                        ids.push(ID::Variable(v));
+                    },
+                    None => ids.push(self.term()?),
+                }
+                if !self.eat(",") {
+                    break;
+                }
+            }
+            self.expect(")")?;
+        }
+
+        Ok((Predicate::new(name, &ids), aggregate))
+    }
+
+    fn aggregate_op(&mut self) -> Option<AggregateOp> {
+        let ops = [
+            ("count", AggregateOp::Count),
+            ("sum", AggregateOp::Sum),
+            ("min", AggregateOp::Min),
+            ("max", AggregateOp::Max),
+        ];
+        self.skip_whitespace();
+        let start = self.pos;
+        for (word, op) in ops.iter() {
+            if self.keyword(word) {
+                if self.peek("(") {
+                    return Some(op.clone());
+                }
+                self.pos = start;
+            }
+        }
+        None
+    }
+
+    fn term(&mut self) -> Result<ID, Parse> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let next = rest.chars().next();
+        match next {
+            Some('#') => {
+                self.pos += 1;
+                Ok(ID::Symbol(self.symbol()?))
+            },
+            Some('$') => Ok(ID::Variable(self.variable()?)),
+            Some('"') => Ok(ID::Str(self.string()?)),
+            Some('[') => {
+                self.pos += 1;
+                let mut set = BTreeSet::new();
+                if !self.eat("]") {
+                    loop {
+                        set.insert(self.term()?);
+                        if !self.eat(",") {
+                            break;
+                        }
+                    }
+                    self.expect("]")?;
+                }
+                Ok(ID::Set(set))
+            },
+            Some('-') | Some('0'..='9') => self.number(),
+            _ if self.keyword("true") => Ok(ID::Bool(true)),
+            _ if self.keyword("false") => Ok(ID::Bool(false)),
+            _ if self.keyword("_") => Ok(ID::Wildcard),
+            _ if self.eat("hex:") => {
+                let start = self.pos;
+                let rest = self.rest();
+                let len = rest.find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(rest.len());
+                self.pos += len;
+                hex::decode(&rest[..len]).map(ID::Bytes).or_else(|_| self.error_at(start, ParseKind::InvalidBytes))
+            },
+            _ => self.error(ParseKind::Expected("a term")),
+        }
+    }
+
+    /// integer, or RFC 3339 date like `2020-12-21T09:23:12+00:00`
+    fn number(&mut self) -> Result<ID, Parse> {
+        let start = self.pos;
+        let rest = self.rest();
+        let bytes = rest.as_bytes();
+        let is_date = bytes.len() > 4 && bytes[..4].iter().all(u8::is_ascii_digit) && bytes[4] == b'-';
+
+        if is_date {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == ':' || c == '+' || c == '-' || c == '.'))
+                .unwrap_or(rest.len());
+            self.pos += len;
+            match DateTime::parse_from_rfc3339(&rest[..len]) {
+                Ok(date) if date.timestamp() >= 0 => Ok(ID::Date(date.timestamp() as u64)),
+                _ => self.error_at(start, ParseKind::InvalidDate),
+            }
+        } else {
+            let sign = if rest.starts_with('-') { 1 } else { 0 };
+            let len = rest[sign..].find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len() - sign) + sign;
+            self.pos += len;
+            rest[..len].parse().map(ID::Integer).or_else(|_| self.error_at(start, ParseKind::InvalidInteger))
+        }
+    }
+
+    /// quoted string, `\"` and `\\` are escaped
+    fn string(&mut self) -> Result<String, Parse> {
+        let start = self.pos;
+        self.expect("\"")?;
+        let mut s = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(s);
+                },
+                '\\' => match chars.next() {
+                    Some((_, escaped)) => s.push(escaped),
+                    None => break,
+                },
+                c => s.push(c),
+            }
+        }
+        self.error_at(start, ParseKind::UnterminatedString)
+    }
+
+    /// `$var <operator> <value>`, in the format of `SymbolTable::print_constraint`
+    fn constraint(&mut self) -> Result<Constraint, Parse> {
+        let id = self.variable()?;
+        self.skip_whitespace();
+        let start = self.pos;
+
+        let kind = if self.keyword("not") {
+            if !self.keyword("in") {
+                return self.error(ParseKind::Expected("in"));
+            }
+            self.set_constraint(false)?
+        } else if self.keyword("in") {
+            self.set_constraint(true)?
+        } else if self.keyword("matches") {
+            if self.eat("*") {
+                ConstraintKind::Str(StrConstraint::Suffix(self.string()?))
+            } else if self.eat("/") {
+                ConstraintKind::Str(StrConstraint::Regex(self.regex()?))
+            } else {
+                let prefix = self.string()?;
+                self.expect("*")?;
+                ConstraintKind::Str(StrConstraint::Prefix(prefix))
+            }
+        } else {
+            let operators = ["<=", ">=", "==", "<", ">"];
+            let operator = match operators.iter().find(|o| self.eat(o)) {
+                Some(o) => *o,
+                None => return self.error(ParseKind::Expected("a constraint operator")),
+            };
+            match (operator, self.term()?) {
+                ("<", ID::Integer(i)) => ConstraintKind::Int(IntConstraint::LessThan(i)),
+                (">", ID::Integer(i)) => ConstraintKind::Int(IntConstraint::GreaterThan(i)),
+                ("<=", ID::Integer(i)) => ConstraintKind::Int(IntConstraint::LessOrEqual(i)),
+                (">=", ID::Integer(i)) => ConstraintKind::Int(IntConstraint::GreaterOrEqual(i)),
+                ("==", ID::Integer(i)) => ConstraintKind::Int(IntConstraint::Equal(i)),
+                ("==", ID::Str(s)) => ConstraintKind::Str(StrConstraint::Equal(s)),
+                ("==", ID::Bytes(b)) => ConstraintKind::Bytes(BytesConstraint::Equal(b)),
+                ("<=", ID::Date(d)) => ConstraintKind::Date(DateConstraint::Before(d)),
+                (">=", ID::Date(d)) => ConstraintKind::Date(DateConstraint::After(d)),
+                _ => return self.error_at(start, ParseKind::InvalidConstraint),
+            }
+        };
+
+        Ok(Constraint { id, kind })
+    }
+
+    /// the set's values must all have the same type, empty sets are
+    /// integer sets
+    fn set_constraint(&mut self, is_in: bool) -> Result<ConstraintKind, Parse> {
+        if self.keyword("set") {
+            return self.named_set(is_in);
+        }
+        self.skip_whitespace();
+        let start = self.pos;
+        let set = match self.term()? {
+            ID::Set(set) => set,
+            _ => return self.error_at(start, ParseKind::InvalidConstraint),
+        };
+
+        fn collect<T: Ord>(set: &BTreeSet<ID>, f: impl Fn(&ID) -> Option<T>) -> Option<BTreeSet<T>> {
+            set.iter().map(f).collect()
+        }
+        let kind = if let Some(s) = collect(&set, |id| if let ID::Integer(i) = id { Some(*i) } else { None }) {
+            ConstraintKind::Int(if is_in { IntConstraint::In(s) } else { IntConstraint::NotIn(s) })
+        } else if let Some(s) = collect(&set, |id| if let ID::Str(s) = id { Some(s.clone()) } else { None }) {
+            ConstraintKind::Str(if is_in { StrConstraint::In(s) } else { StrConstraint::NotIn(s) })
+        } else if let Some(s) = collect(&set, |id| if let ID::Symbol(s) = id { Some(*s) } else { None }) {
+            ConstraintKind::Symbol(if is_in { SymbolConstraint::In(s) } else { SymbolConstraint::NotIn(s) })
+        } else if let Some(s) = collect(&set, |id| if let ID::Bytes(b) = id { Some(b.clone()) } else { None }) {
+            ConstraintKind::Bytes(if is_in { BytesConstraint::In(s) } else { BytesConstraint::NotIn(s) })
+        } else {
+            return self.error_at(start, ParseKind::InvalidConstraint);
+        };
+        Ok(kind)
+    }
+
+    /// `set #name`, a set of the world given to `parse_rule_with_sets`
+    fn named_set(&mut self, is_in: bool) -> Result<ConstraintKind, Parse> {
+        self.skip_whitespace();
+        let start = self.pos;
+        self.expect("#")?;
+        let name = self.name()?;
+        let world = match self.world {
+            Some(world) => world,
+            None => return self.error_at(start, ParseKind::NamedSet),
+        };
+        let handle = match self.symbols.get(name).and_then(|name| world.revocation_set(name)) {
+            Some(handle) => handle.clone(),
+            None => return self.error_at(start, ParseKind::UnknownSet),
+        };
+        Ok(ConstraintKind::Bytes(if is_in { BytesConstraint::InSet(handle) } else { BytesConstraint::NotInSet(handle) }))
+    }
+
+    /// regular expression up to the next `/` that is not escaped by `\`,
+    /// the escapes are kept
+    fn regex(&mut self) -> Result<String, Parse> {
+        let start = self.pos;
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '/' => {
+                    let regex = self.rest()[..i].to_string();
+                    self.pos += i + 1;
+                    return Ok(regex);
+                },
+                '\\' => {
+                    chars.next();
+                },
+                _ => {},
+            }
+        }
+        self.error_at(start, ParseKind::Expected("/"))
+    }
+
+    /// expression in the format of `Expression::print`, optionally bound
+    /// to a variable with `$var = <expression>`
+    fn expression(&mut self) -> Result<Expression, Parse> {
+        let mut ops = Vec::new();
+        self.skip_whitespace();
+        let start = self.pos;
+
+        if self.peek("$") {
+            let v = self.variable()?;
+            if self.peek("=") && !self.peek("==") {
+                self.pos += 1;
+                self.and_expression(&mut ops)?;
+                ops.push(Op::Bind(v));
+                return Ok(Expression { ops });
+            }
+            self.pos = start;
+        }
+
+        self.and_expression(&mut ops)?;
+        Ok(Expression { ops })
+    }
+
+    fn and_expression(&mut self, ops: &mut Vec<Op>) -> Result<(), Parse> {
+        self.comparison(ops)?;
+        while self.eat("&&") {
+            self.comparison(ops)?;
+            ops.push(Op::Binary(Binary::And));
+        }
+        Ok(())
+    }
+
+    /// comparisons are not chained, `$a < $b < $c` is an error
+    fn comparison(&mut self, ops: &mut Vec<Op>) -> Result<(), Parse> {
+        self.add_expression(ops)?;
+
+        let binary = if self.keyword("not") {
+            if !self.keyword("in") {
+                return self.error(ParseKind::Expected("in"));
+            }
+            Binary::NotIn
+        } else if self.keyword("in") {
+            Binary::In
+        } else if self.keyword("matches") {
+            let binary = if self.eat("*") {
+                self.add_expression(ops)?;
+                Binary::Suffix
+            } else if self.eat("/") {
+                self.add_expression(ops)?;
+                self.expect("/")?;
+                Binary::Regex
+            } else {
+                self.add_expression(ops)?;
+                self.expect("*")?;
+                Binary::Prefix
+            };
+            ops.push(Op::Binary(binary));
+            return Ok(());
+        } else {
+            let operators = [
+                ("<=", Binary::LessOrEqual),
+                (">=", Binary::GreaterOrEqual),
+                ("==", Binary::Equal),
+                ("<", Binary::LessThan),
+                (">", Binary::GreaterThan),
+            ];
+            match operators.iter().find(|(o, _)| self.eat(o)) {
+                Some((_, binary)) => binary.clone(),
+                None => return Ok(()),
+            }
+        };
+
+        self.add_expression(ops)?;
+        ops.push(Op::Binary(binary));
+        Ok(())
+    }
+
+    fn add_expression(&mut self, ops: &mut Vec<Op>) -> Result<(), Parse> {
+        self.unary(ops)?;
+        while self.eat("+") {
+            self.unary(ops)?;
+            ops.push(Op::Binary(Binary::Add));
+        }
+        Ok(())
+    }
+
+    /// `-` followed by a digit is a negative integer, `-(4)` negates 4
+    fn unary(&mut self, ops: &mut Vec<Op>) -> Result<(), Parse> {
+        let negated = self.peek("-") && !self.rest()[1..].starts_with(|c: char| c.is_ascii_digit());
+        if negated {
+            self.pos += 1;
+            self.unary(ops)?;
+            ops.push(Op::Unary(Unary::Negate));
+        } else if self.eat("(") {
+            self.and_expression(ops)?;
+            self.expect(")")?;
+        } else {
+            ops.push(Op::Value(self.term()?));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// parses, prints, and checks the printed text gives the same value
+    fn round_trip(syms: &mut SymbolTable, input: &str) -> Rule {
+        let rule = parse_rule(input, syms).unwrap_or_else(|e| panic!("{}: {}", input, e));
+        let printed = syms.print_rule(&rule);
+        let reparsed = parse_rule(&printed, syms).unwrap_or_else(|e| panic!("{}: {}", printed, e));
+        assert_eq!(reparsed, rule, "{} was printed as {}", input, printed);
+        assert_eq!(syms.print_rule(&reparsed), printed);
+        rule
+    }
+
+    #[test]
+    fn facts() {
+        let mut syms = SymbolTable::with_defaults();
+        let f = parse_fact(r#"right(#authority, "file1", #read)"#, &mut syms).unwrap();
+        let read = syms.get("read").unwrap();
+        let right = syms.get("right").unwrap();
+        assert_eq!(f, Fact::new(right, &[
+            ID::Symbol(SymbolTable::AUTHORITY),
+            ID::Str("file1".to_string()),
+            ID::Symbol(read),
+        ]));
+
+        let input = r#"terms(-12, "a \"quoted\" \\ string", 2020-12-21T09:23:12+00:00, hex:00ff, true, false, [#read, 1, "a"], [], #read)"#;
+        let f = parse_fact(input, &mut syms).unwrap();
+        assert_eq!(f.predicate.ids[0], ID::Integer(-12));
+        assert_eq!(f.predicate.ids[1], ID::Str("a \"quoted\" \\ string".to_string()));
+        assert_eq!(f.predicate.ids[2], ID::Date(1_608_542_592));
+        assert_eq!(f.predicate.ids[3], ID::Bytes(vec![0, 255]));
+        assert_eq!(syms.print_fact(&f), input);
+        assert_eq!(parse_fact(&syms.print_fact(&f), &mut syms), Ok(f));
+
+        let empty = parse_fact(" empty( ) ", &mut syms).unwrap();
+        assert_eq!(syms.print_fact(&empty), "empty()");
+
+        let p = parse_predicate("right(#authority, $0, _)", &mut syms).unwrap();
+        assert_eq!(syms.print_predicate(&p), "right(#authority, $0, _)");
+        assert_eq!(parse_predicate("right($0) <- ", &mut syms).unwrap_err().kind, ParseKind::TrailingInput);
+    }
+
+    #[test]
+    fn rules() {
+        let mut syms = SymbolTable::with_defaults();
+        let r = round_trip(
+            &mut syms,
+            "caveat1($0) <- resource(#ambient, $0), operation(#ambient, #read), right(#authority, $0, #read)",
+        );
+        assert_eq!(r.body.len(), 3);
+        assert_eq!(r.head.ids[0], ID::Variable(syms.get("0").unwrap() as u32));
+
+        let corpus = [
+            "grandparent($a, $c) <- parent($a, $b), parent($b, $c)",
+            "orphan($p) <- person($p), !parent(_, $p)",
+            "children($p, count($c)) <- parent($p, $c)",
+            "total(sum($amount)) <- payment(_, $amount)",
+            "latest($e, max($d)) <- event($e, $d)",
+            "ints($i) <- int($i) @ $i < 1, $i > -2, $i <= 3, $i >= 4, $i == 5, $i in [-1, 0, 2], $i not in [7]",
+            "strs($s) <- str($s) @ $s matches \"pre\"*, $s matches *\"suf\", $s == \"eq\", $s matches /^a\\/b+$/",
+            "strs($s) <- str($s) @ $s in [\"a\", \"b, c\"], $s not in [\"\\\"\"]",
+            "dates($d) <- date($d) @ $d <= 2020-12-21T09:23:12+00:00, $d >= 1970-01-01T00:00:00+00:00",
+            "syms($s) <- sym($s) @ $s in [#read, #write], $s not in [#authority]",
+            "bytes($b) <- data($b) @ $b == hex:0102, $b in [hex:, hex:ff], $b not in [hex:00]",
+            "less_than($nb, $val) <- x($nb, $val) | -(5 + -4) < $nb",
+            "exprs($a) <- x($a, $b) | $a + 1 == $b, $a in [1, 2] && -($b not in [3]) == false",
+            "exprs($a) <- x($a, $b) | -(4) < -4, -(-$a) > 0, -(1 < 2)",
+            "strs($s) <- x($s) | $s matches \"pre\"*, $s matches *\"su\" + \"f\", $s matches /\"^a+$\"/",
+            "computed($c) <- x($a, $b) | $c = $a + $b",
+            "dated($d) <- x($d) | $d <= 2020-12-21T09:23:12+00:00 && $d >= 2020-01-01T00:00:00+00:00",
+            "all($a) <- x($a), y($a), !z($a, 1), !w() @ $a > 0 | $a + 1 > 2",
+            "empty() <- ",
+        ];
+        for input in corpus.iter() {
+            let rule = round_trip(&mut syms, input);
+            assert_eq!(&syms.print_rule(&rule), input);
+        }
+
+        let r = parse_rule("orphan($p) <- person($p), !parent(_, $p)", &mut syms).unwrap();
+        assert_eq!(r.negated.len(), 1);
+        assert_eq!(r.negated[0].ids[0], ID::Wildcard);
+
+        let r = parse_rule("children($p, count($c)) <- parent($p, $c)", &mut syms).unwrap();
+        assert_eq!(r.aggregate, Some((AggregateOp::Count, syms.get("c").unwrap() as u32)));
+
+        let r = parse_rule("computed($c) <- x($a, $b) | $c = $a + $b", &mut syms).unwrap();
+        let (a, b, c) = (syms.get("a").unwrap() as u32, syms.get("b").unwrap() as u32, syms.get("c").unwrap() as u32);
+        assert_eq!(r.expressions[0].ops, vec![
+            Op::Value(ID::Variable(a)),
+            Op::Value(ID::Variable(b)),
+            Op::Binary(Binary::Add),
+            Op::Bind(c),
+        ]);
+
+        let r = parse_rule("exprs($a) <- x($a, $b) | -(4) < -4", &mut syms).unwrap();
+        assert_eq!(r.expressions[0].ops, vec![
+            Op::Value(ID::Integer(4)),
+            Op::Unary(Unary::Negate),
+            Op::Value(ID::Integer(-4)),
+            Op::Binary(Binary::LessThan),
+        ]);
+    }
+
+    #[test]
+    fn caveats() {
+        let mut syms = SymbolTable::with_defaults();
+        let input = "caveat1($0) <- resource(#ambient, $0), operation(#ambient, #read) || caveat2($0) <- resource(#ambient, $0) | $0 == \"a||b\"";
+        let caveat = parse_caveat(input, &mut syms).unwrap();
+        assert_eq!(caveat.queries.len(), 2);
+        assert_eq!(syms.print_caveat(&caveat), input);
+        assert_eq!(parse_caveat(&syms.print_caveat(&caveat), &mut syms), Ok(caveat));
+    }
+
+    #[test]
+    fn named_sets() {
+        let mut syms = SymbolTable::with_defaults();
+        let mut w = World::new();
+        let revoked = syms.insert("revoked");
+        let set = w.add_revocation_set(revoked, [vec![0], vec![1, 2]]);
+
+        for input in [
+            "valid($id) <- revocation_id($id) @ $id not in set #revoked",
+            "valid($id) <- revocation_id($id) @ $id in set #revoked",
+        ] {
+            let rule = parse_rule_with_sets(input, &mut syms, &w).unwrap();
+            let printed = syms.print_rule(&rule);
+            assert_eq!(printed, input);
+            assert_eq!(parse_rule_with_sets(&printed, &mut syms, &w), Ok(rule.clone()));
+            match &rule.constraints[0].kind {
+                ConstraintKind::Bytes(BytesConstraint::InSet(h) | BytesConstraint::NotInSet(h)) => assert_eq!(h, &set),
+                kind => panic!("unexpected constraint {:?}", kind),
+            }
+        }
+
+        let input = "caveat($id) <- revocation_id($id) @ $id not in set #revoked || caveat($id) <- allowed($id)";
+        let caveat = parse_caveat_with_sets(input, &mut syms, &w).unwrap();
+        assert_eq!(syms.print_caveat(&caveat), input);
+
+        // without a world, or with a name the world does not know
+        let input = "valid($id) <- revocation_id($id) @ $id not in set #revoked";
+        assert_eq!(parse_rule(input, &mut syms), Err(Parse { offset: 50, kind: ParseKind::NamedSet }));
+        assert_eq!(parse_caveat(input, &mut syms).unwrap_err().kind, ParseKind::NamedSet);
+        assert_eq!(
+            parse_rule_with_sets("valid($id) <- revocation_id($id) @ $id in set #other", &mut syms, &w),
+            Err(Parse { offset: 46, kind: ParseKind::UnknownSet })
+        );
+        assert_eq!(
+            parse_rule(input, &mut syms).unwrap_err().to_string(),
+            "named sets need a world, see parser::parse_rule_with_sets at byte 50"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let mut syms = SymbolTable::with_defaults();
+        let error = |input: &str, syms: &mut SymbolTable| parse_rule(input, syms).unwrap_err();
+
+        assert_eq!(
+            parse_fact("right(#authority, $0)", &mut syms),
+            Err(Parse { offset: 18, kind: ParseKind::VariableInFact })
+        );
+        assert_eq!(
+            parse_fact("right(#authority \"file1\")", &mut syms),
+            Err(Parse { offset: 17, kind: ParseKind::Expected(")") })
+        );
+        assert_eq!(
+            parse_fact("right(\"file1)", &mut syms),
+            Err(Parse { offset: 6, kind: ParseKind::UnterminatedString })
+        );
+        assert_eq!(
+            parse_fact("right(1) extra", &mut syms),
+            Err(Parse { offset: 9, kind: ParseKind::TrailingInput })
+        );
+        assert_eq!(
+            parse_fact("right(2020-13-01T00:00:00Z)", &mut syms),
+            Err(Parse { offset: 6, kind: ParseKind::InvalidDate })
+        );
+        assert_eq!(
+            parse_fact("right(99999999999999999999)", &mut syms),
+            Err(Parse { offset: 6, kind: ParseKind::InvalidInteger })
+        );
+        assert_eq!(parse_fact("right(hex:abc)", &mut syms).unwrap_err().kind, ParseKind::InvalidBytes);
+        assert_eq!(parse_fact("right(%)", &mut syms).unwrap_err(), Parse { offset: 6, kind: ParseKind::Expected("a term") });
+
+        assert_eq!(error("head($a) body($a)", &mut syms), Parse { offset: 9, kind: ParseKind::Expected("<-") });
+        assert_eq!(error("head($a) <- body($a) @ $a < \"a\"", &mut syms), Parse { offset: 26, kind: ParseKind::InvalidConstraint });
+        assert_eq!(error("head($a) <- body($a) @ $a in [1, \"a\"]", &mut syms).kind, ParseKind::InvalidConstraint);
+        assert_eq!(error("head($a) <- body($a) @ $a", &mut syms).kind, ParseKind::Expected("a constraint operator"));
+        assert_eq!(error("head($a) <- body($a) | $a < 1 < 2", &mut syms), Parse { offset: 30, kind: ParseKind::TrailingInput });
+        assert_eq!(error("head($a) <- body($a) | ($a < 1", &mut syms), Parse { offset: 30, kind: ParseKind::Expected(")") });
+
+        let mut limited = SymbolTable::with_defaults();
+        limited.set_max_symbols(Some(8));
+        assert_eq!(
+            parse_fact("right(#read, #write)", &mut limited),
+            Err(Parse { offset: 14, kind: ParseKind::Symbol(crate::error::Symbol::TooManySymbols { max: 8 }) })
+        );
+
+        let e = error("head($a) <- body($a) @ $a < \"a\"", &mut syms);
+        assert_eq!(e.to_string(), "invalid constraint at byte 26");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn from_str() {
+        assert_eq!("right(#read)".parse::<Fact>().unwrap_err().kind, ParseKind::NoSymbolTable);
+
+        let mut syms = SymbolTable::with_defaults();
+        let rule: Rule = with_symbols(&mut syms, || "head($a) <- body($a, #new)".parse()).unwrap();
+        let fact: Fact = with_symbols(&mut syms, || "body(1, #new)".parse()).unwrap();
+        assert_eq!(syms.print_rule(&rule), "head($a) <- body($a, #new)");
+        assert_eq!(syms.print_fact(&fact), "body(1, #new)");
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            with_symbols(&mut syms, || panic!("in the closure"))
+        }));
+        assert!(result.is_err());
+        assert_eq!(syms.get("new"), Some(syms.len() as u64 - 1));
+        assert_eq!("right(#read)".parse::<Fact>().unwrap_err().kind, ParseKind::NoSymbolTable);
+    }
+
+    #[cfg(feature = "std")]
+    mod properties {
+        use super::*;
+        use crate::expression::{Expression, Op, Unary};
+        use proptest::collection::{btree_set, vec};
+        use proptest::prelude::*;
+        use proptest::test_runner::{Config, TestError, TestRunner};
+
+        /// predicates, symbols, variables, then the aggregated variable,
+        /// in the order of their indexes
+        const NAMES: [&str; 10] = ["p0", "p1", "p2", "a", "b", "read", "x", "y", "z", "agg"];
+        const AGGREGATED: u32 = 9;
+
+        fn symbols() -> SymbolTable {
+            SymbolTable::from_symbols(NAMES.iter().map(|s| s.to_string()).collect()).unwrap()
+        }
+
+        fn config() -> Config {
+            Config { cases: 256, failure_persistence: None, ..Config::default() }
+        }
+
+        /// mostly the characters that need escaping or delimit the syntax
+        fn string() -> impl Strategy<Value = String> {
+            prop_oneof![3 => "[a-z\"\\\\ ,()|@#$*/\\[\\]-]{0,6}", 1 => any::<String>()]
+        }
+
+        fn date() -> impl Strategy<Value = u64> {
+            // up to 2100, so that dates print as RFC 3339
+            0u64..4_102_444_800
+        }
+
+        fn variable() -> impl Strategy<Value = u32> {
+            6u32..9
+        }
+
+        fn value() -> impl Strategy<Value = ID> {
+            let leaf = prop_oneof![
+                (3u64..6).prop_map(ID::Symbol),
+                any::<i64>().prop_map(ID::Integer),
+                string().prop_map(ID::Str),
+                date().prop_map(ID::Date),
+                vec(any::<u8>(), 0..4).prop_map(ID::Bytes),
+                any::<bool>().prop_map(ID::Bool),
+            ];
+            prop_oneof![4 => leaf.clone(), 1 => btree_set(leaf, 0..4).prop_map(ID::Set)]
+        }
+
+        fn term() -> impl Strategy<Value = ID> {
+            prop_oneof![3 => value(), 1 => variable().prop_map(ID::Variable), 1 => Just(ID::Wildcard)]
+        }
+
+        fn predicate(ids: impl Strategy<Value = ID>) -> impl Strategy<Value = Predicate> {
+            (0u64..3, vec(ids, 0..4)).prop_map(|(name, ids)| Predicate::new(name, &ids))
+        }
+
+        fn fact() -> impl Strategy<Value = Fact> {
+            predicate(value()).prop_map(|predicate| Fact { predicate })
+        }
+
+        fn constraint() -> impl Strategy<Value = Constraint> {
+            let int = prop_oneof![
+                any::<i64>().prop_map(IntConstraint::LessThan),
+                any::<i64>().prop_map(IntConstraint::GreaterThan),
+                any::<i64>().prop_map(IntConstraint::LessOrEqual),
+                any::<i64>().prop_map(IntConstraint::GreaterOrEqual),
+                any::<i64>().prop_map(IntConstraint::Equal),
+                btree_set(any::<i64>(), 0..4).prop_map(IntConstraint::In),
+                btree_set(any::<i64>(), 0..4).prop_map(IntConstraint::NotIn),
+            ];
+            let string = prop_oneof![
+                string().prop_map(StrConstraint::Prefix),
+                string().prop_map(StrConstraint::Suffix),
+                string().prop_map(StrConstraint::Equal),
+                btree_set(string(), 0..4).prop_map(StrConstraint::In),
+                btree_set(string(), 0..4).prop_map(StrConstraint::NotIn),
+                // the printer does not escape regexes
+                "[a-z^$.+*]{0,8}".prop_map(StrConstraint::Regex),
+            ];
+            let date = prop_oneof![
+                date().prop_map(DateConstraint::Before),
+                date().prop_map(DateConstraint::After),
+            ];
+            let symbol = prop_oneof![
+                btree_set(3u64..6, 0..4).prop_map(SymbolConstraint::In),
+                btree_set(3u64..6, 0..4).prop_map(SymbolConstraint::NotIn),
+            ];
+            let bytes = prop_oneof![
+                vec(any::<u8>(), 0..4).prop_map(BytesConstraint::Equal),
+                btree_set(vec(any::<u8>(), 0..4), 0..4).prop_map(BytesConstraint::In),
+                btree_set(vec(any::<u8>(), 0..4), 0..4).prop_map(BytesConstraint::NotIn),
+            ];
+            let kind = prop_oneof![
+                int.prop_map(ConstraintKind::Int),
+                string.prop_map(ConstraintKind::Str),
+                date.prop_map(ConstraintKind::Date),
+                symbol.prop_map(ConstraintKind::Symbol),
+                bytes.prop_map(ConstraintKind::Bytes),
+            ];
+            (variable(), kind).prop_map(|(id, kind)| Constraint { id, kind })
+        }
+
+        fn expression() -> impl Strategy<Value = Expression> {
+            let leaf = prop_oneof![value(), variable().prop_map(ID::Variable)].prop_map(|v| vec![Op::Value(v)]);
+            let ops = leaf.prop_recursive(4, 16, 2, |inner| {
+                let binary = proptest::sample::select(vec![
+                    Binary::LessThan, Binary::GreaterThan, Binary::LessOrEqual, Binary::GreaterOrEqual,
+                    Binary::Equal, Binary::In, Binary::NotIn, Binary::Prefix, Binary::Suffix, Binary::Regex,
+                    Binary::Add, Binary::And,
+                ]);
+                prop_oneof![
+                    inner.clone().prop_map(|mut ops| {
+                        ops.push(Op::Unary(Unary::Negate));
+                        ops
+                    }),
+                    (inner.clone(), inner, binary).prop_map(|(mut ops, right, op)| {
+                        ops.extend(right);
+                        ops.push(Op::Binary(op));
+                        ops
+                    }),
+                ]
+            });
+            (ops, proptest::option::of(variable())).prop_map(|(mut ops, bind)| {
+                ops.extend(bind.map(Op::Bind));
+                Expression { ops }
+            })
+        }
+
+        fn rule() -> impl Strategy<Value = Rule> {
+            let aggregate = proptest::sample::select(vec![
+                AggregateOp::Count, AggregateOp::Sum, AggregateOp::Min, AggregateOp::Max,
+            ]);
+            (
+                predicate(term()),
+                vec(predicate(term()), 0..3),
+                vec(constraint(), 0..3),
+                vec(expression(), 0..3),
+                vec(predicate(term()), 0..2),
+                proptest::option::of(aggregate),
+            ).prop_map(|(mut head, body, constraints, expressions, negated, aggregate)| {
+                // the aggregated variable appears exactly once in the head
+                let aggregate = aggregate.map(|op| {
+                    head.ids.push(ID::Variable(AGGREGATED));
+                    (op, AGGREGATED)
+                });
+                Rule { head, body, constraints, expressions, negated, aggregate }
+            })
+        }
+
+        /// what the text format cannot distinguish: an empty set in a
+        /// constraint is parsed as an integer set
+        fn normalize(mut rule: Rule) -> Rule {
+            for c in rule.constraints.iter_mut() {
+                let empty = match &c.kind {
+                    ConstraintKind::Str(StrConstraint::In(s)) if s.is_empty() => Some(true),
+                    ConstraintKind::Symbol(SymbolConstraint::In(s)) if s.is_empty() => Some(true),
+                    ConstraintKind::Bytes(BytesConstraint::In(s)) if s.is_empty() => Some(true),
+                    ConstraintKind::Str(StrConstraint::NotIn(s)) if s.is_empty() => Some(false),
+                    ConstraintKind::Symbol(SymbolConstraint::NotIn(s)) if s.is_empty() => Some(false),
+                    ConstraintKind::Bytes(BytesConstraint::NotIn(s)) if s.is_empty() => Some(false),
+                    _ => None,
+                };
+                match empty {
+                    Some(true) => c.kind = ConstraintKind::Int(IntConstraint::In(BTreeSet::new())),
+                    Some(false) => c.kind = ConstraintKind::Int(IntConstraint::NotIn(BTreeSet::new())),
+                    None => {},
+                }
+            }
+            rule
+        }
+
+        /// parse(print(rule)) == normalize(rule), with the given printer
+        fn check_rule(print: fn(&SymbolTable, &Rule) -> String, rule: Rule) -> Result<(), TestCaseError> {
+            let mut syms = symbols();
+            let printed = print(&syms, &rule);
+            let parsed = with_symbols(&mut syms, || printed.parse::<Rule>())
+                .map_err(|e| TestCaseError::fail(format!("{}: {}", e, printed)))?;
+            prop_assert_eq!(parsed, normalize(rule), "printed as {}", printed);
+            prop_assert_eq!(syms.len(), NAMES.len(), "new symbols in {}", printed);
+            Ok(())
+        }
+
+        proptest! {
+            #![proptest_config(config())]
+
+            #[test]
+            fn fact_round_trip(fact in fact()) {
+                let mut syms = symbols();
+                let printed = syms.print_fact(&fact);
+                let parsed = with_symbols(&mut syms, || printed.parse::<Fact>());
+                prop_assert_eq!(parsed, Ok(fact), "printed as {}", printed);
+            }
+
+            #[test]
+            fn rule_round_trip(rule in rule()) {
+                check_rule(SymbolTable::print_rule, rule)?;
+            }
+        }
+
+        /// the properties find and shrink a bug in the printer
+        #[test]
+        fn printer_bug() {
+            fn unescaped(syms: &SymbolTable, rule: &Rule) -> String {
+                syms.print_rule(rule).replace("\\\"", "\"")
+            }
+
+            let mut runner = TestRunner::new(config());
+            match runner.run(&rule(), |rule| check_rule(unescaped, rule)) {
+                Err(TestError::Fail(_, minimal)) => {
+                    let printed = unescaped(&symbols(), &minimal);
+                    assert!(printed.contains('"'), "{}", printed);
+                    let parts = minimal.body.len() + minimal.constraints.len()
+                        + minimal.expressions.len() + minimal.negated.len();
+                    assert!(parts <= 1, "not shrunk: {}", printed);
+                },
+                result => panic!("the printer bug was not found: {:?}", result),
+            }
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! why the queries of a caveat did not match, see
+//! `World::explain_caveat_failure`
+//!
+//! each query is evaluated again, first predicate by predicate, then as a
+//! join of its body without the constraints, expressions and negated
+//! predicates. The bindings of that join are the candidates, and the
+//! report counts the ones each constraint, expression and negated
+//! predicate rejected
+use alloc::{vec, vec::Vec};
+
+use super::{
+    error, evaluate_expressions, negation_matches, Budget, Caveat, CombineIt, HashSet, MatchedVariables, Rule,
+    RunLimits, World, ID,
+};
+use crate::time::{self, Instant};
+
+/// one report per query of the caveat, in order
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaveatFailureReport {
+    pub queries: Vec<QueryReport>,
+}
+
+impl CaveatFailureReport {
+    /// true if no query matched, the caveat failed
+    pub fn failed(&self) -> bool {
+        self.queries.iter().all(|q| q.matches == 0)
+    }
+}
+
+/// counts for one query of a caveat
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryReport {
+    pub query: Rule,
+    /// facts matching each body predicate on its own
+    pub predicates: Vec<usize>,
+    /// bindings of the whole body, before the checks below
+    pub candidates: usize,
+    /// candidates rejected by each constraint
+    pub constraints: Vec<usize>,
+    /// candidates rejected by each expression. The expressions run in
+    /// order, a candidate only counts for the first one rejecting it
+    pub expressions: Vec<usize>,
+    /// candidates for which each negated predicate has a matching fact
+    pub negated: Vec<usize>,
+    /// candidates passing all the checks
+    pub matches: usize,
+    /// set if the limits stopped the evaluation, the counts are then
+    /// lower bounds
+    pub limit: Option<error::RunLimit>,
+}
+
+impl QueryReport {
+    /// index of the first body predicate without a matching fact
+    pub fn empty_predicate(&self) -> Option<usize> {
+        self.predicates.iter().position(|n| *n == 0)
+    }
+}
+
+pub(crate) fn explain(world: &World, caveat: &Caveat, limits: &RunLimits) -> CaveatFailureReport {
+    CaveatFailureReport { queries: caveat.queries.iter().map(|query| explain_query(world, query, limits)).collect() }
+}
+
+fn explain_query(world: &World, query: &Rule, limits: &RunLimits) -> QueryReport {
+    let mut report = QueryReport {
+        query: query.clone(),
+        predicates: query.body.iter().map(|p| world.facts.matching(p).count()).collect(),
+        candidates: 0,
+        constraints: vec![0; query.constraints.len()],
+        expressions: vec![0; query.expressions.len()],
+        negated: vec![0; query.negated.len()],
+        matches: 0,
+        limit: None,
+    };
+
+    let variables = query.body.iter()
+        .flat_map(|p| p.ids.iter())
+        .filter_map(|id| match id {
+            ID::Variable(i) => Some(*i),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    let budget = Budget::new(usize::MAX, limits, Instant::now(time::default_clock()));
+    let candidates = CombineIt::new(MatchedVariables::new(variables), &query.body, &[], &[], &[], &world.facts)
+        .with_budget(&budget);
+
+    for mut bindings in candidates {
+        report.candidates += 1;
+        let mut rejected = false;
+        for (count, c) in report.constraints.iter_mut().zip(&query.constraints) {
+            if !bindings.get(&c.id).is_some_and(|id| c.check(c.id, id)) {
+                *count += 1;
+                rejected = true;
+            }
+        }
+        for (count, e) in report.expressions.iter_mut().zip(&query.expressions) {
+            match evaluate_expressions(core::iter::once(e), bindings.clone(), Some(&budget)) {
+                Some(next) => bindings = next,
+                None => {
+                    *count += 1;
+                    rejected = true;
+                    break;
+                },
+            }
+        }
+        for (count, p) in report.negated.iter_mut().zip(&query.negated) {
+            if negation_matches(p, &bindings, &world.facts) {
+                *count += 1;
+                rejected = true;
+            }
+        }
+        if !rejected {
+            report.matches += 1;
+        }
+    }
+
+    report.limit = budget.check().err();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_caveat, parse_fact};
+    use crate::SymbolTable;
+
+    fn resources(syms: &mut SymbolTable) -> World {
+        let mut w = World::new();
+        for f in [
+            "resource(#ambient, #file2)",
+            "operation(#ambient, #write)",
+            "right(#authority, #file1, #read)",
+            "right(#authority, #file2, #read)",
+            "right(#authority, #file1, #write)",
+        ] {
+            w.add_fact(parse_fact(f, syms).unwrap());
+        }
+        w
+    }
+
+    #[test]
+    fn empty_predicate() {
+        let mut syms = SymbolTable::new();
+        let w = resources(&mut syms);
+        let caveat = parse_caveat(
+            "caveat1(#file1) <- resource(#ambient, #file1) \
+             || caveat2($0) <- resource(#ambient, $0), operation(#ambient, #read), right(#authority, $0, #read)",
+            &mut syms,
+        ).unwrap();
+        assert!(w.check_caveat(&caveat, None).is_err());
+
+        let report = w.explain_caveat_failure(&caveat);
+        assert!(report.failed());
+        assert_eq!(report.queries[0].empty_predicate(), Some(0));
+        assert_eq!(report.queries[1].predicates, [1, 0, 2]);
+        let empty = report.queries[1].empty_predicate().unwrap();
+        assert_eq!(syms.print_predicate(&caveat.queries[1].body[empty]), "operation(#ambient, #read)");
+        assert_eq!(report.queries[1].candidates, 0);
+        assert_eq!(syms.print_caveat_failure_report(&report), "\
+query 0: caveat1(#file1) <- resource(#ambient, #file1)
+  no fact matches resource(#ambient, #file1)
+query 1: caveat2($0) <- resource(#ambient, $0), operation(#ambient, #read), right(#authority, $0, #read)
+  no fact matches operation(#ambient, #read)");
+    }
+
+    #[test]
+    fn rejected_candidates() {
+        let mut syms = SymbolTable::new();
+        let mut w = resources(&mut syms);
+        w.add_fact(parse_fact("size(#file1, 10)", &mut syms).unwrap());
+        w.add_fact(parse_fact("size(#file2, 20)", &mut syms).unwrap());
+        let caveat = parse_caveat(
+            "check($f) <- right(#authority, $f, $op), size($f, $s), !operation(#ambient, $op) @ $f in [#file1] | $s > 15",
+            &mut syms,
+        ).unwrap();
+        assert!(w.check_caveat(&caveat, None).is_err());
+
+        let report = w.explain_caveat_failure(&caveat);
+        assert!(report.failed());
+        let query = &report.queries[0];
+        assert_eq!(query.empty_predicate(), None);
+        assert_eq!(query.candidates, 3);
+        // #file2 is rejected by the constraint, #file1 by the expression,
+        // and the write right by the negation
+        assert_eq!(query.constraints, [1]);
+        assert_eq!(query.expressions, [2]);
+        assert_eq!(query.negated, [1]);
+        assert_eq!(query.matches, 0);
+        assert_eq!(query.limit, None);
+        assert_eq!(syms.print_caveat_failure_report(&report), "\
+query 0: check($f) <- right(#authority, $f, $op), size($f, $s), !operation(#ambient, $op) @ $f in [#file1] | $s > 15
+  right(#authority, $f, $op): 3 matching facts
+  size($f, $s): 2 matching facts
+  3 candidates
+  $f in [#file1]: rejected 1
+  $s > 15: rejected 2
+  !operation(#ambient, $op): rejected 1
+  0 matches");
+
+        // a query that matches
+        let caveat = parse_caveat("check($f) <- right(#authority, $f, #read)", &mut syms).unwrap();
+        let report = w.explain_caveat_failure(&caveat);
+        assert!(!report.failed());
+        assert_eq!(report.queries[0].matches, 2);
+    }
+}
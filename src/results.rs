@@ -0,0 +1,204 @@
+//! results of a query, read by row or by column
+//!
+//! values are converted with the `TryFrom<&ID>` implementations of `i64`,
+//! `String`, `bool`, `Vec<u8>` and `BTreeSet<ID>`. Symbols and dates are
+//! both `u64`, they are read as `ID` with `ResultSet::column` or `Row::get`
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::slice;
+
+use super::{error, HashMap, ID};
+
+/// one set of bindings per match of a query, see `World::query_results`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ResultSet {
+    rows: Vec<HashMap<u32, ID>>,
+}
+
+impl From<Vec<HashMap<u32, ID>>> for ResultSet {
+    fn from(rows: Vec<HashMap<u32, ID>>) -> Self {
+        ResultSet { rows }
+    }
+}
+
+impl ResultSet {
+    /// number of rows
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<Row<'_>> {
+        self.rows.get(index).map(|bindings| Row { bindings })
+    }
+
+    pub fn iter(&self) -> Rows<'_> {
+        Rows { rows: self.rows.iter() }
+    }
+
+    /// the value of the variable in each row, empty if the variable is not
+    /// in the query
+    pub fn column(&self, variable: u32) -> Vec<&ID> {
+        self.rows.iter().filter_map(|row| row.get(&variable)).collect()
+    }
+
+    /// like `column`, with the values converted to `T`. Fails on the first
+    /// value of another type, or if the rows do not have the variable
+    pub fn typed_column<T>(&self, variable: u32) -> Result<Vec<T>, error::Conversion>
+    where
+        T: for<'a> TryFrom<&'a ID, Error = error::Conversion>,
+    {
+        self.iter().map(|row| row.get_as(variable)).collect()
+    }
+
+    pub fn into_rows(self) -> Vec<HashMap<u32, ID>> {
+        self.rows
+    }
+}
+
+impl<'a> IntoIterator for &'a ResultSet {
+    type Item = Row<'a>;
+    type IntoIter = Rows<'a>;
+
+    fn into_iter(self) -> Rows<'a> {
+        self.iter()
+    }
+}
+
+/// iterator returned by `ResultSet::iter`
+#[derive(Debug, Clone)]
+pub struct Rows<'a> {
+    rows: slice::Iter<'a, HashMap<u32, ID>>,
+}
+
+impl<'a> Iterator for Rows<'a> {
+    type Item = Row<'a>;
+
+    fn next(&mut self) -> Option<Row<'a>> {
+        self.rows.next().map(|bindings| Row { bindings })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.rows.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Rows<'_> {}
+
+/// the values of the variables for one match
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Row<'a> {
+    bindings: &'a HashMap<u32, ID>,
+}
+
+impl<'a> Row<'a> {
+    pub fn get(&self, variable: u32) -> Option<&'a ID> {
+        self.bindings.get(&variable)
+    }
+
+    /// the value of the variable converted to `T`
+    pub fn get_as<T>(&self, variable: u32) -> Result<T, error::Conversion>
+    where
+        T: TryFrom<&'a ID, Error = error::Conversion>,
+    {
+        let id = self.get(variable).ok_or(error::Conversion::MissingVariable(variable))?;
+        T::try_from(id)
+    }
+
+    pub fn bindings(&self) -> &'a HashMap<u32, ID> {
+        self.bindings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_predicate;
+    use crate::{int, string, Constraint, ConstraintKind, StrConstraint, SymbolTable, World};
+    use alloc::string::{String, ToString};
+
+    fn routes(syms: &mut SymbolTable) -> World {
+        let mut w = World::new();
+        let route = syms.insert("route");
+        let apps = [syms.add("app_0"), syms.add("app_1"), syms.add("app_2")];
+        let domains = ["example.com", "test.com", "test.fr", "www.example.com", "mx.example.com"];
+        for (i, domain) in domains.iter().enumerate() {
+            w.add_fact(crate::fact(route, &[&int(i as i64), &apps[i % 3], &string(domain)]));
+        }
+        w
+    }
+
+    #[test]
+    fn columns() {
+        let mut syms = SymbolTable::new();
+        let w = routes(&mut syms);
+        let query = parse_predicate("route($id, $app, $domain)", &mut syms).unwrap();
+        let [id, app, domain] = ["id", "app", "domain"].map(|name| syms.get(name).unwrap() as u32);
+        let suffix = Constraint { id: domain, kind: ConstraintKind::Str(StrConstraint::Suffix("example.com".to_string())) };
+
+        let results = w.query_results(&query, &[suffix]);
+        assert_eq!(results.len(), 3);
+        assert!(!results.is_empty());
+
+        let mut domains = results.typed_column::<String>(domain).unwrap();
+        domains.sort();
+        assert_eq!(domains, ["example.com", "mx.example.com", "www.example.com"]);
+        let mut ids = results.typed_column::<i64>(id).unwrap();
+        ids.sort();
+        assert_eq!(ids, [0, 3, 4]);
+        assert_eq!(results.column(domain).len(), 3);
+        assert!(results.column(app).iter().all(|id| matches!(id, ID::Symbol(_))));
+
+        // the rows keep the values of a match together
+        for row in &results {
+            let i = row.get_as::<i64>(id).unwrap();
+            assert_eq!(row.get(app), Some(&syms.add(["app_0", "app_1", "app_2"][i as usize % 3])));
+            assert!(row.get_as::<String>(domain).unwrap().ends_with("example.com"));
+            assert_eq!(row.bindings().len(), 3);
+        }
+        assert_eq!(results.iter().len(), 3);
+        assert!(results.get(2).is_some());
+        assert!(results.get(3).is_none());
+        assert_eq!(results.clone().into_rows().len(), 3);
+
+        // all routes without a constraint
+        assert_eq!(w.query_results(&query, &[]).len(), 5);
+        let none = w.query_results(&parse_predicate("route($id, $app, \"none\")", &mut syms).unwrap(), &[]);
+        assert!(none.is_empty());
+        assert_eq!(none.typed_column::<i64>(id), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn conversion_errors() {
+        let mut syms = SymbolTable::new();
+        let w = routes(&mut syms);
+        let query = parse_predicate("route($id, $app, $domain)", &mut syms).unwrap();
+        let [app, domain] = ["app", "domain"].map(|name| syms.get(name).unwrap() as u32);
+        let missing = syms.insert("missing") as u32;
+        let results = w.query_results(&query, &[]);
+
+        assert_eq!(
+            results.typed_column::<i64>(domain),
+            Err(error::Conversion::InvalidType { expected: "integer", found: "string" })
+        );
+        assert_eq!(
+            results.typed_column::<bool>(app),
+            Err(error::Conversion::InvalidType { expected: "boolean", found: "symbol" })
+        );
+        assert!(results.column(missing).is_empty());
+        let e = results.typed_column::<String>(missing).unwrap_err();
+        assert_eq!(e, error::Conversion::MissingVariable(missing));
+        assert_eq!(syms.print_error(&error::Error::from(e)), "variable $missing is not in the results");
+        assert_eq!(results.get(0).unwrap().get_as::<Vec<u8>>(missing), Err(error::Conversion::MissingVariable(missing)));
+
+        assert_eq!(Vec::<u8>::try_from(&ID::Bytes(vec![1, 2])), Ok(vec![1, 2]));
+        assert_eq!(bool::try_from(&ID::Bool(true)), Ok(true));
+        assert_eq!(
+            crate::BTreeSet::<ID>::try_from(&ID::Date(0)),
+            Err(error::Conversion::InvalidType { expected: "set", found: "date" })
+        );
+    }
+}
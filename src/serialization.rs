@@ -0,0 +1,545 @@
+//! serde helpers used with the `serde` feature
+//!
+//! hash based collections are serialized in sorted order so that the same
+//! values always produce the same output, and byte arrays are written as
+//! base64 strings and dates as RFC 3339 strings in human readable formats
+//! like JSON
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::iter::FromIterator;
+use std::fmt;
+use core::hash::Hash;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+use crate::{SymbolTable, World};
+
+/// `Vec<u8>` as base64 in human readable formats, as bytes otherwise
+pub mod bytes {
+    use super::*;
+
+    pub fn serialize<T: AsRef<[u8]>, S: Serializer>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&STANDARD.encode(bytes.as_ref()))
+        } else {
+            serializer.serialize_bytes(bytes.as_ref())
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(BytesVisitor)
+        } else {
+            deserializer.deserialize_byte_buf(BytesVisitor)
+        }
+    }
+
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a byte array or a base64 string")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Vec<u8>, E> {
+            STANDARD.decode(v).map_err(E::custom)
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(v)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut v = Vec::new();
+            while let Some(b) = seq.next_element()? {
+                v.push(b);
+            }
+            Ok(v)
+        }
+    }
+}
+
+/// timestamps as RFC 3339 strings in human readable formats, as integers
+/// otherwise. Integers are accepted in both cases
+pub mod date {
+    use super::*;
+    use chrono::DateTime;
+
+    pub fn serialize<S: Serializer>(timestamp: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        let date = i64::try_from(*timestamp).ok().and_then(|t| DateTime::from_timestamp(t, 0));
+        match date {
+            Some(date) if serializer.is_human_readable() => serializer.serialize_str(&date.to_rfc3339()),
+            _ => serializer.serialize_u64(*timestamp),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(DateVisitor)
+        } else {
+            deserializer.deserialize_u64(DateVisitor)
+        }
+    }
+
+    struct DateVisitor;
+
+    impl<'de> Visitor<'de> for DateVisitor {
+        type Value = u64;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a timestamp or an RFC 3339 date")
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<u64, E> {
+            Ok(v)
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<u64, E> {
+            u64::try_from(v).map_err(E::custom)
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<u64, E> {
+            let date = DateTime::parse_from_rfc3339(v).map_err(E::custom)?;
+            u64::try_from(date.timestamp()).map_err(|_| E::custom("dates before 1970 are not supported"))
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Bytes(Vec<u8>);
+
+impl Serialize for Bytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        bytes::serialize(&self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        bytes::deserialize(deserializer).map(Bytes)
+    }
+}
+
+/// set of byte strings, a `BTreeSet` or a `HashSet`, as a sorted list of
+/// byte arrays
+pub mod bytes_set {
+    use super::*;
+
+    pub fn serialize<'a, T, S>(set: &'a T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        &'a T: IntoIterator<Item = &'a Vec<u8>>,
+        S: Serializer,
+    {
+        let mut elements = set.into_iter().map(|b| Bytes(b.clone())).collect::<Vec<_>>();
+        elements.sort();
+        elements.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromIterator<Vec<u8>>,
+        D: Deserializer<'de>,
+    {
+        let elements = Vec::<Bytes>::deserialize(deserializer)?;
+        Ok(elements.into_iter().map(|b| b.0).collect())
+    }
+}
+
+/// shared `HashSet<Vec<u8>>` of a `SetHandle`, like `bytes_set`
+pub mod shared_bytes_set {
+    use super::*;
+    use std::sync::Arc;
+
+    pub fn serialize<S: Serializer>(set: &Arc<HashSet<Vec<u8>>>, serializer: S) -> Result<S::Ok, S::Error> {
+        bytes_set::serialize(&**set, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Arc<HashSet<Vec<u8>>>, D::Error> {
+        bytes_set::deserialize(deserializer).map(Arc::new)
+    }
+}
+
+/// `HashMap` as a map sorted by key
+pub mod sorted_map {
+    use super::*;
+
+    pub fn serialize<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: Serialize + Ord,
+        V: Serialize,
+        S: Serializer,
+    {
+        map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        HashMap::<K, V>::deserialize(deserializer)
+    }
+}
+
+/// `HashMap` as a list of key and value pairs sorted by key, for keys that
+/// cannot be map keys in formats like JSON
+pub mod pairs {
+    use super::*;
+
+    pub fn serialize<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: Serialize + Ord,
+        V: Serialize,
+        S: Serializer,
+    {
+        let mut pairs = map.iter().collect::<Vec<_>>();
+        pairs.sort_by_key(|&(k, _)| k);
+        pairs.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Ok(Vec::<(K, V)>::deserialize(deserializer)?.into_iter().collect())
+    }
+}
+
+/// version of the documents written by `World::to_json`
+pub const JSON_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Export<'a> {
+    version: u32,
+    symbols: &'a SymbolTable,
+    world: &'a World,
+}
+
+#[derive(Deserialize)]
+struct Import {
+    symbols: SymbolTable,
+    world: World,
+}
+
+#[derive(Deserialize)]
+struct Version {
+    version: u32,
+}
+
+impl World {
+    /// self-contained JSON document with the symbol table and the world,
+    /// including the provenance of derived facts if the world was run with
+    /// `World::run_with_provenance`
+    pub fn to_json(&self, symbols: &SymbolTable) -> String {
+        let export = Export { version: JSON_VERSION, symbols, world: self };
+        serde_json::to_string_pretty(&export).expect("worlds can be serialized to JSON")
+    }
+
+    /// loads a document written by `World::to_json`
+    pub fn from_json(json: &str) -> Result<(World, SymbolTable), crate::error::Import> {
+        let json_error = |e: serde_json::Error| crate::error::Import::Json(e.to_string());
+
+        // checked first, as newer documents might not have the same fields
+        let version = serde_json::from_str::<Version>(json).map_err(json_error)?.version;
+        if version > JSON_VERSION {
+            return Err(crate::error::Import::UnsupportedVersion { found: version, supported: JSON_VERSION });
+        }
+
+        let import = serde_json::from_str::<Import>(json).map_err(json_error)?;
+        Ok((import.world, import.symbols))
+    }
+}
+
+/// the symbols in order, their position is the index used by `ID::Symbol`
+/// and `Predicate::name`
+impl Serialize for crate::SymbolTable {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.symbols.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for crate::SymbolTable {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let symbols = Vec::<String>::deserialize(deserializer)?;
+        crate::SymbolTable::from_symbols(symbols).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::fmt;
+    use std::time::Duration;
+
+    /// checks the value survives a JSON and a CBOR round trip
+    fn round_trip<T>(value: &T) -> T
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned + PartialEq + fmt::Debug,
+    {
+        let serialized = serde_json::to_string(value).unwrap();
+        let deserialized: T = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(&deserialized, value, "JSON: {}", serialized);
+
+        let serialized = serde_cbor::to_vec(value).unwrap();
+        let deserialized: T = serde_cbor::from_slice(&serialized).unwrap();
+        assert_eq!(&deserialized, value);
+
+        deserialized
+    }
+
+    #[test]
+    fn ids() {
+        let ids = vec![
+            ID::Symbol(1),
+            ID::Variable(2),
+            ID::Integer(-3),
+            ID::Str("hello".to_string()),
+            ID::Date(1_600_000_000),
+            ID::Bytes(vec![0, 1, 255]),
+            ID::Bool(true),
+            ID::Set([ID::Integer(1), ID::Bytes(vec![2])].iter().cloned().collect()),
+            ID::Wildcard,
+        ];
+        round_trip(&ids);
+
+        assert_eq!(serde_json::to_string(&ID::Bytes(vec![0, 1, 255])).unwrap(), r#"{"Bytes":"AAH/"}"#);
+    }
+
+    #[test]
+    fn constraints_and_expressions() {
+        let set = |v: &[i64]| v.iter().cloned().collect::<BTreeSet<_>>();
+        let kinds = vec![
+            ConstraintKind::Int(IntConstraint::LessThan(1)),
+            ConstraintKind::Int(IntConstraint::GreaterThan(2)),
+            ConstraintKind::Int(IntConstraint::LessOrEqual(3)),
+            ConstraintKind::Int(IntConstraint::GreaterOrEqual(4)),
+            ConstraintKind::Int(IntConstraint::Equal(5)),
+            ConstraintKind::Int(IntConstraint::In(set(&[1, 2, 3]))),
+            ConstraintKind::Int(IntConstraint::NotIn(set(&[4, 5]))),
+            ConstraintKind::Str(StrConstraint::Prefix("a".to_string())),
+            ConstraintKind::Str(StrConstraint::Suffix("b".to_string())),
+            ConstraintKind::Str(StrConstraint::Equal("c".to_string())),
+            ConstraintKind::Str(StrConstraint::In(["d".to_string(), "e".to_string()].iter().cloned().collect())),
+            ConstraintKind::Str(StrConstraint::NotIn(["f".to_string()].iter().cloned().collect())),
+            ConstraintKind::Str(StrConstraint::Regex("^g+$".to_string())),
+            ConstraintKind::Date(DateConstraint::Before(10)),
+            ConstraintKind::Date(DateConstraint::After(20)),
+            ConstraintKind::Symbol(SymbolConstraint::In([1, 2].iter().cloned().collect())),
+            ConstraintKind::Symbol(SymbolConstraint::NotIn([3].iter().cloned().collect())),
+            ConstraintKind::Bytes(BytesConstraint::Equal(vec![1, 2])),
+            ConstraintKind::Bytes(BytesConstraint::In([vec![3], vec![4, 5]].iter().cloned().collect())),
+            ConstraintKind::Bytes(BytesConstraint::NotIn([vec![6]].iter().cloned().collect())),
+        ];
+        let constraints = kinds.into_iter().enumerate()
+            .map(|(i, kind)| Constraint { id: i as u32, kind })
+            .collect::<Vec<_>>();
+        round_trip(&constraints);
+
+        // sets are sorted
+        let c = ConstraintKind::Int(IntConstraint::In(set(&[3, 1, 2, 10, -5])));
+        assert_eq!(serde_json::to_string(&c).unwrap(), r#"{"Int":{"In":[-5,1,2,3,10]}}"#);
+
+        let ops = vec![
+            Op::Value(ID::Variable(0)),
+            Op::Unary(Unary::Negate),
+            Op::Bind(1),
+        ].into_iter().chain(vec![
+            Binary::LessThan, Binary::GreaterThan, Binary::LessOrEqual, Binary::GreaterOrEqual,
+            Binary::Equal, Binary::In, Binary::NotIn, Binary::Prefix, Binary::Suffix, Binary::Regex,
+            Binary::Add, Binary::And,
+        ].into_iter().map(Op::Binary)).collect();
+        round_trip(&Expression { ops });
+    }
+
+    #[test]
+    fn world() {
+        let mut syms = SymbolTable::new();
+        let mut w = World::new();
+
+        let a = syms.add("A");
+        let b = syms.add("B");
+        let c = syms.add("C");
+        let parent = syms.insert("parent");
+        let grandparent = syms.insert("grandparent");
+        let children = syms.insert("children");
+        let orphan = syms.insert("orphan");
+        let data = syms.insert("data");
+
+        w.add_fact(fact(parent, &[&a, &b]));
+        w.add_fact(fact(parent, &[&b, &c]));
+        w.add_fact_with_origin(fact(data, &[&ID::Bytes(vec![1, 2, 3]), &string("x"), &date(&UNIX_EPOCH)]), Origin::Block(1));
+        w.declare_predicate(parent, 2);
+
+        w.add_rule(rule(
+            grandparent,
+            &[var(&mut syms, "a"), var(&mut syms, "c")],
+            &[
+                pred(parent, &[var(&mut syms, "a"), var(&mut syms, "b")]),
+                pred(parent, &[var(&mut syms, "b"), var(&mut syms, "c")]),
+            ],
+        ));
+        w.add_rule(Rule {
+            aggregate: Some((AggregateOp::Count, syms.insert("child") as u32)),
+            ..rule(
+                children,
+                &[var(&mut syms, "p"), var(&mut syms, "child")],
+                &[pred(parent, &[var(&mut syms, "p"), var(&mut syms, "child")])],
+            )
+        });
+        w.add_rule_with_origin(Rule {
+            negated: vec![pred(parent, &[&ID::Wildcard, &var(&mut syms, "p")])],
+            expressions: vec![Expression { ops: vec![
+                Op::Value(string("prefix")),
+                Op::Value(string("pre")),
+                Op::Binary(Binary::Prefix),
+            ] }],
+            ..rule(orphan, &[var(&mut syms, "p")], &[pred(parent, &[var(&mut syms, "p"), ID::Wildcard])])
+        }, Origin::Block(1));
+
+        let limits = || RunLimits { max_time: Some(Duration::from_secs(10)), ..Default::default() };
+        w.run_with_provenance(limits()).unwrap();
+        assert!(w.facts.contains(&fact(grandparent, &[&a, &c])));
+        assert!(w.facts.contains(&fact(orphan, &[&a])));
+        assert!(!w.derivations.is_empty());
+
+        let mut deserialized = round_trip(&w);
+        assert_eq!(round_trip(&syms), syms);
+        assert_eq!(round_trip(&Caveat { queries: w.rules.to_vec() }).queries, *w.rules);
+
+        // the same world always gives the same output
+        let mut facts = w.facts.iter().cloned().collect::<Vec<_>>();
+        facts.reverse();
+        let rebuilt = World { facts: facts.into_iter().collect(), ..w.clone() };
+        assert_eq!(serde_json::to_string(&rebuilt).unwrap(), serde_json::to_string(&w).unwrap());
+
+        let d = syms.add("D");
+        w.add_fact(fact(parent, &[&c, &d]));
+        deserialized.add_fact(fact(parent, &[&c, &d]));
+        w.run_with_provenance(limits()).unwrap();
+        deserialized.run_with_provenance(limits()).unwrap();
+        assert_eq!(deserialized, w);
+        assert!(w.facts.contains(&fact(grandparent, &[&b, &d])));
+    }
+
+    #[test]
+    fn symbol_table() {
+        let mut syms = SymbolTable::with_defaults();
+        let mut w = World::new();
+
+        let parent = syms.insert("parent");
+        let grandparent = syms.insert("grandparent");
+        let a = syms.add("A");
+        let b = syms.add("B");
+        let c = syms.add("C");
+        w.add_fact(fact(parent, &[&a, &b]));
+        w.add_fact(fact(parent, &[&b, &c]));
+        w.add_rule(rule(
+            grandparent,
+            &[var(&mut syms, "a"), var(&mut syms, "c")],
+            &[
+                pred(parent, &[var(&mut syms, "a"), var(&mut syms, "b")]),
+                pred(parent, &[var(&mut syms, "b"), var(&mut syms, "c")]),
+            ],
+        ));
+        w.run_with_limits(RunLimits { max_time: Some(Duration::from_secs(10)), ..Default::default() }).unwrap();
+
+        let json = serde_json::to_string(&syms).unwrap();
+        assert!(json.starts_with(r#"["authority","ambient","resource","operation","right","#), "{}", json);
+
+        let (mut syms2, w2) = round_trip(&(syms.clone(), w.clone()));
+        assert_eq!(syms2.print_world(&w2), syms.print_world(&w));
+        assert_eq!(syms2.print_rule(&w2.rules[0]), "grandparent($a, $c) <- parent($a, $b), parent($b, $c)");
+
+        // the index is rebuilt
+        assert_eq!(syms2.get("C"), Some(syms.get("C").unwrap()));
+        assert_eq!(syms2.insert("grandparent"), grandparent);
+        assert_eq!(syms2.insert("D"), syms.len() as u64);
+
+        let error = serde_json::from_str::<SymbolTable>(r#"["a","b","a"]"#).unwrap_err();
+        assert!(error.to_string().contains(r#"duplicate symbol "a""#), "{}", error);
+        assert_eq!(
+            SymbolTable::from_symbols(vec!["a".to_string(), "b".to_string(), "b".to_string()]),
+            Err(crate::error::Symbol::Duplicate("b".to_string()))
+        );
+    }
+
+    #[test]
+    fn json_dump() {
+        let mut syms = SymbolTable::new();
+        let mut w = World::new();
+        let facts = [
+            "resource(#ambient, #file2)",
+            "operation(#ambient, #write)",
+            "right(#authority, #file1, #read)",
+            "right(#authority, #file2, #read)",
+            "right(#authority, #file1, #write)",
+            "expiration(#file1, 2020-12-21T09:23:12+00:00, hex:00ff)",
+        ];
+        for f in facts.iter() {
+            w.add_fact(crate::parser::parse_fact(f, &mut syms).unwrap());
+        }
+        w.add_rule(crate::parser::parse_rule(
+            "can_write($f) <- resource(#ambient, $f), right(#authority, $f, #write)",
+            &mut syms,
+        ).unwrap());
+        w.add_rule(crate::parser::parse_rule(
+            "valid($f) <- expiration($f, $d, $b) @ $d >= 2020-01-01T00:00:00+00:00",
+            &mut syms,
+        ).unwrap());
+        let limits = || RunLimits { max_time: Some(Duration::from_secs(10)), ..Default::default() };
+        w.run_with_provenance(limits()).unwrap();
+
+        let json = w.to_json(&syms);
+        assert!(json.contains(r#""version": 1"#), "{}", json);
+        assert!(json.contains(r#""2020-12-21T09:23:12+00:00""#), "{}", json);
+        assert!(json.contains(r#""AP8=""#), "{}", json);
+
+        let (mut imported, mut imported_syms) = World::from_json(&json).unwrap();
+        assert_eq!(imported, w);
+        assert_eq!(imported_syms, syms);
+        assert_eq!(imported_syms.print_world(&imported), syms.print_world(&w));
+
+        // the imported world keeps running and gives the same results
+        let queries = [
+            "caveat1(#file1) <- resource(#ambient, #file1)",
+            "caveat2($0) <- resource(#ambient, $0), operation(#ambient, #read), right(#authority, $0, #read)",
+            "writable($f) <- can_write($f)",
+            "valid($f) <- valid($f)",
+        ];
+        let fact = "resource(#ambient, #file1)";
+        w.add_fact(crate::parser::parse_fact(fact, &mut syms).unwrap());
+        imported.add_fact(crate::parser::parse_fact(fact, &mut imported_syms).unwrap());
+        w.run_with_provenance(limits()).unwrap();
+        imported.run_with_provenance(limits()).unwrap();
+        for q in queries.iter() {
+            let expected = w.query_rule(crate::parser::parse_rule(q, &mut syms).unwrap());
+            let found = imported.query_rule(crate::parser::parse_rule(q, &mut imported_syms).unwrap());
+            assert_eq!(found, expected, "{}", q);
+        }
+        assert_eq!(imported_syms.print_world(&imported), syms.print_world(&w));
+
+        // integer dates from older documents are still accepted
+        assert_eq!(serde_json::from_str::<ID>(r#"{"Date":1608542592}"#).unwrap(), ID::Date(1_608_542_592));
+
+        let future = json.replacen(r#""version": 1"#, r#""version": 2"#, 1);
+        assert_eq!(
+            World::from_json(&future),
+            Err(crate::error::Import::UnsupportedVersion { found: 2, supported: 1 })
+        );
+        assert!(matches!(World::from_json(r#"{"version": 1}"#), Err(crate::error::Import::Json(_))));
+        assert!(matches!(World::from_json("[]"), Err(crate::error::Import::Json(_))));
+    }
+}
@@ -1,31 +1,275 @@
 //! Logic language implementation for caveats
-use std::collections::HashSet;
-use std::time::{Duration, UNIX_EPOCH};
-use chrono::{DateTime, NaiveDateTime, Utc};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::collections::BTreeSet;
+use alloc::format;
+use core::fmt::Write;
+use chrono::{DateTime, Utc};
+use super::HashMap;
 
 pub type Symbol = u64;
 use super::{ID, World, Fact, Rule, Constraint, ConstraintKind, Caveat,
   IntConstraint, StrConstraint, SymbolConstraint, BytesConstraint,
   DateConstraint, Predicate};
 
-#[derive(Clone, Debug, PartialEq, Default)]
+/// with the `serde` feature, serialized as the list of symbols in order,
+/// see `SymbolTable::from_symbols`
+#[derive(Clone, Debug, Default)]
 pub struct SymbolTable {
     pub symbols: Vec<String>,
+    // position of the first `indexed` symbols, the others were added
+    // directly to `symbols` and are indexed by the next insertion
+    index: HashMap<String, Symbol>,
+    indexed: usize,
+    // checked by `try_insert`
+    max_symbols: Option<usize>,
+}
+
+/// tables are equal if they have the same symbols
+impl PartialEq for SymbolTable {
+    fn eq(&self, other: &SymbolTable) -> bool {
+        self.symbols == other.symbols
+    }
+}
+
+impl From<Vec<String>> for SymbolTable {
+    fn from(symbols: Vec<String>) -> Self {
+        let mut table = SymbolTable { symbols, ..SymbolTable::default() };
+        table.update_index();
+        table
+    }
+}
+
+fn id_symbols(id: &ID, symbols: &mut Vec<Symbol>) {
+    match id {
+        ID::Symbol(s) => symbols.push(*s),
+        ID::Variable(v) => symbols.push(*v as Symbol),
+        ID::Set(s) => s.iter().for_each(|id| id_symbols(id, symbols)),
+        _ => {},
+    }
+}
+
+/// symbols and variable names of the predicate, in order
+fn predicate_symbols(p: &Predicate, symbols: &mut Vec<Symbol>) {
+    symbols.push(p.name);
+    p.ids.iter().for_each(|id| id_symbols(id, symbols));
+}
+
+/// symbols and variable names of the rule, in order. The ones of the
+/// predicates are left out without `predicates`
+fn rule_symbols(r: &Rule, predicates: bool, symbols: &mut Vec<Symbol>) {
+    if predicates {
+        for p in core::iter::once(&r.head).chain(r.body.iter()).chain(r.negated.iter()) {
+            predicate_symbols(p, symbols);
+        }
+    }
+
+    for c in r.constraints.iter() {
+        symbols.push(c.id as Symbol);
+        match &c.kind {
+            ConstraintKind::Symbol(SymbolConstraint::In(s) | SymbolConstraint::NotIn(s)) => symbols.extend(s.iter().copied()),
+            ConstraintKind::Bytes(BytesConstraint::InSet(h) | BytesConstraint::NotInSet(h)) => symbols.push(h.name()),
+            _ => {},
+        }
+    }
+    for e in r.expressions.iter() {
+        for op in e.ops.iter() {
+            match op {
+                crate::Op::Value(id) => id_symbols(id, symbols),
+                crate::Op::Bind(v) => symbols.push(*v as Symbol),
+                _ => {},
+            }
+        }
+    }
+    if let Some((_, v)) = &r.aggregate {
+        symbols.push(*v as Symbol);
+    }
+}
+
+/// indexes of a table's symbols in another table, returned by
+/// `SymbolTable::extend`
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct SymbolRemapping {
+    table: HashMap<Symbol, Symbol>,
+}
+
+impl SymbolRemapping {
+    pub fn get(&self, s: Symbol) -> Option<Symbol> {
+        self.table.get(&s).copied()
+    }
+
+    /// the mapping, as used by `World::merge_translated`
+    pub fn table(&self) -> &HashMap<Symbol, Symbol> {
+        &self.table
+    }
+
+    fn check(&self, symbols: Vec<Symbol>) -> Result<(), crate::error::Merge> {
+        match symbols.into_iter().find(|s| !self.table.contains_key(s)) {
+            Some(s) => Err(crate::error::Merge::MissingSymbol(s)),
+            None => Ok(()),
+        }
+    }
+
+    /// fails on the first symbol or variable name of the fact missing
+    /// from the source table
+    pub(crate) fn check_fact(&self, f: &Fact) -> Result<(), crate::error::Merge> {
+        let mut symbols = Vec::new();
+        predicate_symbols(&f.predicate, &mut symbols);
+        self.check(symbols)
+    }
+
+    /// like `check_fact`
+    pub(crate) fn check_rule(&self, r: &Rule) -> Result<(), crate::error::Merge> {
+        let mut symbols = Vec::new();
+        rule_symbols(r, true, &mut symbols);
+        self.check(symbols)
+    }
 }
 
+/// symbols present at the start of every biscuit symbol table, in order
+pub const DEFAULT_SYMBOLS: [&str; 7] = [
+    "authority",
+    "ambient",
+    "resource",
+    "operation",
+    "right",
+    "current_time",
+    "revocation_id",
+];
+
 impl SymbolTable {
+    pub const AUTHORITY: Symbol = 0;
+    pub const AMBIENT: Symbol = 1;
+    pub const RESOURCE: Symbol = 2;
+    pub const OPERATION: Symbol = 3;
+    pub const RIGHT: Symbol = 4;
+    pub const CURRENT_TIME: Symbol = 5;
+    pub const REVOCATION_ID: Symbol = 6;
+
+    /// empty table, see `with_defaults` for the one used by tokens
     pub fn new() -> Self {
         SymbolTable::default()
     }
 
-    pub fn insert(&mut self, s: &str) -> Symbol {
-        match self.symbols.iter().position(|sym| sym.as_str() == s) {
-            Some(index) => index as u64,
-            None => {
-                self.symbols.push(s.to_string());
-                (self.symbols.len() - 1) as u64
+    /// table starting with `DEFAULT_SYMBOLS`, at the indexes of the
+    /// `SymbolTable::AUTHORITY`, `SymbolTable::AMBIENT`... constants
+    pub fn with_defaults() -> Self {
+        SymbolTable::from(DEFAULT_SYMBOLS.iter().map(|s| s.to_string()).collect::<Vec<_>>())
+    }
+
+    /// table with the symbols at these indexes, they must all be different
+    pub fn from_symbols(symbols: Vec<String>) -> Result<Self, crate::error::Symbol> {
+        let table = SymbolTable::from(symbols);
+        if table.index.len() != table.symbols.len() {
+            let duplicate = table.symbols.iter().enumerate()
+                .find(|&(i, s)| table.index[s] != i as Symbol)
+                .map(|(_, s)| s.clone())
+                .unwrap_or_default();
+            return Err(crate::error::Symbol::Duplicate(duplicate));
+        }
+        Ok(table)
+    }
+
+    /// adds the symbols of `other` missing from this table, after the
+    /// existing ones, and returns where each of `other`'s symbols is now
+    pub fn extend(&mut self, other: &SymbolTable) -> SymbolRemapping {
+        let table = other.symbols.iter().enumerate()
+            .map(|(i, s)| (i as Symbol, self.insert(s)))
+            .collect();
+        SymbolRemapping { table }
+    }
+
+    /// like `extend`, but fails if `other` has a symbol at two indexes, or
+    /// if the new symbols would go over `max_symbols`. The table is left
+    /// unchanged on failure
+    pub fn try_extend(&mut self, other: &SymbolTable) -> Result<SymbolRemapping, crate::error::Merge> {
+        let mut positions = HashMap::new();
+        for (i, s) in other.symbols.iter().enumerate() {
+            if let Some(first) = positions.insert(s.as_str(), i as Symbol) {
+                return Err(crate::error::Merge::Collision { symbol: s.clone(), first, second: i as Symbol });
             }
         }
+
+        let missing = other.symbols.iter().filter(|s| self.get(s).is_none()).count();
+        if let Some(max) = self.max_symbols {
+            if missing > 0 && self.symbols.len() + missing > max {
+                return Err(crate::error::Symbol::TooManySymbols { max }.into());
+            }
+        }
+
+        Ok(self.extend(other))
+    }
+
+    /// indexes the symbols added directly to `symbols`
+    fn update_index(&mut self) {
+        if self.indexed > self.symbols.len() {
+            self.index.clear();
+            self.indexed = 0;
+        }
+
+        for (i, sym) in self.symbols.iter().enumerate().skip(self.indexed) {
+            // duplicates keep the first position, like a linear search
+            self.index.entry(sym.clone()).or_insert(i as u64);
+        }
+        self.indexed = self.symbols.len();
+    }
+
+    /// caps the number of symbols `try_insert` accepts, the symbols
+    /// already in the table are kept
+    pub fn set_max_symbols(&mut self, max: Option<usize>) {
+        self.max_symbols = max;
+    }
+
+    pub fn max_symbols(&self) -> Option<usize> {
+        self.max_symbols
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// size in bytes of the symbols, including their index
+    pub fn estimated_size(&self) -> usize {
+        let per_symbol = 2 * core::mem::size_of::<String>() + core::mem::size_of::<Symbol>();
+        self.symbols.iter().map(|s| per_symbol + 2 * s.len()).sum()
+    }
+
+    /// like `insert`, but fails instead of adding a symbol over
+    /// `max_symbols`. Existing symbols are always found
+    pub fn try_insert(&mut self, s: &str) -> Result<Symbol, crate::error::Symbol> {
+        self.update_index();
+        if let Some(index) = self.index.get(s) {
+            return Ok(*index);
+        }
+
+        match self.max_symbols {
+            Some(max) if self.symbols.len() >= max => Err(crate::error::Symbol::TooManySymbols { max }),
+            _ => Ok(self.insert(s)),
+        }
+    }
+
+    /// adds the symbol if it is not present, ignoring `max_symbols`
+    pub fn insert(&mut self, s: &str) -> Symbol {
+        self.update_index();
+        if let Some(index) = self.index.get(s) {
+            return *index;
+        }
+
+        let index = self.symbols.len() as u64;
+        self.symbols.push(s.to_string());
+        self.index.insert(s.to_string(), index);
+        self.indexed += 1;
+        index
+    }
+
+    /// adds `ns::name`, to keep the predicates of different sources apart,
+    /// see `World::merge_namespaced`
+    pub fn insert_namespaced(&mut self, ns: &str, name: &str) -> Symbol {
+        self.insert(&format!("{}::{}", ns, name))
     }
 
     pub fn add(&mut self, s: &str) -> ID {
@@ -33,21 +277,81 @@ impl SymbolTable {
         ID::Symbol(id)
     }
 
+    /// like `add`, see `try_insert`
+    pub fn try_add(&mut self, s: &str) -> Result<ID, crate::error::Symbol> {
+        self.try_insert(s).map(ID::Symbol)
+    }
+
     pub fn get(&self, s: &str) -> Option<Symbol> {
+        if self.indexed == self.symbols.len() {
+            return self.index.get(s).copied();
+        }
+
         self.symbols
             .iter()
             .position(|sym| sym.as_str() == s)
             .map(|i| i as u64)
     }
 
+    /// unknown symbols are printed as `<index?>`
     pub fn print_symbol(&self, s: Symbol) -> String {
       self.symbols.get(s as usize).map(|s| s.to_string()).unwrap_or_else(|| format!("<{}?>", s))
     }
 
+    /// variables are named by the symbol at their index, as created by
+    /// `var`. Unknown ones print as `$<id?>`
+    pub fn print_variable(&self, v: u32) -> String {
+        format!("${}", self.print_symbol(v as Symbol))
+    }
+
+    fn check_symbol(&self, s: Symbol) -> Result<(), crate::error::Symbol> {
+        if (s as usize) < self.symbols.len() {
+            Ok(())
+        } else {
+            Err(crate::error::Symbol::Unknown(s))
+        }
+    }
+
+    fn check_predicate(&self, p: &Predicate) -> Result<(), crate::error::Symbol> {
+        let mut symbols = Vec::new();
+        predicate_symbols(p, &mut symbols);
+        symbols.into_iter().try_for_each(|s| self.check_symbol(s))
+    }
+
+    /// every symbol and variable name used by the rule, checked in order
+    fn check_rule(&self, r: &Rule) -> Vec<crate::error::Symbol> {
+        let predicates = core::iter::once(&r.head).chain(r.body.iter()).chain(r.negated.iter());
+        let mut errors = predicates.filter_map(|p| self.check_predicate(p).err()).collect::<Vec<_>>();
+
+        let mut symbols = Vec::new();
+        rule_symbols(r, false, &mut symbols);
+        errors.extend(symbols.into_iter().filter_map(|s| self.check_symbol(s).err()));
+        errors
+    }
+
+    /// lists the symbols used by the world's facts and rules that are
+    /// missing from this table, sorted
+    pub fn check_world(&self, w: &World) -> Result<(), Vec<crate::error::Symbol>> {
+        let mut errors = w.facts.iter()
+            .filter_map(|f| self.check_predicate(&f.predicate).err())
+            .chain(w.rules.iter().flat_map(|r| self.check_rule(r)))
+            .collect::<Vec<_>>();
+        errors.sort();
+        errors.dedup();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// facts are sorted, so the same world is always printed the same way
     pub fn print_world(&self, w: &World) -> String {
-        let facts = w
-            .facts
-            .iter()
+        let mut facts = w.facts.iter().collect::<Vec<_>>();
+        facts.sort();
+        let facts = facts
+            .into_iter()
             .map(|f| self.print_fact(f))
             .collect::<Vec<_>>();
         let rules = w
@@ -60,14 +364,11 @@ impl SymbolTable {
 
     pub fn print_id(&self, id: &ID) -> String {
         match id {
-            ID::Variable(i) => format!("${}", self.print_symbol(*i as u64)),
+            ID::Variable(i) => self.print_variable(*i),
             ID::Integer(i) => i.to_string(),
-            ID::Str(s) => format!("\"{}\"", s),
-            ID::Symbol(index) => format!("#{}", self.print_symbol(*index as u64)),
-            ID::Date(d) => {
-                let t = UNIX_EPOCH + Duration::from_secs(*d);
-                format!("{:?}", t)
-            },
+            ID::Str(s) => print_string(s),
+            ID::Symbol(index) => format!("#{}", self.print_symbol(*index)),
+            ID::Date(d) => print_date(*d),
             ID::Bytes(s) => format!("hex:{}", hex::encode(s)),
             ID::Bool(b) => if *b {
                 "true".to_string()
@@ -76,65 +377,68 @@ impl SymbolTable {
             },
             ID::Set(s) => {
                 let ids = s.iter().map(|id| self.print_id(id)).collect::<Vec<_>>();
-                format!("{:?}", ids)
+                format!("[{}]", ids.join(", "))
             }
+            ID::Wildcard => "_".to_string(),
         }
     }
     pub fn print_fact(&self, f: &Fact) -> String {
         self.print_predicate(&f.predicate)
     }
 
+    /// like `print_fact`, but fails on the first symbol missing from the table
+    pub fn try_print_fact(&self, f: &Fact) -> Result<String, crate::error::Symbol> {
+        self.check_predicate(&f.predicate)?;
+        Ok(self.print_fact(f))
+    }
+
     pub fn print_predicate(&self, p: &Predicate) -> String {
         let strings = p
             .ids
             .iter()
             .map(|id| self.print_id(id))
             .collect::<Vec<_>>();
-        format!(
-            "{}({})",
-            self.symbols
-                .get(p.name as usize)
-                .map(|s| s.as_str())
-                .unwrap_or("<?>"),
-            strings.join(", ")
-        )
+        format!("{}({})", self.print_symbol(p.name), strings.join(", "))
     }
 
+    /// prints the constraint as `$variable <operator> <value>`, the values
+    /// of sets being sorted
     pub fn print_constraint(&self, c: &Constraint) -> String {
+        let var = self.print_variable(c.id);
         match &c.kind {
-            ConstraintKind::Int(IntConstraint::LessThan(i)) => format!("${} < {}", self.print_symbol(c.id as u64), i),
-            ConstraintKind::Int(IntConstraint::GreaterThan(i)) => format!("${} > {}", self.print_symbol(c.id as u64), i),
-            ConstraintKind::Int(IntConstraint::LessOrEqual(i)) => format!("${} <= {}", self.print_symbol(c.id as u64), i),
-            ConstraintKind::Int(IntConstraint::GreaterOrEqual(i)) => format!("${} >= {}", self.print_symbol(c.id as u64), i),
-            ConstraintKind::Int(IntConstraint::Equal(i)) => format!("${} == {}", self.print_symbol(c.id as u64), i),
-            ConstraintKind::Int(IntConstraint::In(i)) => format!("${} in {:?}", self.print_symbol(c.id as u64), i),
-            ConstraintKind::Int(IntConstraint::NotIn(i)) => format!("${} not in {:?}", self.print_symbol(c.id as u64), i),
-            ConstraintKind::Str(StrConstraint::Prefix(i)) => format!("${} matches {}*", self.print_symbol(c.id as u64), i),
-            ConstraintKind::Str(StrConstraint::Suffix(i)) => format!("${} matches *{}", self.print_symbol(c.id as u64), i),
-            ConstraintKind::Str(StrConstraint::Equal(i)) => format!("${} == {}", self.print_symbol(c.id as u64), i),
-            ConstraintKind::Str(StrConstraint::Regex(i)) => format!("${} matches /{}/", self.print_symbol(c.id as u64), i),
-            ConstraintKind::Str(StrConstraint::In(i)) => format!("${} in {:?}", self.print_symbol(c.id as u64), i),
-            ConstraintKind::Str(StrConstraint::NotIn(i)) => format!("${} not in {:?}", self.print_symbol(c.id as u64), i),
-            ConstraintKind::Date(DateConstraint::Before(i)) => {
-              let date = DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(*i as i64, 0), Utc);
-              format!("${} <= {}", self.print_symbol(c.id as u64), date.to_rfc3339())
+            ConstraintKind::Int(IntConstraint::LessThan(i)) => format!("{} < {}", var, i),
+            ConstraintKind::Int(IntConstraint::GreaterThan(i)) => format!("{} > {}", var, i),
+            ConstraintKind::Int(IntConstraint::LessOrEqual(i)) => format!("{} <= {}", var, i),
+            ConstraintKind::Int(IntConstraint::GreaterOrEqual(i)) => format!("{} >= {}", var, i),
+            ConstraintKind::Int(IntConstraint::Equal(i)) => format!("{} == {}", var, i),
+            ConstraintKind::Int(IntConstraint::In(i)) => format!("{} in {}", var, print_set(i, |i| i.to_string())),
+            ConstraintKind::Int(IntConstraint::NotIn(i)) => format!("{} not in {}", var, print_set(i, |i| i.to_string())),
+            ConstraintKind::Str(StrConstraint::Prefix(s)) => format!("{} matches {}*", var, print_string(s)),
+            ConstraintKind::Str(StrConstraint::Suffix(s)) => format!("{} matches *{}", var, print_string(s)),
+            ConstraintKind::Str(StrConstraint::Equal(s)) => format!("{} == {}", var, print_string(s)),
+            ConstraintKind::Str(StrConstraint::Regex(s)) => format!("{} matches /{}/", var, s),
+            ConstraintKind::Str(StrConstraint::In(s)) => format!("{} in {}", var, print_set(s, |s| print_string(s))),
+            ConstraintKind::Str(StrConstraint::NotIn(s)) => format!("{} not in {}", var, print_set(s, |s| print_string(s))),
+            ConstraintKind::Date(DateConstraint::Before(d)) => format!("{} <= {}", var, print_date(*d)),
+            ConstraintKind::Date(DateConstraint::After(d)) => format!("{} >= {}", var, print_date(*d)),
+            ConstraintKind::Symbol(SymbolConstraint::In(s)) => {
+                format!("{} in {}", var, print_set(s, |s| format!("#{}", self.print_symbol(*s))))
             },
-            ConstraintKind::Date(DateConstraint::After(i)) => {
-              let date = DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(*i as i64, 0), Utc);
-              format!("${} >= {}", self.print_symbol(c.id as u64), date.to_rfc3339())
+            ConstraintKind::Symbol(SymbolConstraint::NotIn(s)) => {
+                format!("{} not in {}", var, print_set(s, |s| format!("#{}", self.print_symbol(*s))))
             },
-            ConstraintKind::Symbol(SymbolConstraint::In(i)) => format!("${} in {:?}", c.id, i),
-            ConstraintKind::Symbol(SymbolConstraint::NotIn(i)) => {
-                format!("${} not in {:?}", self.print_symbol(c.id as u64), i)
-            }
-            ConstraintKind::Bytes(BytesConstraint::Equal(i)) => format!("${} == hex:{}", c.id, hex::encode(i)),
-            ConstraintKind::Bytes(BytesConstraint::In(i)) => {
-                format!("${} in {:?}", self.print_symbol(c.id as u64), i.iter()
-                        .map(|s| format!("hex:{}", hex::encode(s))).collect::<HashSet<_>>())
+            ConstraintKind::Bytes(BytesConstraint::Equal(b)) => format!("{} == hex:{}", var, hex::encode(b)),
+            ConstraintKind::Bytes(BytesConstraint::In(b)) => {
+                format!("{} in {}", var, print_set(b, |b| format!("hex:{}", hex::encode(b))))
+            },
+            ConstraintKind::Bytes(BytesConstraint::NotIn(b)) => {
+                format!("{} not in {}", var, print_set(b, |b| format!("hex:{}", hex::encode(b))))
             },
-            ConstraintKind::Bytes(BytesConstraint::NotIn(i)) => {
-                format!("${} not in {:?}", self.print_symbol(c.id as u64), i.iter()
-                        .map(|s| format!("hex:{}", hex::encode(s))).collect::<HashSet<_>>())
+            // the content can be large, only the name is printed:
+            // `parser::parse_rule_with_sets` reads it back with a world
+            ConstraintKind::Bytes(BytesConstraint::InSet(h)) => format!("{} in set #{}", var, self.print_symbol(h.name())),
+            ConstraintKind::Bytes(BytesConstraint::NotInSet(h)) => {
+                format!("{} not in set #{}", var, self.print_symbol(h.name()))
             },
         }
     }
@@ -143,22 +447,174 @@ impl SymbolTable {
         e.print(self).unwrap_or_else(|| format!("<invalid expression: {:?}>", e.ops))
     }
 
+    /// prints the rule as
+    ///
+    /// ```text
+    /// head($a, count($b)) <- body($a, $b), !negated($a) @ $a > 0, $b in [1, 2] | $a + 1 == $b
+    /// ```
+    ///
+    /// with the constraints after `@` and the expressions after `|`, in
+    /// the order they are evaluated. Both sections are left out when empty
     pub fn print_rule(&self, r: &Rule) -> String {
-        let res = self.print_predicate(&r.head);
-        let preds: Vec<_> = r.body.iter().map(|p| self.print_predicate(p)).collect();
-
-        let expressions: Vec<_> = r
-            .expressions
-            .iter()
-            .map(|c| self.print_expression(c))
+        let res = match &r.aggregate {
+            None => self.print_predicate(&r.head),
+            Some((op, variable)) => {
+                let strings = r.head.ids.iter().map(|id| match id {
+                    ID::Variable(i) if i == variable => op.print(self.print_variable(*i)),
+                    id => self.print_id(id),
+                }).collect::<Vec<_>>();
+                format!("{}({})", self.print_symbol(r.head.name), strings.join(", "))
+            },
+        };
+        let preds: Vec<_> = r.body.iter().map(|p| self.print_predicate(p))
+            .chain(r.negated.iter().map(|p| format!("!{}", self.print_predicate(p))))
             .collect();
+        let mut printed = format!("{} <- {}", res, preds.join(", "));
 
-        format!(
-            "{} <- {}{}",
-            res,
-            preds.join(", "),
-            format!(" @ {}", expressions.join(", "))
-        )
+        if !r.constraints.is_empty() {
+            let constraints: Vec<_> = r.constraints.iter().map(|c| self.print_constraint(c)).collect();
+            printed.push_str(" @ ");
+            printed.push_str(&constraints.join(", "));
+        }
+        if !r.expressions.is_empty() {
+            let expressions: Vec<_> = r.expressions.iter().map(|e| self.print_expression(e)).collect();
+            printed.push_str(" | ");
+            printed.push_str(&expressions.join(", "));
+        }
+
+        printed
+    }
+
+    pub fn print_check_error(&self, e: &crate::error::Check) -> String {
+        match e {
+            crate::error::Check::ArityMismatch { predicate, expected, found } => format!(
+                "predicate {} expects {} terms, got {}",
+                self.print_symbol(*predicate), expected, found
+            ),
+            crate::error::Check::RuleTooComplex(c) => format!(
+                "rule too complex: {} predicates, {} constraints, {} expression ops, {} bytes of payload",
+                c.predicates, c.constraints, c.expression_ops, c.payload_size
+            ),
+            crate::error::Check::TooManyBodyPredicates { found, max } => format!(
+                "rule has {} body predicates, the maximum is {}", found, max
+            ),
+            crate::error::Check::RunLimit(e) => format!("run limit reached: {}", e),
+        }
+    }
+
+    /// prints the error with the rule it refers to, `rules` being the
+    /// rules of the world that was run
+    pub fn print_run_limit(&self, e: &crate::error::RunLimit, rules: &[Rule]) -> String {
+        match e.context().and_then(|c| c.rule).and_then(|i| rules.get(i)) {
+            Some(rule) => format!("{}: {}", e, self.print_rule(rule)),
+            None => e.to_string(),
+        }
+    }
+
+    /// prints the error followed by its sources, with the names of the
+    /// predicates and variables they refer to
+    pub fn print_error(&self, e: &(dyn core::error::Error + 'static)) -> String {
+        let mut messages = Vec::new();
+        let mut current = Some(e);
+        while let Some(e) = current {
+            messages.push(self.print_error_message(e));
+            current = e.source();
+        }
+        messages.join(": ")
+    }
+
+    fn print_error_message(&self, e: &(dyn core::error::Error + 'static)) -> String {
+        use crate::error;
+
+        let e: &(dyn core::error::Error + 'static) = match e.downcast_ref::<error::Error>() {
+            Some(e) => e.inner(),
+            None => e,
+        };
+        let mut message = String::new();
+        let res = if let Some(e) = e.downcast_ref::<error::Stratification>() {
+            write!(message, "predicate {} depends negatively on itself", self.print_symbol(e.predicate))
+        } else if let Some(e) = e.downcast_ref::<error::Rule>() {
+            e.write(&mut message, &|v| self.print_variable(v))
+        } else if let Some(e) = e.downcast_ref::<error::Expression>() {
+            e.write(&mut message, &|v| self.print_variable(v))
+        } else if let Some(e) = e.downcast_ref::<error::Evaluation>() {
+            e.write(&mut message, &|v| self.print_variable(v))
+        } else if let Some(e) = e.downcast_ref::<error::Conversion>() {
+            e.write(&mut message, &|v| self.print_variable(v))
+        } else if let Some(e) = e.downcast_ref::<error::InvalidFact>() {
+            e.write(&mut message, &|v| self.print_variable(v))
+        } else if let Some(e) = e.downcast_ref::<error::Schema>() {
+            e.write(&mut message, &|s| self.print_symbol(s), &|v| self.print_variable(v))
+        } else if let Some(e) = e.downcast_ref::<error::Merge>() {
+            e.write(&mut message, &|s| self.print_symbol(s))
+        } else if let Some(e) = e.downcast_ref::<error::Check>() {
+            e.write(&mut message, &|s| self.print_symbol(s))
+        } else {
+            write!(message, "{}", e)
+        };
+        res.expect("writing to a String does not fail");
+        message
+    }
+
+    /// one line per query, followed by the counts of its report, see
+    /// `World::explain_caveat_failure`. The first body predicate without
+    /// a matching fact is reported alone, as it explains the failure
+    pub fn print_caveat_failure_report(&self, report: &crate::CaveatFailureReport) -> String {
+        let mut lines = Vec::new();
+        for (index, q) in report.queries.iter().enumerate() {
+            lines.push(format!("query {}: {}", index, self.print_rule(&q.query)));
+            if let Some(i) = q.empty_predicate() {
+                lines.push(format!("  no fact matches {}", self.print_predicate(&q.query.body[i])));
+                continue;
+            }
+
+            for (p, count) in q.query.body.iter().zip(&q.predicates) {
+                lines.push(format!("  {}: {} matching facts", self.print_predicate(p), count));
+            }
+            lines.push(format!("  {} candidates", q.candidates));
+            for (c, count) in q.query.constraints.iter().zip(&q.constraints) {
+                lines.push(format!("  {}: rejected {}", self.print_constraint(c), count));
+            }
+            for (e, count) in q.query.expressions.iter().zip(&q.expressions) {
+                lines.push(format!("  {}: rejected {}", self.print_expression(e), count));
+            }
+            for (p, count) in q.query.negated.iter().zip(&q.negated) {
+                lines.push(format!("  !{}: rejected {}", self.print_predicate(p), count));
+            }
+            lines.push(format!("  {} matches", q.matches));
+            if let Some(limit) = &q.limit {
+                lines.push(format!("  stopped early: {}", limit));
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// one line per step, indented by the depth of its predicate
+    pub fn print_evaluation_trace(&self, trace: &crate::EvaluationTrace) -> String {
+        let mut lines = Vec::from([self.print_rule(&trace.rule)]);
+        self.print_trace_steps(&trace.rule, &trace.steps, &mut lines);
+        if let Some(limit) = &trace.limit {
+            lines.push(format!("stopped early: {}", limit));
+        }
+        lines.join("\n")
+    }
+
+    fn print_trace_steps(&self, rule: &crate::Rule, steps: &[crate::TraceStep], lines: &mut Vec<String>) {
+        use crate::TraceOutcome;
+        for step in steps {
+            let outcome = match &step.outcome {
+                TraceOutcome::Conflict(v) => format!("{} has another value", self.print_variable(*v)),
+                TraceOutcome::Constraint(i) => format!("rejected by {}", self.print_constraint(&rule.constraints[*i])),
+                TraceOutcome::Joined => "joined".to_string(),
+                TraceOutcome::Expression(i) => format!("rejected by {}", self.print_expression(&rule.expressions[*i])),
+                TraceOutcome::Negated(i) => format!("rejected by !{}", self.print_predicate(&rule.negated[*i])),
+                TraceOutcome::Produced(fact) => format!("produced {}", self.print_fact(fact)),
+                TraceOutcome::Aggregated => "aggregated".to_string(),
+                TraceOutcome::Failed(e) => format!("failed: {}", self.print_error(e)),
+            };
+            lines.push(format!("{}{}: {}", "  ".repeat(step.predicate + 1), self.print_fact(&step.fact), outcome));
+            self.print_trace_steps(rule, &step.next, lines);
+        }
     }
 
     pub fn print_caveat(&self, c: &Caveat) -> String {
@@ -171,3 +627,197 @@ impl SymbolTable {
         queries.join(" || ")
     }
 }
+
+fn print_set<T, F: Fn(&T) -> String>(set: &BTreeSet<T>, print: F) -> String {
+    let values = set.iter().map(print).collect::<Vec<_>>();
+    format!("[{}]", values.join(", "))
+}
+
+/// quoted, with `"` and `\` escaped by a `\`
+fn print_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn print_date(timestamp: u64) -> String {
+    DateTime::<Utc>::from_timestamp(timestamp as i64, 0)
+        .map(|date| date.to_rfc3339())
+        .unwrap_or_else(|| format!("<invalid date: {}>", timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Binary, Expression, Op};
+
+    #[test]
+    fn interning() {
+        let mut syms = SymbolTable::new();
+        let names = (0..10_000).map(|i| format!("name{}", i)).collect::<Vec<_>>();
+        for (i, name) in names.iter().enumerate() {
+            assert_eq!(syms.insert(name), i as u64);
+        }
+        assert_eq!(syms.symbols, names);
+
+        // duplicates return the original index
+        assert_eq!(syms.insert("name42"), 42);
+        assert_eq!(syms.add("name9999"), ID::Symbol(9999));
+        assert_eq!(syms.symbols.len(), 10_000);
+        assert_eq!(syms.get("name1234"), Some(1234));
+        assert_eq!(syms.get("unknown"), None);
+        assert!(names.iter().enumerate().all(|(i, name)| syms.print_symbol(i as u64) == *name));
+
+        // built from a vector, or with symbols added directly
+        let mut table = SymbolTable::from(names.clone());
+        assert_eq!(table, syms);
+        assert_eq!(table.get("name5000"), Some(5000));
+        table.symbols.push("pushed".to_string());
+        assert_eq!(table.get("pushed"), Some(10_000));
+        assert_eq!(table.insert("pushed"), 10_000);
+        assert_eq!(table.insert("name0"), 0);
+        table.symbols.truncate(2);
+        assert_eq!(table.get("name5000"), None);
+        assert_eq!(table.insert("name5000"), 2);
+
+        let duplicated = SymbolTable::from(vec!["a".to_string(), "b".to_string(), "a".to_string()]);
+        assert_eq!(duplicated.get("a"), Some(0));
+    }
+
+    #[test]
+    fn print_rule() {
+        let mut syms = SymbolTable::new();
+        let head = syms.insert("head");
+        let body = syms.insert("body");
+        let negated = syms.insert("negated");
+        let a = syms.insert("a") as u32;
+        let b = syms.insert("b") as u32;
+        let c = syms.insert("c") as u32;
+        let (x, y) = (syms.insert("x"), syms.insert("y"));
+
+        let r = Rule {
+            head: Predicate::new(head, &[ID::Variable(a), ID::Variable(c)]),
+            body: vec![Predicate::new(body, &[ID::Variable(a), ID::Variable(b)])],
+            constraints: vec![
+                Constraint { id: a, kind: ConstraintKind::Int(IntConstraint::In(BTreeSet::from([10, 2, 1]))) },
+                Constraint { id: b, kind: ConstraintKind::Str(StrConstraint::Prefix("/dir".to_string())) },
+                Constraint { id: b, kind: ConstraintKind::Str(StrConstraint::NotIn(BTreeSet::from(["z".to_string()]))) },
+                Constraint {
+                    id: a,
+                    kind: ConstraintKind::Symbol(SymbolConstraint::NotIn(BTreeSet::from([y, x]))),
+                },
+                Constraint { id: b, kind: ConstraintKind::Bytes(BytesConstraint::Equal(vec![0xca, 0xfe])) },
+            ],
+            expressions: vec![Expression { ops: vec![
+                Op::Value(ID::Variable(a)),
+                Op::Value(ID::Integer(1)),
+                Op::Binary(Binary::Add),
+                Op::Bind(c),
+            ] }],
+            negated: vec![Predicate::new(negated, &[ID::Variable(a)])],
+            aggregate: None,
+        };
+
+        assert_eq!(
+            syms.print_rule(&r),
+            "head($a, $c) <- body($a, $b), !negated($a) \
+            @ $a in [1, 2, 10], $b matches \"/dir\"*, $b not in [\"z\"], $a not in [#x, #y], $b == hex:cafe \
+            | $c = $a + 1"
+        );
+    }
+
+    #[test]
+    fn unknown_symbols() {
+        let mut syms = SymbolTable::new();
+        let right = syms.insert("right");
+        let file1 = syms.add("file1");
+        let bogus = Fact::new(right, &[file1.clone(), ID::Symbol(42)]);
+
+        assert_eq!(syms.print_fact(&bogus), "right(#file1, #<42?>)");
+        assert_eq!(syms.try_print_fact(&bogus), Err(crate::error::Symbol::Unknown(42)));
+        assert_eq!(syms.print_fact(&Fact::new(7, &[])), "<7?>()");
+        let valid = Fact::new(right, &[file1]);
+        assert_eq!(syms.try_print_fact(&valid), Ok("right(#file1)".to_string()));
+
+        let mut w = World::new();
+        w.add_fact(valid);
+        assert_eq!(syms.check_world(&w), Ok(()));
+
+        w.add_fact(bogus);
+        w.add_rule(Rule {
+            head: Predicate::new(9, &[ID::Variable(30)]),
+            body: vec![Predicate::new(right, &[ID::Variable(30)])],
+            constraints: vec![Constraint {
+                id: 30,
+                kind: ConstraintKind::Symbol(SymbolConstraint::In(BTreeSet::from([42, 0]))),
+            }],
+            expressions: vec![],
+            negated: vec![],
+            aggregate: None,
+        });
+        assert_eq!(syms.check_world(&w), Err(vec![
+            crate::error::Symbol::Unknown(9),
+            crate::error::Symbol::Unknown(30),
+            crate::error::Symbol::Unknown(42),
+        ]));
+    }
+
+    #[test]
+    fn max_symbols() {
+        let mut syms = SymbolTable::with_defaults();
+        syms.set_max_symbols(Some(10));
+        assert_eq!(syms.max_symbols(), Some(10));
+        assert_eq!(syms.len(), 7);
+
+        let file = crate::try_sym(&mut syms, "file").unwrap();
+        let name = crate::try_var(&mut syms, "name").unwrap();
+        assert_eq!(syms.try_insert("read"), Ok(9));
+        assert_eq!(syms.len(), 10);
+        let size = syms.estimated_size();
+
+        let full = crate::error::Symbol::TooManySymbols { max: 10 };
+        assert_eq!(syms.try_insert("write"), Err(full.clone()));
+        assert_eq!(crate::try_sym(&mut syms, "write"), Err(full.clone()));
+        assert_eq!(crate::try_var(&mut syms, "other"), Err(full));
+        assert_eq!((syms.len(), syms.estimated_size()), (10, size));
+        assert_eq!(syms.get("write"), None);
+
+        // existing symbols are still found at the limit
+        assert_eq!(crate::try_sym(&mut syms, "file"), Ok(file));
+        assert_eq!(crate::try_var(&mut syms, "name"), Ok(name));
+        assert_eq!(syms.try_add("authority"), Ok(ID::Symbol(SymbolTable::AUTHORITY)));
+
+        syms.set_max_symbols(None);
+        assert_eq!(syms.try_insert("write"), Ok(10));
+        assert!(syms.estimated_size() > size);
+        assert!(!syms.is_empty() && SymbolTable::new().is_empty());
+    }
+
+    #[test]
+    fn default_symbols() {
+        let mut syms = SymbolTable::with_defaults();
+        let expected = [
+            ("authority", SymbolTable::AUTHORITY),
+            ("ambient", SymbolTable::AMBIENT),
+            ("resource", SymbolTable::RESOURCE),
+            ("operation", SymbolTable::OPERATION),
+            ("right", SymbolTable::RIGHT),
+            ("current_time", SymbolTable::CURRENT_TIME),
+            ("revocation_id", SymbolTable::REVOCATION_ID),
+        ];
+        assert_eq!(syms.symbols.len(), expected.len());
+        for (i, (name, index)) in expected.iter().enumerate() {
+            assert_eq!(*index, i as Symbol);
+            assert_eq!(syms.get(name), Some(*index));
+            assert_eq!(syms.insert(name), *index);
+        }
+
+        // user symbols come after the default ones
+        let file1 = syms.add("file1");
+        let read = syms.add("read");
+        assert_eq!((file1.clone(), read.clone()), (ID::Symbol(7), ID::Symbol(8)));
+
+        let f = Fact::new(SymbolTable::RIGHT, &[ID::Symbol(SymbolTable::AUTHORITY), file1, read]);
+        assert_eq!(syms.print_fact(&f), "right(#authority, #file1, #read)");
+        assert_eq!(syms.print_id(&ID::Symbol(SymbolTable::REVOCATION_ID)), "#revocation_id");
+        assert!(SymbolTable::new().symbols.is_empty());
+    }
+}
@@ -0,0 +1,46 @@
+//! locks shared by the `std` and `no_std` builds, with the API of the
+//! `std` ones. Poisoned `std` locks are used as is
+#[cfg(feature = "std")]
+pub(crate) use std::sync::OnceLock;
+
+#[cfg(feature = "std")]
+pub(crate) struct Mutex<T>(std::sync::Mutex<T>);
+
+#[cfg(feature = "std")]
+impl<T> Mutex<T> {
+    pub(crate) fn new(value: T) -> Self {
+        Mutex(std::sync::Mutex::new(value))
+    }
+
+    pub(crate) fn lock(&self) -> std::sync::MutexGuard<'_, T> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) use spin::Mutex;
+
+#[cfg(not(feature = "std"))]
+pub(crate) struct OnceLock<T>(spin::Once<T>);
+
+#[cfg(not(feature = "std"))]
+impl<T> OnceLock<T> {
+    pub(crate) fn new() -> Self {
+        OnceLock(spin::Once::new())
+    }
+
+    pub(crate) fn get(&self) -> Option<&T> {
+        self.0.get()
+    }
+
+    /// like `std::sync::OnceLock::set`, the value is returned if one was
+    /// already set
+    pub(crate) fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.0.call_once(|| value.take().expect("called once"));
+        match value {
+            Some(value) => Err(value),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,280 @@
+//! test vectors shared with other implementations of the biscuit datalog
+//!
+//! a vector is a JSON document with a symbol table, facts, rules and
+//! caveats in the text format of the `parser` module, the limits of the
+//! run, and its expected results:
+//!
+//! ```json
+//! {
+//!   "name": "family",
+//!   "symbols": ["A", "B", "C", "parent", "grandparent"],
+//!   "facts": ["parent(#A, #B)", "parent(#B, #C)"],
+//!   "rules": ["grandparent($a, $c) <- parent($a, $b), parent($b, $c)"],
+//!   "expected": { "derived": ["grandparent(#A, #C)"] }
+//! }
+//! ```
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::{Fact, RuleErrors, RunLimits, SymbolTable, World};
+use crate::parser::{parse_caveat, parse_fact, parse_rule};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TestVector {
+    pub name: String,
+    /// initial symbol table, the symbols and variable names missing from
+    /// it are added after it while parsing
+    #[serde(default)]
+    pub symbols: Vec<String>,
+    #[serde(default)]
+    pub facts: Vec<String>,
+    #[serde(default)]
+    pub rules: Vec<String>,
+    #[serde(default)]
+    pub caveats: Vec<String>,
+    #[serde(default)]
+    pub limits: TestLimits,
+    pub expected: Expectations,
+}
+
+/// limits of the run and of the caveat checks
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TestLimits {
+    pub max_facts: u32,
+    pub max_iterations: u32,
+    pub max_time_ms: u64,
+    /// stops the run on the first rule error, see `RuleErrors`
+    pub fatal_rule_errors: bool,
+}
+
+impl Default for TestLimits {
+    fn default() -> Self {
+        TestLimits { max_facts: 1000, max_iterations: 100, max_time_ms: 1000, fatal_rule_errors: false }
+    }
+}
+
+impl TestLimits {
+    pub fn run_limits(&self) -> RunLimits {
+        RunLimits {
+            max_facts: Some(self.max_facts),
+            max_iterations: Some(self.max_iterations),
+            max_time: Some(Duration::from_millis(self.max_time_ms)),
+            rule_errors: if self.fatal_rule_errors { RuleErrors::Fatal } else { RuleErrors::Skip },
+            ..RunLimits::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Expectations {
+    /// facts generated by the rules, in any order. Not checked if missing
+    pub derived: Option<Vec<String>>,
+    /// for each caveat, true if it is verified after the run
+    pub caveats: Vec<bool>,
+    /// the run's error, see `error_code`
+    pub error: Option<String>,
+}
+
+/// differences between a vector's expectations and the actual results
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestVectorResult {
+    pub name: String,
+    pub failures: Vec<String>,
+}
+
+impl TestVectorResult {
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl fmt::Display for TestVectorResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_success() {
+            return write!(f, "{}: ok", self.name);
+        }
+        write!(f, "{}: {} failures", self.name, self.failures.len())?;
+        for failure in self.failures.iter() {
+            write!(f, "\n  {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+/// name of the error in test vectors, independent of the error message
+pub fn error_code(e: &crate::error::Error) -> &'static str {
+    use crate::error::{Error, RunLimit};
+
+    match e {
+        Error::RunLimit(RunLimit::TooManyFacts(_)) => "too_many_facts",
+        Error::RunLimit(RunLimit::TooManyIterations(_)) => "too_many_iterations",
+        Error::RunLimit(RunLimit::Timeout(_)) => "timeout",
+        Error::RunLimit(RunLimit::TooManyCandidates(_)) => "too_many_candidates",
+        Error::RunLimit(RunLimit::TooMuchMemory(_)) => "too_much_memory",
+        Error::RunLimit(RunLimit::Cancelled) => "cancelled",
+        Error::Stratification(_) => "stratification",
+        Error::RuleFailed(_) => "rule_failed",
+        _ => "other",
+    }
+}
+
+/// builds the world, runs it and checks the caveats
+pub fn run_test_vector(vector: &TestVector) -> TestVectorResult {
+    let mut result = TestVectorResult { name: vector.name.clone(), failures: Vec::new() };
+    let mut syms = match SymbolTable::from_symbols(vector.symbols.clone()) {
+        Ok(syms) => syms,
+        Err(e) => {
+            result.failures.push(format!("symbols: {}", e));
+            return result;
+        },
+    };
+
+    let mut w = World::new();
+    for (i, f) in vector.facts.iter().enumerate() {
+        match parse_fact(f, &mut syms) {
+            Ok(f) => w.add_fact(f),
+            Err(e) => result.failures.push(format!("fact {}: {}: {}", i, e, f)),
+        }
+    }
+    for (i, r) in vector.rules.iter().enumerate() {
+        match parse_rule(r, &mut syms) {
+            Ok(r) => {
+                w.add_rule(r);
+            },
+            Err(e) => result.failures.push(format!("rule {}: {}: {}", i, e, r)),
+        }
+    }
+    let mut caveats = Vec::new();
+    for (i, c) in vector.caveats.iter().enumerate() {
+        match parse_caveat(c, &mut syms) {
+            Ok(c) => caveats.push(c),
+            Err(e) => result.failures.push(format!("caveat {}: {}: {}", i, e, c)),
+        }
+    }
+    let mut expected = HashSet::new();
+    for (i, f) in vector.expected.derived.iter().flatten().enumerate() {
+        match parse_fact(f, &mut syms) {
+            Ok(f) => {
+                expected.insert(f);
+            },
+            Err(e) => result.failures.push(format!("expected fact {}: {}: {}", i, e, f)),
+        }
+    }
+    if !result.is_success() {
+        return result;
+    }
+
+    let initial = w.facts.iter().cloned().collect::<HashSet<_>>();
+    let limits = vector.limits.run_limits();
+    match (w.run_with_limits(limits.clone()), &vector.expected.error) {
+        (Ok(()), Some(code)) => result.failures.push(format!("expected the run to fail with {}", code)),
+        (Err(e), None) => result.failures.push(format!("the run failed: {}", e)),
+        (Err(e), Some(code)) if error_code(&e) != code => {
+            result.failures.push(format!("expected the run to fail with {}, got {}: {}", code, error_code(&e), e))
+        },
+        _ => {},
+    }
+
+    if vector.expected.derived.is_some() {
+        let derived = w.facts.iter().filter(|f| !initial.contains(f)).cloned().collect::<HashSet<_>>();
+        let print = |facts: Vec<&Fact>| {
+            let mut printed = facts.into_iter().map(|f| syms.print_fact(f)).collect::<Vec<_>>();
+            printed.sort();
+            printed
+        };
+        for f in print(expected.difference(&derived).collect()) {
+            result.failures.push(format!("missing fact: {}", f));
+        }
+        for f in print(derived.difference(&expected).collect()) {
+            result.failures.push(format!("unexpected fact: {}", f));
+        }
+    }
+
+    if vector.expected.caveats.len() != caveats.len() {
+        result.failures.push(format!(
+            "{} caveats, but {} expected outcomes",
+            caveats.len(), vector.expected.caveats.len()
+        ));
+    } else {
+        let failed = match w.check_caveats(&caveats, Some(&limits), &Default::default(), &syms) {
+            Ok(()) => Vec::new(),
+            Err(failed) => failed,
+        };
+        for (i, expected) in vector.expected.caveats.iter().enumerate() {
+            match (failed.iter().find(|f| f.index == i), expected) {
+                (Some(f), true) => result.failures.push(format!("caveat {} failed ({:?}): {}", i, f.error, f.caveat)),
+                (None, false) => result.failures.push(format!("caveat {} succeeded: {}", i, vector.caveats[i])),
+                _ => {},
+            }
+        }
+    }
+
+    result
+}
+
+/// loads the `.json` files of the directory, in the order of their names
+pub fn load_test_vectors<P: AsRef<Path>>(dir: P) -> io::Result<Vec<TestVector>> {
+    let mut paths = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    paths.retain(|p| p.extension().is_some_and(|e| e == "json"));
+    paths.sort();
+
+    paths.iter().map(|path| {
+        let content = std::fs::read_to_string(path)?;
+        serde_json::from_str(&content)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failures() {
+        let vector: TestVector = serde_json::from_str(r#"{
+            "name": "wrong",
+            "facts": ["parent(#A, #B)", "parent(#B, #C)", "right(#A, #read)"],
+            "rules": ["grandparent($a, $c) <- parent($a, $b), parent($b, $c)"],
+            "caveats": ["c($a) <- right($a, #read)", "c($a) <- right($a, #write)"],
+            "expected": {
+                "derived": ["grandparent(#A, #B)"],
+                "caveats": [false, true],
+                "error": "too_many_facts"
+            }
+        }"#).unwrap();
+
+        let result = run_test_vector(&vector);
+        assert_eq!(result.failures, vec![
+            "expected the run to fail with too_many_facts".to_string(),
+            "missing fact: grandparent(#A, #B)".to_string(),
+            "unexpected fact: grandparent(#A, #C)".to_string(),
+            "caveat 0 succeeded: c($a) <- right($a, #read)".to_string(),
+            "caveat 1 failed (NoMatch): c($a) <- right($a, #write)".to_string(),
+        ]);
+        assert!(result.to_string().starts_with("wrong: 5 failures\n  expected the run"));
+
+        let fixed = TestVector {
+            expected: Expectations {
+                derived: Some(vec!["grandparent(#A, #C)".to_string()]),
+                caveats: vec![true, false],
+                error: None,
+            },
+            ..vector.clone()
+        };
+        assert_eq!(run_test_vector(&fixed).to_string(), "wrong: ok");
+
+        let invalid = TestVector { rules: vec!["grandparent($a) <-- parent($a)".to_string()], ..fixed };
+        assert_eq!(run_test_vector(&invalid).failures, vec![
+            "rule 0: expected a name at byte 18: grandparent($a) <-- parent($a)".to_string(),
+        ]);
+    }
+}
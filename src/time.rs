@@ -0,0 +1,116 @@
+//! time measurements of the runs
+//!
+//! time comes from a `Clock`: the one given to
+//! `World::run_with_limits_and_clock`, or else the one set with
+//! `set_clock`, or else `SystemClock` on targets with a system clock
+//! (`std`, except `wasm32-unknown-unknown`). Without a clock, time limits
+//! are not enforced and the durations in `RunStats` are zero
+use core::time::Duration;
+
+/// source of time for the runs
+pub trait Clock: Sync {
+    /// time elapsed since a fixed point, it must never decrease
+    fn now(&self) -> Duration;
+}
+
+/// monotonic clock of the operating system
+#[cfg(all(feature = "std", not(all(target_arch = "wasm32", target_os = "unknown"))))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[cfg(all(feature = "std", not(all(target_arch = "wasm32", target_os = "unknown"))))]
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+        START.get_or_init(std::time::Instant::now).elapsed()
+    }
+}
+
+static CLOCK: spin::Once<&'static dyn Clock> = spin::Once::new();
+
+/// sets the clock used by the runs that are not given one, returns false
+/// if one was already set
+pub fn set_clock(clock: &'static dyn Clock) -> bool {
+    let mut set = false;
+    CLOCK.call_once(|| {
+        set = true;
+        clock
+    });
+    set
+}
+
+/// the clock used by the runs that are not given one
+pub(crate) fn default_clock() -> Option<&'static dyn Clock> {
+    CLOCK.get().copied().or_else(system_clock)
+}
+
+#[cfg(all(feature = "std", not(all(target_arch = "wasm32", target_os = "unknown"))))]
+fn system_clock() -> Option<&'static dyn Clock> {
+    Some(&SystemClock)
+}
+
+#[cfg(not(all(feature = "std", not(all(target_arch = "wasm32", target_os = "unknown")))))]
+fn system_clock() -> Option<&'static dyn Clock> {
+    None
+}
+
+/// point in time given by a `Clock`, if there is one
+#[derive(Clone, Copy)]
+pub(crate) struct Instant<'c> {
+    clock: Option<&'c dyn Clock>,
+    at: Duration,
+}
+
+impl<'c> Instant<'c> {
+    pub(crate) fn now(clock: Option<&'c dyn Clock>) -> Self {
+        Instant { clock, at: clock.map_or(Duration::ZERO, |clock| clock.now()) }
+    }
+
+    /// zero without a clock
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.clock.map_or(Duration::ZERO, |clock| clock.now().saturating_sub(self.at))
+    }
+
+    /// `None` without a clock, so that deadlines are never reached
+    pub(crate) fn checked_add(&self, duration: Duration) -> Option<Self> {
+        self.clock?;
+        self.at.checked_add(duration).map(|at| Instant { clock: self.clock, at })
+    }
+
+    /// true once the clock reached this point
+    pub(crate) fn has_passed(&self) -> bool {
+        self.clock.is_some_and(|clock| clock.now() >= self.at)
+    }
+}
+
+#[cfg(all(test, not(feature = "std")))]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    /// advances by one millisecond every time it is read
+    struct Ticks(AtomicU64);
+
+    impl Clock for Ticks {
+        fn now(&self) -> Duration {
+            Duration::from_millis(self.0.fetch_add(1, Ordering::Relaxed))
+        }
+    }
+
+    static TICKS: Ticks = Ticks(AtomicU64::new(0));
+
+    #[test]
+    fn injected_clock() {
+        let start = Instant::now(default_clock());
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        assert!(start.checked_add(Duration::from_secs(1)).is_none());
+
+        assert!(set_clock(&TICKS));
+        assert!(!set_clock(&TICKS));
+        let start = Instant::now(default_clock());
+        // other tests may read the clock in between
+        assert!(start.elapsed() >= Duration::from_millis(1));
+        assert!(!start.checked_add(Duration::from_secs(3600)).unwrap().has_passed());
+        assert!(start.checked_add(Duration::ZERO).unwrap().has_passed());
+    }
+}
@@ -0,0 +1,95 @@
+//! `tracing` spans and events of the runs, with the `tracing` feature
+//!
+//! without it, the functions of this module do nothing and are optimized
+//! away. The spans are at the `DEBUG` level:
+//! - `iteration`, with the `iteration` index and the number of `new` facts
+//! - `rule`, inside an iteration, with the `rule` index, the number of
+//!   `candidates` examined, the number of facts `emitted`, and the `duration`
+//!
+//! and the `check_caveat` methods emit a `DEBUG` event for each query that
+//! did not match, with its `query` index and its printed `rule`
+use super::{Budget, Rule, SymbolTable};
+use crate::time::Clock;
+
+#[cfg(feature = "tracing")]
+use crate::time::Instant;
+#[cfg(feature = "tracing")]
+use tracing::field::{debug, Empty};
+
+/// span of a pass over the rules of a stratum
+pub(crate) struct IterationSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+}
+
+impl IterationSpan {
+    #[cfg(feature = "tracing")]
+    pub(crate) fn enter(iteration: u32) -> Self {
+        IterationSpan { span: tracing::debug_span!("iteration", iteration, new = Empty).entered() }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn enter(_iteration: u32) -> Self {
+        IterationSpan {}
+    }
+
+    #[cfg(feature = "tracing")]
+    pub(crate) fn exit(self, new: usize) {
+        self.span.record("new", new);
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn exit(self, _new: usize) {}
+}
+
+/// span of a rule application
+pub(crate) struct RuleSpan<'c> {
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+    #[cfg(feature = "tracing")]
+    start: Instant<'c>,
+    #[cfg(feature = "tracing")]
+    candidates: u64,
+    #[cfg(not(feature = "tracing"))]
+    clock: core::marker::PhantomData<&'c dyn Clock>,
+}
+
+impl<'c> RuleSpan<'c> {
+    #[cfg(feature = "tracing")]
+    pub(crate) fn enter(rule: usize, budget: &Budget<'_>, clock: Option<&'c dyn Clock>) -> Self {
+        RuleSpan {
+            span: tracing::debug_span!("rule", rule, candidates = Empty, emitted = Empty, duration = Empty).entered(),
+            start: Instant::now(clock),
+            candidates: budget.candidates(),
+        }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn enter(_rule: usize, _budget: &Budget<'_>, _clock: Option<&'c dyn Clock>) -> Self {
+        RuleSpan { clock: core::marker::PhantomData }
+    }
+
+    #[cfg(feature = "tracing")]
+    pub(crate) fn exit(self, budget: &Budget<'_>, emitted: usize) {
+        self.span.record("candidates", budget.candidates() - self.candidates);
+        self.span.record("emitted", emitted);
+        self.span.record("duration", debug(self.start.elapsed()));
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn exit(self, _budget: &Budget<'_>, _emitted: usize) {}
+}
+
+/// a query of a caveat did not match, printed with `symbols` if there
+/// are some
+#[cfg(feature = "tracing")]
+pub(crate) fn failed_query(query: usize, rule: &Rule, symbols: Option<&SymbolTable>) {
+    tracing::debug!(
+        query,
+        rule = %symbols.map_or_else(|| SymbolTable::new().print_rule(rule), |symbols| symbols.print_rule(rule)),
+        "caveat query failed",
+    );
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn failed_query(_query: usize, _rule: &Rule, _symbols: Option<&SymbolTable>) {}
@@ -0,0 +1,128 @@
+//! generated worlds to benchmark the evaluation, see `benches/workloads.rs`
+//!
+//! each generator returns a world holding the facts, the symbol table
+//! they were built with, and a rule that is not added to the world, so
+//! the same inputs can be evaluated with `World::run_with_limits` after
+//! `World::add_rule`, or with `Rule::apply`. The number of facts the rule
+//! derives is documented for each of them
+use alloc::format;
+
+use super::{int, string, Fact, Rule, SymbolTable, World};
+use crate::parser::parse_rule;
+
+fn workload_rule(rule: &str, symbols: &mut SymbolTable) -> Rule {
+    parse_rule(rule, symbols).expect("workload rules are valid")
+}
+
+/// a chain of `n` edges between `n + 1` nodes, and a rule computing its
+/// transitive closure. The `path` facts start as a copy of the edges, and
+/// the rule derives the `n * (n - 1) / 2` longer paths, one length per
+/// iteration
+pub fn transitive_chain(n: usize) -> (World, SymbolTable, Rule) {
+    let mut symbols = SymbolTable::new();
+    let edge = symbols.insert("edge");
+    let path = symbols.insert("path");
+
+    let mut world = World::new();
+    for i in 0..n as i64 {
+        world.add_fact(Fact::new(edge, &[int(i), int(i + 1)]));
+        world.add_fact(Fact::new(path, &[int(i), int(i + 1)]));
+    }
+    let rule = workload_rule("path($x, $z) <- path($x, $y), edge($y, $z)", &mut symbols);
+    (world, symbols, rule)
+}
+
+/// `m` left facts and `n` right facts with the same key, the rule joins
+/// them on that key and derives `m * n` facts
+pub fn join(m: usize, n: usize) -> (World, SymbolTable, Rule) {
+    let mut symbols = SymbolTable::new();
+    let left = symbols.insert("left");
+    let right = symbols.insert("right");
+    let key = symbols.add("key");
+
+    let mut world = World::new();
+    for i in 0..m as i64 {
+        world.add_fact(Fact::new(left, &[int(i), key.clone()]));
+    }
+    for j in 0..n as i64 {
+        world.add_fact(Fact::new(right, &[key.clone(), int(j)]));
+    }
+    let rule = workload_rule("joined($x, $y) <- left($x, $k), right($k, $y)", &mut symbols);
+    (world, symbols, rule)
+}
+
+/// `hosts` host names under each of `domains` domains, the rule keeps
+/// the ones under the first domain with a suffix constraint, deriving
+/// `hosts` facts if there is at least one domain
+pub fn suffix_filter(domains: usize, hosts: usize) -> (World, SymbolTable, Rule) {
+    let mut symbols = SymbolTable::new();
+    let host = symbols.insert("host");
+
+    let mut world = World::new();
+    for d in 0..domains {
+        for h in 0..hosts {
+            world.add_fact(Fact::new(host, &[string(&format!("h{}.domain{}.com", h, d))]));
+        }
+    }
+    let rule = workload_rule(r#"matched($h) <- host($h) @ $h matches *".domain0.com""#, &mut symbols);
+    (world, symbols, rule)
+}
+
+/// `n` integers, and a rule evaluating several expressions for each of
+/// them: it binds a computed value, and keeps the integers at least half
+/// of `n`, deriving `n / 2` facts (rounded down)
+pub fn expressions(n: usize) -> (World, SymbolTable, Rule) {
+    let mut symbols = SymbolTable::new();
+    let value = symbols.insert("value");
+
+    let mut world = World::new();
+    for i in 0..n as i64 {
+        world.add_fact(Fact::new(value, &[int(i)]));
+    }
+    let rule = workload_rule(
+        &format!("scaled($x, $y) <- value($x) | $y = $x + $x + $x + 1, $x + $x >= {}, -$x < 1 && $y > $x", n),
+        &mut symbols,
+    );
+    (world, symbols, rule)
+}
+
+/// facts of `world` with the name of the rule's head, as generated by it
+pub fn derived(world: &World, rule: &Rule) -> usize {
+    world.facts.iter().filter(|f| f.predicate.name == rule.head.name).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RunLimits;
+
+    fn run((mut world, _, rule): (World, SymbolTable, Rule)) -> usize {
+        let before = world.facts.len();
+        world.add_rule(rule);
+        world.run_with_limits(RunLimits::unlimited()).unwrap();
+        world.facts.len() - before
+    }
+
+    #[test]
+    fn derived_facts() {
+        for n in [0, 1, 2, 5, 20] {
+            assert_eq!(run(transitive_chain(n)), n * n.saturating_sub(1) / 2, "chain of {}", n);
+            assert_eq!(run(expressions(n)), n / 2, "{} expressions", n);
+        }
+        for (m, n) in [(0, 3), (1, 1), (4, 7)] {
+            assert_eq!(run(join(m, n)), m * n, "{}x{} join", m, n);
+        }
+        assert_eq!(run(suffix_filter(0, 5)), 0);
+        assert_eq!(run(suffix_filter(3, 5)), 5);
+
+        // the same rule gives the same facts with `Rule::apply`
+        let (mut world, symbols, rule) = join(3, 4);
+        let mut facts = alloc::vec::Vec::new();
+        rule.apply(&world.facts, &mut facts);
+        assert_eq!(facts.len(), 12);
+        assert_eq!(symbols.print_rule(&rule), "joined($x, $y) <- left($x, $k), right($k, $y)");
+        world.add_rule(rule.clone());
+        world.run_with_limits(RunLimits::unlimited()).unwrap();
+        assert_eq!(derived(&world, &rule), 12);
+    }
+}
@@ -0,0 +1,26 @@
+//! runs the vectors of `tests/vectors`, they are JSON documents so the
+//! test needs the `serde` feature, and a plain `cargo test` skips it. Run
+//! it with:
+//!
+//! ```text
+//! cargo test --all-features --test testvectors
+//! ```
+#![cfg(feature = "serde")]
+
+use datalog::testvectors::{load_test_vectors, run_test_vector};
+
+#[test]
+fn test_vectors() {
+    let vectors = load_test_vectors(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/vectors")).unwrap();
+    let mut names = vectors.iter().map(|v| v.name.as_str()).collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, ["date", "family", "int_expr", "iterations", "numbers", "resource", "rule_error", "set", "str"]);
+
+    let failed = vectors.iter().map(run_test_vector).filter(|r| !r.is_success()).collect::<Vec<_>>();
+    assert!(
+        failed.is_empty(),
+        "{} test vectors failed:\n{}",
+        failed.len(),
+        failed.iter().map(|r| r.to_string()).collect::<Vec<_>>().join("\n")
+    );
+}